@@ -1,9 +1,39 @@
-use futures::future::Future;
+use std::fmt;
+
+use bytes::Bytes;
+use futures::future::{BoxFuture, Future};
 use multiaddr::{Multiaddr, PeerId};
 
+/// Features a connection supports on top of bidirectional streams.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+	/// [`Connection::open_uni`] and [`Connection::accept_uni`] are available.
+	pub uni_streams: bool,
+	/// [`Connection::send_datagram`] and [`Connection::recv_datagram`] are available.
+	pub datagrams: bool,
+}
+
+/// Returned by the optional methods of a [`Connection`] which does not support them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsupported {
+	UniStreams,
+	Datagrams,
+}
+
+impl fmt::Display for Unsupported {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::UniStreams => f.write_str("unidirectional streams are not supported"),
+			Self::Datagrams => f.write_str("datagrams are not supported"),
+		}
+	}
+}
+
+impl std::error::Error for Unsupported {}
+
 pub trait Connection: Unpin + Send + Sync + 'static {
-	type Output;
-	type Error: std::error::Error + Send + Sync + 'static;
+	type Output: Send + 'static;
+	type Error: std::error::Error + From<Unsupported> + Send + Sync + 'static;
 	type Close: Future<Output = Result<(), Self::Error>>;
 	type Stream: Future<Output = Result<Self::Output, Self::Error>>;
 
@@ -13,4 +43,30 @@ pub trait Connection: Unpin + Send + Sync + 'static {
 	fn close(&mut self) -> Self::Close;
 	fn remote_address(&self) -> &Multiaddr;
 	fn remote_peer_id(&self) -> Option<PeerId>;
+
+	/// Which of the optional methods below the connection supports.
+	fn capabilities(&self) -> Capabilities {
+		Capabilities::default()
+	}
+
+	/// Open a stream only writable by the local side.
+	fn open_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		Box::pin(async { Err(Unsupported::UniStreams.into()) })
+	}
+
+	/// Accept a stream opened with [`Connection::open_uni`] by the remote, only readable by the local side.
+	fn accept_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		Box::pin(async { Err(Unsupported::UniStreams.into()) })
+	}
+
+	/// Send an unreliable, unordered message, which may be lost.
+	fn send_datagram(&mut self, payload: Bytes) -> BoxFuture<'static, Result<(), Self::Error>> {
+		let _ = payload;
+		Box::pin(async { Err(Unsupported::Datagrams.into()) })
+	}
+
+	/// Receive the next message sent with [`Connection::send_datagram`] by the remote.
+	fn recv_datagram(&mut self) -> BoxFuture<'static, Result<Bytes, Self::Error>> {
+		Box::pin(async { Err(Unsupported::Datagrams.into()) })
+	}
 }
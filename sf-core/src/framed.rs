@@ -0,0 +1,352 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::Sink;
+use unsigned_varint::{decode, encode};
+
+use crate::Stream;
+
+/// Largest frame accepted by default, in bytes.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+/// Bytes read from the stream at once.
+const READ_CHUNK: usize = 8 * 1024;
+/// Buffered bytes past which the sink writes them to the stream before accepting a new frame.
+const BACKPRESSURE_BOUNDARY: usize = 8 * 1024;
+
+/// Frames of bytes sent over a [`Stream`], each prefixed by its length encoded as an unsigned varint.
+///
+/// Sending is done through [`Sink<Bytes>`] and receiving through [`futures::Stream`], which ends once the remote
+/// finished sending on a frame boundary.
+#[derive(Debug)]
+pub struct Framed<S> {
+	stream: S,
+	max_frame_size: usize,
+	read_buf: BytesMut,
+	/// Scratch buffer the stream reads into, zeroed once rather than before every read.
+	read_chunk: Box<[u8]>,
+	write_buf: BytesMut,
+	eof: bool,
+}
+
+impl<S: Stream> Framed<S> {
+	pub fn new(stream: S) -> Self {
+		Self {
+			stream,
+			max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+			read_buf: BytesMut::new(),
+			read_chunk: vec![0; READ_CHUNK].into_boxed_slice(),
+			write_buf: BytesMut::new(),
+			eof: false,
+		}
+	}
+
+	/// Largest frame sent or received, bigger ones fail with [`io::ErrorKind::InvalidInput`] when sent and
+	/// [`io::ErrorKind::InvalidData`] when received.
+	pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+		self.max_frame_size = max_frame_size;
+		self
+	}
+
+	pub fn max_frame_size(&self) -> usize {
+		self.max_frame_size
+	}
+
+	pub fn get_ref(&self) -> &S {
+		&self.stream
+	}
+
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	/// The underlying stream, the bytes buffered but not sent or not decoded yet are lost.
+	pub fn into_inner(self) -> S {
+		self.stream
+	}
+
+	/// Split the next frame off the read buffer, if it holds a whole one.
+	fn decode_frame(&mut self) -> io::Result<Option<Bytes>> {
+		let (len, rest) = match decode::usize(&self.read_buf) {
+			Ok(decoded) => decoded,
+			Err(decode::Error::Insufficient) => return Ok(None),
+			Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+		};
+		if len > self.max_frame_size {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("frame of {len} bytes exceeds the maximum of {}", self.max_frame_size),
+			));
+		}
+
+		let prefix_len = self.read_buf.len() - rest.len();
+		if rest.len() < len {
+			self.read_buf.reserve(prefix_len + len - self.read_buf.len());
+			return Ok(None);
+		}
+		self.read_buf.advance(prefix_len);
+		Ok(Some(self.read_buf.split_to(len).freeze()))
+	}
+
+	/// Read up to [`READ_CHUNK`] bytes from the stream at the end of the read buffer.
+	///
+	/// Only the bytes read are copied, handing the spare capacity of the read buffer to the stream instead would mean
+	/// zeroing it first.
+	fn poll_read_chunk(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+		let read = ready!(Pin::new(&mut self.stream).poll_read(cx, &mut self.read_chunk))?.min(READ_CHUNK);
+		self.read_buf.extend_from_slice(&self.read_chunk[..read]);
+		Poll::Ready(Ok(read))
+	}
+
+	/// Write the buffered frames to the stream, without flushing it.
+	fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while !self.write_buf.is_empty() {
+			let written = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.write_buf))?;
+			if written == 0 {
+				return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+			}
+			self.write_buf.advance(written);
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<S: Stream> futures::Stream for Framed<S> {
+	type Item = io::Result<Bytes>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		loop {
+			if let Some(frame) = this.decode_frame().transpose() {
+				return Poll::Ready(Some(frame));
+			}
+			if this.eof {
+				return Poll::Ready(None);
+			}
+
+			let read = match ready!(this.poll_read_chunk(cx)) {
+				Ok(read) => read,
+				Err(error) => return Poll::Ready(Some(Err(error))),
+			};
+
+			if read == 0 {
+				this.eof = true;
+				if !this.read_buf.is_empty() {
+					return Poll::Ready(Some(Err(io::Error::new(
+						io::ErrorKind::UnexpectedEof,
+						"the stream ended in the middle of a frame",
+					))));
+				}
+			}
+		}
+	}
+}
+
+impl<S: Stream> Sink<Bytes> for Framed<S> {
+	type Error = io::Error;
+
+	fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		if this.write_buf.len() >= BACKPRESSURE_BOUNDARY {
+			ready!(this.poll_write_buf(cx))?;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(self: Pin<&mut Self>, frame: Bytes) -> io::Result<()> {
+		let this = self.get_mut();
+		if frame.len() > this.max_frame_size {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!(
+					"frame of {} bytes exceeds the maximum of {}",
+					frame.len(),
+					this.max_frame_size
+				),
+			));
+		}
+
+		let mut prefix = encode::usize_buffer();
+		let prefix = encode::usize(frame.len(), &mut prefix);
+		this.write_buf.reserve(prefix.len() + frame.len());
+		this.write_buf.put_slice(prefix);
+		this.write_buf.put(frame);
+		Ok(())
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_write_buf(cx))?;
+		Pin::new(&mut this.stream).poll_flush(cx)
+	}
+
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_write_buf(cx))?;
+		Pin::new(&mut this.stream).poll_close(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::VecDeque;
+
+	use futures::executor::block_on;
+	use futures::future::BoxFuture;
+	use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt, TryStreamExt};
+	use proptest::prelude::*;
+
+	/// A stream reading `input` in the given chunks and writing at most `write_chunk` bytes at once to `output`.
+	#[derive(Default)]
+	struct ChunkedStream {
+		input: VecDeque<Vec<u8>>,
+		output: Vec<u8>,
+		write_chunk: usize,
+	}
+
+	impl ChunkedStream {
+		fn reading(input: &[u8], chunks: &[usize]) -> Self {
+			let mut rest = input;
+			let mut stream = Self::default();
+			for &chunk in chunks.iter().cycle() {
+				if rest.is_empty() {
+					break;
+				}
+				let (head, tail) = rest.split_at(chunk.min(rest.len()));
+				stream.input.push_back(head.to_vec());
+				rest = tail;
+			}
+			stream
+		}
+
+		fn writing(write_chunk: usize) -> Self {
+			Self {
+				write_chunk,
+				..Default::default()
+			}
+		}
+	}
+
+	impl AsyncRead for ChunkedStream {
+		fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+			let this = self.get_mut();
+			let Some(chunk) = this.input.front_mut() else {
+				return Poll::Ready(Ok(0));
+			};
+			let len = buf.len().min(chunk.len());
+			buf[..len].copy_from_slice(&chunk[..len]);
+			chunk.drain(..len);
+			if chunk.is_empty() {
+				this.input.pop_front();
+			}
+			Poll::Ready(Ok(len))
+		}
+	}
+
+	impl AsyncWrite for ChunkedStream {
+		fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			let len = buf.len().min(self.write_chunk);
+			self.get_mut().output.extend_from_slice(&buf[..len]);
+			Poll::Ready(Ok(len))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	impl Stream for ChunkedStream {
+		type Error = io::Error;
+
+		fn close_send(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+			Box::pin(async { Ok(()) })
+		}
+
+		fn close_read(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+			Box::pin(async { Ok(()) })
+		}
+	}
+
+	fn encode(frames: &[Vec<u8>], write_chunk: usize) -> Vec<u8> {
+		let mut framed = Framed::new(ChunkedStream::writing(write_chunk));
+		block_on(async {
+			for frame in frames {
+				framed.feed(Bytes::copy_from_slice(frame)).await.unwrap();
+			}
+			framed.close().await.unwrap();
+		});
+		framed.into_inner().output
+	}
+
+	fn decode(input: &[u8], chunks: &[usize], max_frame_size: usize) -> io::Result<Vec<Bytes>> {
+		let framed = Framed::new(ChunkedStream::reading(input, chunks)).with_max_frame_size(max_frame_size);
+		block_on(framed.try_collect())
+	}
+
+	proptest! {
+		#[test]
+		fn test_round_trip_across_chunk_boundaries(
+			frames in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..300), 0..20),
+			write_chunk in 1..64usize,
+			read_chunks in prop::collection::vec(1..64usize, 1..16),
+		) {
+			let encoded = encode(&frames, write_chunk);
+			let decoded = decode(&encoded, &read_chunks, DEFAULT_MAX_FRAME_SIZE).unwrap();
+			prop_assert_eq!(decoded, frames);
+		}
+
+		#[test]
+		fn test_truncated_input_fails(
+			frames in prop::collection::vec(prop::collection::vec(any::<u8>(), 1..300), 1..10),
+			cut in any::<prop::sample::Index>(),
+			read_chunks in prop::collection::vec(1..64usize, 1..16),
+		) {
+			let encoded = encode(&frames, usize::MAX);
+			let truncated = &encoded[..cut.index(encoded.len())];
+			let boundaries = frames.iter().scan(0, |end, frame| {
+				*end += encode::usize(frame.len(), &mut encode::usize_buffer()).len() + frame.len();
+				Some(*end)
+			});
+			let on_boundary = truncated.is_empty() || boundaries.into_iter().any(|end| end == truncated.len());
+
+			match decode(truncated, &read_chunks, DEFAULT_MAX_FRAME_SIZE) {
+				Ok(decoded) => {
+					let decoded = decoded.into_iter().map(Vec::from).collect::<Vec<_>>();
+					prop_assert!(on_boundary && frames.starts_with(&decoded));
+				}
+				Err(error) => prop_assert!(!on_boundary && error.kind() == io::ErrorKind::UnexpectedEof),
+			}
+		}
+	}
+
+	#[test]
+	fn test_rejects_frames_over_the_max_size() {
+		let encoded = encode(&[vec![0; 16], vec![1; 17]], usize::MAX);
+
+		let mut framed = Framed::new(ChunkedStream::reading(&encoded, &[5])).with_max_frame_size(16);
+		block_on(async {
+			assert_eq!(framed.next().await.unwrap().unwrap(), vec![0; 16]);
+			let error = framed.next().await.unwrap().unwrap_err();
+			assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+		});
+
+		let mut framed = Framed::new(ChunkedStream::writing(usize::MAX)).with_max_frame_size(16);
+		let error = block_on(framed.send(Bytes::from(vec![0; 17]))).unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+		assert!(framed.into_inner().output.is_empty());
+	}
+
+	#[test]
+	fn test_rejects_invalid_length_prefixes() {
+		let mut framed = Framed::new(ChunkedStream::reading(&[0xff; 11], &[3]));
+		let error = block_on(framed.next()).unwrap().unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+	}
+}
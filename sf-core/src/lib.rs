@@ -1,10 +1,14 @@
 mod connection;
+mod framed;
 mod listener;
 mod protocol;
 mod stream;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 mod transport;
 
 pub use connection::*;
+pub use framed::*;
 pub use listener::*;
 pub use protocol::*;
 pub use stream::*;
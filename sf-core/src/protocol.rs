@@ -2,4 +2,6 @@
 pub enum Protocol {
 	WebTransport,
 	WebRTC,
+	Memory,
+	Tcp,
 }
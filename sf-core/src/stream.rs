@@ -14,4 +14,25 @@ pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static {
 			send_result.and(read_result)
 		})
 	}
+
+	/// Send the data written to this stream before the data of the connection's streams with a lower priority.
+	///
+	/// Streams start at 0, transports without prioritization ignore it.
+	fn set_priority(&mut self, priority: i32) {
+		let _ = priority;
+	}
+
+	fn priority(&self) -> i32 {
+		0
+	}
+
+	/// Limit the bytes a write accepts before they are handed to the transport, at least 1.
+	fn set_send_window(&mut self, window: usize) {
+		let _ = window;
+	}
+
+	/// Bytes accepted by writes and not handed to the transport yet, which a flush waits for.
+	fn buffered_send(&self) -> usize {
+		0
+	}
 }
@@ -0,0 +1,100 @@
+//! Conformance tests shared by the transports, each crate keeps only the cases specific to it.
+
+use std::pin::Pin;
+
+use futures::{AsyncReadExt, AsyncWriteExt, future};
+use multiaddr::{Multiaddr, PeerId};
+
+use crate::{Connection, Stream, Transport, TransportEvent};
+
+pub async fn next_event<T: Transport + Unpin>(transport: &mut T) -> TransportEvent<T::Connection> {
+	future::poll_fn(|cx| Pin::new(&mut *transport).poll(cx)).await
+}
+
+/// Start `transport` listening on `address`, returns the address it listens on.
+pub async fn listen<T: Transport + Unpin>(transport: &mut T, address: &str) -> Multiaddr {
+	transport.listen_on(address.parse().unwrap()).unwrap();
+	loop {
+		if let TransportEvent::ListenAddr { address, .. } = next_event(transport).await {
+			return address;
+		}
+	}
+}
+
+/// A connection dialed by a fresh transport to one listening on `address`, the listener side of it and the listening
+/// transport, kept alive alongside.
+pub async fn connected<T: Transport + Unpin>(
+	transport: impl Fn() -> T,
+	address: &str,
+) -> (T::Connection, T::Connection, T) {
+	let mut listener = transport();
+	let address = listen(&mut listener, address).await;
+
+	let accept = async {
+		loop {
+			if let TransportEvent::NewConnection { connection, .. } = next_event(&mut listener).await {
+				break connection;
+			}
+		}
+	};
+	let (dialed, accepted) = future::join(transport().dial(PeerId::random(), address), accept).await;
+	(dialed.unwrap(), accepted, listener)
+}
+
+/// Each side opens a stream, writes `len` bytes and reads them back echoed by the other side.
+pub async fn streams_both_ways<T>(transport: impl Fn() -> T, address: &str, len: usize)
+where
+	T: Transport + Unpin,
+	T::Connection: Connection + Clone,
+	<T::Connection as Connection>::Output: Stream,
+{
+	let (dialer, listener, _transport) = connected(transport, address).await;
+
+	for (mut opener, mut acceptor) in [(dialer.clone(), listener.clone()), (listener, dialer)] {
+		let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+		let send = async {
+			let mut stream = opener.open_stream().await.unwrap();
+			stream.write_all(&data).await.unwrap();
+			stream.close_send().await.unwrap();
+			let mut echoed = Vec::new();
+			stream.read_to_end(&mut echoed).await.unwrap();
+			echoed
+		};
+		let echo = async {
+			let mut stream = acceptor.accept_stream().await.unwrap();
+			let mut received = Vec::new();
+			stream.read_to_end(&mut received).await.unwrap();
+			stream.write_all(&received).await.unwrap();
+			stream.close_send().await.unwrap();
+		};
+
+		let (echoed, ()) = future::join(send, echo).await;
+		assert_eq!(
+			(echoed.len(), echoed.iter().zip(&data).position(|(a, b)| a != b)),
+			(len, None)
+		);
+	}
+}
+
+/// Closing a connection stops both sides from opening or accepting streams, returns them as [`connected`] does.
+pub async fn close_ends_streams<T>(transport: impl Fn() -> T, address: &str) -> (T::Connection, T::Connection, T)
+where
+	T: Transport + Unpin,
+	T::Connection: Connection,
+{
+	let (mut dialer, mut listener, listening) = connected(transport, address).await;
+
+	dialer.close().await.unwrap();
+
+	assert!(listener.accept_stream().await.is_err());
+	assert!(dialer.open_stream().await.is_err());
+	(dialer, listener, listening)
+}
+
+/// Dialing `address` fails, returns the error.
+pub async fn dial_fails<T: Transport>(transport: &T, address: Multiaddr) -> T::Error {
+	match transport.dial(PeerId::random(), address).await {
+		Ok(_) => panic!("expected the dial to fail"),
+		Err(error) => error,
+	}
+}
@@ -1,5 +1,6 @@
 use std::{
 	pin::Pin,
+	sync::atomic::{AtomicU64, Ordering},
 	task::{Context, Poll},
 };
 
@@ -15,16 +16,83 @@ pub trait Transport: Send + Sync + 'static {
 
 	fn supported_protocols_for_dialing(&self) -> Protocol;
 	fn dial(&self, peer_id: PeerId, address: Multiaddr) -> Self::Dial;
-	fn listen_on(&mut self, address: Multiaddr) -> Result<(), Self::Error>;
+	/// Start listening on `address`, next to the listeners already running.
+	fn listen_on(&mut self, address: Multiaddr) -> Result<ListenerId, Self::Error>;
 
-	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent>;
+	/// Stop the listener `id`, returns whether it was running. A [`TransportEvent::ListenerClosed`] follows.
+	fn remove_listener(&mut self, id: ListenerId) -> bool;
+
+	/// Stop every listener, no connection is accepted afterwards. Established connections are left untouched.
+	fn shutdown(&mut self);
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::Connection>>;
+}
+
+/// Identifies a listener of a transport, unique for the lifetime of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerId(u64);
+
+impl ListenerId {
+	pub fn next() -> Self {
+		static NEXT: AtomicU64 = AtomicU64::new(0);
+		Self(NEXT.fetch_add(1, Ordering::Relaxed))
+	}
 }
 
-pub enum TransportEvent {
-	NewConnection { address: Multiaddr },
-	ListenAddr { address: Multiaddr },
+/// Something that happened on a listener of a transport.
+pub enum TransportEvent<C> {
+	/// A remote dialed one of our listeners, the connection is ready to be used.
+	NewConnection {
+		listener_id: ListenerId,
+		address: Multiaddr,
+		connection: C,
+	},
+	ListenAddr {
+		listener_id: ListenerId,
+		address: Multiaddr,
+	},
+
+	AddrExpired {
+		listener_id: ListenerId,
+		address: Multiaddr,
+	},
+
+	ListenError {
+		listener_id: ListenerId,
+		error: std::io::Error,
+	},
+
+	/// The listener stopped, its addresses expired before this event.
+	ListenerClosed { listener_id: ListenerId },
+}
 
-	AddrExpired { address: Multiaddr },
+impl<C> TransportEvent<C> {
+	pub fn listener_id(&self) -> ListenerId {
+		match self {
+			Self::NewConnection { listener_id, .. }
+			| Self::ListenAddr { listener_id, .. }
+			| Self::AddrExpired { listener_id, .. }
+			| Self::ListenError { listener_id, .. }
+			| Self::ListenerClosed { listener_id } => *listener_id,
+		}
+	}
 
-	ListenError { error: std::io::Error },
+	/// Convert the connection carried by a [`TransportEvent::NewConnection`], leaving the other events untouched.
+	pub fn map_connection<D>(self, f: impl FnOnce(C) -> D) -> TransportEvent<D> {
+		match self {
+			Self::NewConnection {
+				listener_id,
+				address,
+				connection,
+			} => TransportEvent::NewConnection {
+				listener_id,
+				address,
+				connection: f(connection),
+			},
+			Self::ListenAddr { listener_id, address } => TransportEvent::ListenAddr { listener_id, address },
+			Self::AddrExpired { listener_id, address } => TransportEvent::AddrExpired { listener_id, address },
+			Self::ListenError { listener_id, error } => TransportEvent::ListenError { listener_id, error },
+			Self::ListenerClosed { listener_id } => TransportEvent::ListenerClosed { listener_id },
+		}
+	}
 }
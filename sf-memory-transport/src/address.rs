@@ -0,0 +1,56 @@
+use multiaddr::{Multiaddr, Protocol};
+
+use crate::error::Error;
+
+/// `/memory/<port>` address.
+pub(crate) fn port_to_multiaddr(port: u64) -> Multiaddr {
+	Multiaddr::empty().with(Protocol::Memory(port))
+}
+
+/// The port of a `/memory/<port>` listen address, 0 to pick a free one.
+pub(crate) fn listen_port(address: &Multiaddr) -> Result<u64, Error> {
+	let mut iter = address.iter();
+	match (iter.next(), iter.next()) {
+		(Some(Protocol::Memory(port)), None) => Ok(port),
+		_ => Err(Error::InvalidMultiaddr(address.clone())),
+	}
+}
+
+/// The port of a `/memory/<port>[/p2p/<peer>]` dial address.
+pub(crate) fn dial_port(address: &Multiaddr) -> Result<u64, Error> {
+	let mut iter = address.iter();
+	match (iter.next(), iter.next(), iter.next()) {
+		(Some(Protocol::Memory(port)), None | Some(Protocol::P2p(_)), None) if port != 0 => Ok(port),
+		_ => Err(Error::InvalidMultiaddr(address.clone())),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_addresses() {
+		assert_eq!(listen_port(&"/memory/0".parse().unwrap()).unwrap(), 0);
+		assert!(
+			listen_port(
+				&"/memory/1/p2p/12D3KooWQWBgSAg1Z4kjoonCwSmCwmtbP4ZQFAYyna6oYQPLhc8i"
+					.parse()
+					.unwrap()
+			)
+			.is_err()
+		);
+
+		assert_eq!(
+			dial_port(
+				&"/memory/7/p2p/12D3KooWQWBgSAg1Z4kjoonCwSmCwmtbP4ZQFAYyna6oYQPLhc8i"
+					.parse()
+					.unwrap()
+			)
+			.unwrap(),
+			7
+		);
+		assert!(dial_port(&"/memory/0".parse().unwrap()).is_err());
+		assert!(dial_port(&"/ip4/127.0.0.1/udp/7".parse().unwrap()).is_err());
+	}
+}
@@ -0,0 +1,190 @@
+use std::{
+	fmt,
+	sync::{Arc, Mutex},
+};
+
+use futures::{
+	FutureExt, StreamExt,
+	channel::{mpsc, oneshot},
+	future::{self, BoxFuture, Either, Shared},
+	lock::Mutex as AsyncMutex,
+};
+use multiaddr::{Multiaddr, PeerId};
+
+use crate::{error::Error, stream::Stream};
+
+/// Code and reason a connection was closed with, by either end.
+#[derive(Debug, Clone)]
+pub(crate) struct Reason {
+	code: u32,
+	reason: String,
+}
+
+/// Resolves once the connection is closed.
+pub(crate) type Closed = Shared<oneshot::Receiver<Reason>>;
+
+/// State shared by both ends of a connection.
+struct Link {
+	/// Taken by the first end to close the connection.
+	close: Mutex<Option<oneshot::Sender<Reason>>>,
+	closed: Closed,
+}
+
+impl Link {
+	fn close(&self, code: u32, reason: &str) {
+		if let Some(close) = self.close.lock().unwrap().take() {
+			let _ = close.send(Reason {
+				code,
+				reason: reason.to_owned(),
+			});
+		}
+	}
+
+	fn is_closed(&self) -> bool {
+		self.close.lock().unwrap().is_none()
+	}
+
+	fn closed(&self) -> BoxFuture<'static, Error> {
+		let closed = self.closed.clone();
+		Box::pin(async move {
+			// The sender is only dropped along with the receivers, after sending.
+			let Reason { code, reason } = closed.await.unwrap_or_else(|_| Reason {
+				code: 0,
+				reason: String::new(),
+			});
+			Error::ConnectionClosed { code, reason }
+		})
+	}
+}
+
+/// One end of a connection, closing it once its clones are all dropped.
+struct End {
+	link: Arc<Link>,
+	/// Streams opened by this end, accepted by the other.
+	outgoing: mpsc::UnboundedSender<Stream>,
+	incoming: AsyncMutex<mpsc::UnboundedReceiver<Stream>>,
+}
+
+impl Drop for End {
+	fn drop(&mut self) {
+		self.link.close(0, "Connection dropped");
+	}
+}
+
+/// One end of an in-memory connection.
+///
+/// Clones share the end, closing the connection drops the streams of both ends.
+#[derive(Clone)]
+pub struct Connection {
+	end: Arc<End>,
+	remote_address: Multiaddr,
+	remote_peer_id: Option<PeerId>,
+}
+
+impl Connection {
+	/// Both ends of a new connection, the first one reaching `listener_address` and the second `dialer_address`.
+	pub(crate) fn pair(listener_address: Multiaddr, dialer_address: Multiaddr) -> (Self, Self) {
+		let (close, closed) = oneshot::channel();
+		let link = Arc::new(Link {
+			close: Mutex::new(Some(close)),
+			closed: closed.shared(),
+		});
+		let (dialer_outgoing, listener_incoming) = mpsc::unbounded();
+		let (listener_outgoing, dialer_incoming) = mpsc::unbounded();
+
+		let dialer = Self {
+			end: Arc::new(End {
+				link: link.clone(),
+				outgoing: dialer_outgoing,
+				incoming: AsyncMutex::new(dialer_incoming),
+			}),
+			remote_address: listener_address,
+			remote_peer_id: None,
+		};
+		let listener = Self {
+			end: Arc::new(End {
+				link,
+				outgoing: listener_outgoing,
+				incoming: AsyncMutex::new(listener_incoming),
+			}),
+			remote_address: dialer_address,
+			remote_peer_id: None,
+		};
+		(dialer, listener)
+	}
+
+	/// Resolve once the connection is closed, by either end, with the reason.
+	pub fn closed(&self) -> BoxFuture<'static, Error> {
+		self.end.link.closed()
+	}
+
+	/// Close the connection, both ends see `code` and `reason`.
+	pub fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		tracing::debug!(code, reason, remote_address = %self.remote_address, "Closing connection");
+		self.end.link.close(code, reason);
+		Box::pin(async { Ok(()) })
+	}
+
+	/// Record the identity of the remote once it has been authenticated by the upper layer.
+	pub fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+}
+
+impl fmt::Debug for Connection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Connection")
+			.field("remote_address", &self.remote_address)
+			.field("remote_peer_id", &self.remote_peer_id)
+			.field("closed", &self.end.link.is_closed())
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Connection for Connection {
+	type Error = Error;
+	type Output = Stream;
+
+	type Close = BoxFuture<'static, Result<(), Self::Error>>;
+	type Stream = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+	fn open_stream(&mut self) -> Self::Stream {
+		let end = self.end.clone();
+		Box::pin(async move {
+			if end.link.is_closed() {
+				return Err(end.link.closed().await);
+			}
+			let (local, remote) = Stream::pair(end.link.closed.clone());
+			if end.outgoing.unbounded_send(remote).is_err() {
+				// The remote end is dropped, which closed the connection.
+				return Err(end.link.closed().await);
+			}
+			Ok(local)
+		})
+	}
+
+	fn accept_stream(&mut self) -> Self::Stream {
+		let end = self.end.clone();
+		Box::pin(async move {
+			let closed = end.link.closed();
+			let mut incoming = end.incoming.lock().await;
+			match future::select(incoming.next(), closed).await {
+				Either::Left((Some(stream), _)) => Ok(stream),
+				Either::Left((None, closed)) => Err(closed.await),
+				Either::Right((error, _)) => Err(error),
+			}
+		})
+	}
+
+	fn close(&mut self) -> Self::Close {
+		self.close_with_reason(0, "Closing connection")
+	}
+
+	fn remote_address(&self) -> &Multiaddr {
+		&self.remote_address
+	}
+
+	fn remote_peer_id(&self) -> Option<PeerId> {
+		self.remote_peer_id
+	}
+}
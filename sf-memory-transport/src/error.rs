@@ -0,0 +1,24 @@
+use multiaddr::Multiaddr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("invalid multiaddr: {0}")]
+	InvalidMultiaddr(Multiaddr),
+
+	#[error("memory port {0} is already in use")]
+	AddrInUse(u64),
+
+	#[error("no listener on memory port {0}")]
+	ConnectionRefused(u64),
+
+	#[error("the connection is closed with code {code}: {reason}")]
+	ConnectionClosed { code: u32, reason: String },
+
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error(transparent)]
+	Unsupported(#[from] sf_core::Unsupported),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,220 @@
+//! `/memory/<port>` transport: connections between the nodes of a single process, over channels.
+//!
+//! Meant for tests, it needs neither sockets nor certificates and behaves the same on every run.
+
+mod address;
+pub mod connection;
+pub mod error;
+mod listener;
+pub mod stream;
+
+use std::{
+	collections::VecDeque,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use futures::{StreamExt, future::Ready};
+use multiaddr::{Multiaddr, PeerId};
+use sf_core::{Listener as _, ListenerId, Protocol, Transport, TransportEvent};
+
+use address::{dial_port, listen_port};
+pub use connection::Connection;
+pub use error::Error;
+pub use listener::Listener;
+pub use stream::Stream;
+
+#[derive(Default)]
+pub struct MemoryTransport {
+	pending_events: VecDeque<TransportEvent<Connection>>,
+
+	listeners: Vec<Listener>,
+}
+
+impl MemoryTransport {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Report the addresses of a stopped listener as expired, then the listener as closed.
+	fn close_listener(&mut self, listener: Listener) {
+		let listener_id = listener.id();
+		for address in listener.addresses() {
+			self.pending_events.push_back(TransportEvent::AddrExpired {
+				listener_id,
+				address: address.clone(),
+			});
+		}
+		self.pending_events
+			.push_back(TransportEvent::ListenerClosed { listener_id });
+	}
+}
+
+impl Transport for MemoryTransport {
+	type Connection = Connection;
+	type Error = Error;
+	type Dial = Ready<Result<Connection, Error>>;
+
+	fn supported_protocols_for_dialing(&self) -> Protocol {
+		Protocol::Memory
+	}
+
+	fn dial(&self, _peer_id: PeerId, ma: Multiaddr) -> Self::Dial {
+		tracing::debug!(%ma, "dial");
+		futures::future::ready(dial_port(&ma).and_then(|port| listener::connect(port, ma)))
+	}
+
+	fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Self::Error> {
+		let port = listen_port(&addr)?;
+		let id = ListenerId::next();
+		let listener = Listener::new(id, port)?;
+		self.listeners.push(listener);
+		Ok(id)
+	}
+
+	fn remove_listener(&mut self, id: ListenerId) -> bool {
+		let Some(position) = self.listeners.iter().position(|listener| listener.id() == id) else {
+			return false;
+		};
+		let listener = self.listeners.remove(position);
+		self.close_listener(listener);
+		true
+	}
+
+	fn shutdown(&mut self) {
+		self.pending_events.clear();
+		self.listeners.clear();
+	}
+
+	#[tracing::instrument(level = "trace", name = "Transport::poll", skip(self, cx))]
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Connection>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(event);
+		}
+
+		let mut i = 0;
+		while i < self.listeners.len() {
+			match self.listeners[i].poll_next_unpin(cx) {
+				Poll::Ready(Some(event)) => return Poll::Ready(event),
+				Poll::Ready(None) => {
+					let listener = self.listeners.remove(i);
+					self.close_listener(listener);
+					if let Some(event) = self.pending_events.pop_front() {
+						return Poll::Ready(event);
+					}
+				}
+				Poll::Pending => i += 1,
+			}
+		}
+
+		Poll::Pending
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::{AsyncReadExt, AsyncWriteExt, executor::block_on};
+	use sf_core::Connection as _;
+	use sf_core::test_util::{self, next_event};
+
+	/// A dialed connection and the listener side of it, the listener being kept alive alongside.
+	async fn connected() -> (Connection, Connection, MemoryTransport) {
+		test_util::connected(MemoryTransport::new, "/memory/0").await
+	}
+
+	#[test]
+	fn test_listen_and_remove() {
+		block_on(async {
+			let mut transport = MemoryTransport::new();
+			let id = transport.listen_on("/memory/0".parse().unwrap()).unwrap();
+			let TransportEvent::ListenAddr { address, .. } = next_event(&mut transport).await else {
+				panic!("expected a listen address");
+			};
+			assert_ne!(address, "/memory/0".parse().unwrap());
+			assert!(matches!(
+				MemoryTransport::new().listen_on(address.clone()),
+				Err(Error::AddrInUse(_))
+			));
+
+			assert!(transport.remove_listener(id));
+			assert!(matches!(
+				next_event(&mut transport).await,
+				TransportEvent::AddrExpired { address: expired, .. } if expired == address
+			));
+			assert!(matches!(
+				next_event(&mut transport).await,
+				TransportEvent::ListenerClosed { .. }
+			));
+
+			let result = transport.dial(PeerId::random(), address).await;
+			assert!(matches!(result, Err(Error::ConnectionRefused(_))));
+		});
+	}
+
+	#[test]
+	fn test_streams_both_ways() {
+		block_on(test_util::streams_both_ways(MemoryTransport::new, "/memory/0", 200_000));
+	}
+
+	#[test]
+	fn test_close_ends_streams() {
+		block_on(test_util::close_ends_streams(MemoryTransport::new, "/memory/0"));
+	}
+
+	#[test]
+	fn test_close_aborts_streams() {
+		block_on(async {
+			let (mut dialer, mut listener, _transport) = connected().await;
+			let mut stream = dialer.open_stream().await.unwrap();
+			let mut accepted = listener.accept_stream().await.unwrap();
+
+			dialer.close_with_reason(7, "bye").await.unwrap();
+
+			assert!(matches!(
+				listener.closed().await,
+				Error::ConnectionClosed { code: 7, reason } if reason == "bye"
+			));
+			assert!(listener.accept_stream().await.is_err());
+			assert!(dialer.open_stream().await.is_err());
+			assert!(stream.write_all(b"ping").await.is_err());
+			assert!(accepted.read(&mut [0; 4]).await.is_err());
+		});
+	}
+
+	#[test]
+	fn test_dropping_the_dialer_closes_the_connection() {
+		block_on(async {
+			let (dialer, listener, _transport) = connected().await;
+
+			drop(dialer);
+
+			assert!(matches!(
+				listener.closed().await,
+				Error::ConnectionClosed { code: 0, .. }
+			));
+		});
+	}
+
+	#[test]
+	fn test_send_window_bounds_buffered_bytes() {
+		block_on(async {
+			let (mut dialer, mut listener, _transport) = connected().await;
+			let mut stream = dialer.open_stream().await.unwrap();
+			let mut accepted = listener.accept_stream().await.unwrap();
+			sf_core::Stream::set_send_window(&mut stream, 1000);
+
+			assert_eq!(stream.write(&[1; 4000]).await.unwrap(), 1000);
+			assert_eq!(sf_core::Stream::buffered_send(&stream), 1000);
+			// A single write is queued until the remote reads.
+			let write = stream.write(&[2; 4000]);
+			futures::pin_mut!(write);
+			assert!(futures::poll!(write.as_mut()).is_pending());
+
+			let mut buf = [0; 4000];
+			assert_eq!(accepted.read(&mut buf).await.unwrap(), 1000);
+			assert_eq!(write.await.unwrap(), 1000);
+		});
+	}
+}
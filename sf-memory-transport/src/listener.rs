@@ -0,0 +1,136 @@
+use std::{
+	collections::{HashMap, VecDeque, hash_map::Entry},
+	pin::Pin,
+	sync::{
+		LazyLock, Mutex,
+		atomic::{AtomicU64, Ordering},
+	},
+	task::{Context, Poll},
+};
+
+use futures::{Stream, StreamExt, channel::mpsc};
+use multiaddr::Multiaddr;
+use sf_core::{Connection as _, Listener as ListenerTrait, ListenerId, TransportEvent};
+
+use crate::{address::port_to_multiaddr, connection::Connection, error::Error};
+
+/// Listeners of the process by port, dialers hand them the listener end of their connections.
+static LISTENERS: LazyLock<Mutex<HashMap<u64, mpsc::UnboundedSender<Connection>>>> = LazyLock::new(Default::default);
+
+/// Next port to try when one is picked for a listener or a dialer, ports are never reused within the process.
+static NEXT_PORT: AtomicU64 = AtomicU64::new(1);
+
+/// A port not listened on yet.
+fn free_port(listeners: &HashMap<u64, mpsc::UnboundedSender<Connection>>) -> u64 {
+	loop {
+		let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+		if !listeners.contains_key(&port) {
+			return port;
+		}
+	}
+}
+
+/// Open a connection to the listener on `port`, returns the dialer end of it.
+pub(crate) fn connect(port: u64, address: Multiaddr) -> Result<Connection, Error> {
+	let listeners = LISTENERS.lock().unwrap();
+	let listener = listeners.get(&port).ok_or(Error::ConnectionRefused(port))?;
+	// The dialer gets an address of its own, so that the listener can tell its connections apart.
+	let dialer_address = port_to_multiaddr(free_port(&listeners));
+
+	let (dialer, listener_end) = Connection::pair(address, dialer_address);
+	listener
+		.unbounded_send(listener_end)
+		.map_err(|_| Error::ConnectionRefused(port))?;
+	Ok(dialer)
+}
+
+pub struct Listener {
+	id: ListenerId,
+	port: u64,
+	incoming: mpsc::UnboundedReceiver<Connection>,
+
+	pending_events: VecDeque<<Self as Stream>::Item>,
+
+	/// Addresses reported through [`TransportEvent::ListenAddr`] and not expired since.
+	addresses: Vec<Multiaddr>,
+}
+
+impl Listener {
+	/// Take `port`, or a free one when it is 0.
+	pub(crate) fn new(id: ListenerId, port: u64) -> Result<Self, Error> {
+		let (sender, incoming) = mpsc::unbounded();
+		let mut listeners = LISTENERS.lock().unwrap();
+		let port = if port == 0 { free_port(&listeners) } else { port };
+		match listeners.entry(port) {
+			Entry::Occupied(_) => return Err(Error::AddrInUse(port)),
+			Entry::Vacant(entry) => entry.insert(sender),
+		};
+
+		Ok(Self {
+			id,
+			port,
+			incoming,
+			pending_events: VecDeque::from([TransportEvent::ListenAddr {
+				listener_id: id,
+				address: port_to_multiaddr(port),
+			}]),
+			addresses: Vec::new(),
+		})
+	}
+
+	/// Addresses the listener currently reports.
+	pub fn addresses(&self) -> &[Multiaddr] {
+		&self.addresses
+	}
+}
+
+impl Drop for Listener {
+	fn drop(&mut self) {
+		// Connections not accepted yet are dropped along with `incoming`, which closes them.
+		LISTENERS.lock().unwrap().remove(&self.port);
+	}
+}
+
+impl Stream for Listener {
+	type Item = TransportEvent<Connection>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			if let TransportEvent::ListenAddr { address, .. } = &event {
+				self.addresses.push(address.clone());
+			}
+			return Poll::Ready(Some(event));
+		}
+
+		match futures::ready!(self.incoming.poll_next_unpin(cx)) {
+			Some(connection) => {
+				let address = connection.remote_address().clone();
+				tracing::trace!(%address, "New connection");
+				Poll::Ready(Some(TransportEvent::NewConnection {
+					listener_id: self.id,
+					address,
+					connection,
+				}))
+			}
+			None => Poll::Ready(None),
+		}
+	}
+}
+
+impl ListenerTrait for Listener {
+	type Error = Error;
+	type Connection = Connection;
+
+	fn id(&self) -> ListenerId {
+		self.id
+	}
+
+	fn local_address(&self) -> Multiaddr {
+		port_to_multiaddr(self.port)
+	}
+
+	fn poll_if_addr(&mut self, _cx: &mut Context<'_>) -> Poll<<Self as Stream>::Item> {
+		// The address of a memory listener never changes.
+		Poll::Pending
+	}
+}
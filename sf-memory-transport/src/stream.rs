@@ -0,0 +1,189 @@
+use std::{
+	fmt, io,
+	pin::Pin,
+	sync::{
+		Arc,
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+	},
+	task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{AsyncRead, AsyncWrite, FutureExt, Sink, StreamExt, channel::mpsc, future::BoxFuture, ready};
+
+use crate::connection::Closed;
+
+/// Default largest write handed to the remote at once, only one of them is queued at a time.
+const DEFAULT_SEND_WINDOW: usize = 64 * 1024;
+
+/// A bidirectional stream between two ends of an in-memory connection.
+pub struct Stream {
+	id: u64,
+
+	/// `None` once the send half is finished.
+	send: Option<mpsc::Sender<Bytes>>,
+	/// Bytes written and not read by the remote yet.
+	sent: Arc<AtomicUsize>,
+	send_window: usize,
+
+	recv: mpsc::Receiver<Bytes>,
+	/// Bytes written by the remote and not read yet, its `sent`.
+	received: Arc<AtomicUsize>,
+	/// Received bytes not handed to the reader yet.
+	read_buf: Bytes,
+
+	/// Resolves once the connection is closed, `None` afterwards.
+	closed: Option<Closed>,
+}
+
+impl Stream {
+	/// Both ends of a new stream of the connection closed by `closed`.
+	pub(crate) fn pair(closed: Closed) -> (Self, Self) {
+		static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+		let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+		// No buffer, each sender still gets a slot so that a single write is queued at a time.
+		let (a_send, b_recv) = mpsc::channel(0);
+		let (b_send, a_recv) = mpsc::channel(0);
+		let a_sent = Arc::new(AtomicUsize::new(0));
+		let b_sent = Arc::new(AtomicUsize::new(0));
+
+		let a = Self::new(id, a_send, a_sent.clone(), a_recv, b_sent.clone(), closed.clone());
+		let b = Self::new(id, b_send, b_sent, b_recv, a_sent, closed);
+		(a, b)
+	}
+
+	fn new(
+		id: u64,
+		send: mpsc::Sender<Bytes>,
+		sent: Arc<AtomicUsize>,
+		recv: mpsc::Receiver<Bytes>,
+		received: Arc<AtomicUsize>,
+		closed: Closed,
+	) -> Self {
+		Self {
+			id,
+			send: Some(send),
+			sent,
+			send_window: DEFAULT_SEND_WINDOW,
+			recv,
+			received,
+			read_buf: Bytes::new(),
+			closed: Some(closed),
+		}
+	}
+
+	/// Fail once the connection is closed, the streams are aborted along with it.
+	fn poll_aborted(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+		if let Some(closed) = &mut self.closed {
+			if closed.poll_unpin(cx).is_pending() {
+				return Ok(());
+			}
+			self.closed = None;
+		}
+		Err(io::Error::new(
+			io::ErrorKind::ConnectionAborted,
+			"the connection is closed",
+		))
+	}
+}
+
+impl fmt::Debug for Stream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Stream")
+			.field("id", &self.id)
+			.field("finished", &self.send.is_none())
+			.field("sent", &self.sent.load(Ordering::Relaxed))
+			.field("buffered", &self.read_buf.len())
+			.field("aborted", &self.closed.is_none())
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Stream for Stream {
+	type Error = crate::Error;
+
+	fn close_send(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		Box::pin(async move {
+			futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_close(cx))
+				.await
+				.map_err(crate::Error::Io)
+		})
+	}
+
+	fn close_read(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		// The remote's writes fail from now on.
+		self.recv.close();
+		Box::pin(async { Ok(()) })
+	}
+
+	fn set_send_window(&mut self, window: usize) {
+		self.send_window = window.max(1);
+	}
+
+	fn buffered_send(&self) -> usize {
+		self.sent.load(Ordering::Relaxed)
+	}
+}
+
+impl AsyncWrite for Stream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		this.poll_aborted(cx)?;
+		let Some(send) = &mut this.send else {
+			return Poll::Ready(Err(io::Error::new(
+				io::ErrorKind::BrokenPipe,
+				"the send stream is finished",
+			)));
+		};
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+
+		let stopped = |_| io::Error::new(io::ErrorKind::BrokenPipe, "the remote stopped reading");
+		ready!(Pin::new(&mut *send).poll_ready(cx)).map_err(stopped)?;
+		let len = buf.len().min(this.send_window);
+		Pin::new(send)
+			.start_send(Bytes::copy_from_slice(&buf[..len]))
+			.map_err(stopped)?;
+		this.sent.fetch_add(len, Ordering::Relaxed);
+		Poll::Ready(Ok(len))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(self.get_mut().poll_aborted(cx))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		this.poll_aborted(cx)?;
+		// The remote reads the queued writes, then the end of the stream.
+		this.send = None;
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl AsyncRead for Stream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		this.poll_aborted(cx)?;
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+
+		if this.read_buf.is_empty() {
+			match ready!(this.recv.poll_next_unpin(cx)) {
+				Some(bytes) => {
+					this.received.fetch_sub(bytes.len(), Ordering::Relaxed);
+					this.read_buf = bytes;
+				}
+				None => return Poll::Ready(Ok(0)),
+			}
+		}
+
+		let len = buf.len().min(this.read_buf.len());
+		buf[..len].copy_from_slice(&this.read_buf[..len]);
+		this.read_buf.advance(len);
+		Poll::Ready(Ok(len))
+	}
+}
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use anyhow::Context;
 use clap::Parser;
 use futures::StreamExt;
 use libp2p_identity::Keypair;
 use moq_native::quic;
 use sf_node::{Builder, Event, Node};
+use sf_wt_transport::{CertificateConfig, CertificateManager};
 use tracing::info;
 
 #[derive(Parser, Clone)]
@@ -38,13 +41,13 @@ async fn main() -> anyhow::Result<()> {
 
 	let tls = config.tls.load()?;
 
-	if tls.server.is_none() {
-		anyhow::bail!("missing TLS certificates");
-	}
-
-	let config = quic::Config { bind, tls };
-
-	let transport = sf_wt_transport::WebTransport::new(config, true);
+	let transport = if tls.server.is_some() {
+		sf_wt_transport::WebTransport::new(quic::Config { bind, tls }, true)
+	} else {
+		info!("No TLS certificate given, serving self-signed ones");
+		let certificates = CertificateManager::new(CertificateConfig::default())?;
+		sf_wt_transport::WebTransport::self_signed(bind, certificates, true)?
+	};
 	builder.with_web_transport(transport);
 	let mut node: Node = builder.build();
 
@@ -55,7 +58,7 @@ async fn main() -> anyhow::Result<()> {
 	node.listen(address).await?;
 
 	//let address = loop {
-	//	if let Event::NewListenAddr { address } = node.select_next_some().await {
+	//	if let Event::NewListenAddr { address, .. } = node.select_next_some().await {
 	//		info!(address = %address, "Listening on");
 	//		break address;
 	//	}
@@ -67,11 +70,17 @@ async fn main() -> anyhow::Result<()> {
 		tracing::info!("loop");
 		tokio::select! {
 			event = node.next() => {
-				tracing::trace!(?event)
+				match event {
+					Some(Event::NewConnection { connection }) => info!(?connection, "New connection"),
+					event => tracing::trace!(?event),
+				}
 			},
 			_ = tokio::signal::ctrl_c() => {
-				// TODO: Handle shutdown gracefully.
 				info!("Ctrl+C received, shutting down");
+				node.shutdown(Duration::from_secs(5)).await;
+				while let Some(event) = node.next().await {
+					tracing::trace!(?event);
+				}
 				break;
 			}
 		}
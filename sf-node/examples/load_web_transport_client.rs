@@ -1,9 +1,9 @@
 use anyhow::Context;
 use clap::Parser;
-use futures::StreamExt;
 use libp2p_identity::Keypair;
 use moq_native::quic;
-use sf_node::{Builder, Event, Node};
+use sf_node::{Builder, Node};
+use sf_wt_transport::{CertificateConfig, CertificateManager};
 use tracing::info;
 
 #[derive(Parser, Clone)]
@@ -38,13 +38,13 @@ async fn main() -> anyhow::Result<()> {
 
 	let tls = config.tls.load()?;
 
-	if tls.server.is_none() {
-		anyhow::bail!("missing TLS certificates");
-	}
-
-	let config = quic::Config { bind, tls };
-
-	let transport = sf_wt_transport::WebTransport::new(config, true);
+	let transport = if tls.server.is_some() {
+		sf_wt_transport::WebTransport::new(quic::Config { bind, tls }, true)
+	} else {
+		info!("No TLS certificate given, serving self-signed ones");
+		let certificates = CertificateManager::new(CertificateConfig::default())?;
+		sf_wt_transport::WebTransport::self_signed(bind, certificates, true)?
+	};
 	builder.with_web_transport(transport);
 	let node: Node = builder.build();
 
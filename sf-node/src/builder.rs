@@ -1,12 +1,23 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use sf_core::{Protocol, Transport as TransportTrait};
 
-use crate::{Node, transport::Transport};
+use crate::{
+	Node,
+	connection_manager::ConnectionLimits,
+	node::Config,
+	peer_store::{PeerStoreBackend, PeerStoreConfig},
+	ping::PingConfig,
+	pubsub::PubsubConfig,
+	relay::RelayConfig,
+	transport::Transport,
+};
 
 pub struct Builder {
 	keypair: libp2p_identity::Keypair,
 	transports: HashMap<Protocol, Transport>,
+	config: Config,
 }
 
 impl Builder {
@@ -14,6 +25,7 @@ impl Builder {
 		Self {
 			keypair,
 			transports: HashMap::new(),
+			config: Config::default(),
 		}
 	}
 
@@ -22,7 +34,78 @@ impl Builder {
 			.insert(transport.supported_protocols_for_dialing(), transport.into());
 	}
 
+	/// Dial and listen on `/memory/<port>` addresses, reaching the nodes of the same process only.
+	pub fn with_memory_transport(&mut self, transport: sf_memory_transport::MemoryTransport) {
+		self.transports
+			.insert(transport.supported_protocols_for_dialing(), transport.into());
+	}
+
+	/// Dial and listen on `/webrtc-direct/` addresses, which browsers reach without a signaling server.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn with_webrtc(&mut self, transport: sf_webrtc_transport::WebRtc) {
+		self.transports
+			.insert(transport.supported_protocols_for_dialing(), transport.into());
+	}
+
+	/// Dial and listen on `/tcp/<port>/tls` addresses, which stay reachable from networks blocking UDP.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn with_tcp_transport(&mut self, transport: sf_tcp_transport::TcpTransport) {
+		self.transports
+			.insert(transport.supported_protocols_for_dialing(), transport.into());
+	}
+
+	pub fn with_connection_limits(&mut self, limits: ConnectionLimits) {
+		self.config.limits = limits;
+	}
+
+	pub fn with_ping(&mut self, config: PingConfig) {
+		self.config.ping = config;
+	}
+
+	/// Agent version advertised to the peers during identify.
+	pub fn with_agent_version(&mut self, agent_version: impl Into<String>) {
+		self.config.agent_version = Some(agent_version.into());
+	}
+
+	/// Keep the known peers in `backend`, so that they survive restarts. They are only kept in memory by default.
+	///
+	/// Changes are written every [`PeerStoreConfig::with_save_interval`] and once the node shut down.
+	pub fn with_peer_store(&mut self, backend: impl PeerStoreBackend) {
+		self.config.peer_store = Some(Box::new(backend));
+	}
+
+	pub fn with_peer_store_config(&mut self, config: PeerStoreConfig) {
+		self.config.peer_store_config = config;
+	}
+
+	pub fn with_pubsub(&mut self, config: PubsubConfig) {
+		self.config.pubsub = config;
+	}
+
+	/// Relay circuits between the other peers, within the limits of `config`. See [`Node::reserve`].
+	pub fn with_relay(&mut self, config: RelayConfig) {
+		self.config.relay = Some(config);
+	}
+
+	/// Order in which the transports are dialed when a peer advertises several addresses, see [`Node::dial`].
+	/// Defaults to [`crate::DEFAULT_TRANSPORT_PREFERENCE`].
+	pub fn with_transport_preference(&mut self, preference: Vec<Protocol>) {
+		self.config.transport_preference = preference;
+	}
+
+	/// Time a single address has to connect and authenticate before the dial falls back to the next one, see
+	/// [`Node::dial`]. Defaults to [`crate::DEFAULT_DIAL_TIMEOUT`].
+	pub fn with_dial_timeout(&mut self, timeout: Duration) {
+		self.config.dial_timeout = timeout;
+	}
+
+	/// Time the remote of a new connection has to authenticate before it is dropped, in both directions. Defaults to
+	/// [`crate::DEFAULT_HANDSHAKE_TIMEOUT`].
+	pub fn with_handshake_timeout(&mut self, timeout: Duration) {
+		self.config.handshake_timeout = timeout;
+	}
+
 	pub fn build(self) -> Node {
-		Node::new(self.keypair.public().to_peer_id(), self.transports)
+		Node::new(self.keypair, self.transports, self.config)
 	}
 }
@@ -1,14 +1,92 @@
 use std::pin::Pin;
 
-use crate::{error::Error, stream::Stream};
+use crate::{error::Error, negotiation, relay, stream::Stream};
+use bytes::Bytes;
+use futures::future::BoxFuture;
 use multiaddr::{Multiaddr, PeerId};
-use sf_core::Connection as ConnectionTrait;
+use sf_core::{Capabilities, Connection as ConnectionTrait};
 
+/// Application error code of the close frames sent by [`crate::Node::shutdown`].
+pub const CLOSE_CODE_SHUTDOWN: u32 = 1;
+
+/// A connection of the node with a remote peer.
+///
+/// The node accepts the inbound streams of its connections itself, they are received through the handlers of
+/// [`crate::Node::set_stream_handler`].
+#[derive(Debug, Clone)]
 pub enum Connection {
 	WebTransport(sf_wt_transport::Connection),
+	Memory(sf_memory_transport::Connection),
+	#[cfg(not(target_arch = "wasm32"))]
+	WebRtc(sf_webrtc_transport::Connection),
+	#[cfg(not(target_arch = "wasm32"))]
+	Tcp(sf_tcp_transport::Connection),
+	Relayed(relay::Circuit),
 }
 
 impl Connection {
+	/// Resolve once the connection is closed, by either side, with the reason.
+	pub(crate) fn closed(&self) -> BoxFuture<'static, Error> {
+		match self {
+			Self::WebTransport(connection) => {
+				let fut = connection.closed();
+				Box::pin(async move { Error::Transport(Box::new(fut.await)) })
+			}
+			Self::Memory(connection) => {
+				let fut = connection.closed();
+				Box::pin(async move { Error::Transport(Box::new(fut.await)) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let fut = connection.closed();
+				Box::pin(async move { Error::Transport(Box::new(fut.await)) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let fut = connection.closed();
+				Box::pin(async move { Error::Transport(Box::new(fut.await)) })
+			}
+			Self::Relayed(connection) => connection.closed(),
+		}
+	}
+
+	/// Close the connection, sending `code` and `reason` to the remote.
+	pub(crate) fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		match self {
+			Self::WebTransport(connection) => {
+				let fut = connection.close_with_reason(code, reason);
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			Self::Memory(connection) => {
+				let fut = connection.close_with_reason(code, reason);
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let fut = connection.close_with_reason(code, reason);
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let fut = connection.close_with_reason(code, reason);
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			Self::Relayed(connection) => connection.close_with_reason(code, reason),
+		}
+	}
+
+	pub(crate) fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		match self {
+			Self::WebTransport(connection) => connection.set_remote_peer_id(peer_id),
+			Self::Memory(connection) => connection.set_remote_peer_id(peer_id),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection.set_remote_peer_id(peer_id),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection.set_remote_peer_id(peer_id),
+			Self::Relayed(connection) => connection.set_remote_peer_id(peer_id),
+		}
+	}
+
 	pub async fn open_stream(&mut self) -> Result<Stream, Error> {
 		match self {
 			Self::WebTransport(connection) => {
@@ -18,10 +96,44 @@ impl Connection {
 					.map_err(|e| Error::Transport(Box::new(e)))?;
 				Ok(Stream::WebTransport(stream))
 			}
+			Self::Memory(connection) => {
+				let stream = connection
+					.open_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Memory(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let stream = connection
+					.open_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebRtc(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let stream = connection
+					.open_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Tcp(stream))
+			}
+			Self::Relayed(connection) => Ok(Stream::Relayed(connection.open_stream().await?)),
 		}
 	}
 
-	pub async fn accept_stream(&mut self) -> Result<Stream, Error> {
+	/// Open a stream and negotiate `protocol` on it, failing with [`Error::ProtocolNotSupported`] when the remote
+	/// does not handle it.
+	pub async fn open_stream_with_protocol(&mut self, protocol: &str) -> Result<Stream, Error> {
+		let mut stream = self.open_stream().await?;
+		negotiation::select(&mut stream, protocol).await?;
+		Ok(stream)
+	}
+
+	/// Accept the next inbound stream. Only used by the handshake and the accept loop of the node, which would
+	/// otherwise race over the streams.
+	pub(crate) async fn accept_stream(&mut self) -> Result<Stream, Error> {
 		match self {
 			Self::WebTransport(connection) => {
 				let stream = connection
@@ -30,6 +142,160 @@ impl Connection {
 					.map_err(|e| Error::Transport(Box::new(e)))?;
 				Ok(Stream::WebTransport(stream))
 			}
+			Self::Memory(connection) => {
+				let stream = connection
+					.accept_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Memory(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let stream = connection
+					.accept_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebRtc(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let stream = connection
+					.accept_stream()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Tcp(stream))
+			}
+			Self::Relayed(connection) => Ok(Stream::Relayed(connection.accept_stream().await?)),
+		}
+	}
+
+	/// Whether the connection is a circuit through a relay rather than a direct connection.
+	pub fn is_relayed(&self) -> bool {
+		matches!(self, Self::Relayed(_))
+	}
+
+	/// Whether the transport supports unidirectional streams and datagrams.
+	pub fn capabilities(&self) -> Capabilities {
+		match self {
+			Self::WebTransport(connection) => connection.capabilities(),
+			Self::Memory(connection) => connection.capabilities(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection.capabilities(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection.capabilities(),
+			Self::Relayed(connection) => connection.capabilities(),
+		}
+	}
+
+	/// Open a stream only writable by the local side, if [`Connection::capabilities`] has `uni_streams`.
+	pub async fn open_uni(&mut self) -> Result<Stream, Error> {
+		match self {
+			Self::WebTransport(connection) => {
+				let stream = connection.open_uni().await.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebTransport(stream))
+			}
+			Self::Memory(connection) => {
+				let stream = connection.open_uni().await.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Memory(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let stream = connection.open_uni().await.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebRtc(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let stream = connection.open_uni().await.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Tcp(stream))
+			}
+			Self::Relayed(connection) => Ok(Stream::Relayed(connection.open_uni().await?)),
+		}
+	}
+
+	/// Accept a stream opened with [`Connection::open_uni`] by the remote, only readable by the local side.
+	pub async fn accept_uni(&mut self) -> Result<Stream, Error> {
+		match self {
+			Self::WebTransport(connection) => {
+				let stream = connection
+					.accept_uni()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebTransport(stream))
+			}
+			Self::Memory(connection) => {
+				let stream = connection
+					.accept_uni()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Memory(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let stream = connection
+					.accept_uni()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::WebRtc(stream))
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let stream = connection
+					.accept_uni()
+					.await
+					.map_err(|e| Error::Transport(Box::new(e)))?;
+				Ok(Stream::Tcp(stream))
+			}
+			Self::Relayed(connection) => Ok(Stream::Relayed(connection.accept_uni().await?)),
+		}
+	}
+
+	/// Send an unreliable, unordered message, if [`Connection::capabilities`] has `datagrams`.
+	pub async fn send_datagram(&mut self, payload: Bytes) -> Result<(), Error> {
+		match self {
+			Self::WebTransport(connection) => connection
+				.send_datagram(payload)
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			Self::Memory(connection) => connection
+				.send_datagram(payload)
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection
+				.send_datagram(payload)
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection
+				.send_datagram(payload)
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			Self::Relayed(connection) => connection.send_datagram(payload).await,
+		}
+	}
+
+	/// Receive the next message sent with [`Connection::send_datagram`] by the remote.
+	pub async fn recv_datagram(&mut self) -> Result<Bytes, Error> {
+		match self {
+			Self::WebTransport(connection) => connection
+				.recv_datagram()
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			Self::Memory(connection) => connection
+				.recv_datagram()
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection
+				.recv_datagram()
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection
+				.recv_datagram()
+				.await
+				.map_err(|e| Error::Transport(Box::new(e))),
+			Self::Relayed(connection) => connection.recv_datagram().await,
 		}
 	}
 }
@@ -49,9 +315,38 @@ impl ConnectionTrait for Connection {
 					Ok(Stream::WebTransport(stream))
 				})
 			}
+			Self::Memory(connection) => {
+				let fut = connection.open_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::Memory(stream))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let fut = connection.open_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::WebRtc(stream))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let fut = connection.open_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::Tcp(stream))
+				})
+			}
+			Self::Relayed(connection) => {
+				let fut = connection.open_stream();
+				Box::pin(async move { Ok(Stream::Relayed(fut.await?)) })
+			}
 		}
 	}
 
+	/// Races with the node, which already accepts the inbound streams of its connections: register a handler with
+	/// [`crate::Node::set_stream_handler`] instead.
 	fn accept_stream(&mut self) -> Self::Stream {
 		match self {
 			Self::WebTransport(connection) => {
@@ -61,27 +356,105 @@ impl ConnectionTrait for Connection {
 					Ok(Stream::WebTransport(stream))
 				})
 			}
+			Self::Memory(connection) => {
+				let fut = connection.accept_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::Memory(stream))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let fut = connection.accept_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::WebRtc(stream))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let fut = connection.accept_stream();
+				Box::pin(async move {
+					let stream = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Stream::Tcp(stream))
+				})
+			}
+			Self::Relayed(connection) => {
+				let fut = connection.accept_stream();
+				Box::pin(async move { Ok(Stream::Relayed(fut.await?)) })
+			}
 		}
 	}
 
+	fn capabilities(&self) -> Capabilities {
+		Self::capabilities(self)
+	}
+
+	fn open_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		let mut connection = self.clone();
+		Box::pin(async move { Self::open_uni(&mut connection).await })
+	}
+
+	fn accept_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		let mut connection = self.clone();
+		Box::pin(async move { Self::accept_uni(&mut connection).await })
+	}
+
+	fn send_datagram(&mut self, payload: Bytes) -> BoxFuture<'static, Result<(), Self::Error>> {
+		let mut connection = self.clone();
+		Box::pin(async move { Self::send_datagram(&mut connection, payload).await })
+	}
+
+	fn recv_datagram(&mut self) -> BoxFuture<'static, Result<Bytes, Self::Error>> {
+		let mut connection = self.clone();
+		Box::pin(async move { Self::recv_datagram(&mut connection).await })
+	}
+
 	fn close(&mut self) -> Self::Close {
 		match self {
 			Self::WebTransport(connection) => {
 				let fut = connection.close();
 				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
 			}
+			Self::Memory(connection) => {
+				let fut = connection.close();
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => {
+				let fut = connection.close();
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => {
+				let fut = connection.close();
+				Box::pin(async move { fut.await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			Self::Relayed(connection) => connection.close(),
 		}
 	}
 
 	fn remote_address(&self) -> &Multiaddr {
 		match self {
 			Self::WebTransport(connection) => connection.remote_address(),
+			Self::Memory(connection) => connection.remote_address(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection.remote_address(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection.remote_address(),
+			Self::Relayed(connection) => connection.remote_address(),
 		}
 	}
 
 	fn remote_peer_id(&self) -> Option<PeerId> {
 		match self {
 			Self::WebTransport(connection) => connection.remote_peer_id(),
+			Self::Memory(connection) => connection.remote_peer_id(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(connection) => connection.remote_peer_id(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(connection) => connection.remote_peer_id(),
+			Self::Relayed(connection) => connection.remote_peer_id(),
 		}
 	}
 }
@@ -91,3 +464,23 @@ impl From<sf_wt_transport::Connection> for Connection {
 		Self::WebTransport(connection)
 	}
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<sf_webrtc_transport::Connection> for Connection {
+	fn from(connection: sf_webrtc_transport::Connection) -> Self {
+		Self::WebRtc(connection)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<sf_tcp_transport::Connection> for Connection {
+	fn from(connection: sf_tcp_transport::Connection) -> Self {
+		Self::Tcp(connection)
+	}
+}
+
+impl From<sf_memory_transport::Connection> for Connection {
+	fn from(connection: sf_memory_transport::Connection) -> Self {
+		Self::Memory(connection)
+	}
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use multiaddr::PeerId;
+
+use crate::error::Error;
+
+/// Identifies a connection for the lifetime of the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+	fn next() -> Self {
+		static NEXT: AtomicU64 = AtomicU64::new(0);
+		Self(NEXT.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// Which side of the connection the local node is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+	Dialer,
+	Listener,
+}
+
+/// Why a connection left the pool.
+#[derive(Debug)]
+pub enum CloseCause {
+	/// A connection to the same peer opened simultaneously from the other side was kept instead.
+	Duplicate,
+
+	/// A direct connection to the peer replaced this relayed one.
+	Upgraded,
+
+	/// The remote stopped answering pings.
+	KeepAliveTimeout,
+
+	/// The local node shut down.
+	Shutdown,
+
+	/// The connection was closed, by either side, or failed.
+	Transport(Error),
+}
+
+/// Limits enforced by the node on its connection pool, none are set by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+	max_connections: Option<usize>,
+	max_connections_per_peer: Option<usize>,
+	max_pending_inbound: Option<usize>,
+}
+
+impl ConnectionLimits {
+	/// Maximum number of established connections, both inbound and outbound.
+	pub fn with_max_connections(mut self, limit: usize) -> Self {
+		self.max_connections = Some(limit);
+		self
+	}
+
+	/// Maximum number of established connections with a single peer.
+	pub fn with_max_connections_per_peer(mut self, limit: usize) -> Self {
+		self.max_connections_per_peer = Some(limit);
+		self
+	}
+
+	/// Maximum number of accepted connections still running the handshake.
+	pub fn with_max_pending_inbound(mut self, limit: usize) -> Self {
+		self.max_pending_inbound = Some(limit);
+		self
+	}
+
+	pub(crate) fn allows_pending_inbound(&self, pending: usize) -> bool {
+		self.max_pending_inbound.is_none_or(|limit| pending < limit)
+	}
+}
+
+/// What the pool decided to do with a newly authenticated connection.
+#[derive(Debug)]
+pub(crate) enum Registration<C> {
+	/// The connection joined the pool, `replaced` lost the simultaneous open tie-break or went through a relay, and must
+	/// be closed.
+	Established { id: ConnectionId, replaced: Vec<C> },
+
+	/// The connection lost the simultaneous open tie-break against `existing` and must be closed.
+	Duplicate { existing: C },
+
+	/// Keeping the connection would exceed the limits, `existing` is the connection to use instead if any.
+	LimitReached { existing: Option<C> },
+}
+
+struct Entry<C> {
+	id: ConnectionId,
+	endpoint: Endpoint,
+	/// Whether the connection goes through a relay, a direct connection replaces it.
+	relayed: bool,
+	connection: C,
+	/// Set once the node decided to close the connection, it is not handed out anymore.
+	closing: Option<CloseCause>,
+}
+
+/// Pool of the authenticated connections of a node, keyed by remote peer.
+pub(crate) struct ConnectionManager<C> {
+	local_peer_id: PeerId,
+	limits: ConnectionLimits,
+	peers: HashMap<PeerId, Vec<Entry<C>>>,
+}
+
+impl<C: Clone> ConnectionManager<C> {
+	pub(crate) fn new(local_peer_id: PeerId, limits: ConnectionLimits) -> Self {
+		Self {
+			local_peer_id,
+			limits,
+			peers: HashMap::new(),
+		}
+	}
+
+	pub(crate) fn limits(&self) -> &ConnectionLimits {
+		&self.limits
+	}
+
+	/// A live connection to `peer_id`, if any, direct ones first.
+	pub(crate) fn connection(&self, peer_id: &PeerId) -> Option<C> {
+		self.live(peer_id)
+			.min_by_key(|entry| entry.relayed)
+			.map(|entry| entry.connection.clone())
+	}
+
+	/// A live connection to `peer_id` which does not go through a relay, if any.
+	pub(crate) fn direct_connection(&self, peer_id: &PeerId) -> Option<C> {
+		self.live(peer_id)
+			.find(|entry| !entry.relayed)
+			.map(|entry| entry.connection.clone())
+	}
+
+	pub(crate) fn connected_peers(&self) -> impl Iterator<Item = &PeerId> {
+		self.peers
+			.iter()
+			.filter(|(_, entries)| entries.iter().any(|entry| entry.closing.is_none()))
+			.map(|(peer_id, _)| peer_id)
+	}
+
+	/// Whether no connection is left, including those being closed.
+	pub(crate) fn is_empty(&self) -> bool {
+		self.peers.is_empty()
+	}
+
+	/// Number of connections counted against the limits.
+	pub(crate) fn num_connections(&self) -> usize {
+		self.peers
+			.values()
+			.flatten()
+			.filter(|entry| entry.closing.is_none())
+			.count()
+	}
+
+	pub(crate) fn is_full(&self) -> bool {
+		self.limits
+			.max_connections
+			.is_some_and(|limit| self.num_connections() >= limit)
+	}
+
+	/// Add a freshly authenticated connection to the pool.
+	///
+	/// A direct connection replaces the relayed connections to the same peer, a relayed one is a duplicate of a live
+	/// direct connection.
+	pub(crate) fn register(
+		&mut self,
+		peer_id: PeerId,
+		connection: C,
+		endpoint: Endpoint,
+		relayed: bool,
+	) -> Registration<C> {
+		if relayed && let Some(existing) = self.direct_connection(&peer_id) {
+			return Registration::Duplicate { existing };
+		}
+
+		// Both peers dialed each other at the same time: keep the connection dialed by the smallest peer id so that
+		// both ends settle on the same one.
+		let simultaneous = self
+			.live(&peer_id)
+			.any(|entry| entry.relayed == relayed && entry.endpoint != endpoint);
+		if simultaneous {
+			let dialer = match endpoint {
+				Endpoint::Dialer => self.local_peer_id,
+				Endpoint::Listener => peer_id,
+			};
+			if dialer != self.local_peer_id.min(peer_id) {
+				let existing = self.live(&peer_id).find(|entry| entry.relayed == relayed);
+				return Registration::Duplicate {
+					existing: existing
+						.expect("simultaneous open implies a live connection")
+						.connection
+						.clone(),
+				};
+			}
+
+			let mut replaced = self.mark_closing(
+				&peer_id,
+				|entry| entry.relayed == relayed && entry.endpoint != endpoint,
+				|| CloseCause::Duplicate,
+			);
+			if !relayed {
+				replaced.extend(self.mark_closing(&peer_id, |entry| entry.relayed, || CloseCause::Upgraded));
+			}
+			let id = self.insert(peer_id, connection, endpoint, relayed);
+			return Registration::Established { id, replaced };
+		}
+
+		// The relayed connections replaced by a direct one do not count against the limits.
+		let upgraded = if relayed {
+			0
+		} else {
+			self.live(&peer_id).filter(|entry| entry.relayed).count()
+		};
+		let per_peer = self.live(&peer_id).count() - upgraded;
+		let per_peer_reached = self
+			.limits
+			.max_connections_per_peer
+			.is_some_and(|limit| per_peer >= limit);
+		let full = self
+			.limits
+			.max_connections
+			.is_some_and(|limit| self.num_connections() - upgraded >= limit);
+		if per_peer_reached || full {
+			return Registration::LimitReached {
+				existing: self.connection(&peer_id),
+			};
+		}
+
+		let replaced = if relayed {
+			Vec::new()
+		} else {
+			self.mark_closing(&peer_id, |entry| entry.relayed, || CloseCause::Upgraded)
+		};
+		let id = self.insert(peer_id, connection, endpoint, relayed);
+		Registration::Established { id, replaced }
+	}
+
+	/// Remove a closed connection, returning the cause recorded when the node closed it itself.
+	pub(crate) fn remove(&mut self, peer_id: &PeerId, id: ConnectionId) -> Option<CloseCause> {
+		let entries = self.peers.get_mut(peer_id)?;
+		let position = entries.iter().position(|entry| entry.id == id)?;
+		let entry = entries.remove(position);
+		if entries.is_empty() {
+			self.peers.remove(peer_id);
+		}
+		entry.closing
+	}
+
+	/// Remove every connection at once, with the cause recorded for it or `cause` when none was.
+	pub(crate) fn remove_all(&mut self, cause: impl Fn() -> CloseCause) -> Vec<(PeerId, ConnectionId, CloseCause)> {
+		self.peers
+			.drain()
+			.flat_map(|(peer_id, entries)| entries.into_iter().map(move |entry| (peer_id, entry)))
+			.map(|(peer_id, entry)| (peer_id, entry.id, entry.closing.unwrap_or_else(&cause)))
+			.collect()
+	}
+
+	/// Whether the connection `id` is still in the pool, closing or not.
+	pub(crate) fn contains(&self, peer_id: &PeerId, id: ConnectionId) -> bool {
+		self.peers
+			.get(peer_id)
+			.is_some_and(|entries| entries.iter().any(|entry| entry.id == id))
+	}
+
+	/// Stop handing out the connection `id`, the node is about to close it for `cause`.
+	pub(crate) fn close(&mut self, peer_id: &PeerId, id: ConnectionId, cause: CloseCause) -> Option<C> {
+		let entry = self
+			.peers
+			.get_mut(peer_id)?
+			.iter_mut()
+			.find(|entry| entry.id == id && entry.closing.is_none())?;
+		entry.closing = Some(cause);
+		Some(entry.connection.clone())
+	}
+
+	/// Stop handing out any connection, the node is about to close them all for `cause`.
+	pub(crate) fn close_all(&mut self, cause: impl Fn() -> CloseCause) -> Vec<C> {
+		let peers: Vec<_> = self.peers.keys().copied().collect();
+		peers
+			.iter()
+			.flat_map(|peer_id| self.mark_closing(peer_id, |_| true, &cause))
+			.collect()
+	}
+
+	fn live(&self, peer_id: &PeerId) -> impl Iterator<Item = &Entry<C>> {
+		self.peers
+			.get(peer_id)
+			.into_iter()
+			.flatten()
+			.filter(|entry| entry.closing.is_none())
+	}
+
+	fn insert(&mut self, peer_id: PeerId, connection: C, endpoint: Endpoint, relayed: bool) -> ConnectionId {
+		let id = ConnectionId::next();
+		self.peers.entry(peer_id).or_default().push(Entry {
+			id,
+			endpoint,
+			relayed,
+			connection,
+			closing: None,
+		});
+		id
+	}
+
+	fn mark_closing(
+		&mut self,
+		peer_id: &PeerId,
+		filter: impl Fn(&Entry<C>) -> bool,
+		cause: impl Fn() -> CloseCause,
+	) -> Vec<C> {
+		let mut closing = Vec::new();
+		for entry in self.peers.get_mut(peer_id).into_iter().flatten() {
+			if entry.closing.is_none() && filter(entry) {
+				entry.closing = Some(cause());
+				closing.push(entry.connection.clone());
+			}
+		}
+		closing
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn peers() -> (PeerId, PeerId) {
+		let a = PeerId::random();
+		let b = PeerId::random();
+		(a.min(b), a.max(b))
+	}
+
+	#[test]
+	fn test_reuses_live_connection() {
+		let (local, remote) = peers();
+		let mut manager = ConnectionManager::new(local, ConnectionLimits::default());
+
+		assert!(manager.connection(&remote).is_none());
+		assert!(matches!(
+			manager.register(remote, "first", Endpoint::Dialer, false),
+			Registration::Established { replaced, .. } if replaced.is_empty()
+		));
+		assert_eq!(manager.connection(&remote), Some("first"));
+	}
+
+	#[test]
+	fn test_simultaneous_open_keeps_connection_dialed_by_smallest_peer() {
+		let (small, big) = peers();
+
+		// The smallest peer keeps its outbound connection and drops the inbound one.
+		let mut manager = ConnectionManager::new(small, ConnectionLimits::default());
+		manager.register(big, "outbound", Endpoint::Dialer, false);
+		assert!(matches!(
+			manager.register(big, "inbound", Endpoint::Listener, false),
+			Registration::Duplicate { existing: "outbound" }
+		));
+
+		// The biggest peer replaces its outbound connection by the inbound one.
+		let mut manager = ConnectionManager::new(big, ConnectionLimits::default());
+		let Registration::Established { id: outbound, .. } =
+			manager.register(small, "outbound", Endpoint::Dialer, false)
+		else {
+			panic!("expected the first connection to be established");
+		};
+		assert!(matches!(
+			manager.register(small, "inbound", Endpoint::Listener, false),
+			Registration::Established { replaced, .. } if replaced == ["outbound"]
+		));
+		assert_eq!(manager.connection(&small), Some("inbound"));
+		assert!(matches!(manager.remove(&small, outbound), Some(CloseCause::Duplicate)));
+	}
+
+	#[test]
+	fn test_direct_connection_replaces_relayed_one() {
+		let (local, remote) = peers();
+		let limits = ConnectionLimits::default().with_max_connections_per_peer(1);
+		let mut manager = ConnectionManager::new(local, limits);
+
+		let Registration::Established { id: relayed, .. } =
+			manager.register(remote, "relayed", Endpoint::Listener, true)
+		else {
+			panic!("expected the relayed connection to be established");
+		};
+		// Not a simultaneous open, although the endpoints differ.
+		assert!(matches!(
+			manager.register(remote, "direct", Endpoint::Dialer, false),
+			Registration::Established { replaced, .. } if replaced == ["relayed"]
+		));
+		assert_eq!(manager.connection(&remote), Some("direct"));
+		assert!(matches!(manager.remove(&remote, relayed), Some(CloseCause::Upgraded)));
+
+		assert!(matches!(
+			manager.register(remote, "relayed again", Endpoint::Dialer, true),
+			Registration::Duplicate { existing: "direct" }
+		));
+	}
+
+	#[test]
+	fn test_enforces_limits() {
+		let (local, remote) = peers();
+		let limits = ConnectionLimits::default()
+			.with_max_connections(2)
+			.with_max_connections_per_peer(1);
+		let mut manager = ConnectionManager::new(local, limits);
+
+		manager.register(remote, "first", Endpoint::Listener, false);
+		assert!(matches!(
+			manager.register(remote, "second", Endpoint::Listener, false),
+			Registration::LimitReached {
+				existing: Some("first")
+			}
+		));
+
+		manager.register(PeerId::random(), "third", Endpoint::Listener, false);
+		assert!(manager.is_full());
+		assert!(matches!(
+			manager.register(PeerId::random(), "fourth", Endpoint::Dialer, false),
+			Registration::LimitReached { existing: None }
+		));
+	}
+
+	#[test]
+	fn test_remove_frees_the_slot() {
+		let (local, remote) = peers();
+		let mut manager = ConnectionManager::new(local, ConnectionLimits::default().with_max_connections(1));
+
+		let Registration::Established { id, .. } = manager.register(remote, "first", Endpoint::Dialer, false) else {
+			panic!("expected the connection to be established");
+		};
+		assert!(manager.is_full());
+		assert!(manager.remove(&remote, id).is_none());
+		assert!(!manager.is_full());
+		assert_eq!(manager.connected_peers().count(), 0);
+	}
+
+	#[test]
+	fn test_close_records_cause() {
+		let (local, remote) = peers();
+		let mut manager = ConnectionManager::new(local, ConnectionLimits::default());
+
+		let Registration::Established { id, .. } = manager.register(remote, "first", Endpoint::Dialer, false) else {
+			panic!("expected the connection to be established");
+		};
+		assert_eq!(manager.close(&remote, id, CloseCause::KeepAliveTimeout), Some("first"));
+		assert!(manager.close(&remote, id, CloseCause::KeepAliveTimeout).is_none());
+		assert!(manager.connection(&remote).is_none());
+		assert!(matches!(
+			manager.remove(&remote, id),
+			Some(CloseCause::KeepAliveTimeout)
+		));
+	}
+
+	#[test]
+	fn test_close_all() {
+		let (local, remote) = peers();
+		let mut manager = ConnectionManager::new(local, ConnectionLimits::default());
+		let other = PeerId::random();
+		let mut ids = Vec::new();
+		for (peer_id, connection) in [(remote, "first"), (other, "second")] {
+			let Registration::Established { id, .. } = manager.register(peer_id, connection, Endpoint::Dialer, false)
+			else {
+				panic!("expected the connection to be established");
+			};
+			ids.push((peer_id, id));
+		}
+
+		let mut closing = manager.close_all(|| CloseCause::Shutdown);
+		closing.sort();
+		assert_eq!(closing, ["first", "second"]);
+		assert_eq!(manager.num_connections(), 0);
+		assert!(!manager.is_empty());
+		assert!(manager.close_all(|| CloseCause::Shutdown).is_empty());
+
+		for (peer_id, id) in ids {
+			assert!(manager.contains(&peer_id, id));
+			assert!(matches!(manager.remove(&peer_id, id), Some(CloseCause::Shutdown)));
+			assert!(!manager.contains(&peer_id, id));
+		}
+		assert!(manager.is_empty());
+	}
+
+	#[test]
+	fn test_remove_all() {
+		let (local, remote) = peers();
+		let mut manager = ConnectionManager::new(local, ConnectionLimits::default());
+		let Registration::Established { id: closing, .. } =
+			manager.register(remote, "closing", Endpoint::Dialer, false)
+		else {
+			panic!("expected the connection to be established");
+		};
+		manager.close(&remote, closing, CloseCause::KeepAliveTimeout);
+		let Registration::Established { id: live, .. } = manager.register(remote, "live", Endpoint::Listener, false)
+		else {
+			panic!("expected the connection to be established");
+		};
+
+		let mut removed = manager.remove_all(|| CloseCause::Shutdown);
+		removed.sort_by_key(|(_, id, _)| *id == live);
+		assert!(matches!(
+			removed.as_slice(),
+			[
+				(_, first, CloseCause::KeepAliveTimeout),
+				(_, second, CloseCause::Shutdown),
+			] if *first == closing && *second == live
+		));
+		assert!(manager.is_empty());
+	}
+
+	#[test]
+	fn test_pending_inbound_limit() {
+		let limits = ConnectionLimits::default().with_max_pending_inbound(2);
+		assert!(limits.allows_pending_inbound(1));
+		assert!(!limits.allows_pending_inbound(2));
+		assert!(ConnectionLimits::default().allows_pending_inbound(usize::MAX));
+	}
+}
@@ -1,6 +1,8 @@
-use multiaddr::Multiaddr;
+use multiaddr::{Multiaddr, PeerId};
 use sf_core::Protocol;
 
+use crate::relay::{LimitReached, Refusal};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
 	#[error("no protocols in multiaddr: {0}")]
@@ -11,4 +13,103 @@ pub enum Error {
 
 	#[error("transport error: {0}")]
 	Transport(#[from] Box<dyn std::error::Error + Send + Sync + 'static>),
+
+	#[error("peer id mismatch: expected {expected}, authenticated {actual}")]
+	PeerIdMismatch { expected: Box<PeerId>, actual: Box<PeerId> },
+
+	#[error("invalid handshake signature from {0}")]
+	InvalidSignature(PeerId),
+
+	#[error("invalid handshake nonce of {0} bytes")]
+	InvalidNonce(usize),
+
+	#[error("invalid public key: {0}")]
+	InvalidPublicKey(#[from] libp2p_identity::DecodingError),
+
+	#[error("signing error: {0}")]
+	Signing(#[from] libp2p_identity::SigningError),
+
+	#[error("message too large: {0} bytes")]
+	MessageTooLarge(usize),
+
+	#[error("connection limit reached")]
+	ConnectionLimit,
+
+	#[error("protocol not supported by the remote: {0}")]
+	ProtocolNotSupported(String),
+
+	#[error("invalid protocol: {0}")]
+	InvalidProtocol(String),
+
+	#[error("ping payload mismatch")]
+	PingMismatch,
+
+	#[error("operation timed out")]
+	Timeout,
+
+	#[error("invalid message: {0}")]
+	InvalidMessage(#[from] serde_json::Error),
+
+	#[error("invalid protobuf message: {0}")]
+	InvalidProtobuf(#[from] prost::DecodeError),
+
+	#[error("invalid peer id: {0}")]
+	InvalidPeerId(#[from] libp2p_identity::ParseError),
+
+	#[error("no known address for {0}")]
+	NoKnownAddress(PeerId),
+
+	#[error("not dialing {0} again yet, previous dials failed")]
+	DialBackoff(Multiaddr),
+
+	#[error(transparent)]
+	Unsupported(#[from] sf_core::Unsupported),
+
+	#[error("invalid relay address: {0}")]
+	InvalidRelayAddress(Multiaddr),
+
+	#[error("the relay refused the request: {0:?}")]
+	RelayRefused(Refusal),
+
+	#[error("unexpected answer from the relay")]
+	InvalidRelayResponse,
+
+	#[error("the relayed connection is closed")]
+	CircuitClosed,
+
+	#[error("the relayed connection is closed with code {code}: {reason}")]
+	CircuitClosedWithReason { code: u32, reason: String },
+
+	#[error("the relay ended the circuit at its limit: {0:?}")]
+	CircuitLimit(LimitReached),
+
+	#[error("no direct address to punch a hole with {0}")]
+	NoDirectAddress(PeerId),
+
+	#[error("unexpected hole punching message")]
+	UnexpectedHolePunchMessage,
+
+	#[error("no connected peer subscribed to {0}")]
+	NoPeersSubscribed(String),
+
+	#[error("codec error: {0}")]
+	Codec(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+	#[error("the remote answers too many requests already")]
+	TooManyRequests,
+
+	#[error("the remote refused the request as invalid")]
+	InvalidRequest,
+
+	#[error("the response of the remote exceeds its size limit")]
+	ResponseTooLarge,
+
+	#[error("invalid response")]
+	InvalidResponse,
+
+	#[error("the node is shut down")]
+	Shutdown,
+
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
 }
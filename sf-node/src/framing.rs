@@ -0,0 +1,57 @@
+use std::io;
+
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, io as futures_io};
+use unsigned_varint::{aio, encode, io::ReadError};
+
+use crate::error::Error;
+
+/// Write `payload` prefixed by its length encoded as an unsigned varint.
+pub(crate) async fn write_length_prefixed<S>(stream: &mut S, payload: impl AsRef<[u8]>) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+{
+	let payload = payload.as_ref();
+	let mut buf = encode::usize_buffer();
+	stream.write_all(encode::usize(payload.len(), &mut buf)).await?;
+	stream.write_all(payload).await?;
+	stream.flush().await?;
+	Ok(())
+}
+
+/// Read a message written by [`write_length_prefixed`], refusing anything bigger than `max_size`.
+pub(crate) async fn read_length_prefixed<S>(stream: &mut S, max_size: usize) -> Result<Vec<u8>, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let len = aio::read_usize(&mut *stream).await.map_err(|e| match e {
+		ReadError::Io(e) => e,
+		e => io::Error::new(io::ErrorKind::InvalidData, e),
+	})?;
+	if len > max_size {
+		return Err(Error::MessageTooLarge(len));
+	}
+
+	let mut payload = vec![0; len];
+	stream.read_exact(&mut payload).await?;
+	Ok(payload)
+}
+
+/// Skip a message written by [`write_length_prefixed`] without buffering it, refusing anything bigger than `max_size`.
+pub(crate) async fn discard_length_prefixed<S>(stream: &mut S, max_size: usize) -> Result<(), Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let len = aio::read_usize(&mut *stream).await.map_err(|e| match e {
+		ReadError::Io(e) => e,
+		e => io::Error::new(io::ErrorKind::InvalidData, e),
+	})?;
+	if len > max_size {
+		return Err(Error::MessageTooLarge(len));
+	}
+
+	let discarded = futures_io::copy(stream.take(len as u64), &mut futures_io::sink()).await?;
+	if discarded < len as u64 {
+		return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+	}
+	Ok(())
+}
@@ -0,0 +1,190 @@
+//! Authenticate both ends of a connection.
+//!
+//! The handshake runs on the first bidirectional stream of every connection, each side signing the nonce chosen by
+//! the other one with its identity key:
+//!
+//! ```text
+//! dialer   -> listener: nonce_d
+//! listener -> dialer:   nonce_l, public_key_l, sign_l(PROTOCOL | "listener" | nonce_d | nonce_l)
+//! dialer   -> listener: public_key_d, sign_d(PROTOCOL | "dialer" | nonce_l | nonce_d)
+//! listener -> dialer:   ack
+//! ```
+//!
+//! The final acknowledgement ensures the dial only succeeds once the listener accepted the dialer.
+
+use futures::{AsyncRead, AsyncWrite};
+use libp2p_identity::{Keypair, PublicKey};
+use multiaddr::PeerId;
+use sf_core::Stream as _;
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+
+const PROTOCOL: &[u8] = b"/sf/handshake/1.0.0";
+const NONCE_LEN: usize = 32;
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+const DIALER: &[u8] = b"dialer";
+const LISTENER: &[u8] = b"listener";
+
+/// Open the handshake stream on a freshly dialed connection and return the authenticated remote peer.
+pub(crate) async fn outbound(connection: &mut Connection, keypair: &Keypair) -> Result<PeerId, Error> {
+	let mut stream = connection.open_stream().await?;
+	let peer_id = dialer(&mut stream, keypair).await?;
+	stream.close().await?;
+	Ok(peer_id)
+}
+
+/// Accept the handshake stream on a freshly accepted connection and return the authenticated remote peer.
+pub(crate) async fn inbound(connection: &mut Connection, keypair: &Keypair) -> Result<PeerId, Error> {
+	let mut stream = connection.accept_stream().await?;
+	let peer_id = listener(&mut stream, keypair).await?;
+	stream.close().await?;
+	Ok(peer_id)
+}
+
+async fn dialer<S>(stream: &mut S, keypair: &Keypair) -> Result<PeerId, Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let local_nonce: [u8; NONCE_LEN] = rand::random();
+	write_length_prefixed(stream, local_nonce).await?;
+
+	let remote_nonce = read_nonce(stream).await?;
+	let peer_id = recv_identity(stream, LISTENER, &local_nonce, &remote_nonce).await?;
+	send_identity(stream, keypair, DIALER, &remote_nonce, &local_nonce).await?;
+	read_length_prefixed(stream, 0).await?;
+
+	Ok(peer_id)
+}
+
+async fn listener<S>(stream: &mut S, keypair: &Keypair) -> Result<PeerId, Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let remote_nonce = read_nonce(stream).await?;
+
+	let local_nonce: [u8; NONCE_LEN] = rand::random();
+	write_length_prefixed(stream, local_nonce).await?;
+	send_identity(stream, keypair, LISTENER, &remote_nonce, &local_nonce).await?;
+
+	let peer_id = recv_identity(stream, DIALER, &local_nonce, &remote_nonce).await?;
+	write_length_prefixed(stream, []).await?;
+
+	Ok(peer_id)
+}
+
+async fn read_nonce<S>(stream: &mut S) -> Result<[u8; NONCE_LEN], Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let nonce = read_length_prefixed(stream, NONCE_LEN).await?;
+	nonce
+		.try_into()
+		.map_err(|nonce: Vec<u8>| Error::InvalidNonce(nonce.len()))
+}
+
+async fn send_identity<S>(
+	stream: &mut S,
+	keypair: &Keypair,
+	role: &[u8],
+	remote_nonce: &[u8],
+	local_nonce: &[u8],
+) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+{
+	let signature = keypair.sign(&signed_payload(role, remote_nonce, local_nonce))?;
+	write_length_prefixed(stream, keypair.public().encode_protobuf()).await?;
+	write_length_prefixed(stream, signature).await
+}
+
+async fn recv_identity<S>(stream: &mut S, role: &[u8], local_nonce: &[u8], remote_nonce: &[u8]) -> Result<PeerId, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let public_key = PublicKey::try_decode_protobuf(&read_length_prefixed(stream, MAX_MESSAGE_SIZE).await?)?;
+	let signature = read_length_prefixed(stream, MAX_MESSAGE_SIZE).await?;
+
+	let peer_id = public_key.to_peer_id();
+	if !public_key.verify(&signed_payload(role, local_nonce, remote_nonce), &signature) {
+		return Err(Error::InvalidSignature(peer_id));
+	}
+
+	Ok(peer_id)
+}
+
+fn signed_payload(role: &[u8], signed_nonce: &[u8], own_nonce: &[u8]) -> Vec<u8> {
+	[PROTOCOL, role, signed_nonce, own_nonce].concat()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+	fn pipe() -> (Compat<tokio::io::DuplexStream>, Compat<tokio::io::DuplexStream>) {
+		let (a, b) = tokio::io::duplex(4096);
+		(a.compat(), b.compat())
+	}
+
+	#[tokio::test]
+	async fn test_both_sides_authenticate() {
+		let dialer_key = Keypair::generate_ed25519();
+		let listener_key = Keypair::generate_ed25519();
+		let (mut a, mut b) = pipe();
+
+		let (dialed, accepted) = tokio::join!(dialer(&mut a, &dialer_key), listener(&mut b, &listener_key));
+
+		assert_eq!(dialed.unwrap(), listener_key.public().to_peer_id());
+		assert_eq!(accepted.unwrap(), dialer_key.public().to_peer_id());
+	}
+
+	#[tokio::test]
+	async fn test_rejects_key_it_does_not_own() {
+		let dialer_key = Keypair::generate_ed25519();
+		let victim_key = Keypair::generate_ed25519();
+		let attacker_key = Keypair::generate_ed25519();
+		let (mut a, mut b) = pipe();
+
+		// Claim the victim's public key but sign with another one.
+		let impostor = async {
+			let remote_nonce = read_nonce(&mut b).await.unwrap();
+			let local_nonce = [7u8; NONCE_LEN];
+			write_length_prefixed(&mut b, local_nonce).await.unwrap();
+			let signature = attacker_key
+				.sign(&signed_payload(LISTENER, &remote_nonce, &local_nonce))
+				.unwrap();
+			write_length_prefixed(&mut b, victim_key.public().encode_protobuf())
+				.await
+				.unwrap();
+			write_length_prefixed(&mut b, signature).await.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(dialer(&mut a, &dialer_key), impostor);
+
+		assert!(matches!(result, Err(Error::InvalidSignature(peer_id)) if peer_id == victim_key.public().to_peer_id()));
+	}
+
+	#[tokio::test]
+	async fn test_rejects_reflected_signature() {
+		let dialer_key = Keypair::generate_ed25519();
+		let (mut a, mut b) = pipe();
+
+		// Replay the dialer's own role as the listener: the signature must not verify.
+		let reflector = async {
+			let remote_nonce = read_nonce(&mut b).await.unwrap();
+			let local_nonce = [1u8; NONCE_LEN];
+			write_length_prefixed(&mut b, local_nonce).await.unwrap();
+			send_identity(&mut b, &dialer_key, DIALER, &remote_nonce, &local_nonce)
+				.await
+				.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(dialer(&mut a, &dialer_key), reflector);
+
+		assert!(matches!(result, Err(Error::InvalidSignature(_))));
+	}
+}
@@ -0,0 +1,362 @@
+//! Upgrade relayed connections to direct QUIC connections by punching a hole through the NATs of both peers.
+//!
+//! The peer which accepted a relayed connection opens a [`PROTOCOL`] stream over it and sends its QUIC addresses, the
+//! remote answers with its own. Those are first the addresses the other peers observed, which are the public addresses
+//! of a peer behind a NAT, then the addresses it listens on, which reach it on the same network. The initiator measures
+//! the round trip of that exchange, sends a sync message and waits half of it, while the remote dials as soon as the
+//! sync arrives: both dials leave at about the same time, each opening the NAT of its peer for the packets of the other
+//! one. The QUIC transport sends its dials from the listening socket, so the mapping a dial opens is the one the
+//! observed addresses point at. The node then swaps the relayed connection for the direct one, see
+//! [`crate::Event::HolePunchSucceeded`].
+
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::{AsyncRead, AsyncWrite};
+use multiaddr::{Multiaddr, PeerId, Protocol};
+use serde::{Deserialize, Serialize};
+use sf_core::Stream as _;
+use tracing::debug;
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+use crate::identify::ObservedAddrs;
+use crate::node::Event;
+use crate::relay;
+use crate::runtime;
+use crate::stream::Stream;
+
+pub(crate) const PROTOCOL: &str = "/sf/hole-punch/1.0.0";
+const MAX_MESSAGE_SIZE: usize = 4096;
+/// Time given to the remote to answer during the address exchange.
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time given to the simultaneous dials to connect and authenticate the peer.
+pub(crate) const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+	/// QUIC addresses of the sender, observed ones first.
+	Connect { addresses: Vec<Multiaddr> },
+	/// Dial now, the initiator dials once half the round trip elapsed.
+	Sync,
+}
+
+/// Addresses of the local node to advertise.
+#[derive(Clone)]
+pub(crate) struct LocalAddrs {
+	pub(crate) listen: Arc<RwLock<Vec<Multiaddr>>>,
+	/// See [`crate::Node::observed_addrs`].
+	pub(crate) observed: Arc<RwLock<ObservedAddrs>>,
+}
+
+impl LocalAddrs {
+	/// The addresses a peer can punch a hole to: the observed ones completed by a matching listen address, such as
+	/// with its `/certhash/`, then the listen addresses themselves.
+	fn punchable(&self) -> Vec<Multiaddr> {
+		let listen = self
+			.listen
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.iter()
+			.filter(|address| is_punchable(address))
+			.cloned()
+			.collect::<Vec<_>>();
+		let observed = self
+			.observed
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.confirmed(Instant::now());
+
+		let mut addresses = Vec::new();
+		let translated = observed
+			.iter()
+			.flat_map(|observed| listen.iter().filter_map(|listen| translate(observed, listen)));
+		for address in translated.chain(listen.iter().cloned()) {
+			if !addresses.contains(&address) {
+				addresses.push(address);
+			}
+		}
+		addresses
+	}
+}
+
+/// Addresses of `peer` to dial right away, handed to the node which owns the transports.
+#[derive(Debug)]
+pub(crate) struct Punch {
+	pub(crate) peer: PeerId,
+	pub(crate) addresses: Vec<Multiaddr>,
+}
+
+/// Run the exchange over the relayed `connection` to `peer`, as the side which accepted it.
+///
+/// Hands the addresses of the remote to `punches` once it is time to dial them.
+pub(crate) async fn initiate(
+	local_addrs: LocalAddrs,
+	peer: PeerId,
+	mut connection: Connection,
+	punches: mpsc::UnboundedSender<Punch>,
+) -> Result<(), Error> {
+	let local = local_addrs.punchable();
+	if local.is_empty() {
+		return Err(Error::NoDirectAddress(peer));
+	}
+
+	let mut stream = connection.open_stream_with_protocol(PROTOCOL).await?;
+	let started = Instant::now();
+	send(&mut stream, &Message::Connect { addresses: local }).await?;
+	let Message::Connect { addresses } = runtime::timeout(EXCHANGE_TIMEOUT, recv(&mut stream)).await?? else {
+		return Err(Error::UnexpectedHolePunchMessage);
+	};
+	let rtt = started.elapsed();
+	let addresses = addresses.into_iter().filter(is_punchable).collect::<Vec<_>>();
+	if addresses.is_empty() {
+		return Err(Error::NoDirectAddress(peer));
+	}
+
+	send(&mut stream, &Message::Sync).await?;
+	let _ = stream.close().await;
+	debug!(%peer, ?rtt, "Synchronized hole punch");
+	runtime::sleep(rtt / 2).await;
+	let _ = punches.unbounded_send(Punch { peer, addresses });
+	Ok(())
+}
+
+/// Answer the exchange started by `peer` over a relayed connection, dialing its addresses once synchronized.
+///
+/// Failures are reported to `events`, the node reports the outcome of the dial.
+pub(crate) async fn handle(
+	local_addrs: LocalAddrs,
+	punches: mpsc::UnboundedSender<Punch>,
+	events: mpsc::UnboundedSender<Event>,
+	peer: PeerId,
+	mut stream: Stream,
+) {
+	let result = async {
+		let Message::Connect { addresses } = recv(&mut stream).await? else {
+			return Err(Error::UnexpectedHolePunchMessage);
+		};
+		let addresses = addresses.into_iter().filter(is_punchable).collect::<Vec<_>>();
+		if addresses.is_empty() {
+			return Err(Error::NoDirectAddress(peer));
+		}
+
+		let local = local_addrs.punchable();
+		let no_local = local.is_empty();
+		// Answered even when empty, for the initiator to give up too.
+		send(&mut stream, &Message::Connect { addresses: local }).await?;
+		if no_local {
+			return Err(Error::NoDirectAddress(peer));
+		}
+		let Message::Sync = runtime::timeout(EXCHANGE_TIMEOUT, recv(&mut stream)).await?? else {
+			return Err(Error::UnexpectedHolePunchMessage);
+		};
+		let _ = punches.unbounded_send(Punch { peer, addresses });
+		Ok(())
+	}
+	.await;
+	if let Err(error) = result {
+		debug!(%peer, ?error, "Failed to synchronize hole punch");
+		let _ = events.unbounded_send(Event::HolePunchFailed { peer_id: peer, error });
+	}
+	let _ = stream.close().await;
+}
+
+/// The IP and UDP port of `observed` followed by the components of `listen` after its own, when `observed` is a QUIC
+/// address of the same IP version as `listen`.
+fn translate(observed: &Multiaddr, listen: &Multiaddr) -> Option<Multiaddr> {
+	let mut observed = observed.iter();
+	let mut listen = listen.iter();
+	let ip = match (observed.next()?, listen.next()?) {
+		(ip @ Protocol::Ip4(_), Protocol::Ip4(_)) | (ip @ Protocol::Ip6(_), Protocol::Ip6(_)) => ip,
+		_ => return None,
+	};
+	let (port @ Protocol::Udp(_), Protocol::Udp(_)) = (observed.next()?, listen.next()?) else {
+		return None;
+	};
+	if observed.next()? != Protocol::QuicV1 {
+		return None;
+	}
+	Some([ip, port].into_iter().chain(listen).collect())
+}
+
+/// Whether `address` is a direct QUIC address, dialed through the WebTransport transport.
+fn is_punchable(address: &Multiaddr) -> bool {
+	!relay::is_circuit(address) && address.iter().any(|protocol| protocol == Protocol::WebTransport)
+}
+
+async fn send<S>(stream: &mut S, message: &Message) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+{
+	write_length_prefixed(stream, serde_json::to_vec(message)?).await
+}
+
+async fn recv<S>(stream: &mut S) -> Result<Message, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	Ok(serde_json::from_slice(
+		&read_length_prefixed(stream, MAX_MESSAGE_SIZE).await?,
+	)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::StreamExt;
+	use moq_native::quic;
+
+	use crate::test_util::{self, drive, listen, next_event};
+	use crate::{CloseCause, Node, RelayConfig};
+
+	#[test]
+	fn test_observed_addresses_come_first() {
+		let listen: Multiaddr =
+			"/ip4/192.168.1.2/udp/4433/quic-v1/webtransport/certhash/uEiAkH5a4DPGKUuOBjYw0CgwjvYCFtXQ8qKQk1mgG93y-oA"
+				.parse()
+				.unwrap();
+		let mut observed = ObservedAddrs::default();
+		let now = Instant::now();
+		let reports = [
+			"/ip4/1.2.3.4/tcp/5678/tls",
+			"/ip6/::1/udp/5678/quic-v1/webtransport",
+			"/ip4/1.2.3.4/udp/5678/quic-v1/webtransport",
+		];
+		for (i, address) in reports.into_iter().enumerate() {
+			for _ in 0..crate::identify::MIN_CONFIRMATIONS {
+				observed.record(
+					PeerId::random(),
+					address.parse().unwrap(),
+					now + Duration::from_secs(i as u64),
+				);
+			}
+		}
+		// Reported by a single peer, which is not trusted.
+		observed.record(
+			PeerId::random(),
+			"/ip4/6.6.6.6/udp/5678/quic-v1/webtransport".parse().unwrap(),
+			now + Duration::from_secs(3),
+		);
+		let local_addrs = LocalAddrs {
+			listen: Arc::new(RwLock::new(vec![listen.clone(), "/memory/7".parse().unwrap()])),
+			observed: Arc::new(RwLock::new(observed)),
+		};
+
+		let translated: Multiaddr =
+			"/ip4/1.2.3.4/udp/5678/quic-v1/webtransport/certhash/uEiAkH5a4DPGKUuOBjYw0CgwjvYCFtXQ8qKQk1mgG93y-oA"
+				.parse()
+				.unwrap();
+		assert_eq!(local_addrs.punchable(), [translated, listen]);
+	}
+
+	const QUIC_ADDRESS: &str = "/ip4/127.0.0.1/udp/0/quic-v1/webtransport";
+
+	/// A node on memory addresses, and on QUIC addresses too if `quic` is set.
+	fn node(quic: bool, relay: Option<RelayConfig>) -> Node {
+		test_util::node(|builder| {
+			if quic {
+				let tls = moq_native::tls::Args {
+					self_sign: vec!["localhost".into()],
+					..Default::default()
+				}
+				.load()
+				.unwrap();
+				let bind = "127.0.0.1:0".parse().unwrap();
+				builder.with_web_transport(sf_wt_transport::WebTransport::new(quic::Config { bind, tls }, false));
+			}
+			if let Some(config) = relay {
+				builder.with_relay(config);
+			}
+		})
+	}
+
+	/// A relayed connection from a dialer to a target reserved on a relay, the target driven in the background.
+	async fn relayed(dialer_quic: bool) -> (Node, Connection, PeerId, mpsc::UnboundedReceiver<Event>) {
+		let mut relay = node(false, Some(RelayConfig::default()));
+		let relay_address = listen(&mut relay, "/memory/0").await;
+		let relay_id = relay.peer_id;
+		drive(relay);
+
+		let mut target = node(true, None);
+		listen(&mut target, QUIC_ADDRESS).await;
+		target.add_address(relay_id, relay_address.clone());
+		let reservation = target.reserve(relay_id).await.unwrap();
+		let target_id = target.peer_id;
+		let target_events = drive(target);
+
+		let mut dialer = node(dialer_quic, None);
+		if dialer_quic {
+			listen(&mut dialer, QUIC_ADDRESS).await;
+		}
+		dialer.add_address(relay_id, relay_address);
+		let connection = dialer.dial(target_id, reservation.address).await.unwrap();
+		assert!(connection.is_relayed());
+		(dialer, connection, target_id, target_events)
+	}
+
+	#[tokio::test]
+	async fn test_upgrades_relayed_connection() {
+		let (mut dialer, relayed, target, mut target_events) = relayed(true).await;
+		let closed = relayed.closed();
+
+		let (mut punched, mut upgraded) = (false, false);
+		while !(punched && upgraded) {
+			match next_event(&mut dialer).await {
+				Event::HolePunchSucceeded { peer_id, .. } if peer_id == target => punched = true,
+				Event::HolePunchFailed { error, .. } => panic!("hole punch failed: {error}"),
+				Event::ConnectionClosed {
+					cause: CloseCause::Upgraded,
+					..
+				} => upgraded = true,
+				_ => {}
+			}
+		}
+		let direct = dialer.connection(&target).unwrap();
+		assert!(!direct.is_relayed());
+		assert!(matches!(direct, Connection::WebTransport(_)));
+		runtime::timeout(Duration::from_secs(5), closed).await.unwrap();
+
+		loop {
+			let event = runtime::timeout(Duration::from_secs(10), target_events.next())
+				.await
+				.unwrap()
+				.unwrap();
+			match event {
+				Event::HolePunchSucceeded { peer_id, .. } if peer_id == dialer.peer_id => break,
+				Event::HolePunchFailed { error, .. } => panic!("hole punch failed: {error}"),
+				_ => {}
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_reports_failure_without_direct_address() {
+		let (mut dialer, relayed, target, mut target_events) = relayed(false).await;
+
+		loop {
+			let event = runtime::timeout(Duration::from_secs(10), target_events.next())
+				.await
+				.unwrap()
+				.unwrap();
+			if let Event::HolePunchFailed { peer_id, error } = event {
+				assert_eq!(peer_id, dialer.peer_id);
+				assert!(matches!(error, Error::NoDirectAddress(peer) if peer == dialer.peer_id));
+				break;
+			}
+		}
+		// The side which answered the exchange reports the failure too.
+		loop {
+			if let Event::HolePunchFailed { peer_id, error } = next_event(&mut dialer).await {
+				assert_eq!(peer_id, target);
+				assert!(matches!(error, Error::NoDirectAddress(peer) if peer == target));
+				break;
+			}
+		}
+		// The relayed connection stays in use.
+		assert!(dialer.connection(&target).unwrap().is_relayed());
+		drop(relayed);
+	}
+}
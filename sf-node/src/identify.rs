@@ -0,0 +1,272 @@
+//! Exchange what each peer knows about itself.
+//!
+//! Every established connection opens a stream negotiating [`PROTOCOL`] from both sides, the remote answers with a
+//! single [`IdentifyInfo`] message and closes the stream. The message includes the address the remote sees the
+//! connection come from, which is how a node behind a NAT learns its public addresses. An observed address is only
+//! trusted once several peers reported it, see [`ObservedAddrs`].
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::{AsyncRead, AsyncWrite};
+use multiaddr::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use sf_core::Stream as _;
+use tracing::debug;
+
+use crate::connection::Connection;
+use crate::connection_manager::ConnectionManager;
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+use crate::relay;
+use crate::stream::Stream;
+use crate::stream_handler::WeakStreamHandlers;
+
+pub(crate) const PROTOCOL: &str = "/sf/identify/1.0.0";
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Distinct peers which must report an address before it is trusted.
+pub(crate) const MIN_CONFIRMATIONS: usize = 3;
+/// Time a report counts as a confirmation.
+const OBSERVATION_TTL: Duration = Duration::from_secs(30 * 60);
+/// Reports kept, the oldest are forgotten first.
+const MAX_OBSERVATIONS: usize = 256;
+
+/// Agent version advertised when none is configured on the [`crate::Builder`].
+pub(crate) const DEFAULT_AGENT_VERSION: &str = concat!("sf-node/", env!("CARGO_PKG_VERSION"));
+
+/// What a peer advertises about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentifyInfo {
+	pub peer_id: PeerId,
+	/// Addresses the peer listens on.
+	pub listen_addrs: Vec<Multiaddr>,
+	/// Stream protocols the peer handles.
+	pub protocols: Vec<String>,
+	pub agent_version: String,
+	/// Address the peer sees the connection of the requesting node come from, if direct.
+	#[serde(default)]
+	pub observed_addr: Option<Multiaddr>,
+}
+
+/// Source of the information advertised by the local node.
+#[derive(Clone)]
+pub(crate) struct LocalInfo {
+	pub(crate) peer_id: PeerId,
+	pub(crate) agent_version: String,
+	pub(crate) listen_addrs: Arc<RwLock<Vec<Multiaddr>>>,
+	/// Weak to not keep the handlers alive from one of their own handlers.
+	pub(crate) handlers: WeakStreamHandlers,
+	/// To report the address the requesting peer is seen on.
+	pub(crate) connections: Arc<Mutex<ConnectionManager<Connection>>>,
+}
+
+impl LocalInfo {
+	fn snapshot(&self, peer_id: &PeerId) -> IdentifyInfo {
+		let mut protocols = self.handlers.protocols();
+		protocols.sort();
+		let observed_addr = self
+			.connections
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.direct_connection(peer_id)
+			.map(|connection| sf_core::Connection::remote_address(&connection).clone());
+
+		IdentifyInfo {
+			peer_id: self.peer_id,
+			listen_addrs: self.listen_addrs.read().unwrap_or_else(PoisonError::into_inner).clone(),
+			protocols,
+			agent_version: self.agent_version.clone(),
+			observed_addr,
+		}
+	}
+}
+
+/// Addresses the peers observed the local node on, a single peer being able to report anything.
+#[derive(Debug, Default)]
+pub(crate) struct ObservedAddrs {
+	/// The last address each peer reported, and when.
+	reports: HashMap<PeerId, (Multiaddr, Instant)>,
+}
+
+impl ObservedAddrs {
+	/// Record that `peer_id` observed the local node on `address`, replacing its previous report. Circuit addresses
+	/// are ignored as the relay is what the peer observed.
+	pub(crate) fn record(&mut self, peer_id: PeerId, address: Multiaddr, now: Instant) {
+		if relay::is_circuit(&address) {
+			return;
+		}
+		self.reports
+			.retain(|_, (_, at)| now.duration_since(*at) < OBSERVATION_TTL);
+		self.reports.insert(peer_id, (address, now));
+		if self.reports.len() > MAX_OBSERVATIONS
+			&& let Some(oldest) = self
+				.reports
+				.iter()
+				.min_by_key(|(_, (_, at))| *at)
+				.map(|(peer_id, _)| *peer_id)
+		{
+			self.reports.remove(&oldest);
+		}
+	}
+
+	/// The addresses reported by at least [`MIN_CONFIRMATIONS`] peers within [`OBSERVATION_TTL`], the most recently
+	/// reported first.
+	pub(crate) fn confirmed(&self, now: Instant) -> Vec<Multiaddr> {
+		let mut confirmations: HashMap<&Multiaddr, (usize, Instant)> = HashMap::new();
+		for (address, at) in self.reports.values() {
+			if now.duration_since(*at) >= OBSERVATION_TTL {
+				continue;
+			}
+			let (count, last) = confirmations.entry(address).or_insert((0, *at));
+			*count += 1;
+			*last = (*last).max(*at);
+		}
+
+		let mut confirmed: Vec<_> = confirmations
+			.into_iter()
+			.filter(|(_, (count, _))| *count >= MIN_CONFIRMATIONS)
+			.collect();
+		confirmed.sort_by_key(|(_, (_, last))| std::cmp::Reverse(*last));
+		confirmed.into_iter().map(|(address, _)| address.clone()).collect()
+	}
+}
+
+/// Answer an identify request with the current local information.
+pub(crate) async fn handle(local: LocalInfo, peer_id: PeerId, mut stream: Stream) {
+	if let Err(error) = send(&mut stream, &local.snapshot(&peer_id)).await {
+		debug!(%peer_id, ?error, "Failed to send identify info");
+	}
+	let _ = stream.close().await;
+}
+
+/// Ask the remote of `connection`, authenticated as `peer_id`, to identify itself.
+pub(crate) async fn request(peer_id: PeerId, mut connection: Connection) -> Result<IdentifyInfo, Error> {
+	let mut stream = connection.open_stream_with_protocol(PROTOCOL).await?;
+	let info = recv(&mut stream, peer_id).await?;
+	let _ = stream.close().await;
+	Ok(info)
+}
+
+async fn send<S>(stream: &mut S, info: &IdentifyInfo) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+{
+	write_length_prefixed(stream, serde_json::to_vec(info)?).await
+}
+
+async fn recv<S>(stream: &mut S, peer_id: PeerId) -> Result<IdentifyInfo, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let info: IdentifyInfo = serde_json::from_slice(&read_length_prefixed(stream, MAX_MESSAGE_SIZE).await?)?;
+	if info.peer_id != peer_id {
+		return Err(Error::PeerIdMismatch {
+			expected: Box::new(peer_id),
+			actual: Box::new(info.peer_id),
+		});
+	}
+	Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio_util::compat::TokioAsyncReadCompatExt;
+
+	fn info(peer_id: PeerId) -> IdentifyInfo {
+		IdentifyInfo {
+			peer_id,
+			listen_addrs: vec!["/ip4/127.0.0.1/udp/4433/quic-v1/webtransport".parse().unwrap()],
+			protocols: vec![PROTOCOL.to_owned()],
+			agent_version: DEFAULT_AGENT_VERSION.to_owned(),
+			observed_addr: Some("/ip4/1.2.3.4/udp/5678/quic-v1/webtransport".parse().unwrap()),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_exchange_info() {
+		let (a, b) = tokio::io::duplex(4096);
+		let (mut a, mut b) = (a.compat(), b.compat());
+		let info = info(PeerId::random());
+
+		let (sent, received) = tokio::join!(send(&mut a, &info), recv(&mut b, info.peer_id));
+
+		sent.unwrap();
+		assert_eq!(received.unwrap(), info);
+	}
+
+	#[tokio::test]
+	async fn test_rejects_info_of_another_peer() {
+		let (a, b) = tokio::io::duplex(4096);
+		let (mut a, mut b) = (a.compat(), b.compat());
+		let info = info(PeerId::random());
+
+		let (sent, received) = tokio::join!(send(&mut a, &info), recv(&mut b, PeerId::random()));
+
+		sent.unwrap();
+		assert!(matches!(received, Err(Error::PeerIdMismatch { .. })));
+	}
+
+	fn observed(port: u16) -> Multiaddr {
+		format!("/ip4/1.2.3.4/udp/{port}/quic-v1/webtransport").parse().unwrap()
+	}
+
+	#[test]
+	fn test_observed_addresses_need_confirmations() {
+		let mut observed_addrs = ObservedAddrs::default();
+		let now = Instant::now();
+		let reporters: Vec<_> = (0..MIN_CONFIRMATIONS).map(|_| PeerId::random()).collect();
+
+		// A single peer reporting again does not confirm its address.
+		for _ in 0..MIN_CONFIRMATIONS {
+			observed_addrs.record(reporters[0], observed(1), now);
+		}
+		observed_addrs.record(
+			reporters[1],
+			relay::circuit_address(PeerId::random(), PeerId::random()),
+			now,
+		);
+		assert!(observed_addrs.confirmed(now).is_empty());
+
+		for (i, reporter) in reporters.iter().enumerate() {
+			observed_addrs.record(*reporter, observed(1), now + Duration::from_secs(i as u64));
+		}
+		let later = now + Duration::from_secs(MIN_CONFIRMATIONS as u64);
+		for reporter in &reporters {
+			observed_addrs.record(*reporter, observed(2), later);
+		}
+		// A peer reporting another address withdraws its previous report.
+		assert_eq!(observed_addrs.confirmed(later), [observed(2)]);
+	}
+
+	#[test]
+	fn test_observed_addresses_expire() {
+		let mut observed_addrs = ObservedAddrs::default();
+		let now = Instant::now();
+		for _ in 0..MIN_CONFIRMATIONS {
+			observed_addrs.record(PeerId::random(), observed(1), now);
+		}
+		assert_eq!(observed_addrs.confirmed(now), [observed(1)]);
+
+		assert!(observed_addrs.confirmed(now + OBSERVATION_TTL).is_empty());
+		observed_addrs.record(PeerId::random(), observed(1), now + OBSERVATION_TTL);
+		assert!(observed_addrs.confirmed(now + OBSERVATION_TTL).is_empty());
+		assert_eq!(observed_addrs.reports.len(), 1);
+	}
+
+	#[test]
+	fn test_observed_addresses_are_bounded() {
+		let mut observed_addrs = ObservedAddrs::default();
+		let now = Instant::now();
+		let first = PeerId::random();
+		observed_addrs.record(first, observed(1), now);
+		for i in 0..MAX_OBSERVATIONS {
+			observed_addrs.record(PeerId::random(), observed(2), now + Duration::from_millis(i as u64 + 1));
+		}
+
+		assert_eq!(observed_addrs.reports.len(), MAX_OBSERVATIONS);
+		assert!(!observed_addrs.reports.contains_key(&first));
+	}
+}
@@ -1,11 +1,45 @@
 mod builder;
 mod connection;
+mod connection_manager;
 mod error;
+mod framing;
+mod handshake;
+mod hole_punch;
+mod identify;
 mod listener;
+mod negotiation;
 mod node;
+mod peer_store;
+mod ping;
+mod pubsub;
+mod relay;
+mod request_response;
+mod runtime;
 mod stream;
+mod stream_handler;
+#[cfg(test)]
+mod test_util;
 mod transport;
 
 pub use builder::Builder;
+pub use connection::{CLOSE_CODE_SHUTDOWN, Connection};
+pub use connection_manager::{CloseCause, ConnectionId, ConnectionLimits, Endpoint};
+pub use error::Error;
+pub use identify::IdentifyInfo;
+pub use listener::Listener;
+pub use node::DEFAULT_DIAL_TIMEOUT;
+pub use node::DEFAULT_HANDSHAKE_TIMEOUT;
+pub use node::DEFAULT_TRANSPORT_PREFERENCE;
 pub use node::Event;
 pub use node::Node;
+pub use peer_store::{AddressRecord, FileBackend, MemoryBackend, PeerInfo, PeerStoreBackend, PeerStoreConfig};
+pub use ping::PingConfig;
+pub use pubsub::{Message, MessageId, PubsubConfig, Validation};
+pub use relay::{Circuit, CircuitLimit, CircuitStream, LimitReached, Refusal, RelayConfig, Reservation};
+pub use request_response::{
+	BincodeCodec, BincodeError, Codec, JsonCodec, ProstCodec, RequestResponse, RequestResponseConfig,
+};
+pub use stream::Stream;
+pub use transport::Transport;
+
+pub use sf_core::{ListenerId, Protocol};
@@ -5,40 +5,64 @@ use std::{
 
 use futures::Stream;
 use multiaddr::Multiaddr;
-use sf_core::{Listener as ListenerTrait, TransportEvent};
+use sf_core::{Listener as ListenerTrait, ListenerId, TransportEvent};
 
 use crate::{connection::Connection, error::Error};
 
+// Listeners live as long as their transport, boxing them would only add an indirection.
+#[allow(clippy::large_enum_variant)]
 pub enum Listener {
 	WebTransport(sf_wt_transport::Listener),
+	Memory(sf_memory_transport::Listener),
 }
 
 impl ListenerTrait for Listener {
 	type Connection = Connection;
 	type Error = Error;
 
+	fn id(&self) -> ListenerId {
+		match self {
+			Self::WebTransport(listener) => listener.id(),
+			Self::Memory(listener) => listener.id(),
+		}
+	}
+
 	fn local_address(&self) -> Multiaddr {
 		match self {
 			Self::WebTransport(listener) => listener.local_address(),
+			Self::Memory(listener) => listener.local_address(),
 		}
 	}
 
 	fn poll_if_addr(&mut self, cx: &mut Context<'_>) -> Poll<<Self as Stream>::Item> {
 		match self {
-			Self::WebTransport(listener) => listener.poll_if_addr(cx),
+			Self::WebTransport(listener) => listener
+				.poll_if_addr(cx)
+				.map(|event| event.map_connection(Connection::WebTransport)),
+			Self::Memory(listener) => listener
+				.poll_if_addr(cx)
+				.map(|event| event.map_connection(Connection::Memory)),
 		}
 	}
 }
 
 impl Stream for Listener {
-	type Item = TransportEvent;
+	type Item = TransportEvent<Connection>;
 
 	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
 		match self.get_mut() {
 			Self::WebTransport(listener) => {
 				let result = Pin::new(listener).poll_next(cx);
 				match result {
-					Poll::Ready(Some(event)) => Poll::Ready(Some(event)),
+					Poll::Ready(Some(event)) => Poll::Ready(Some(event.map_connection(Connection::WebTransport))),
+					Poll::Ready(None) => Poll::Ready(None),
+					Poll::Pending => Poll::Pending,
+				}
+			}
+			Self::Memory(listener) => {
+				let result = Pin::new(listener).poll_next(cx);
+				match result {
+					Poll::Ready(Some(event)) => Poll::Ready(Some(event.map_connection(Connection::Memory))),
 					Poll::Ready(None) => Poll::Ready(None),
 					Poll::Pending => Poll::Pending,
 				}
@@ -52,3 +76,9 @@ impl From<sf_wt_transport::Listener> for Listener {
 		Self::WebTransport(listener)
 	}
 }
+
+impl From<sf_memory_transport::Listener> for Listener {
+	fn from(listener: sf_memory_transport::Listener) -> Self {
+		Self::Memory(listener)
+	}
+}
@@ -0,0 +1,101 @@
+//! Agree on the application protocol spoken on a new stream.
+//!
+//! The opener proposes a protocol name, the acceptor echoes it back when it has a handler for it or answers
+//! [`NOT_AVAILABLE`] before closing the stream.
+
+use futures::{AsyncRead, AsyncWrite};
+
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+
+const NOT_AVAILABLE: &str = "na";
+const MAX_PROTOCOL_LEN: usize = 256;
+
+/// Propose `protocol` and wait for the remote to accept it.
+pub(crate) async fn select<S>(stream: &mut S, protocol: &str) -> Result<(), Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	if protocol.is_empty() || protocol.len() > MAX_PROTOCOL_LEN || protocol == NOT_AVAILABLE {
+		return Err(Error::InvalidProtocol(protocol.to_string()));
+	}
+
+	write_length_prefixed(stream, protocol).await?;
+	let answer = read_length_prefixed(stream, MAX_PROTOCOL_LEN).await?;
+	if answer == protocol.as_bytes() {
+		Ok(())
+	} else {
+		Err(Error::ProtocolNotSupported(protocol.to_string()))
+	}
+}
+
+/// Read the protocol proposed by the remote and confirm it when `supported`, returns `None` when it was rejected.
+pub(crate) async fn accept<S>(stream: &mut S, supported: impl FnOnce(&str) -> bool) -> Result<Option<String>, Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let proposal = read_length_prefixed(stream, MAX_PROTOCOL_LEN).await?;
+	let protocol = String::from_utf8(proposal).map_err(|e| Error::InvalidProtocol(e.to_string()))?;
+
+	if protocol != NOT_AVAILABLE && supported(&protocol) {
+		write_length_prefixed(stream, &protocol).await?;
+		Ok(Some(protocol))
+	} else {
+		write_length_prefixed(stream, NOT_AVAILABLE).await?;
+		Ok(None)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::{AsyncReadExt, AsyncWriteExt};
+	use tokio_util::compat::TokioAsyncReadCompatExt;
+
+	#[tokio::test]
+	async fn test_select_supported_protocol() {
+		let (a, b) = tokio::io::duplex(1024);
+		let (mut a, mut b) = (a.compat(), b.compat());
+
+		let (selected, accepted) = tokio::join!(
+			async {
+				select(&mut a, "/sf/echo/1.0.0").await?;
+				a.write_all(b"ping").await?;
+				Ok::<_, Error>(())
+			},
+			async {
+				let protocol = accept(&mut b, |protocol| protocol == "/sf/echo/1.0.0").await?;
+				let mut buf = [0u8; 4];
+				b.read_exact(&mut buf).await?;
+				Ok::<_, Error>((protocol, buf))
+			}
+		);
+
+		selected.unwrap();
+		assert_eq!(accepted.unwrap(), (Some("/sf/echo/1.0.0".to_string()), *b"ping"));
+	}
+
+	#[tokio::test]
+	async fn test_reject_unknown_protocol() {
+		let (a, b) = tokio::io::duplex(1024);
+		let (mut a, mut b) = (a.compat(), b.compat());
+
+		let (selected, accepted) = tokio::join!(select(&mut a, "/sf/unknown/1.0.0"), accept(&mut b, |_| false));
+
+		assert!(matches!(selected, Err(Error::ProtocolNotSupported(protocol)) if protocol == "/sf/unknown/1.0.0"));
+		assert_eq!(accepted.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn test_refuse_invalid_proposal() {
+		let (a, _b) = tokio::io::duplex(1024);
+		let mut a = a.compat();
+
+		assert!(matches!(select(&mut a, "").await, Err(Error::InvalidProtocol(_))));
+		assert!(matches!(
+			select(&mut a, NOT_AVAILABLE).await,
+			Err(Error::InvalidProtocol(_))
+		));
+	}
+}
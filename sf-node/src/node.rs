@@ -1,51 +1,721 @@
 use std::collections::HashMap;
-use std::pin::Pin;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::{Pin, pin};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use futures::stream::FusedStream;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, BoxFuture, Either, FutureExt, Shared};
+use futures::stream::{FusedStream, FuturesUnordered, StreamExt};
+use libp2p_identity::Keypair;
 use multiaddr::{Multiaddr, PeerId, Protocol as MultiaddrProtocol};
-use sf_core::{Protocol, Transport as TransportTrait, TransportEvent};
-use tracing::{error, info};
+use sf_core::{Connection as _, ListenerId, Protocol, Transport as TransportTrait, TransportEvent};
+use tracing::{debug, error, info, warn};
 
-use crate::connection::Connection;
+use crate::connection::{CLOSE_CODE_SHUTDOWN, Connection};
+use crate::connection_manager::{
+	CloseCause, ConnectionId, ConnectionLimits, ConnectionManager, Endpoint, Registration,
+};
 use crate::error::Error;
+use crate::handshake;
+use crate::hole_punch::{self, Punch};
+use crate::identify::{self, IdentifyInfo, LocalInfo, ObservedAddrs};
+use crate::peer_store::{MemoryBackend, PeerInfo, PeerStore, PeerStoreBackend, PeerStoreConfig};
+use crate::ping::{self, PingConfig};
+use crate::pubsub::{self, Message, MessageId, Pubsub, PubsubConfig, Validation};
+use crate::relay::{self, Relay, RelayConfig, Reservation};
+use crate::runtime;
+use crate::stream::Stream;
+use crate::stream_handler::StreamHandlers;
 use crate::transport::Transport;
 
+/// Dial upgrading a relayed connection, with the peer it reaches.
+type PunchDial = BoxFuture<'static, (PeerId, Result<Connection, Error>)>;
+/// Handshake of an accepted connection.
+type InboundUpgrade = BoxFuture<'static, Result<(PeerId, Connection), Error>>;
+
 pub struct Node {
 	pub peer_id: PeerId,
+	keypair: Keypair,
 	transports: HashMap<Protocol, Transport>,
+
+	connections: Arc<Mutex<ConnectionManager<Connection>>>,
+	peer_store: Arc<Mutex<PeerStore>>,
+	/// Addresses reported by the transports, advertised through identify.
+	listen_addrs: Arc<RwLock<Vec<Multiaddr>>>,
+	/// Addresses the connected peers see the node on, advertised when punching holes once confirmed.
+	observed_addrs: Arc<RwLock<ObservedAddrs>>,
+	handlers: StreamHandlers,
+	ping: PingConfig,
+	pubsub: Arc<Pubsub>,
+	/// Fires every [`PubsubConfig::with_heartbeat_interval`] to maintain the pubsub meshes.
+	pubsub_heartbeat: futures_timer::Delay,
+	/// Fires every [`PeerStoreConfig::with_save_interval`] to save the changes of the peer store.
+	peer_store_save: futures_timer::Delay,
+	peer_store_save_interval: Duration,
+	/// Transports to fall back on first when a dial fails, see [`Node::dial`].
+	transport_preference: Vec<Protocol>,
+	/// Time a single address has to connect and authenticate before the next one is dialed.
+	dial_timeout: Duration,
+	/// Time the remote of a new connection has to authenticate, in both directions.
+	handshake_timeout: Duration,
+	/// Relays the node holds a reservation with, see [`Node::reserve`].
+	reservations: relay::Reservations,
+	/// Circuits accepted through those relays, to authenticate.
+	relayed_rx: mpsc::UnboundedReceiver<relay::Inbound>,
+	/// Addresses to dial to upgrade a relayed connection, once synchronized with the remote.
+	punches_tx: mpsc::UnboundedSender<Punch>,
+	punches_rx: mpsc::UnboundedReceiver<Punch>,
+	/// Dials upgrading a relayed connection, with the peer they reach. Only reached through `&mut self`, the mutex
+	/// keeps the node `Sync`.
+	pending_punches: Mutex<FuturesUnordered<PunchDial>>,
+	/// Dials in flight by peer, resolving once they end, which concurrent dials of the same peer wait for.
+	pending_dials: Mutex<HashMap<PeerId, Shared<oneshot::Receiver<Infallible>>>>,
+	/// Accepted connections still running the handshake, behind a mutex for the same reason.
+	pending_inbound: Mutex<FuturesUnordered<InboundUpgrade>>,
+
+	/// Events raised outside of [`Node::poll_next_event`], by dials and background tasks.
+	events_tx: mpsc::UnboundedSender<Event>,
+	events_rx: mpsc::UnboundedReceiver<Event>,
+
+	state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	Running,
+	/// [`Node::shutdown`] is in progress.
+	ShuttingDown,
+	/// Every connection was closed, [`Event::Closed`] follows once their closure was reported.
+	ShutDown,
+	/// [`Event::Closed`] was emitted.
+	Closed,
+}
+
+/// Transports dialed first by default: the UDP ones, which need no extra round trip, then TCP for the networks
+/// blocking UDP.
+pub const DEFAULT_TRANSPORT_PREFERENCE: [Protocol; 4] = [
+	Protocol::WebTransport,
+	Protocol::WebRTC,
+	Protocol::Tcp,
+	Protocol::Memory,
+];
+
+/// Time a relayed connection replaced by a direct one stays open, for its streams to finish and for the remote to
+/// switch to the direct connection too.
+const UPGRADED_CLOSE_DELAY: Duration = Duration::from_secs(2);
+
+/// Time a single address has to connect and authenticate by default, see [`crate::Builder::with_dial_timeout`].
+pub const DEFAULT_DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time the remote of a new connection has to authenticate by default, see [`crate::Builder::with_handshake_timeout`].
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration of a [`Node`], assembled by the [`crate::Builder`].
+pub(crate) struct Config {
+	pub(crate) limits: ConnectionLimits,
+	pub(crate) ping: PingConfig,
+	pub(crate) pubsub: PubsubConfig,
+	pub(crate) agent_version: Option<String>,
+	pub(crate) peer_store: Option<Box<dyn PeerStoreBackend>>,
+	pub(crate) peer_store_config: PeerStoreConfig,
+	pub(crate) transport_preference: Vec<Protocol>,
+	pub(crate) dial_timeout: Duration,
+	pub(crate) handshake_timeout: Duration,
+	/// Relay circuits for the other peers when set.
+	pub(crate) relay: Option<RelayConfig>,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			limits: ConnectionLimits::default(),
+			ping: PingConfig::default(),
+			pubsub: PubsubConfig::default(),
+			agent_version: None,
+			peer_store: None,
+			peer_store_config: PeerStoreConfig::default(),
+			transport_preference: DEFAULT_TRANSPORT_PREFERENCE.to_vec(),
+			dial_timeout: DEFAULT_DIAL_TIMEOUT,
+			handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+			relay: None,
+		}
+	}
 }
 
 #[derive(Debug)]
 pub enum Event {
-	NewConnection,
+	/// A remote peer dialed one of our listeners and proved its identity.
+	NewConnection { connection: Connection },
+
+	NewListenAddr {
+		listener_id: ListenerId,
+		address: Multiaddr,
+	},
+
+	/// A listener stopped listening on `address`, for instance because the network interface went down.
+	ExpiredListenAddr {
+		listener_id: ListenerId,
+		address: Multiaddr,
+	},
+
+	/// A listener stopped, after its addresses expired.
+	ListenerClosed { listener_id: ListenerId },
+
+	/// A connection with a peer joined the pool, either dialed or accepted.
+	ConnectionEstablished {
+		peer_id: PeerId,
+		connection_id: ConnectionId,
+		endpoint: Endpoint,
+	},
 
-	NewListenAddr { address: Multiaddr },
+	/// A connection left the pool.
+	ConnectionClosed {
+		peer_id: PeerId,
+		connection_id: ConnectionId,
+		cause: CloseCause,
+	},
+
+	/// A connected peer advertised its information, which was added to the peer store.
+	Identified { peer_id: PeerId, info: IdentifyInfo },
+
+	/// A ping to a connected peer was answered.
+	Ping { peer: PeerId, rtt: Duration },
+
+	/// A message published on a subscribed topic passed validation, `propagation_source` forwarded it.
+	Message {
+		propagation_source: PeerId,
+		id: MessageId,
+		message: Message,
+	},
+
+	/// A connected peer subscribed to `topic`.
+	Subscribed { peer_id: PeerId, topic: String },
+
+	/// A connected peer unsubscribed from `topic`.
+	Unsubscribed { peer_id: PeerId, topic: String },
+
+	/// A relayed connection to `peer_id` was replaced by a direct connection on `address`.
+	HolePunchSucceeded { peer_id: PeerId, address: Multiaddr },
+
+	/// A relayed connection to `peer_id` could not be upgraded to a direct one, it stays in use.
+	HolePunchFailed { peer_id: PeerId, error: Error },
+
+	/// The node shut down, no event follows.
+	Closed,
+}
+
+/// A dial registered in [`Node::pending_dials`] until dropped.
+struct PendingDial<'a> {
+	pending_dials: &'a Mutex<HashMap<PeerId, Shared<oneshot::Receiver<Infallible>>>>,
+	peer_id: PeerId,
+	/// Wakes the dials waiting for this one once dropped.
+	_done: oneshot::Sender<Infallible>,
+}
+
+impl<'a> PendingDial<'a> {
+	/// Register a dial of `peer_id`, or return the one in flight.
+	fn start(
+		pending_dials: &'a Mutex<HashMap<PeerId, Shared<oneshot::Receiver<Infallible>>>>,
+		peer_id: PeerId,
+	) -> Result<Self, Shared<oneshot::Receiver<Infallible>>> {
+		let mut pending = pending_dials.lock().unwrap_or_else(PoisonError::into_inner);
+		if let Some(in_flight) = pending.get(&peer_id) {
+			return Err(in_flight.clone());
+		}
+		let (done, in_flight) = oneshot::channel();
+		pending.insert(peer_id, in_flight.shared());
+		Ok(Self {
+			pending_dials,
+			peer_id,
+			_done: done,
+		})
+	}
+}
+
+impl Drop for PendingDial<'_> {
+	fn drop(&mut self) {
+		self.pending_dials
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&self.peer_id);
+	}
+}
+
+/// Result of adding an authenticated connection to the pool.
+enum Established {
+	New(Connection),
+	/// The pool already holds a connection to that peer which must be used instead.
+	Existing(Connection),
 }
 
 impl Node {
-	pub fn new(peer_id: PeerId, transports: HashMap<Protocol, Transport>) -> Self {
-		Self { peer_id, transports }
+	pub(crate) fn new(keypair: Keypair, transports: HashMap<Protocol, Transport>, config: Config) -> Self {
+		let peer_id = keypair.public().to_peer_id();
+		let (events_tx, events_rx) = mpsc::unbounded();
+		let handlers = StreamHandlers::default();
+		let closing = handlers.closing();
+		handlers.insert(ping::PROTOCOL.to_owned(), {
+			let closing = closing.clone();
+			move |peer_id, stream| ping::handle(closing.clone(), peer_id, stream)
+		});
+
+		let connections = Arc::new(Mutex::new(ConnectionManager::new(peer_id, config.limits)));
+		let listen_addrs = Arc::default();
+		let observed_addrs: Arc<RwLock<ObservedAddrs>> = Arc::default();
+		let local = LocalInfo {
+			peer_id,
+			agent_version: config
+				.agent_version
+				.unwrap_or_else(|| identify::DEFAULT_AGENT_VERSION.to_owned()),
+			listen_addrs: Arc::clone(&listen_addrs),
+			handlers: handlers.downgrade(),
+			connections: Arc::clone(&connections),
+		};
+		handlers.insert(identify::PROTOCOL.to_owned(), move |peer_id, stream| {
+			identify::handle(local.clone(), peer_id, stream)
+		});
+
+		let pubsub = Arc::new(Pubsub::new(keypair.clone(), config.pubsub, events_tx.clone()));
+		handlers.insert(pubsub::PROTOCOL.to_owned(), {
+			let pubsub = Arc::clone(&pubsub);
+			let closing = closing.clone();
+			move |peer_id, stream| pubsub::handle(Arc::clone(&pubsub), closing.clone(), peer_id, stream)
+		});
+
+		let reservations = relay::Reservations::default();
+		let (relayed_tx, relayed_rx) = mpsc::unbounded();
+		handlers.insert(relay::STOP_PROTOCOL.to_owned(), {
+			let reservations = Arc::clone(&reservations);
+			move |relay, stream| relay::handle_stop(Arc::clone(&reservations), relayed_tx.clone(), relay, stream)
+		});
+
+		let (punches_tx, punches_rx) = mpsc::unbounded();
+		let local_addrs = hole_punch::LocalAddrs {
+			listen: Arc::clone(&listen_addrs),
+			observed: Arc::clone(&observed_addrs),
+		};
+		handlers.insert(hole_punch::PROTOCOL.to_owned(), {
+			let punches_tx = punches_tx.clone();
+			let events_tx = events_tx.clone();
+			move |peer_id, stream| {
+				hole_punch::handle(
+					local_addrs.clone(),
+					punches_tx.clone(),
+					events_tx.clone(),
+					peer_id,
+					stream,
+				)
+			}
+		});
+
+		if let Some(config) = config.relay {
+			let relay = Arc::new(Relay::new(config));
+			let connections = Arc::clone(&connections);
+			handlers.insert(relay::HOP_PROTOCOL.to_owned(), move |peer_id, stream| {
+				relay::handle_hop(
+					Arc::clone(&relay),
+					Arc::clone(&connections),
+					closing.clone(),
+					peer_id,
+					stream,
+				)
+			});
+		}
+
+		Self {
+			peer_id,
+			keypair,
+			transports,
+			connections,
+			peer_store_save: futures_timer::Delay::new(config.peer_store_config.save_interval()),
+			peer_store_save_interval: config.peer_store_config.save_interval(),
+			peer_store: Arc::new(Mutex::new(PeerStore::new(
+				config.peer_store.unwrap_or_else(|| Box::new(MemoryBackend)),
+				config.peer_store_config,
+				SystemTime::now(),
+			))),
+			listen_addrs,
+			observed_addrs,
+			handlers,
+			ping: config.ping,
+			pubsub,
+			pubsub_heartbeat: futures_timer::Delay::new(config.pubsub.heartbeat_interval()),
+			transport_preference: config.transport_preference,
+			dial_timeout: config.dial_timeout,
+			handshake_timeout: config.handshake_timeout,
+			reservations,
+			relayed_rx,
+			punches_tx,
+			punches_rx,
+			pending_punches: Mutex::default(),
+			pending_dials: Mutex::default(),
+			pending_inbound: Mutex::default(),
+			events_tx,
+			events_rx,
+			state: State::Running,
+		}
 	}
 
+	/// Dial `address` and authenticate the remote, which must be `remote_peer_id` and match the `/p2p/` component of
+	/// the address if any.
+	///
+	/// A live connection to `remote_peer_id` is reused instead of dialing again, and a dial of `remote_peer_id`
+	/// already in flight is waited for. The outcome is recorded in the peer store, an address which failed recently is
+	/// not dialed again until its backoff elapsed.
+	///
+	/// When `address` fails, the other addresses of `remote_peer_id` in the peer store are dialed in the order of
+	/// [`crate::Builder::with_transport_preference`], skipping those no transport handles. Each address is given
+	/// [`crate::Builder::with_dial_timeout`] before the next one is dialed. The error of `address` is returned if they
+	/// all fail.
 	pub async fn dial(&self, remote_peer_id: PeerId, address: Multiaddr) -> Result<Connection, Error> {
+		let _pending = loop {
+			if let Some(connection) = self.connection(&remote_peer_id) {
+				debug!(peer_id = %self.peer_id, %remote_peer_id, "Reusing connection");
+				return Ok(connection);
+			}
+			match PendingDial::start(&self.pending_dials, remote_peer_id) {
+				Ok(pending) => break pending,
+				// Dial ourselves if it failed, `address` may differ.
+				Err(in_flight) => {
+					debug!(peer_id = %self.peer_id, %remote_peer_id, "Waiting for the dial in flight");
+					let _ = in_flight.await;
+				}
+			}
+		};
+
+		let fallbacks = self.fallback_addresses(&remote_peer_id, &address);
+		self.dial_in_order(remote_peer_id, address, fallbacks).await
+	}
+
+	/// Dial `address`, then each of `fallbacks` until one succeeds, returns the error of `address` if none does.
+	async fn dial_in_order(
+		&self,
+		remote_peer_id: PeerId,
+		address: Multiaddr,
+		fallbacks: Vec<Multiaddr>,
+	) -> Result<Connection, Error> {
+		let error = match self.dial_address(remote_peer_id, address.clone()).await {
+			Ok(connection) => return Ok(connection),
+			Err(error @ (Error::Shutdown | Error::ConnectionLimit)) => return Err(error),
+			Err(error) => error,
+		};
+
+		for fallback in fallbacks {
+			debug!(peer_id = %self.peer_id, %remote_peer_id, %address, %fallback, "Falling back");
+			match self.dial_address(remote_peer_id, fallback).await {
+				Ok(connection) => return Ok(connection),
+				Err(error @ (Error::Shutdown | Error::ConnectionLimit)) => return Err(error),
+				Err(_) => {}
+			}
+		}
+		Err(error)
+	}
+
+	/// Dial `address` alone, recording the outcome in the peer store.
+	async fn dial_address(&self, remote_peer_id: PeerId, address: Multiaddr) -> Result<Connection, Error> {
+		if self.state != State::Running {
+			return Err(Error::Shutdown);
+		}
+		if self.connections().is_full() {
+			return Err(Error::ConnectionLimit);
+		}
+		if let Some(retry_after) = self
+			.peer_store()
+			.retry_after(&remote_peer_id, &address, SystemTime::now())
+		{
+			debug!(peer_id = %self.peer_id, %remote_peer_id, %address, ?retry_after, "Address is backing off");
+			return Err(Error::DialBackoff(address));
+		}
+
+		let result = runtime::timeout(self.dial_timeout, self.connect(remote_peer_id, address.clone()))
+			.await
+			.and_then(|result| result);
+		match &result {
+			Ok(_) => self
+				.peer_store()
+				.dial_succeeded(remote_peer_id, address, SystemTime::now()),
+			Err(Error::ConnectionLimit) => {}
+			Err(_) => self
+				.peer_store()
+				.dial_failed(&remote_peer_id, &address, SystemTime::now()),
+		}
+		result
+	}
+
+	/// Known addresses of `peer_id` other than `dialed` which the node can dial, most preferred first.
+	fn fallback_addresses(&self, peer_id: &PeerId, dialed: &Multiaddr) -> Vec<Multiaddr> {
+		let mut addresses: Vec<_> = self
+			.peer_store()
+			.addresses(peer_id, SystemTime::now())
+			.into_iter()
+			.filter(|address| address != dialed)
+			.filter_map(|address| Some((self.address_rank(&address)?, address)))
+			.collect();
+		addresses.sort_by_key(|(rank, _)| *rank);
+		addresses.into_iter().map(|(_, address)| address).collect()
+	}
+
+	/// Position of the transport of `address` in the transport preference, `None` when no transport dials it.
+	///
+	/// Transports left out of the preference come after the others, then circuits through a relay.
+	fn address_rank(&self, address: &Multiaddr) -> Option<usize> {
+		let last = self.transport_preference.len();
+		if relay::is_circuit(address) {
+			return Some(last + 1);
+		}
+		let protocol = extract_protocol_from_multiaddr(address)
+			.ok()
+			.filter(|protocol| self.transports.contains_key(protocol))?;
+		Some(
+			self.transport_preference
+				.iter()
+				.position(|preferred| *preferred == protocol)
+				.unwrap_or(last),
+		)
+	}
+
+	async fn connect(&self, remote_peer_id: PeerId, address: Multiaddr) -> Result<Connection, Error> {
 		info!(peer_id = %self.peer_id, %remote_peer_id, %address, "Attempting to dial");
 
-		let protocol = extract_protocol_from_multiaddr(&address)?;
+		let dialed = if relay::is_circuit(&address) {
+			self.dial_circuit(remote_peer_id, &address).await
+		} else {
+			let protocol = extract_protocol_from_multiaddr(&address)?;
 
-		let transport = self.transports.get(&protocol).ok_or_else(|| {
-			error!(peer_id = %self.peer_id, %remote_peer_id, %address, ?protocol, "Transport not found for protocol");
-			Error::TransportNotFound(protocol)
-		})?;
+			let transport = self.transports.get(&protocol).ok_or_else(|| {
+				error!(peer_id = %self.peer_id, %remote_peer_id, %address, ?protocol, "Transport not found for protocol");
+				Error::TransportNotFound(protocol)
+			})?;
 
-		let dial = { transport.dial(remote_peer_id, address.clone()) };
+			transport.dial(remote_peer_id, address.clone()).await
+		};
 
-		dial.await.inspect_err(|e| {
+		let mut connection = dialed.inspect_err(|e| {
 			error!(peer_id = %self.peer_id, %remote_peer_id, %address, ?e, "Failed to dial");
+		})?;
+
+		let authenticated = runtime::timeout(
+			self.handshake_timeout,
+			handshake::outbound(&mut connection, &self.keypair),
+		)
+		.await
+		.and_then(|authenticated| authenticated)
+		.inspect_err(|e| {
+			error!(peer_id = %self.peer_id, %remote_peer_id, %address, ?e, "Handshake failed");
+		})?;
+
+		let expected = extract_peer_id_from_multiaddr(&address)
+			.into_iter()
+			.chain([remote_peer_id]);
+		for expected in expected {
+			if expected != authenticated {
+				error!(peer_id = %self.peer_id, %expected, %authenticated, %address, "Dialed the wrong peer");
+				return Err(Error::PeerIdMismatch {
+					expected: Box::new(expected),
+					actual: Box::new(authenticated),
+				});
+			}
+		}
+
+		connection.set_remote_peer_id(authenticated);
+		match self.establish(authenticated, connection, Endpoint::Dialer)? {
+			Established::New(connection) | Established::Existing(connection) => Ok(connection),
+		}
+	}
+
+	/// Open a circuit to `remote_peer_id` through the relay of the circuit `address`, dialing the relay if needed.
+	///
+	/// Boxed as it dials the relay, which is how [`Node::connect`] got here.
+	fn dial_circuit<'a>(
+		&'a self,
+		remote_peer_id: PeerId,
+		address: &'a Multiaddr,
+	) -> BoxFuture<'a, Result<Connection, Error>> {
+		Box::pin(async move {
+			let (relay_address, relay) = relay::parse_circuit_address(address)?;
+			if relay == remote_peer_id || relay == self.peer_id {
+				return Err(Error::InvalidRelayAddress(address.clone()));
+			}
+
+			let mut relay_connection = match self.connection(&relay) {
+				Some(connection) => connection,
+				None => {
+					// Not through another circuit, which could lead back to this one.
+					let mut addresses: Vec<_> = self
+						.fallback_addresses(&relay, &relay_address)
+						.into_iter()
+						.filter(|address| !relay::is_circuit(address))
+						.collect();
+					let first = if relay_address.iter().count() > 1 {
+						relay_address
+					} else if !addresses.is_empty() {
+						addresses.remove(0)
+					} else {
+						return Err(Error::NoKnownAddress(relay));
+					};
+					self.dial_in_order(relay, first, addresses).await?
+				}
+			};
+
+			let stream = relay::connect(&mut relay_connection, remote_peer_id).await?;
+			Ok(Connection::Relayed(relay::Circuit::new(
+				stream,
+				yamux::Mode::Client,
+				address.clone(),
+			)))
+		})
+	}
+
+	/// Dial `peer_id` on the unexpired addresses known from the peer store, in the order of the transport preference
+	/// then in the order they were learned.
+	///
+	/// A live connection to `peer_id` is reused instead of dialing again.
+	pub async fn dial_peer(&self, peer_id: PeerId) -> Result<Connection, Error> {
+		if let Some(connection) = self.connection(&peer_id) {
+			return Ok(connection);
+		}
+
+		let mut addresses = self.peer_store().addresses(&peer_id, SystemTime::now());
+		addresses.sort_by_key(|address| self.address_rank(address).unwrap_or(usize::MAX));
+		// The other addresses are dialed as fallbacks of the first one.
+		let address = addresses.into_iter().next().ok_or(Error::NoKnownAddress(peer_id))?;
+		self.dial(peer_id, address).await
+	}
+
+	/// Reserve a slot on `relay`, dialing it if needed, so that the peers can reach the local node through it on
+	/// [`Reservation::address`].
+	///
+	/// The reservation lasts [`Reservation::expires_in`] and is renewed in the background before then, for as long as
+	/// the connection to the relay stays open. Once it closes, the relay drops the reservation and `reserve` must be
+	/// called again.
+	pub async fn reserve(&self, relay: PeerId) -> Result<Reservation, Error> {
+		let mut connection = self.dial_peer(relay).await?;
+		let (expires_in, limit) = relay::reserve(&mut connection).await?;
+		let expires = Instant::now() + expires_in;
+		self.reservations
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(relay, expires);
+		info!(peer_id = %self.peer_id, %relay, ?expires_in, "Reserved a relay slot");
+		runtime::spawn(relay::keep_reserved(
+			Arc::clone(&self.reservations),
+			relay,
+			connection,
+			expires,
+		));
+
+		Ok(Reservation {
+			relay,
+			address: relay::circuit_address(relay, self.peer_id),
+			expires_in,
+			limit,
 		})
 	}
 
-	pub async fn listen(&mut self, address: Multiaddr) -> Result<(), Error> {
+	/// Add an address `peer_id` can be dialed on to the peer store.
+	pub fn add_address(&self, peer_id: PeerId, address: Multiaddr) {
+		self.peer_store().add_address(peer_id, address, SystemTime::now());
+	}
+
+	/// Peers with at least one address in the peer store, including those saved by a previous run.
+	pub fn known_peers(&self) -> Vec<PeerId> {
+		self.peer_store().peers().copied().collect()
+	}
+
+	/// What the peer store knows about `peer_id`.
+	pub fn peer_info(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+		self.peer_store().get(peer_id).cloned()
+	}
+
+	/// Addresses the node is listening on, as reported by the transports.
+	pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+		self.listen_addrs.read().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+
+	/// Addresses the connected peers reported seeing the direct connections of the node come from, most recent first.
+	///
+	/// Behind a NAT, these are the public addresses of the node rather than the ones it listens on. An address is only
+	/// returned once several distinct peers reported it recently, a single peer could report anything.
+	pub fn observed_addrs(&self) -> Vec<Multiaddr> {
+		self.observed_addrs
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.confirmed(Instant::now())
+	}
+
+	/// Handle inbound streams negotiating `protocol`, replacing the previous handler if any.
+	///
+	/// The node accepts the inbound streams of every established connection and dispatches them to these handlers,
+	/// streams proposing a protocol without handler are rejected. This is the way to receive streams on the
+	/// connections of the node, see [`Connection`].
+	pub fn set_stream_handler<F, Fut>(&self, protocol: impl Into<String>, handler: F)
+	where
+		F: Fn(PeerId, Stream) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = ()> + Send + 'static,
+	{
+		self.handlers.insert(protocol.into(), handler);
+	}
+
+	/// Stop handling inbound streams negotiating `protocol`, returns whether a handler was registered.
+	pub fn remove_stream_handler(&self, protocol: &str) -> bool {
+		self.handlers.remove(protocol)
+	}
+
+	/// Receive the messages published on `topic`, as [`Event::Message`]. Returns whether the node was not subscribed
+	/// yet.
+	pub fn subscribe(&self, topic: impl Into<String>) -> bool {
+		self.pubsub.subscribe(topic.into())
+	}
+
+	/// Stop receiving the messages published on `topic`, returns whether the node was subscribed.
+	pub fn unsubscribe(&self, topic: &str) -> bool {
+		self.pubsub.unsubscribe(topic)
+	}
+
+	/// Sign `data` with the node keypair and publish it on `topic`, to every connected peer subscribed to it.
+	///
+	/// Fails when no connected peer is subscribed to `topic`.
+	pub fn publish(&self, topic: impl Into<String>, data: impl Into<Vec<u8>>) -> Result<MessageId, Error> {
+		self.pubsub.publish(topic.into(), data.into())
+	}
+
+	/// Check the messages received on `topic` before they are delivered and forwarded, replacing the previous
+	/// validator if any. The validator is given the peer which forwarded the message.
+	pub fn set_topic_validator<F>(&self, topic: impl Into<String>, validator: F)
+	where
+		F: Fn(&PeerId, &Message) -> Validation + Send + Sync + 'static,
+	{
+		self.pubsub.set_validator(topic.into(), Arc::new(validator));
+	}
+
+	/// Accept every message received on `topic` again, returns whether a validator was set.
+	pub fn remove_topic_validator(&self, topic: &str) -> bool {
+		self.pubsub.remove_validator(topic)
+	}
+
+	/// Peers the messages of `topic` are forwarded to, empty when not subscribed to it.
+	pub fn mesh_peers(&self, topic: &str) -> Vec<PeerId> {
+		self.pubsub.mesh_peers(topic)
+	}
+
+	/// A live connection to `peer_id`, if any.
+	pub fn connection(&self, peer_id: &PeerId) -> Option<Connection> {
+		self.connections().connection(peer_id)
+	}
+
+	/// Peers with at least one live connection.
+	pub fn connected_peers(&self) -> Vec<PeerId> {
+		self.connections().connected_peers().copied().collect()
+	}
+
+	/// Start listening on `address`, next to the listeners already running.
+	pub async fn listen(&mut self, address: Multiaddr) -> Result<ListenerId, Error> {
+		if self.state != State::Running {
+			return Err(Error::Shutdown);
+		}
 		let protocol = extract_protocol_from_multiaddr(&address)?;
 
 		let transport = self.transports.get_mut(&protocol).ok_or_else(|| {
@@ -55,69 +725,878 @@ impl Node {
 
 		transport.listen_on(address.clone()).inspect_err(|e| {
 			error!(peer_id = %self.peer_id, %address, ?e, "Failed to listen");
-		})?;
+		})
+	}
+
+	/// Stop the listener `id`, returns whether it was running.
+	///
+	/// Its addresses are reported as expired, followed by [`Event::ListenerClosed`].
+	pub fn remove_listener(&mut self, id: ListenerId) -> bool {
+		self.transports
+			.values_mut()
+			.any(|transport| transport.remove_listener(id))
+	}
+
+	/// Gracefully shut the node down.
+	///
+	/// The transports stop listening and inbound streams are not accepted anymore. The long-lived streams of the
+	/// node's own protocols, such as ping and pubsub, are ended right away while the others being handled are given
+	/// up to `deadline` to finish, then every connection is closed with [`CLOSE_CODE_SHUTDOWN`]. The connections whose
+	/// remote did not acknowledge the closure by `deadline` are dropped. The node then reports the closed connections
+	/// followed by [`Event::Closed`], and ends.
+	pub async fn shutdown(&mut self, deadline: Duration) {
+		if self.state != State::Running {
+			return;
+		}
+		self.state = State::ShuttingDown;
+		info!(peer_id = %self.peer_id, "Shutting down");
+
+		for transport in self.transports.values_mut() {
+			transport.shutdown();
+		}
+		self.pending_inbound
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clear();
+		self.pending_punches
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clear();
+		self.listen_addrs
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clear();
+
+		let expires = Instant::now() + deadline;
+		if runtime::timeout(deadline, self.handlers.close()).await.is_err() {
+			warn!(peer_id = %self.peer_id, "Streams still in flight at the shutdown deadline");
+		}
+
+		let connections = self.connections().close_all(|| CloseCause::Shutdown);
+		let closed = future::join_all(connections.into_iter().map(|mut connection| {
+			let closed = connection.closed();
+			let close = connection.close_with_reason(CLOSE_CODE_SHUTDOWN, "Shutting down");
+			async move {
+				if let Err(error) = close.await {
+					debug!(?error, "Failed to close connection");
+				}
+				closed.await;
+			}
+		}));
+		let remaining = expires.saturating_duration_since(Instant::now());
+		if runtime::timeout(remaining, closed).await.is_err() {
+			warn!(peer_id = %self.peer_id, "Connections still closing at the shutdown deadline, dropping them");
+			// Reported while holding the lock, as when they close.
+			let mut connections = self.connections();
+			for (peer_id, connection_id, cause) in connections.remove_all(|| CloseCause::Shutdown) {
+				let _ = self.events_tx.unbounded_send(Event::ConnectionClosed {
+					peer_id,
+					connection_id,
+					cause,
+				});
+			}
+		}
 
-		Ok(())
+		self.state = State::ShutDown;
 	}
 
-	fn poll_next_event(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Event> {
+	fn connections(&self) -> MutexGuard<'_, ConnectionManager<Connection>> {
+		self.connections.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn peer_store(&self) -> MutexGuard<'_, PeerStore> {
+		self.peer_store.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Write the changes of the peer store, if any, on a thread of its own.
+	fn save_peer_store(&self) {
+		if let Some(save) = self.peer_store().save() {
+			runtime::spawn_blocking(move || save.run());
+		}
+	}
+
+	/// Add an authenticated connection to the pool and watch for its closure.
+	fn establish(&self, peer_id: PeerId, connection: Connection, endpoint: Endpoint) -> Result<Established, Error> {
+		let relayed = connection.is_relayed();
+		let registration = self
+			.connections()
+			.register(peer_id, connection.clone(), endpoint, relayed);
+		match registration {
+			Registration::Established { id, replaced } => {
+				for replaced in replaced {
+					if replaced.is_relayed() && !relayed {
+						debug!(peer_id = %self.peer_id, remote_peer_id = %peer_id, "Closing upgraded relayed connection");
+						runtime::spawn(async move {
+							runtime::sleep(UPGRADED_CLOSE_DELAY).await;
+							close(replaced);
+						});
+					} else {
+						debug!(peer_id = %self.peer_id, remote_peer_id = %peer_id, "Closing duplicate connection");
+						close(replaced);
+					}
+				}
+				self.peer_store().seen(peer_id, SystemTime::now());
+				self.watch(peer_id, id, &connection);
+				self.keep_alive(peer_id, id, &connection);
+				self.identify(peer_id, &connection);
+				runtime::spawn(self.handlers.clone().accept_streams(peer_id, connection.clone()));
+				runtime::spawn(pubsub::run(Arc::clone(&self.pubsub), peer_id, id, connection.clone()));
+				let _ = self.events_tx.unbounded_send(Event::ConnectionEstablished {
+					peer_id,
+					connection_id: id,
+					endpoint,
+				});
+				if relayed && endpoint == Endpoint::Listener {
+					self.hole_punch(peer_id, &connection);
+				}
+				Ok(Established::New(connection))
+			}
+			Registration::Duplicate { existing } => {
+				debug!(peer_id = %self.peer_id, remote_peer_id = %peer_id, "Closing duplicate connection");
+				close(connection);
+				Ok(Established::Existing(existing))
+			}
+			Registration::LimitReached { existing } => {
+				warn!(peer_id = %self.peer_id, remote_peer_id = %peer_id, ?endpoint, "Connection limit reached");
+				close(connection);
+				existing.map(Established::Existing).ok_or(Error::ConnectionLimit)
+			}
+		}
+	}
+
+	/// Remove the connection from the pool once closed and report it.
+	fn watch(&self, peer_id: PeerId, connection_id: ConnectionId, connection: &Connection) {
+		let closed = connection.closed();
+		let connections = Arc::clone(&self.connections);
+		let peer_store = Arc::clone(&self.peer_store);
+		let events_tx = self.events_tx.clone();
+
+		runtime::spawn(async move {
+			let error = closed.await;
+			peer_store
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.seen(peer_id, SystemTime::now());
+			let mut connections = connections.lock().unwrap_or_else(PoisonError::into_inner);
+			// Dropped at the shutdown deadline, which reported it already.
+			if !connections.contains(&peer_id, connection_id) {
+				return;
+			}
+			let cause = connections
+				.remove(&peer_id, connection_id)
+				.unwrap_or(CloseCause::Transport(error));
+			// Report while holding the lock, an empty pool then means every closure was reported.
+			let _ = events_tx.unbounded_send(Event::ConnectionClosed {
+				peer_id,
+				connection_id,
+				cause,
+			});
+		});
+	}
+
+	/// Ask the remote to identify itself and record the answer in the peer store.
+	fn identify(&self, peer_id: PeerId, connection: &Connection) {
+		let request = identify::request(peer_id, connection.clone());
+		let peer_store = Arc::clone(&self.peer_store);
+		let observed_addrs = Arc::clone(&self.observed_addrs);
+		let events_tx = self.events_tx.clone();
+
+		runtime::spawn(async move {
+			match request.await {
+				Ok(info) => {
+					if let Some(address) = info.observed_addr.clone() {
+						observed_addrs.write().unwrap_or_else(PoisonError::into_inner).record(
+							peer_id,
+							address,
+							Instant::now(),
+						);
+					}
+					peer_store
+						.lock()
+						.unwrap_or_else(PoisonError::into_inner)
+						.identified(&info, SystemTime::now());
+					let _ = events_tx.unbounded_send(Event::Identified { peer_id, info });
+				}
+				Err(error) => debug!(%peer_id, ?error, "Failed to identify peer"),
+			}
+		});
+	}
+
+	/// Ping the remote until the connection closes, closing it ourselves once the remote stops answering.
+	fn keep_alive(&self, peer_id: PeerId, connection_id: ConnectionId, connection: &Connection) {
+		let closed = connection.closed();
+		let connections = Arc::clone(&self.connections);
+		let peer_store = Arc::clone(&self.peer_store);
+		let events_tx = self.events_tx.clone();
+		let ping = ping::run(peer_id, connection.clone(), self.ping, move |rtt| {
+			peer_store
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.record_rtt(&peer_id, rtt);
+			let _ = events_tx.unbounded_send(Event::Ping { peer: peer_id, rtt });
+		});
+
+		runtime::spawn(async move {
+			if let Either::Right(((), _)) = future::select(closed, pin!(ping)).await {
+				let connection = connections.lock().unwrap_or_else(PoisonError::into_inner).close(
+					&peer_id,
+					connection_id,
+					CloseCause::KeepAliveTimeout,
+				);
+				if let Some(connection) = connection {
+					warn!(%peer_id, "Peer stopped answering pings, closing connection");
+					close(connection);
+				}
+			}
+		});
+	}
+
+	/// Try to upgrade the relayed connection accepted from `peer_id` to a direct one.
+	fn hole_punch(&self, peer_id: PeerId, connection: &Connection) {
+		let local_addrs = hole_punch::LocalAddrs {
+			listen: Arc::clone(&self.listen_addrs),
+			observed: Arc::clone(&self.observed_addrs),
+		};
+		let initiate = hole_punch::initiate(local_addrs, peer_id, connection.clone(), self.punches_tx.clone());
+		let events_tx = self.events_tx.clone();
+
+		runtime::spawn(async move {
+			if let Err(error) = initiate.await {
+				debug!(%peer_id, ?error, "Failed to synchronize hole punch");
+				let _ = events_tx.unbounded_send(Event::HolePunchFailed { peer_id, error });
+			}
+		});
+	}
+
+	/// Dial every address of `punch` at once through the QUIC transport, keeping the first to authenticate the peer.
+	fn punch(&self, punch: Punch) -> PunchDial {
+		let Punch { peer, addresses } = punch;
+		let Some(transport) = self.transports.get(&Protocol::WebTransport) else {
+			return Box::pin(future::ready((
+				peer,
+				Err(Error::TransportNotFound(Protocol::WebTransport)),
+			)));
+		};
+		if addresses.is_empty() {
+			return Box::pin(future::ready((peer, Err(Error::NoDirectAddress(peer)))));
+		}
+
+		let dials = addresses.into_iter().map(|address| {
+			info!(peer_id = %self.peer_id, remote_peer_id = %peer, %address, "Punching a hole");
+			let dial = transport.dial(peer, address);
+			let keypair = self.keypair.clone();
+			Box::pin(async move {
+				let mut connection = dial.await?;
+				let authenticated = handshake::outbound(&mut connection, &keypair).await?;
+				if authenticated != peer {
+					return Err(Error::PeerIdMismatch {
+						expected: Box::new(peer),
+						actual: Box::new(authenticated),
+					});
+				}
+				connection.set_remote_peer_id(authenticated);
+				Ok(connection)
+			})
+		});
+		let dials = future::select_ok(dials);
+
+		Box::pin(async move {
+			let result = match runtime::timeout(hole_punch::DIAL_TIMEOUT, dials).await {
+				Ok(Ok((connection, _))) => Ok(connection),
+				Ok(Err(error)) | Err(error) => Err(error),
+			};
+			(peer, result)
+		})
+	}
+
+	fn poll_next_event(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
 		let this = &mut *self;
 
-		'outer: loop {
+		loop {
+			if let Poll::Ready(Some(event)) = this.events_rx.poll_next_unpin(cx) {
+				return Poll::Ready(Some(event));
+			}
+
+			match this.state {
+				State::Running => {}
+				State::ShuttingDown => return Poll::Pending,
+				State::ShutDown => {
+					if !this.connections().is_empty() {
+						return Poll::Pending;
+					}
+					if let Poll::Ready(Some(event)) = this.events_rx.poll_next_unpin(cx) {
+						return Poll::Ready(Some(event));
+					}
+					this.save_peer_store();
+					this.state = State::Closed;
+					return Poll::Ready(Some(Event::Closed));
+				}
+				State::Closed => return Poll::Ready(None),
+			}
+
+			if this.pubsub_heartbeat.poll_unpin(cx).is_ready() {
+				this.pubsub.heartbeat();
+				this.pubsub_heartbeat.reset(this.pubsub.heartbeat_interval());
+				continue;
+			}
+
+			if this.peer_store_save.poll_unpin(cx).is_ready() {
+				this.save_peer_store();
+				this.peer_store_save.reset(this.peer_store_save_interval);
+				continue;
+			}
+
+			let mut progress = false;
+			let limits = *this.connections().limits();
+			loop {
+				let pending_inbound = this.pending_inbound.get_mut().unwrap_or_else(PoisonError::into_inner);
+				let Poll::Ready(Some(result)) = pending_inbound.poll_next_unpin(cx) else {
+					break;
+				};
+				match result.and_then(|(peer_id, connection)| this.establish(peer_id, connection, Endpoint::Listener)) {
+					Ok(Established::New(connection)) => {
+						let _ = this.events_tx.unbounded_send(Event::NewConnection { connection });
+						progress = true;
+					}
+					Ok(Established::Existing(_)) => {}
+					Err(error) => warn!(peer_id = %this.peer_id, ?error, "Failed to accept connection"),
+				}
+			}
+
+			while let Poll::Ready(Some(inbound)) = this.relayed_rx.poll_next_unpin(cx) {
+				let address = inbound.remote_address();
+				let pending = this
+					.pending_inbound
+					.get_mut()
+					.unwrap_or_else(PoisonError::into_inner)
+					.len();
+				if !limits.allows_pending_inbound(pending) {
+					warn!(peer_id = %this.peer_id, %address, pending, "Too many pending inbound connections");
+					continue;
+				}
+
+				info!(peer_id = %this.peer_id, %address, "Accepted relayed connection");
+				let connection = Connection::Relayed(relay::Circuit::new(inbound.stream, yamux::Mode::Server, address));
+				this.pending_inbound
+					.get_mut()
+					.unwrap_or_else(PoisonError::into_inner)
+					.push(Box::pin(upgrade_relayed(
+						connection,
+						this.keypair.clone(),
+						inbound.source,
+						this.handshake_timeout,
+					)));
+				progress = true;
+			}
+
+			while let Poll::Ready(Some(punch)) = this.punches_rx.poll_next_unpin(cx) {
+				let punch = this.punch(punch);
+				this.pending_punches
+					.get_mut()
+					.unwrap_or_else(PoisonError::into_inner)
+					.push(punch);
+				progress = true;
+			}
+
+			loop {
+				let pending_punches = this.pending_punches.get_mut().unwrap_or_else(PoisonError::into_inner);
+				let Poll::Ready(Some((peer_id, result))) = pending_punches.poll_next_unpin(cx) else {
+					break;
+				};
+				let error = result
+					.and_then(|connection| this.establish(peer_id, connection, Endpoint::Dialer))
+					.err();
+				// The dial of the remote may have won the race, either way the peer is now reached directly.
+				let direct = this.connections().direct_connection(&peer_id);
+				let event = match direct {
+					Some(connection) => Event::HolePunchSucceeded {
+						peer_id,
+						address: connection.remote_address().clone(),
+					},
+					None => Event::HolePunchFailed {
+						peer_id,
+						error: error.unwrap_or(Error::ConnectionLimit),
+					},
+				};
+				let _ = this.events_tx.unbounded_send(event);
+				progress = true;
+			}
+
 			for v in this.transports.values_mut() {
-				match Pin::new(v).poll(cx) {
-					Poll::Ready(event) => {
-						match event {
-							TransportEvent::NewConnection { address } => {
-								info!(peer_id = %this.peer_id, %address, "Accepted connection");
-							}
-							TransportEvent::ListenAddr { address } => {
-								info!(peer_id = %this.peer_id, %address, "Listening on");
+				while let Poll::Ready(event) = Pin::new(&mut *v).poll(cx) {
+					match event {
+						TransportEvent::NewConnection {
+							listener_id,
+							address,
+							connection,
+						} => {
+							let pending = this
+								.pending_inbound
+								.get_mut()
+								.unwrap_or_else(PoisonError::into_inner)
+								.len();
+							if !limits.allows_pending_inbound(pending) {
+								warn!(peer_id = %this.peer_id, ?listener_id, %address, pending, "Too many pending inbound connections");
+								close(connection);
+								continue;
 							}
-							TransportEvent::AddrExpired { address } => {
-								info!(peer_id = %this.peer_id, %address, "Listen address expired");
-							}
-							TransportEvent::ListenError { error } => {
-								info!(peer_id = %this.peer_id, ?error, "Failed to listen");
+
+							info!(peer_id = %this.peer_id, ?listener_id, %address, "Accepted connection");
+							this.pending_inbound
+								.get_mut()
+								.unwrap_or_else(PoisonError::into_inner)
+								.push(Box::pin(upgrade_inbound(
+									connection,
+									this.keypair.clone(),
+									this.handshake_timeout,
+								)));
+							progress = true;
+						}
+						TransportEvent::ListenAddr { listener_id, address } => {
+							info!(peer_id = %this.peer_id, ?listener_id, %address, "Listening on");
+							let mut listen_addrs = this.listen_addrs.write().unwrap_or_else(PoisonError::into_inner);
+							if !listen_addrs.contains(&address) {
+								listen_addrs.push(address.clone());
 							}
+							drop(listen_addrs);
+							return Poll::Ready(Some(Event::NewListenAddr { listener_id, address }));
+						}
+						TransportEvent::AddrExpired { listener_id, address } => {
+							info!(peer_id = %this.peer_id, ?listener_id, %address, "Listen address expired");
+							this.listen_addrs
+								.write()
+								.unwrap_or_else(PoisonError::into_inner)
+								.retain(|listen_addr| *listen_addr != address);
+							return Poll::Ready(Some(Event::ExpiredListenAddr { listener_id, address }));
+						}
+						TransportEvent::ListenError { listener_id, error } => {
+							info!(peer_id = %this.peer_id, ?listener_id, ?error, "Failed to listen");
+						}
+						TransportEvent::ListenerClosed { listener_id } => {
+							info!(peer_id = %this.peer_id, ?listener_id, "Listener closed");
+							return Poll::Ready(Some(Event::ListenerClosed { listener_id }));
 						}
-						continue 'outer;
 					}
-					Poll::Pending => {}
 				}
 			}
 
-			return Poll::Pending;
+			if !progress {
+				return Poll::Pending;
+			}
 		}
 	}
 }
 
+/// Authenticate the remote of an accepted connection, which is dropped unless it completes the handshake in `timeout`.
+async fn upgrade_inbound(
+	mut connection: Connection,
+	keypair: Keypair,
+	timeout: Duration,
+) -> Result<(PeerId, Connection), Error> {
+	let peer_id = runtime::timeout(timeout, handshake::inbound(&mut connection, &keypair)).await??;
+	connection.set_remote_peer_id(peer_id);
+	Ok((peer_id, connection))
+}
+
+/// Authenticate the remote of a relayed connection, which must be the source announced by the relay.
+async fn upgrade_relayed(
+	connection: Connection,
+	keypair: Keypair,
+	source: PeerId,
+	timeout: Duration,
+) -> Result<(PeerId, Connection), Error> {
+	let (peer_id, connection) = upgrade_inbound(connection, keypair, timeout).await?;
+	if peer_id != source {
+		return Err(Error::PeerIdMismatch {
+			expected: Box::new(source),
+			actual: Box::new(peer_id),
+		});
+	}
+	Ok((peer_id, connection))
+}
+
+fn close(mut connection: Connection) {
+	let close = connection.close();
+	runtime::spawn(async move {
+		if let Err(error) = close.await {
+			debug!(?error, "Failed to close connection");
+		}
+	});
+}
+
 impl futures::Stream for Node {
 	type Item = Event;
 
 	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-		self.poll_next_event(cx).map(Some)
+		self.poll_next_event(cx)
 	}
 }
 
 impl FusedStream for Node {
 	fn is_terminated(&self) -> bool {
-		false
+		self.state == State::Closed
 	}
 }
 
 fn extract_protocol_from_multiaddr(address: &Multiaddr) -> Result<Protocol, Error> {
-	let components = address.iter();
-	let mut p2p_protocol: Option<Protocol> = None;
-
-	for component in components {
+	for component in address.iter() {
 		match component {
-			MultiaddrProtocol::WebTransport => {
-				p2p_protocol = Some(Protocol::WebTransport);
+			MultiaddrProtocol::WebTransport => return Ok(Protocol::WebTransport),
+			MultiaddrProtocol::WebRTCDirect => return Ok(Protocol::WebRTC),
+			MultiaddrProtocol::Memory(_) => return Ok(Protocol::Memory),
+			MultiaddrProtocol::Tls => return Ok(Protocol::Tcp),
+			_ => {}
+		}
+	}
+	Err(Error::NoProtocolsInMultiaddr(address.clone()))
+}
+
+/// The last `/p2p/` component of `address`, which is the target rather than the relay of a circuit address.
+fn extract_peer_id_from_multiaddr(address: &Multiaddr) -> Option<PeerId> {
+	address
+		.iter()
+		.filter_map(|component| match component {
+			MultiaddrProtocol::P2p(peer_id) => Some(peer_id),
+			_ => None,
+		})
+		.last()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::{AsyncReadExt, AsyncWriteExt};
+	use sf_memory_transport::MemoryTransport;
+
+	use crate::Builder;
+	use crate::test_util::{self, next_event};
+
+	fn node() -> Node {
+		test_util::node(|_| {})
+	}
+
+	/// A node listening on a memory address, driven in the background, and its events.
+	async fn listening() -> (PeerId, Multiaddr, mpsc::UnboundedReceiver<Event>) {
+		test_util::listening(node()).await
+	}
+
+	#[test]
+	fn test_node_is_send_and_sync() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<Node>();
+	}
+
+	#[tokio::test]
+	async fn test_dial_futures_are_send() {
+		fn assert_send<T: Send>(_: T) {}
+		let node = node();
+		let peer_id = PeerId::random();
+		assert_send(node.dial(peer_id, "/memory/1".parse().unwrap()));
+		assert_send(node.dial_peer(peer_id));
+		assert_send(node.reserve(peer_id));
+	}
+
+	#[tokio::test]
+	async fn test_dial_authenticates_and_identifies() {
+		let (listener, address, mut listener_events) = listening().await;
+		let mut dialer = node();
+
+		let connection = dialer.dial(listener, address.clone()).await.unwrap();
+
+		assert_eq!(connection.remote_peer_id(), Some(listener));
+		assert_eq!(dialer.connected_peers(), [listener]);
+		loop {
+			if let Event::Identified { peer_id, info } = next_event(&mut dialer).await {
+				assert_eq!(peer_id, listener);
+				assert_eq!(info.listen_addrs, [address]);
+				assert!(info.observed_addr.is_some());
+				// A single peer does not confirm the address it observed.
+				assert!(dialer.observed_addrs().is_empty());
+				break;
+			}
+		}
+		loop {
+			if let Some(Event::ConnectionEstablished { peer_id, endpoint, .. }) = listener_events.next().await {
+				assert_eq!(peer_id, dialer.peer_id);
+				assert_eq!(endpoint, Endpoint::Listener);
+				break;
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_dial_wrong_peer_fails() {
+		let (_, address, _listener_events) = listening().await;
+		let expected = PeerId::random();
+
+		let result = node().dial(expected, address).await;
+
+		assert!(matches!(result, Err(Error::PeerIdMismatch { expected: e, .. }) if *e == expected));
+	}
+
+	#[tokio::test]
+	async fn test_concurrent_dials_share_one_connection() {
+		let (listener, address, mut listener_events) = listening().await;
+		let dialer = node();
+
+		let (first, second) =
+			future::join(dialer.dial(listener, address.clone()), dialer.dial(listener, address)).await;
+		first.unwrap();
+		second.unwrap();
+
+		let mut established = 0;
+		while let Ok(Some(event)) = runtime::timeout(Duration::from_millis(300), listener_events.next()).await {
+			if let Event::ConnectionEstablished { .. } = event {
+				established += 1;
+			}
+		}
+		assert_eq!(established, 1);
+	}
+
+	#[tokio::test]
+	async fn test_dial_falls_back_to_other_addresses() {
+		let tcp = || sf_tcp_transport::TcpTransport::new(sf_tcp_transport::Certificate::generate().unwrap()).unwrap();
+		let mut builder = Builder::new(Keypair::generate_ed25519());
+		builder.with_tcp_transport(tcp());
+		let mut listener = builder.build();
+		listener
+			.listen("/ip4/127.0.0.1/tcp/0/tls".parse().unwrap())
+			.await
+			.unwrap();
+		let Event::NewListenAddr { address, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+		let listener_peer_id = listener.peer_id;
+		tokio::spawn(async move { while listener.next().await.is_some() {} });
+
+		let mut builder = Builder::new(Keypair::generate_ed25519());
+		builder.with_memory_transport(MemoryTransport::new());
+		builder.with_tcp_transport(tcp());
+		let dialer = builder.build();
+		dialer.add_address(
+			listener_peer_id,
+			"/dns4/unknown/udp/4433/quic-v1/webtransport".parse().unwrap(),
+		);
+		dialer.add_address(listener_peer_id, address.clone());
+		let refused: Multiaddr = "/memory/1000000".parse().unwrap();
+
+		let connection = dialer.dial(listener_peer_id, refused.clone()).await.unwrap();
+
+		assert_eq!(connection.remote_address(), &address);
+		assert!(matches!(
+			dialer.dial(PeerId::random(), refused.clone()).await,
+			Err(Error::Transport(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_dial_falls_back_from_unresponsive_address() {
+		let (listener, address, _listener_events) = listening().await;
+		// Accepts TCP connections in its backlog but never answers the TLS handshake, like a black-holed network.
+		let black_hole = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let unresponsive: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}/tls", black_hole.local_addr().unwrap().port())
+			.parse()
+			.unwrap();
+
+		let dialer = test_util::node(|builder| {
+			builder.with_tcp_transport(
+				sf_tcp_transport::TcpTransport::new(sf_tcp_transport::Certificate::generate().unwrap()).unwrap(),
+			);
+			builder.with_dial_timeout(Duration::from_millis(200));
+		});
+		dialer.add_address(listener, address.clone());
+
+		let started = Instant::now();
+		let connection = dialer.dial(listener, unresponsive.clone()).await.unwrap();
+
+		assert_eq!(connection.remote_address(), &address);
+		assert!(started.elapsed() < Duration::from_secs(2));
+		assert!(
+			dialer
+				.peer_store()
+				.retry_after(&listener, &unresponsive, SystemTime::now())
+				.is_some()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_handshake_times_out_on_stalled_peer() {
+		let timeout = Duration::from_millis(200);
+		let mut listener = test_util::node(|builder| {
+			builder.with_connection_limits(ConnectionLimits::default().with_max_pending_inbound(1));
+			builder.with_handshake_timeout(timeout);
+		});
+		let address = test_util::listen(&mut listener, "/memory/0").await;
+		let listener_peer_id = listener.peer_id;
+		tokio::spawn(async move { while listener.next().await.is_some() {} });
+
+		// A peer which connects but never runs the handshake only holds the pending slot until the timeout.
+		let stalled = MemoryTransport::new()
+			.dial(listener_peer_id, address.clone())
+			.await
+			.unwrap();
+		runtime::sleep(timeout * 2).await;
+		node().dial(listener_peer_id, address).await.unwrap();
+		drop(stalled);
+
+		// Nor does a listener which never answers hold the dialer.
+		let mut silent = MemoryTransport::new();
+		silent.listen_on("/memory/0".parse().unwrap()).unwrap();
+		let TransportEvent::ListenAddr { address, .. } = future::poll_fn(|cx| Pin::new(&mut silent).poll(cx)).await
+		else {
+			panic!("expected a listen address");
+		};
+		let dialer = test_util::node(|builder| {
+			builder.with_handshake_timeout(timeout);
+		});
+		assert!(matches!(
+			dialer.dial(PeerId::random(), address).await,
+			Err(Error::Timeout)
+		));
+	}
+
+	#[tokio::test]
+	async fn test_stream_negotiation() {
+		let mut listener = node();
+		listener.set_stream_handler("/echo/1", |_, mut stream| async move {
+			let mut received = Vec::new();
+			stream.read_to_end(&mut received).await.unwrap();
+			stream.write_all(&received).await.unwrap();
+			stream.close().await.unwrap();
+		});
+		let address = test_util::listen(&mut listener, "/memory/0").await;
+		let listener_peer_id = listener.peer_id;
+		tokio::spawn(async move { while listener.next().await.is_some() {} });
+
+		let mut connection = node().dial(listener_peer_id, address).await.unwrap();
+
+		let mut stream = connection.open_stream_with_protocol("/echo/1").await.unwrap();
+		stream.write_all(b"hello").await.unwrap();
+		stream.close().await.unwrap();
+		let mut echoed = Vec::new();
+		stream.read_to_end(&mut echoed).await.unwrap();
+		assert_eq!(echoed, b"hello");
+
+		let result = connection.open_stream_with_protocol("/unknown/1").await;
+		assert!(matches!(result, Err(Error::ProtocolNotSupported(protocol)) if protocol == "/unknown/1"));
+	}
+
+	#[tokio::test]
+	async fn test_shutdown_closes_connections() {
+		let (listener, address, mut listener_events) = listening().await;
+		let mut dialer = node();
+		dialer.dial(listener, address.clone()).await.unwrap();
+
+		dialer.shutdown(Duration::from_secs(1)).await;
+
+		let mut closed = false;
+		loop {
+			match next_event(&mut dialer).await {
+				Event::ConnectionClosed { peer_id, cause, .. } => {
+					assert_eq!(peer_id, listener);
+					assert!(matches!(cause, CloseCause::Shutdown));
+					closed = true;
+				}
+				Event::Closed => break,
+				_ => {}
+			}
+		}
+		assert!(closed);
+		assert!(dialer.next().await.is_none());
+		assert!(matches!(dialer.dial(listener, address).await, Err(Error::Shutdown)));
+
+		loop {
+			if let Some(Event::ConnectionClosed { peer_id, .. }) = listener_events.next().await {
+				assert_eq!(peer_id, dialer.peer_id);
 				break;
 			}
-			_ => {}
 		}
 	}
-	p2p_protocol.ok_or_else(|| Error::NoProtocolsInMultiaddr(address.clone()))
+
+	#[tokio::test]
+	async fn test_shutdown_drops_connections_at_the_deadline() {
+		// A circuit over a stream the remote never reads and already full, so that closing it never completes.
+		let mut silent = MemoryTransport::new();
+		silent.listen_on("/memory/0".parse().unwrap()).unwrap();
+		let TransportEvent::ListenAddr { address, .. } = future::poll_fn(|cx| Pin::new(&mut silent).poll(cx)).await
+		else {
+			panic!("expected a listen address");
+		};
+		let mut dialed = MemoryTransport::new()
+			.dial(PeerId::random(), address.clone())
+			.await
+			.unwrap();
+		let TransportEvent::NewConnection {
+			connection: _accepted, ..
+		} = future::poll_fn(|cx| Pin::new(&mut silent).poll(cx)).await
+		else {
+			panic!("expected a connection");
+		};
+		let mut stream = dialed.open_stream().await.unwrap();
+		while runtime::timeout(Duration::from_millis(10), stream.write_all(&[0; 1024]))
+			.await
+			.is_ok()
+		{}
+		let circuit = relay::Circuit::new(Stream::Memory(stream), yamux::Mode::Client, address);
+
+		let mut node = node();
+		let remote = PeerId::random();
+		assert!(matches!(
+			node.establish(remote, Connection::Relayed(circuit), Endpoint::Dialer),
+			Ok(Established::New(_))
+		));
+
+		let deadline = Duration::from_millis(200);
+		runtime::timeout(deadline * 5, node.shutdown(deadline))
+			.await
+			.expect("shutdown outlived its deadline");
+
+		let mut closed = false;
+		loop {
+			match next_event(&mut node).await {
+				Event::ConnectionClosed { peer_id, cause, .. } => {
+					assert_eq!(peer_id, remote);
+					assert!(matches!(cause, CloseCause::Shutdown));
+					assert!(!closed, "closure reported twice");
+					closed = true;
+				}
+				Event::Closed => break,
+				_ => {}
+			}
+		}
+		assert!(closed);
+	}
+
+	#[tokio::test]
+	async fn test_shutdown_ends_long_lived_streams() {
+		let ping = PingConfig::default().with_interval(Duration::from_millis(50));
+		let node = || test_util::node(|builder| builder.with_ping(ping));
+		let mut listener = node();
+		listener.subscribe("topic");
+		let address = test_util::listen(&mut listener, "/memory/0").await;
+		let listener_peer_id = listener.peer_id;
+		let (pinged_tx, mut pinged_rx) = mpsc::unbounded();
+		tokio::spawn(async move {
+			while let Some(event) = listener.next().await {
+				if let Event::Ping { .. } = event {
+					let _ = pinged_tx.unbounded_send(());
+				}
+			}
+		});
+
+		let mut dialer = node();
+		dialer.dial(listener_peer_id, address).await.unwrap();
+		// The listener pings through a stream handled by the dialer, and sends its subscriptions on another one.
+		pinged_rx.next().await.unwrap();
+		while !matches!(next_event(&mut dialer).await, Event::Subscribed { .. }) {}
+
+		let started = Instant::now();
+		dialer.shutdown(Duration::from_secs(3)).await;
+		assert!(
+			started.elapsed() < Duration::from_secs(1),
+			"shutdown took {:?}",
+			started.elapsed()
+		);
+	}
 }
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use multiaddr::PeerId;
+
+use super::PeerInfo;
+
+/// Where the peer store keeps its records across restarts.
+pub trait PeerStoreBackend: Send + 'static {
+	/// Read the records saved by a previous run.
+	fn load(&mut self) -> io::Result<HashMap<PeerId, PeerInfo>>;
+
+	/// Replace the saved records by `peers`. Called away from the async executor, blocking I/O is fine.
+	fn save(&mut self, peers: &HashMap<PeerId, PeerInfo>) -> io::Result<()>;
+}
+
+/// Keeps nothing, the peers are forgotten when the node stops.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryBackend;
+
+impl PeerStoreBackend for MemoryBackend {
+	fn load(&mut self) -> io::Result<HashMap<PeerId, PeerInfo>> {
+		Ok(HashMap::new())
+	}
+
+	fn save(&mut self, _peers: &HashMap<PeerId, PeerInfo>) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Saves the peers as JSON to a file, a missing file loads as an empty store.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+	path: PathBuf,
+}
+
+impl FileBackend {
+	pub fn new(path: impl Into<PathBuf>) -> Self {
+		Self { path: path.into() }
+	}
+}
+
+impl PeerStoreBackend for FileBackend {
+	fn load(&mut self) -> io::Result<HashMap<PeerId, PeerInfo>> {
+		match fs::read(&self.path) {
+			Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from),
+			Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+			Err(error) => Err(error),
+		}
+	}
+
+	fn save(&mut self, peers: &HashMap<PeerId, PeerInfo>) -> io::Result<()> {
+		// Write next to the file, flushed to disk, and rename so that a crash never leaves a truncated store behind.
+		let tmp = self.path.with_extension("tmp");
+		let mut file = File::create(&tmp)?;
+		file.write_all(&serde_json::to_vec(peers)?)?;
+		file.sync_all()?;
+		fs::rename(tmp, &self.path)
+	}
+}
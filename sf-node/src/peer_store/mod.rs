@@ -0,0 +1,504 @@
+mod backend;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use multiaddr::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::identify::IdentifyInfo;
+
+pub use backend::{FileBackend, MemoryBackend, PeerStoreBackend};
+
+/// What the node learned about a remote peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInfo {
+	/// Addresses the peer can be dialed on, most recently learned last.
+	pub addresses: Vec<AddressRecord>,
+	pub protocols: Vec<String>,
+	pub agent_version: Option<String>,
+	/// Last time a connection with the peer was established or closed.
+	pub last_seen: Option<SystemTime>,
+	/// Round trip time of the last answered ping.
+	pub rtt: Option<Duration>,
+}
+
+/// An address of a peer and how dialing it went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressRecord {
+	pub address: Multiaddr,
+	/// The address is forgotten after this time unless seen again.
+	pub expires: SystemTime,
+	/// Consecutive failed dials.
+	pub failures: u32,
+	/// The address is not dialed again before this time.
+	pub retry_after: Option<SystemTime>,
+}
+
+impl AddressRecord {
+	fn is_backing_off(&self, now: SystemTime) -> bool {
+		self.retry_after.is_some_and(|retry_after| now < retry_after)
+	}
+}
+
+/// Peers connected without a known address kept at most, such as the ones which dialed the node but were not
+/// identified yet.
+const MAX_PEERS_WITHOUT_ADDRESS: usize = 1024;
+
+/// Expiry and dial backoff policy of the peer store.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerStoreConfig {
+	address_ttl: Duration,
+	initial_backoff: Duration,
+	max_backoff: Duration,
+	save_interval: Duration,
+}
+
+impl Default for PeerStoreConfig {
+	fn default() -> Self {
+		Self {
+			address_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+			initial_backoff: Duration::from_secs(5),
+			max_backoff: Duration::from_secs(10 * 60),
+			save_interval: Duration::from_secs(5),
+		}
+	}
+}
+
+impl PeerStoreConfig {
+	/// How long an address is kept after it was last learned or successfully dialed.
+	pub fn with_address_ttl(mut self, ttl: Duration) -> Self {
+		self.address_ttl = ttl;
+		self
+	}
+
+	/// Delay before retrying an address after its first failed dial, doubled on each consecutive failure.
+	pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+		self.initial_backoff = backoff;
+		self
+	}
+
+	/// Upper bound of the delay between two dials of a failing address.
+	pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+		self.max_backoff = backoff;
+		self
+	}
+
+	/// Delay between two saves to the backend, the changes made in between are written at once.
+	pub fn with_save_interval(mut self, interval: Duration) -> Self {
+		self.save_interval = interval;
+		self
+	}
+
+	pub(crate) fn save_interval(&self) -> Duration {
+		self.save_interval
+	}
+
+	fn backoff(&self, failures: u32) -> Duration {
+		let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+		self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+	}
+}
+
+/// Known peers, fed by dials, identify exchanges and pings, and saved to a [`PeerStoreBackend`].
+///
+/// Changes are only marked, the node takes them with [`PeerStore::save`] every
+/// [`PeerStoreConfig::with_save_interval`]. Round trip times are only kept in memory until the next change worth
+/// saving, to not write on every ping.
+pub(crate) struct PeerStore {
+	config: PeerStoreConfig,
+	backend: Arc<Mutex<Backend>>,
+	peers: HashMap<PeerId, PeerInfo>,
+	/// Changed since the last [`PeerStore::save`].
+	dirty: bool,
+	/// Number of saves taken so far.
+	generation: u64,
+}
+
+/// The backend and the generation of the records it holds, shared with the saves in flight.
+struct Backend {
+	backend: Box<dyn PeerStoreBackend>,
+	generation: u64,
+}
+
+/// A snapshot of the peer store to write to its backend, with blocking I/O.
+pub(crate) struct PendingSave {
+	backend: Arc<Mutex<Backend>>,
+	peers: HashMap<PeerId, PeerInfo>,
+	generation: u64,
+}
+
+impl PendingSave {
+	pub(crate) fn run(self) {
+		let mut backend = self.backend.lock().unwrap_or_else(PoisonError::into_inner);
+		// A later snapshot may have been written first when the saves overlap.
+		if backend.generation >= self.generation {
+			return;
+		}
+		backend.generation = self.generation;
+		if let Err(error) = backend.backend.save(&self.peers) {
+			warn!(?error, "Failed to save the peer store");
+		}
+	}
+}
+
+impl PeerStore {
+	pub(crate) fn new(mut backend: Box<dyn PeerStoreBackend>, config: PeerStoreConfig, now: SystemTime) -> Self {
+		let peers = backend.load().unwrap_or_else(|error| {
+			warn!(?error, "Failed to load the peer store");
+			HashMap::new()
+		});
+		let mut store = Self {
+			config,
+			backend: Arc::new(Mutex::new(Backend { backend, generation: 0 })),
+			peers,
+			dirty: false,
+			generation: 0,
+		};
+		store.prune(now);
+		store
+	}
+
+	/// The changes since the last call, if any, to write with [`PendingSave::run`] away from the async executor.
+	pub(crate) fn save(&mut self) -> Option<PendingSave> {
+		if !self.dirty {
+			return None;
+		}
+		self.dirty = false;
+		self.generation += 1;
+		let peers = self
+			.peers
+			.iter()
+			.filter(|(_, info)| !info.addresses.is_empty())
+			.map(|(peer_id, info)| (*peer_id, info.clone()))
+			.collect();
+		Some(PendingSave {
+			backend: Arc::clone(&self.backend),
+			peers,
+			generation: self.generation,
+		})
+	}
+
+	pub(crate) fn get(&self, peer_id: &PeerId) -> Option<&PeerInfo> {
+		self.peers.get(peer_id)
+	}
+
+	/// Peers with at least one address.
+	pub(crate) fn peers(&self) -> impl Iterator<Item = &PeerId> {
+		self.peers
+			.iter()
+			.filter(|(_, info)| !info.addresses.is_empty())
+			.map(|(peer_id, _)| peer_id)
+	}
+
+	/// Addresses of `peer_id` which have not expired, in the order they were learned.
+	pub(crate) fn addresses(&self, peer_id: &PeerId, now: SystemTime) -> Vec<Multiaddr> {
+		self.peers
+			.get(peer_id)
+			.into_iter()
+			.flat_map(|info| &info.addresses)
+			.filter(|record| record.expires > now)
+			.map(|record| record.address.clone())
+			.collect()
+	}
+
+	/// When dialing `address` failed recently, the time after which it may be dialed again.
+	pub(crate) fn retry_after(&self, peer_id: &PeerId, address: &Multiaddr, now: SystemTime) -> Option<SystemTime> {
+		self.record(peer_id, address)
+			.filter(|record| record.is_backing_off(now))
+			.and_then(|record| record.retry_after)
+	}
+
+	pub(crate) fn add_address(&mut self, peer_id: PeerId, address: Multiaddr, now: SystemTime) {
+		self.learn(peer_id, address, now);
+		self.dirty = true;
+	}
+
+	/// Record what the peer advertised about itself, keeping the addresses learned otherwise.
+	pub(crate) fn identified(&mut self, info: &IdentifyInfo, now: SystemTime) {
+		for address in &info.listen_addrs {
+			self.learn(info.peer_id, address.clone(), now);
+		}
+		let entry = self.peers.entry(info.peer_id).or_default();
+		entry.protocols = info.protocols.clone();
+		entry.agent_version = Some(info.agent_version.clone());
+		self.dirty = true;
+	}
+
+	pub(crate) fn dial_succeeded(&mut self, peer_id: PeerId, address: Multiaddr, now: SystemTime) {
+		let record = self.learn(peer_id, address, now);
+		record.failures = 0;
+		record.retry_after = None;
+		self.dirty = true;
+	}
+
+	/// Count a failed dial of `address`, delaying the next attempt. An address not known yet is only kept until its
+	/// backoff elapsed.
+	pub(crate) fn dial_failed(&mut self, peer_id: &PeerId, address: &Multiaddr, now: SystemTime) {
+		let config = self.config;
+		let addresses = &mut self.peers.entry(*peer_id).or_default().addresses;
+		let position = match addresses.iter().position(|record| record.address == *address) {
+			Some(position) => position,
+			None => {
+				addresses.push(AddressRecord {
+					address: address.clone(),
+					expires: now,
+					failures: 0,
+					retry_after: None,
+				});
+				addresses.len() - 1
+			}
+		};
+		let record = &mut addresses[position];
+		record.failures = record.failures.saturating_add(1);
+		let retry_after = now + config.backoff(record.failures);
+		record.retry_after = Some(retry_after);
+		record.expires = record.expires.max(retry_after);
+		self.dirty = true;
+	}
+
+	/// Record that a connection with `peer_id` was established or closed.
+	///
+	/// Peers without an address are not saved, and only the [`MAX_PEERS_WITHOUT_ADDRESS`] seen last are kept.
+	pub(crate) fn seen(&mut self, peer_id: PeerId, now: SystemTime) {
+		self.peers.entry(peer_id).or_default().last_seen = Some(now);
+		self.dirty = true;
+
+		let without_address = self.peers.values().filter(|info| info.addresses.is_empty()).count();
+		if without_address > MAX_PEERS_WITHOUT_ADDRESS {
+			let oldest = self
+				.peers
+				.iter()
+				.filter(|(_, info)| info.addresses.is_empty())
+				.min_by_key(|(_, info)| info.last_seen)
+				.map(|(peer_id, _)| *peer_id);
+			if let Some(oldest) = oldest {
+				self.peers.remove(&oldest);
+			}
+		}
+	}
+
+	pub(crate) fn record_rtt(&mut self, peer_id: &PeerId, rtt: Duration) {
+		if let Some(info) = self.peers.get_mut(peer_id) {
+			info.rtt = Some(rtt);
+		}
+	}
+
+	/// Forget the expired addresses and the peers left without any.
+	fn prune(&mut self, now: SystemTime) {
+		for info in self.peers.values_mut() {
+			info.addresses.retain(|record| record.expires > now);
+		}
+		self.peers.retain(|_, info| !info.addresses.is_empty());
+	}
+
+	fn record(&self, peer_id: &PeerId, address: &Multiaddr) -> Option<&AddressRecord> {
+		self.peers
+			.get(peer_id)?
+			.addresses
+			.iter()
+			.find(|record| record.address == *address)
+	}
+
+	/// Add `address` or push back its expiry.
+	fn learn(&mut self, peer_id: PeerId, address: Multiaddr, now: SystemTime) -> &mut AddressRecord {
+		let expires = now + self.config.address_ttl;
+		let addresses = &mut self.peers.entry(peer_id).or_default().addresses;
+		let position = match addresses.iter().position(|record| record.address == address) {
+			Some(position) => position,
+			None => {
+				addresses.push(AddressRecord {
+					address,
+					expires,
+					failures: 0,
+					retry_after: None,
+				});
+				addresses.len() - 1
+			}
+		};
+		let record = &mut addresses[position];
+		record.expires = record.expires.max(expires);
+		record
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const HOUR: Duration = Duration::from_secs(60 * 60);
+
+	fn address(n: u8) -> Multiaddr {
+		format!("/ip4/10.0.0.{n}/udp/4433/quic-v1/webtransport")
+			.parse()
+			.unwrap()
+	}
+
+	fn store(config: PeerStoreConfig) -> PeerStore {
+		PeerStore::new(Box::new(MemoryBackend), config, SystemTime::UNIX_EPOCH)
+	}
+
+	#[test]
+	fn test_identified_merges_addresses() {
+		let now = SystemTime::UNIX_EPOCH;
+		let peer_id = PeerId::random();
+		let mut store = store(PeerStoreConfig::default());
+
+		store.add_address(peer_id, address(1), now);
+		store.identified(
+			&IdentifyInfo {
+				peer_id,
+				listen_addrs: vec![address(1), address(2)],
+				protocols: vec!["/echo".to_owned()],
+				agent_version: "test".to_owned(),
+				observed_addr: None,
+			},
+			now,
+		);
+
+		let info = store.get(&peer_id).unwrap();
+		assert_eq!(store.addresses(&peer_id, now), [address(1), address(2)]);
+		assert_eq!(info.protocols, ["/echo"]);
+		assert_eq!(info.agent_version.as_deref(), Some("test"));
+		assert!(store.addresses(&PeerId::random(), now).is_empty());
+	}
+
+	#[test]
+	fn test_addresses_expire() {
+		let now = SystemTime::UNIX_EPOCH;
+		let peer_id = PeerId::random();
+		let mut store = store(PeerStoreConfig::default().with_address_ttl(HOUR));
+
+		store.add_address(peer_id, address(1), now);
+		store.add_address(peer_id, address(2), now + HOUR / 2);
+
+		assert_eq!(store.addresses(&peer_id, now + HOUR), [address(2)]);
+		store.prune(now + 2 * HOUR);
+		assert!(store.get(&peer_id).is_none());
+	}
+
+	#[test]
+	fn test_dial_failures_back_off() {
+		let now = SystemTime::UNIX_EPOCH;
+		let peer_id = PeerId::random();
+		let config = PeerStoreConfig::default()
+			.with_initial_backoff(Duration::from_secs(1))
+			.with_max_backoff(Duration::from_secs(3));
+		let mut store = store(config);
+		store.add_address(peer_id, address(1), now);
+
+		let mut backoffs = Vec::new();
+		for _ in 0..3 {
+			store.dial_failed(&peer_id, &address(1), now);
+			let retry_after = store.retry_after(&peer_id, &address(1), now).unwrap();
+			backoffs.push(retry_after.duration_since(now).unwrap().as_secs());
+		}
+		assert_eq!(backoffs, [1, 2, 3]);
+
+		store.dial_succeeded(peer_id, address(1), now);
+		assert!(store.retry_after(&peer_id, &address(1), now).is_none());
+		assert_eq!(store.get(&peer_id).unwrap().addresses[0].failures, 0);
+	}
+
+	#[test]
+	fn test_unknown_addresses_back_off() {
+		let now = SystemTime::UNIX_EPOCH;
+		let peer_id = PeerId::random();
+		let mut store = store(PeerStoreConfig::default().with_initial_backoff(Duration::from_secs(1)));
+
+		store.dial_failed(&peer_id, &address(1), now);
+		assert_eq!(
+			store.retry_after(&peer_id, &address(1), now),
+			Some(now + Duration::from_secs(1))
+		);
+		store.dial_failed(&peer_id, &address(1), now + Duration::from_secs(1));
+		assert_eq!(
+			store.retry_after(&peer_id, &address(1), now + Duration::from_secs(1)),
+			Some(now + Duration::from_secs(3))
+		);
+
+		// Forgotten once the backoff elapsed, rather than kept as an address of the peer.
+		assert!(store.addresses(&peer_id, now + Duration::from_secs(3)).is_empty());
+		store.prune(now + Duration::from_secs(3));
+		assert!(store.get(&peer_id).is_none());
+	}
+
+	#[test]
+	fn test_peers_without_address_are_bounded() {
+		let now = SystemTime::UNIX_EPOCH;
+		let mut store = store(PeerStoreConfig::default());
+		let known = PeerId::random();
+		store.add_address(known, address(1), now);
+		store.seen(known, now);
+
+		let peers = (0..=MAX_PEERS_WITHOUT_ADDRESS as u64)
+			.map(|n| {
+				let peer_id = PeerId::random();
+				store.seen(peer_id, now + Duration::from_secs(n + 1));
+				peer_id
+			})
+			.collect::<Vec<_>>();
+
+		assert!(store.get(&known).is_some());
+		assert!(store.get(&peers[0]).is_none());
+		assert!(store.get(&peers[1]).is_some());
+		assert_eq!(store.peers.len(), MAX_PEERS_WITHOUT_ADDRESS + 1);
+		assert_eq!(store.save().unwrap().peers.len(), 1);
+	}
+
+	#[test]
+	fn test_changes_are_saved_at_once() {
+		struct Counting(Arc<Mutex<Vec<usize>>>);
+
+		impl PeerStoreBackend for Counting {
+			fn load(&mut self) -> std::io::Result<HashMap<PeerId, PeerInfo>> {
+				Ok(HashMap::new())
+			}
+
+			fn save(&mut self, peers: &HashMap<PeerId, PeerInfo>) -> std::io::Result<()> {
+				self.0.lock().unwrap().push(peers.len());
+				Ok(())
+			}
+		}
+
+		let now = SystemTime::UNIX_EPOCH;
+		let saves = Arc::default();
+		let mut store = PeerStore::new(Box::new(Counting(Arc::clone(&saves))), PeerStoreConfig::default(), now);
+		assert!(store.save().is_none());
+
+		for n in 0..3 {
+			store.add_address(PeerId::random(), address(n), now);
+		}
+		let first = store.save().unwrap();
+		store.add_address(PeerId::random(), address(3), now);
+		let second = store.save().unwrap();
+		assert!(store.save().is_none());
+		// The older snapshot is not written over the newer one.
+		second.run();
+		first.run();
+
+		assert_eq!(*saves.lock().unwrap(), [4]);
+	}
+
+	#[test]
+	fn test_file_backend_survives_restart() {
+		let path = std::env::temp_dir().join(format!("sf-node-peer-store-{}.json", PeerId::random()));
+		let now = SystemTime::now();
+		let peer_id = PeerId::random();
+
+		let mut store = PeerStore::new(Box::new(FileBackend::new(&path)), PeerStoreConfig::default(), now);
+		store.add_address(peer_id, address(1), now);
+		store.dial_failed(&peer_id, &address(1), now);
+		store.seen(peer_id, now);
+		let saved = store.get(&peer_id).cloned();
+		store.save().unwrap().run();
+		assert!(store.save().is_none());
+
+		let store = PeerStore::new(Box::new(FileBackend::new(&path)), PeerStoreConfig::default(), now);
+		assert_eq!(store.get(&peer_id).cloned(), saved);
+
+		std::fs::remove_file(path).unwrap();
+	}
+}
@@ -0,0 +1,228 @@
+//! Check that connected peers are still alive.
+//!
+//! Every established connection periodically opens a stream negotiating [`PROTOCOL`], writes a random payload and
+//! waits for the remote to echo it back. The stream is kept open across pings and reopened after a failure.
+
+use std::pin::pin;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use multiaddr::PeerId;
+use tracing::debug;
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::runtime;
+use crate::stream::Stream;
+use crate::stream_handler::Closing;
+
+pub(crate) const PROTOCOL: &str = "/sf/ping/1.0.0";
+const PAYLOAD_LEN: usize = 32;
+
+/// Configuration of the ping protocol run on every connection.
+#[derive(Debug, Clone, Copy)]
+pub struct PingConfig {
+	interval: Duration,
+	timeout: Duration,
+	max_failures: u32,
+}
+
+impl Default for PingConfig {
+	fn default() -> Self {
+		Self {
+			interval: Duration::from_secs(15),
+			timeout: Duration::from_secs(20),
+			max_failures: 3,
+		}
+	}
+}
+
+impl PingConfig {
+	/// Delay between two pings.
+	pub fn with_interval(mut self, interval: Duration) -> Self {
+		self.interval = interval;
+		self
+	}
+
+	/// Time given to the remote to echo a ping before it counts as a failure.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Number of consecutive failures after which the connection is closed, clamped to at least one.
+	pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+		self.max_failures = max_failures.max(1);
+		self
+	}
+}
+
+/// Echo the pings received on `stream` until the remote closes it, or until the node shuts down.
+pub(crate) async fn handle(closing: Closing, peer_id: PeerId, mut stream: Stream) {
+	if let Either::Left((Err(error), _)) = future::select(pin!(echo(&mut stream)), closing).await {
+		debug!(%peer_id, ?error, "Ping stream failed");
+	}
+	let _ = sf_core::Stream::close(&mut stream).await;
+}
+
+/// Ping the remote of `connection` every interval, reporting each round trip time to `on_rtt`.
+///
+/// Only returns once `max_failures` pings failed in a row, the caller is then expected to close the connection.
+pub(crate) async fn run(peer_id: PeerId, mut connection: Connection, config: PingConfig, on_rtt: impl Fn(Duration)) {
+	let mut stream = None;
+	let mut failures = 0;
+
+	loop {
+		runtime::sleep(config.interval).await;
+
+		let result = runtime::timeout(config.timeout, async {
+			let stream = match &mut stream {
+				Some(stream) => stream,
+				None => stream.insert(connection.open_stream_with_protocol(PROTOCOL).await?),
+			};
+			ping(stream).await
+		})
+		.await
+		.and_then(|result| result);
+
+		match result {
+			Ok(rtt) => {
+				failures = 0;
+				on_rtt(rtt);
+			}
+			Err(error) => {
+				failures += 1;
+				debug!(%peer_id, ?error, failures, "Ping failed");
+				stream = None;
+				if failures >= config.max_failures {
+					return;
+				}
+			}
+		}
+	}
+}
+
+async fn ping<S>(stream: &mut S) -> Result<Duration, Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let payload: [u8; PAYLOAD_LEN] = rand::random();
+	let started = Instant::now();
+
+	stream.write_all(&payload).await?;
+	stream.flush().await?;
+
+	let mut echoed = [0u8; PAYLOAD_LEN];
+	stream.read_exact(&mut echoed).await?;
+	if echoed != payload {
+		return Err(Error::PingMismatch);
+	}
+
+	Ok(started.elapsed())
+}
+
+async fn echo<S>(stream: &mut S) -> Result<(), Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let mut payload = [0u8; PAYLOAD_LEN];
+	loop {
+		match stream.read_exact(&mut payload).await {
+			Ok(()) => {}
+			Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+			Err(error) => return Err(error.into()),
+		}
+		stream.write_all(&payload).await?;
+		stream.flush().await?;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio_util::compat::TokioAsyncReadCompatExt;
+
+	use crate::test_util::{self, listening, next_event};
+	use crate::{CloseCause, Event, Node};
+
+	fn node(ping: PingConfig) -> Node {
+		test_util::node(|builder| builder.with_ping(ping))
+	}
+
+	#[tokio::test]
+	async fn test_ping_is_echoed() {
+		let (a, b) = tokio::io::duplex(4096);
+		let (mut a, mut b) = (a.compat(), b.compat());
+
+		let pinger = async {
+			for _ in 0..3 {
+				ping(&mut a).await.unwrap();
+			}
+			a.close().await.unwrap();
+		};
+		let ((), echoed) = tokio::join!(pinger, echo(&mut b));
+
+		echoed.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_ping_rejects_wrong_echo() {
+		let (a, b) = tokio::io::duplex(4096);
+		let (mut a, mut b) = (a.compat(), b.compat());
+
+		let corrupter = async {
+			let mut payload = [0u8; PAYLOAD_LEN];
+			b.read_exact(&mut payload).await.unwrap();
+			payload[0] ^= 1;
+			b.write_all(&payload).await.unwrap();
+		};
+		let (result, ()) = tokio::join!(ping(&mut a), corrupter);
+
+		assert!(matches!(result, Err(Error::PingMismatch)));
+	}
+
+	#[tokio::test]
+	async fn test_round_trip_is_reported() {
+		let (listener, address, _) = listening(node(PingConfig::default())).await;
+		let mut pinger = node(PingConfig::default().with_interval(Duration::from_millis(50)));
+		pinger.dial(listener, address).await.unwrap();
+
+		loop {
+			if let Event::Ping { peer, .. } = next_event(&mut pinger).await {
+				assert_eq!(peer, listener);
+				break;
+			}
+		}
+		assert!(pinger.peer_info(&listener).unwrap().rtt.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_unanswered_pings_close_the_connection() {
+		let silent = node(PingConfig::default());
+		// Read the pings without ever echoing them.
+		silent.set_stream_handler(PROTOCOL, |_, mut stream| async move {
+			let _ = futures::io::copy(&mut stream, &mut futures::io::sink()).await;
+		});
+		let (listener, address, _) = listening(silent).await;
+		let ping = PingConfig::default()
+			.with_interval(Duration::from_millis(50))
+			.with_timeout(Duration::from_millis(50))
+			.with_max_failures(2);
+		let mut pinger = node(ping);
+		pinger.dial(listener, address).await.unwrap();
+
+		loop {
+			match next_event(&mut pinger).await {
+				Event::Ping { .. } => panic!("the silent peer answered a ping"),
+				Event::ConnectionClosed { peer_id, cause, .. } => {
+					assert_eq!(peer_id, listener);
+					assert!(matches!(cause, CloseCause::KeepAliveTimeout), "closed by {cause:?}");
+					break;
+				}
+				_ => {}
+			}
+		}
+	}
+}
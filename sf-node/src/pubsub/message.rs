@@ -0,0 +1,197 @@
+use libp2p_identity::{Keypair, PublicKey};
+use multiaddr::PeerId;
+use serde::{Deserialize, Serialize};
+use unsigned_varint::encode;
+
+use crate::error::Error;
+
+/// Prefix of the signed payload, so that a message signature is never valid in another protocol.
+const SIGNING_PREFIX: &[u8] = b"sf-pubsub:";
+
+/// Identifies a message across the network, peers forward every message once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId {
+	pub source: PeerId,
+	pub sequence_number: u64,
+}
+
+/// A message published on a topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+	/// Peer which published and signed the message.
+	pub source: PeerId,
+	/// Increases with every message of `source`.
+	pub sequence_number: u64,
+	pub topic: String,
+	pub data: Vec<u8>,
+}
+
+impl Message {
+	pub fn id(&self) -> MessageId {
+		MessageId {
+			source: self.source,
+			sequence_number: self.sequence_number,
+		}
+	}
+
+	/// The bytes signed by the source: the fields in order, each prefixed by its length as an unsigned varint so that
+	/// two different messages never sign the same bytes. Independent of the wire encoding.
+	fn signed_payload(&self) -> Vec<u8> {
+		let source = self.source.to_bytes();
+		let mut payload = SIGNING_PREFIX.to_vec();
+		let mut buf = encode::usize_buffer();
+		for field in [
+			&source[..],
+			&self.sequence_number.to_be_bytes(),
+			self.topic.as_bytes(),
+			&self.data,
+		] {
+			payload.extend_from_slice(encode::usize(field.len(), &mut buf));
+			payload.extend_from_slice(field);
+		}
+		payload
+	}
+}
+
+/// A message along with the key and the signature of its source.
+#[derive(Debug, Clone)]
+pub(crate) struct SignedMessage {
+	pub(crate) message: Message,
+	/// Public key of the source, protobuf encoded.
+	key: Vec<u8>,
+	signature: Vec<u8>,
+}
+
+/// A [`SignedMessage`] as encoded on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct RawMessage {
+	#[prost(bytes = "vec", tag = "1")]
+	source: Vec<u8>,
+	#[prost(uint64, tag = "2")]
+	sequence_number: u64,
+	#[prost(string, tag = "3")]
+	topic: String,
+	#[prost(bytes = "vec", tag = "4")]
+	data: Vec<u8>,
+	#[prost(bytes = "vec", tag = "5")]
+	key: Vec<u8>,
+	#[prost(bytes = "vec", tag = "6")]
+	signature: Vec<u8>,
+}
+
+impl From<&SignedMessage> for RawMessage {
+	fn from(signed: &SignedMessage) -> Self {
+		Self {
+			source: signed.message.source.to_bytes(),
+			sequence_number: signed.message.sequence_number,
+			topic: signed.message.topic.clone(),
+			data: signed.message.data.clone(),
+			key: signed.key.clone(),
+			signature: signed.signature.clone(),
+		}
+	}
+}
+
+impl TryFrom<RawMessage> for SignedMessage {
+	type Error = Error;
+
+	fn try_from(raw: RawMessage) -> Result<Self, Self::Error> {
+		Ok(Self {
+			message: Message {
+				source: PeerId::from_bytes(&raw.source)?,
+				sequence_number: raw.sequence_number,
+				topic: raw.topic,
+				data: raw.data,
+			},
+			key: raw.key,
+			signature: raw.signature,
+		})
+	}
+}
+
+impl SignedMessage {
+	/// Sign `message` with `keypair`, which must be the key of its source.
+	pub(crate) fn sign(message: Message, keypair: &Keypair) -> Result<Self, Error> {
+		let signature = keypair.sign(&message.signed_payload())?;
+		Ok(Self {
+			message,
+			key: keypair.public().encode_protobuf(),
+			signature,
+		})
+	}
+
+	/// Check that the source of the message signed it.
+	pub(crate) fn verify(&self) -> Result<(), Error> {
+		let source = self.message.source;
+		let key = PublicKey::try_decode_protobuf(&self.key)?;
+		if key.to_peer_id() != source {
+			return Err(Error::PeerIdMismatch {
+				expected: Box::new(source),
+				actual: Box::new(key.to_peer_id()),
+			});
+		}
+		if !key.verify(&self.message.signed_payload(), &self.signature) {
+			return Err(Error::InvalidSignature(source));
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use prost::Message as _;
+
+	use super::*;
+
+	fn signed(keypair: &Keypair) -> SignedMessage {
+		let message = Message {
+			source: keypair.public().to_peer_id(),
+			sequence_number: 7,
+			topic: "state".to_owned(),
+			data: b"hello".to_vec(),
+		};
+		SignedMessage::sign(message, keypair).unwrap()
+	}
+
+	#[test]
+	fn test_signature_round_trip() {
+		let keypair = Keypair::generate_ed25519();
+		let signed = signed(&keypair);
+
+		let raw = RawMessage::decode(RawMessage::from(&signed).encode_to_vec().as_slice()).unwrap();
+		let decoded = SignedMessage::try_from(raw).unwrap();
+
+		decoded.verify().unwrap();
+		assert_eq!(decoded.message, signed.message);
+	}
+
+	#[test]
+	fn test_rejects_tampered_messages() {
+		let keypair = Keypair::generate_ed25519();
+
+		let mut tampered = signed(&keypair);
+		tampered.message.data = b"bye".to_vec();
+		assert!(matches!(tampered.verify(), Err(Error::InvalidSignature(_))));
+
+		let mut forged = signed(&keypair);
+		forged.message.source = PeerId::random();
+		assert!(matches!(forged.verify(), Err(Error::PeerIdMismatch { .. })));
+
+		// Moving bytes from one field to the next changes the signed payload.
+		let mut shifted = signed(&keypair);
+		shifted.message.topic = "stat".to_owned();
+		shifted.message.data = b"ehello".to_vec();
+		assert!(matches!(shifted.verify(), Err(Error::InvalidSignature(_))));
+	}
+
+	#[test]
+	fn test_data_is_encoded_as_bytes() {
+		let keypair = Keypair::generate_ed25519();
+		let mut signed = signed(&keypair);
+		signed.message.data = vec![0xff; 1000];
+
+		let len = RawMessage::from(&signed).encoded_len();
+
+		assert!(len < 1200, "{len} bytes");
+	}
+}
@@ -0,0 +1,833 @@
+//! Topic based publish/subscribe, in the spirit of gossipsub.
+//!
+//! Every established connection opens a long lived stream negotiating [`PROTOCOL`] from both sides, each side only
+//! writes on the stream it opened. Peers announce the topics they subscribe to, then graft a few of the peers sharing
+//! a topic into their mesh for that topic, prune the extra ones and keep the mesh within bounds on every heartbeat.
+//!
+//! Published messages are signed by their source and sent to every peer subscribed to the topic. The peers receiving
+//! them check the signature, drop those already seen, run the validator of the topic, and forward the accepted ones
+//! to their mesh only.
+
+mod message;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::future;
+use futures::{AsyncRead, AsyncWrite, StreamExt};
+use libp2p_identity::Keypair;
+use multiaddr::PeerId;
+use prost::Message as _;
+use rand::seq::SliceRandom;
+use sf_core::Stream as _;
+use tracing::debug;
+
+pub use message::{Message, MessageId};
+use message::{RawMessage, SignedMessage};
+
+use crate::connection::Connection;
+use crate::connection_manager::ConnectionId;
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+use crate::node::Event;
+use crate::stream::Stream;
+use crate::stream_handler::Closing;
+
+pub(crate) const PROTOCOL: &str = "/sf/pubsub/1.0.0";
+
+/// Configuration of the publish/subscribe protocol, see [`crate::Builder::with_pubsub`].
+#[derive(Debug, Clone, Copy)]
+pub struct PubsubConfig {
+	mesh_n: usize,
+	mesh_n_low: usize,
+	mesh_n_high: usize,
+	heartbeat_interval: Duration,
+	seen_ttl: Duration,
+	max_transmit_size: usize,
+}
+
+impl Default for PubsubConfig {
+	fn default() -> Self {
+		Self {
+			mesh_n: 6,
+			mesh_n_low: 4,
+			mesh_n_high: 12,
+			heartbeat_interval: Duration::from_secs(1),
+			seen_ttl: Duration::from_secs(120),
+			max_transmit_size: 1024 * 1024,
+		}
+	}
+}
+
+impl PubsubConfig {
+	/// Number of peers the mesh of a topic is brought back to when it leaves its bounds.
+	pub fn with_mesh_n(mut self, mesh_n: usize) -> Self {
+		self.mesh_n = mesh_n;
+		self
+	}
+
+	/// Size under which the mesh of a topic grafts more peers.
+	pub fn with_mesh_n_low(mut self, mesh_n_low: usize) -> Self {
+		self.mesh_n_low = mesh_n_low;
+		self
+	}
+
+	/// Size over which the mesh of a topic prunes peers.
+	pub fn with_mesh_n_high(mut self, mesh_n_high: usize) -> Self {
+		self.mesh_n_high = mesh_n_high;
+		self
+	}
+
+	/// Delay between two checks of the meshes.
+	pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+		self.heartbeat_interval = interval;
+		self
+	}
+
+	/// Time a message id is remembered, a message seen again within that time is dropped.
+	pub fn with_seen_ttl(mut self, ttl: Duration) -> Self {
+		self.seen_ttl = ttl;
+		self
+	}
+
+	/// Largest encoded message exchanged with a peer, bigger ones are neither published nor accepted.
+	pub fn with_max_transmit_size(mut self, size: usize) -> Self {
+		self.max_transmit_size = size;
+		self
+	}
+
+	pub(crate) fn heartbeat_interval(&self) -> Duration {
+		self.heartbeat_interval
+	}
+}
+
+/// Outcome of the validator of a topic, see [`crate::Node::set_topic_validator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+	/// Deliver the message and forward it to the mesh.
+	Accept,
+	/// Drop the message, it is invalid.
+	Reject,
+	/// Drop the message without judging it, for instance because it is outdated.
+	Ignore,
+}
+
+type Validator = Arc<dyn Fn(&PeerId, &Message) -> Validation + Send + Sync>;
+
+/// What peers send each other on their pubsub streams, encoded as protobuf.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Rpc {
+	#[prost(message, repeated, tag = "1")]
+	subscriptions: Vec<Subscription>,
+	#[prost(message, repeated, tag = "2")]
+	messages: Vec<RawMessage>,
+	/// Topics whose mesh the sender added the receiver to.
+	#[prost(string, repeated, tag = "3")]
+	graft: Vec<String>,
+	/// Topics whose mesh the sender removed the receiver from.
+	#[prost(string, repeated, tag = "4")]
+	prune: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Subscription {
+	#[prost(string, tag = "1")]
+	topic: String,
+	#[prost(bool, tag = "2")]
+	subscribe: bool,
+}
+
+/// Ids of the messages seen recently, each forgotten once its time to live elapsed.
+struct SeenCache {
+	ttl: Duration,
+	ids: HashSet<MessageId>,
+	expiries: VecDeque<(Instant, MessageId)>,
+}
+
+impl SeenCache {
+	fn new(ttl: Duration) -> Self {
+		Self {
+			ttl,
+			ids: HashSet::new(),
+			expiries: VecDeque::new(),
+		}
+	}
+
+	/// Remember `id`, returns whether it was not seen yet.
+	fn insert(&mut self, id: MessageId, now: Instant) -> bool {
+		self.prune(now);
+		if !self.ids.insert(id) {
+			return false;
+		}
+		self.expiries.push_back((now + self.ttl, id));
+		true
+	}
+
+	fn contains(&self, id: &MessageId) -> bool {
+		self.ids.contains(id)
+	}
+
+	fn prune(&mut self, now: Instant) {
+		while let Some((expires, id)) = self.expiries.front() {
+			if *expires > now {
+				break;
+			}
+			self.ids.remove(id);
+			self.expiries.pop_front();
+		}
+	}
+}
+
+/// A peer with a pubsub stream open on one of its connections, or which announced its topics on one.
+#[derive(Default)]
+struct Peer {
+	/// Writes on the stream opened by the local node, and the connection it was opened on.
+	outbound: Option<(ConnectionId, mpsc::UnboundedSender<Rpc>)>,
+	topics: HashSet<String>,
+}
+
+impl Peer {
+	fn send(&self, rpc: Rpc) {
+		if let Some((_, sender)) = &self.outbound {
+			let _ = sender.unbounded_send(rpc);
+		}
+	}
+}
+
+struct State {
+	subscriptions: HashSet<String>,
+	peers: HashMap<PeerId, Peer>,
+	/// Peers messages are forwarded to, for each subscribed topic.
+	mesh: HashMap<String, HashSet<PeerId>>,
+	seen: SeenCache,
+	validators: HashMap<String, Validator>,
+}
+
+impl State {
+	/// Connected peers subscribed to `topic`.
+	fn topic_peers<'a>(&'a self, topic: &'a str) -> impl Iterator<Item = PeerId> + 'a {
+		self.peers
+			.iter()
+			.filter(move |(_, peer)| peer.outbound.is_some() && peer.topics.contains(topic))
+			.map(|(peer_id, _)| *peer_id)
+	}
+
+	fn send(&self, peer_id: &PeerId, rpc: Rpc) {
+		if let Some(peer) = self.peers.get(peer_id) {
+			peer.send(rpc);
+		}
+	}
+
+	/// Add up to `count` of the peers subscribed to `topic` and not in its mesh yet, telling each of them.
+	fn graft(&mut self, topic: &str, count: usize) {
+		let mesh = self.mesh.get(topic).cloned().unwrap_or_default();
+		let mut candidates: Vec<_> = self
+			.topic_peers(topic)
+			.filter(|peer_id| !mesh.contains(peer_id))
+			.collect();
+		candidates.shuffle(&mut rand::thread_rng());
+		candidates.truncate(count);
+
+		for peer_id in candidates {
+			debug!(%peer_id, topic, "Grafting peer");
+			self.send(
+				&peer_id,
+				Rpc {
+					graft: vec![topic.to_owned()],
+					..Rpc::default()
+				},
+			);
+			self.mesh.entry(topic.to_owned()).or_default().insert(peer_id);
+		}
+	}
+
+	/// Remove `count` random peers from the mesh of `topic`, telling each of them.
+	fn prune(&mut self, topic: &str, count: usize) {
+		let Some(mesh) = self.mesh.get_mut(topic) else {
+			return;
+		};
+		let mut pruned: Vec<_> = mesh.iter().copied().collect();
+		pruned.shuffle(&mut rand::thread_rng());
+		pruned.truncate(count);
+		for peer_id in &pruned {
+			mesh.remove(peer_id);
+		}
+
+		for peer_id in pruned {
+			debug!(%peer_id, topic, "Pruning peer");
+			self.send(
+				&peer_id,
+				Rpc {
+					prune: vec![topic.to_owned()],
+					..Rpc::default()
+				},
+			);
+		}
+	}
+}
+
+/// The publish/subscribe protocol of a node, shared with the tasks running its streams.
+pub(crate) struct Pubsub {
+	keypair: Keypair,
+	config: PubsubConfig,
+	sequence_number: AtomicU64,
+	state: Mutex<State>,
+	events: mpsc::UnboundedSender<Event>,
+}
+
+impl Pubsub {
+	pub(crate) fn new(keypair: Keypair, config: PubsubConfig, events: mpsc::UnboundedSender<Event>) -> Self {
+		Self {
+			keypair,
+			config,
+			// Random so that a restarted node does not reuse the ids of its previous messages.
+			sequence_number: AtomicU64::new(rand::random()),
+			state: Mutex::new(State {
+				subscriptions: HashSet::new(),
+				peers: HashMap::new(),
+				mesh: HashMap::new(),
+				seen: SeenCache::new(config.seen_ttl),
+				validators: HashMap::new(),
+			}),
+			events,
+		}
+	}
+
+	pub(crate) fn heartbeat_interval(&self) -> Duration {
+		self.config.heartbeat_interval
+	}
+
+	fn state(&self) -> MutexGuard<'_, State> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Subscribe to `topic`, returns whether the node was not subscribed yet.
+	pub(crate) fn subscribe(&self, topic: String) -> bool {
+		let mut state = self.state();
+		if !state.subscriptions.insert(topic.clone()) {
+			return false;
+		}
+
+		for peer in state.peers.values() {
+			peer.send(Rpc {
+				subscriptions: vec![Subscription {
+					topic: topic.clone(),
+					subscribe: true,
+				}],
+				..Rpc::default()
+			});
+		}
+		state.mesh.insert(topic.clone(), HashSet::new());
+		state.graft(&topic, self.config.mesh_n);
+		true
+	}
+
+	/// Unsubscribe from `topic`, returns whether the node was subscribed.
+	pub(crate) fn unsubscribe(&self, topic: &str) -> bool {
+		let mut state = self.state();
+		if !state.subscriptions.remove(topic) {
+			return false;
+		}
+
+		let mesh = state.mesh.get(topic).map_or(0, HashSet::len);
+		state.prune(topic, mesh);
+		state.mesh.remove(topic);
+		for peer in state.peers.values() {
+			peer.send(Rpc {
+				subscriptions: vec![Subscription {
+					topic: topic.to_owned(),
+					subscribe: false,
+				}],
+				..Rpc::default()
+			});
+		}
+		true
+	}
+
+	pub(crate) fn set_validator(&self, topic: String, validator: Validator) {
+		self.state().validators.insert(topic, validator);
+	}
+
+	pub(crate) fn remove_validator(&self, topic: &str) -> bool {
+		self.state().validators.remove(topic).is_some()
+	}
+
+	/// Peers of the mesh of `topic`, empty when not subscribed to it.
+	pub(crate) fn mesh_peers(&self, topic: &str) -> Vec<PeerId> {
+		self.state()
+			.mesh
+			.get(topic)
+			.map(|mesh| mesh.iter().copied().collect())
+			.unwrap_or_default()
+	}
+
+	/// Sign and send `data` to every connected peer subscribed to `topic`.
+	pub(crate) fn publish(&self, topic: String, data: Vec<u8>) -> Result<MessageId, Error> {
+		let message = Message {
+			source: self.keypair.public().to_peer_id(),
+			sequence_number: self.sequence_number.fetch_add(1, Ordering::Relaxed),
+			topic,
+			data,
+		};
+		let id = message.id();
+		let signed = SignedMessage::sign(message, &self.keypair)?;
+		let raw = RawMessage::from(&signed);
+		let len = raw.encoded_len();
+		if len > self.config.max_transmit_size {
+			return Err(Error::MessageTooLarge(len));
+		}
+
+		let mut state = self.state();
+		let topic = &signed.message.topic;
+		let recipients: Vec<_> = state.topic_peers(topic).collect();
+		if recipients.is_empty() {
+			return Err(Error::NoPeersSubscribed(topic.clone()));
+		}
+
+		state.seen.insert(id, Instant::now());
+		for peer_id in recipients {
+			state.send(
+				&peer_id,
+				Rpc {
+					messages: vec![raw.clone()],
+					..Rpc::default()
+				},
+			);
+		}
+		Ok(id)
+	}
+
+	/// Keep the mesh of every subscribed topic within its bounds and forget the expired message ids.
+	pub(crate) fn heartbeat(&self) {
+		let mut state = self.state();
+		state.seen.prune(Instant::now());
+
+		let topics: Vec<_> = state.subscriptions.iter().cloned().collect();
+		for topic in topics {
+			let mesh = state.mesh.get(&topic).map_or(0, HashSet::len);
+			if mesh < self.config.mesh_n_low {
+				state.graft(&topic, self.config.mesh_n - mesh);
+			} else if mesh > self.config.mesh_n_high {
+				state.prune(&topic, mesh - self.config.mesh_n);
+			}
+		}
+	}
+
+	/// Start sending to `peer_id` on a stream opened on `connection_id`, announcing the local subscriptions.
+	fn add_peer(&self, peer_id: PeerId, connection_id: ConnectionId, sender: mpsc::UnboundedSender<Rpc>) {
+		let mut state = self.state();
+		let subscriptions = state
+			.subscriptions
+			.iter()
+			.map(|topic| Subscription {
+				topic: topic.clone(),
+				subscribe: true,
+			})
+			.collect::<Vec<_>>();
+		if !subscriptions.is_empty() {
+			let _ = sender.unbounded_send(Rpc {
+				subscriptions,
+				..Rpc::default()
+			});
+		}
+		state.peers.entry(peer_id).or_default().outbound = Some((connection_id, sender));
+	}
+
+	/// Forget `peer_id` once the stream opened on `connection_id` closed, unless another connection replaced it.
+	fn remove_peer(&self, peer_id: PeerId, connection_id: ConnectionId) {
+		let mut state = self.state();
+		let replaced = state
+			.peers
+			.get(&peer_id)
+			.and_then(|peer| peer.outbound.as_ref())
+			.is_some_and(|(id, _)| *id != connection_id);
+		if replaced {
+			return;
+		}
+
+		state.peers.remove(&peer_id);
+		for mesh in state.mesh.values_mut() {
+			mesh.remove(&peer_id);
+		}
+	}
+
+	fn handle_rpc(&self, peer_id: PeerId, rpc: Rpc) {
+		let mut state = self.state();
+		for Subscription { topic, subscribe } in rpc.subscriptions {
+			let topics = &mut state.peers.entry(peer_id).or_default().topics;
+			let event = if subscribe {
+				topics.insert(topic.clone()).then(|| Event::Subscribed {
+					peer_id,
+					topic: topic.clone(),
+				})
+			} else {
+				topics.remove(&topic).then(|| Event::Unsubscribed {
+					peer_id,
+					topic: topic.clone(),
+				})
+			};
+			if !subscribe && let Some(mesh) = state.mesh.get_mut(&topic) {
+				mesh.remove(&peer_id);
+			}
+			if let Some(event) = event {
+				let _ = self.events.unbounded_send(event);
+			}
+		}
+
+		for topic in rpc.graft {
+			let mesh_full = state
+				.mesh
+				.get(&topic)
+				.is_some_and(|mesh| mesh.len() >= self.config.mesh_n_high);
+			match state.mesh.get_mut(&topic) {
+				Some(mesh) if !mesh_full => {
+					mesh.insert(peer_id);
+				}
+				// Not subscribed, or enough peers already.
+				_ => state.send(
+					&peer_id,
+					Rpc {
+						prune: vec![topic],
+						..Rpc::default()
+					},
+				),
+			}
+		}
+
+		for topic in rpc.prune {
+			if let Some(mesh) = state.mesh.get_mut(&topic) {
+				mesh.remove(&peer_id);
+			}
+		}
+		drop(state);
+
+		for raw in rpc.messages {
+			match SignedMessage::try_from(raw) {
+				Ok(signed) => self.handle_message(peer_id, signed),
+				Err(error) => debug!(%peer_id, ?error, "Dropping undecodable message"),
+			}
+		}
+	}
+
+	/// Deliver and forward `signed` if it was not seen yet and passes the checks.
+	fn handle_message(&self, propagation_source: PeerId, signed: SignedMessage) {
+		let id = signed.message.id();
+		{
+			let state = self.state();
+			if !state.subscriptions.contains(&signed.message.topic) || state.seen.contains(&id) {
+				return;
+			}
+		}
+
+		// Checked before the id is remembered, so that a forged copy cannot shadow the genuine message.
+		if let Err(error) = signed.verify() {
+			debug!(%propagation_source, ?id, ?error, "Dropping message with an invalid signature");
+			return;
+		}
+		let validator = {
+			let mut state = self.state();
+			if !state.seen.insert(id, Instant::now()) {
+				return;
+			}
+			state.validators.get(&signed.message.topic).cloned()
+		};
+		let validation = validator.map_or(Validation::Accept, |validator| {
+			validator(&propagation_source, &signed.message)
+		});
+		if validation != Validation::Accept {
+			debug!(%propagation_source, ?id, ?validation, "Dropping message");
+			return;
+		}
+
+		let state = self.state();
+		let source = signed.message.source;
+		let forward_to = state
+			.mesh
+			.get(&signed.message.topic)
+			.into_iter()
+			.flatten()
+			.filter(|peer_id| **peer_id != propagation_source && **peer_id != source);
+		let raw = RawMessage::from(&signed);
+		for peer_id in forward_to {
+			state.send(
+				peer_id,
+				Rpc {
+					messages: vec![raw.clone()],
+					..Rpc::default()
+				},
+			);
+		}
+		drop(state);
+
+		let _ = self.events.unbounded_send(Event::Message {
+			propagation_source,
+			id,
+			message: signed.message,
+		});
+	}
+}
+
+/// Open the pubsub stream of `connection` and write the messages for `peer_id` on it until the connection closes.
+pub(crate) async fn run(pubsub: Arc<Pubsub>, peer_id: PeerId, connection_id: ConnectionId, mut connection: Connection) {
+	let closed = connection.closed();
+	let mut stream = match connection.open_stream_with_protocol(PROTOCOL).await {
+		Ok(stream) => stream,
+		Err(error) => {
+			debug!(%peer_id, ?error, "Failed to open pubsub stream");
+			return;
+		}
+	};
+
+	let (sender, mut receiver) = mpsc::unbounded();
+	pubsub.add_peer(peer_id, connection_id, sender);
+	let sending = async {
+		while let Some(rpc) = receiver.next().await {
+			if let Err(error) = send(&mut stream, &rpc).await {
+				debug!(%peer_id, ?error, "Failed to send on pubsub stream");
+				break;
+			}
+		}
+		let _ = stream.close().await;
+	};
+	future::select(pin!(sending), closed).await;
+	pubsub.remove_peer(peer_id, connection_id);
+}
+
+/// Read the messages `peer_id` sends on `stream` until it closes the stream, or until the node shuts down.
+pub(crate) async fn handle(pubsub: Arc<Pubsub>, closing: Closing, peer_id: PeerId, mut stream: Stream) {
+	let receiving = async {
+		loop {
+			match recv(&mut stream, pubsub.config.max_transmit_size).await {
+				Ok(rpc) => pubsub.handle_rpc(peer_id, rpc),
+				Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof => break,
+				Err(error) => {
+					debug!(%peer_id, ?error, "Pubsub stream failed");
+					break;
+				}
+			}
+		}
+	};
+	future::select(pin!(receiving), closing).await;
+	let _ = stream.close().await;
+}
+
+async fn send<S>(stream: &mut S, rpc: &Rpc) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+{
+	write_length_prefixed(stream, rpc.encode_to_vec()).await
+}
+
+async fn recv<S>(stream: &mut S, max_size: usize) -> Result<Rpc, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	Ok(Rpc::decode(read_length_prefixed(stream, max_size).await?.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use multiaddr::Multiaddr;
+
+	use crate::test_util::{self, listen};
+	use crate::{Node, runtime};
+
+	const TOPIC: &str = "state";
+
+	fn node() -> Node {
+		test_util::node(|builder| {
+			builder.with_pubsub(PubsubConfig::default().with_heartbeat_interval(Duration::from_millis(50)))
+		})
+	}
+
+	/// Drive `nodes` until `done` holds, returns the events they reported meanwhile along with the index of their node.
+	async fn drive_until(
+		nodes: &mut [&mut Node],
+		mut done: impl FnMut(&[&mut Node], &[(usize, Event)]) -> bool,
+	) -> Vec<(usize, Event)> {
+		let mut events = Vec::new();
+		runtime::timeout(Duration::from_secs(5), async {
+			while !done(nodes, &events) {
+				let mut streams = futures::stream::select_all(
+					nodes
+						.iter_mut()
+						.enumerate()
+						.map(|(index, node)| (&mut **node).map(move |event| (index, event))),
+				);
+				// Bounded, the meshes are maintained without reporting any event.
+				if let Ok(Some(event)) = runtime::timeout(Duration::from_millis(20), streams.next()).await {
+					events.push(event);
+				}
+			}
+		})
+		.await
+		.expect("condition not met within 5s");
+		events
+	}
+
+	async fn connect(dialer: &Node, listener: &mut Node, address: Multiaddr) {
+		let peer_id = listener.peer_id;
+		let mut listeners = [listener];
+		let accept = drive_until(&mut listeners, |_, events| {
+			events
+				.iter()
+				.any(|(_, event)| matches!(event, Event::NewConnection { .. }))
+		});
+		let (dialed, _) = tokio::join!(dialer.dial(peer_id, address), accept);
+		dialed.unwrap();
+	}
+
+	/// Subscribed nodes connected in a line, each one to the next.
+	async fn line(len: usize) -> Vec<Node> {
+		let mut nodes: Vec<_> = (0..len).map(|_| node()).collect();
+		for node in &nodes {
+			assert!(node.subscribe(TOPIC));
+		}
+		for i in 1..len {
+			let (dialers, listeners) = nodes.split_at_mut(i);
+			let address = listen(&mut listeners[0], "/memory/0").await;
+			connect(&dialers[i - 1], &mut listeners[0], address).await;
+		}
+		nodes
+	}
+
+	/// Messages delivered to the node `index` among `events`.
+	fn delivered(events: &[(usize, Event)], index: usize) -> Vec<(PeerId, Message)> {
+		events
+			.iter()
+			.filter_map(|(node, event)| match event {
+				Event::Message {
+					propagation_source,
+					message,
+					..
+				} if *node == index => Some((*propagation_source, message.clone())),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_seen_cache_forgets_expired_ids() {
+		let mut seen = SeenCache::new(Duration::from_secs(10));
+		let id = MessageId {
+			source: PeerId::random(),
+			sequence_number: 1,
+		};
+		let now = Instant::now();
+
+		assert!(seen.insert(id, now));
+		assert!(!seen.insert(id, now + Duration::from_secs(5)));
+		assert!(seen.insert(id, now + Duration::from_secs(10)));
+	}
+
+	#[tokio::test]
+	async fn test_messages_reach_peers_through_the_mesh() {
+		let mut nodes = line(3).await;
+		let [a, b, c] = nodes.as_mut_slice() else {
+			unreachable!()
+		};
+		let (a_id, b_id) = (a.peer_id, b.peer_id);
+		drive_until(&mut [a, b, c], |nodes, _| {
+			nodes[0].mesh_peers(TOPIC) == [b_id] && nodes[1].mesh_peers(TOPIC).len() == 2
+		})
+		.await;
+
+		let id = a.publish(TOPIC, b"hello".to_vec()).unwrap();
+
+		let events = drive_until(&mut [a, b, c], |_, events| !delivered(events, 2).is_empty()).await;
+		let [(propagation_source, message)] = &delivered(&events, 2)[..] else {
+			panic!("expected a single message");
+		};
+		assert_eq!(*propagation_source, b_id);
+		assert_eq!(message.id(), id);
+		assert_eq!(message.source, a_id);
+		assert_eq!(message.data, b"hello");
+		assert!(delivered(&events, 0).is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_duplicates_are_delivered_once() {
+		let mut nodes = line(3).await;
+		let address = listen(&mut nodes[0], "/memory/0").await;
+		let (first, rest) = nodes.split_at_mut(1);
+		connect(&rest[1], &mut first[0], address).await;
+		let [a, b, c] = nodes.as_mut_slice() else {
+			unreachable!()
+		};
+		drive_until(&mut [a, b, c], |nodes, _| {
+			nodes.iter().all(|node| node.mesh_peers(TOPIC).len() == 2)
+		})
+		.await;
+
+		a.publish(TOPIC, b"hello".to_vec()).unwrap();
+
+		// Both receive the message from the source, then from each other.
+		let started = Instant::now();
+		let events = drive_until(&mut [a, b, c], |_, _| started.elapsed() > Duration::from_millis(300)).await;
+		assert_eq!(delivered(&events, 1).len(), 1);
+		assert_eq!(delivered(&events, 2).len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_validator_stops_rejected_messages() {
+		let mut nodes = line(3).await;
+		let [a, b, c] = nodes.as_mut_slice() else {
+			unreachable!()
+		};
+		b.set_topic_validator(TOPIC, |_, message| match &message.data[..] {
+			b"bad" => Validation::Reject,
+			_ => Validation::Accept,
+		});
+		let b_id = b.peer_id;
+		drive_until(&mut [a, b, c], |nodes, _| {
+			nodes[0].mesh_peers(TOPIC) == [b_id] && nodes[2].mesh_peers(TOPIC) == [b_id]
+		})
+		.await;
+
+		a.publish(TOPIC, b"bad".to_vec()).unwrap();
+		a.publish(TOPIC, b"good".to_vec()).unwrap();
+
+		let events = drive_until(&mut [a, b, c], |_, events| !delivered(events, 2).is_empty()).await;
+		for index in [1, 2] {
+			let delivered = delivered(&events, index);
+			assert_eq!(delivered.len(), 1);
+			assert_eq!(delivered[0].1.data, b"good");
+		}
+	}
+
+	#[tokio::test]
+	async fn test_publish_requires_subscribed_peers() {
+		let mut nodes = line(2).await;
+		let [a, b] = nodes.as_mut_slice() else { unreachable!() };
+		let announced = |topic_event: fn(&Event) -> bool| {
+			move |_: &[&mut Node], events: &[(usize, Event)]| {
+				events.iter().any(|(node, event)| *node == 0 && topic_event(event))
+			}
+		};
+		drive_until(
+			&mut [a, b],
+			announced(|event| matches!(event, Event::Subscribed { topic, .. } if topic == TOPIC)),
+		)
+		.await;
+
+		assert!(b.unsubscribe(TOPIC));
+		assert!(!b.unsubscribe(TOPIC));
+		drive_until(
+			&mut [a, b],
+			announced(|event| matches!(event, Event::Unsubscribed { topic, .. } if topic == TOPIC)),
+		)
+		.await;
+
+		assert!(matches!(
+			a.publish(TOPIC, b"hello".to_vec()),
+			Err(Error::NoPeersSubscribed(topic)) if topic == TOPIC
+		));
+		assert!(b.mesh_peers(TOPIC).is_empty());
+	}
+}
@@ -0,0 +1,275 @@
+use std::{
+	collections::VecDeque,
+	fmt, io,
+	pin::Pin,
+	sync::{Arc, PoisonError},
+	task::{Context, Poll},
+};
+
+use futures::{
+	AsyncRead, AsyncWrite, FutureExt, StreamExt,
+	channel::{mpsc, oneshot},
+	future::{BoxFuture, Shared},
+	lock::Mutex,
+	ready,
+};
+use multiaddr::{Multiaddr, PeerId};
+
+use super::frame::{End, Ending, Framed};
+use crate::error::Error;
+use crate::runtime;
+use crate::stream::Stream;
+
+enum Command {
+	Open(oneshot::Sender<Result<yamux::Stream, Error>>),
+	Close,
+}
+
+/// Drives the yamux session run over a relayed stream, which only makes progress while polled.
+///
+/// Stops once the session fails, the relay ends the circuit, or every handle to the circuit is dropped.
+struct Driver {
+	muxer: yamux::Connection<Framed<Stream>>,
+	commands: mpsc::UnboundedReceiver<Command>,
+	/// Outbound streams waiting for the session to accept a new one.
+	opening: VecDeque<oneshot::Sender<Result<yamux::Stream, Error>>>,
+	incoming: mpsc::UnboundedSender<yamux::Stream>,
+	closing: bool,
+	/// Dropped along with the driver, which resolves [`Circuit::closed`].
+	_closed: oneshot::Sender<()>,
+}
+
+impl Future for Driver {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		let this = self.get_mut();
+
+		while !this.closing {
+			match this.commands.poll_next_unpin(cx) {
+				Poll::Ready(Some(Command::Open(reply))) => this.opening.push_back(reply),
+				Poll::Ready(Some(Command::Close) | None) => this.closing = true,
+				Poll::Pending => break,
+			}
+		}
+		if this.closing {
+			if let Err(error) = ready!(this.muxer.poll_close(cx)) {
+				tracing::debug!(?error, "Failed to close the relayed yamux session");
+			}
+			return Poll::Ready(());
+		}
+
+		while let Some(reply) = this.opening.front() {
+			if reply.is_canceled() {
+				this.opening.pop_front();
+				continue;
+			}
+			match this.muxer.poll_new_outbound(cx) {
+				Poll::Ready(result) => {
+					let failed = result.is_err();
+					let reply = this.opening.pop_front().expect("checked above");
+					let _ = reply.send(result.map_err(|e| Error::Io(io::Error::other(e))));
+					if failed {
+						return Poll::Ready(());
+					}
+				}
+				Poll::Pending => break,
+			}
+		}
+
+		loop {
+			match ready!(this.muxer.poll_next_inbound(cx)) {
+				Some(Ok(stream)) => {
+					let _ = this.incoming.unbounded_send(stream);
+				}
+				Some(Err(error)) => {
+					tracing::debug!(?error, "Relayed yamux session failed");
+					return Poll::Ready(());
+				}
+				None => return Poll::Ready(()),
+			}
+		}
+	}
+}
+
+struct Inner {
+	commands: mpsc::UnboundedSender<Command>,
+	closed: Shared<oneshot::Receiver<()>>,
+	/// Shared with the framing of the relayed stream, which sends and receives the last frame of the circuit.
+	ending: Arc<std::sync::Mutex<Ending>>,
+	incoming: Mutex<mpsc::UnboundedReceiver<yamux::Stream>>,
+}
+
+/// A connection relayed through a third peer, each stream being a yamux stream over the relayed one.
+///
+/// Clones share the yamux session, which is closed once they are all dropped.
+#[derive(Clone)]
+pub struct Circuit {
+	inner: Arc<Inner>,
+	remote_address: Multiaddr,
+	remote_peer_id: Option<PeerId>,
+}
+
+impl Circuit {
+	/// Start the yamux session over `stream`, the relayed stream of a circuit reaching `remote_address`.
+	pub(crate) fn new(stream: Stream, mode: yamux::Mode, remote_address: Multiaddr) -> Self {
+		let (commands, commands_rx) = mpsc::unbounded();
+		let (incoming_tx, incoming) = mpsc::unbounded();
+		let (closed_tx, closed) = oneshot::channel();
+		let ending = Arc::default();
+
+		runtime::spawn(Driver {
+			muxer: yamux::Connection::new(Framed::new(stream, Arc::clone(&ending)), yamux::Config::default(), mode),
+			commands: commands_rx,
+			opening: VecDeque::new(),
+			incoming: incoming_tx,
+			closing: false,
+			_closed: closed_tx,
+		});
+
+		Self {
+			inner: Arc::new(Inner {
+				commands,
+				closed: closed.shared(),
+				ending,
+				incoming: Mutex::new(incoming),
+			}),
+			remote_address,
+			remote_peer_id: None,
+		}
+	}
+
+	/// Resolve once the circuit is closed, by either end or by the relay, with the code and reason it was closed with
+	/// or the limit the relay ended it at.
+	pub(crate) fn closed(&self) -> BoxFuture<'static, Error> {
+		let closed = self.inner.closed.clone();
+		let ending = Arc::clone(&self.inner.ending);
+		Box::pin(async move {
+			let _ = closed.await;
+			let ending = ending.lock().unwrap_or_else(PoisonError::into_inner);
+			match ending.received.clone().or_else(|| ending.sent.clone()) {
+				Some(end) => end.into(),
+				None => Error::CircuitClosed,
+			}
+		})
+	}
+
+	/// Close the circuit, both ends see `code` and `reason`.
+	pub(crate) fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		tracing::debug!(code, reason, remote_address = %self.remote_address, "Closing circuit");
+		self.inner
+			.ending
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.sent
+			.get_or_insert_with(|| End::Closed {
+				code,
+				reason: reason.to_owned(),
+			});
+		let _ = self.inner.commands.unbounded_send(Command::Close);
+		let closed = self.closed();
+		Box::pin(async move {
+			closed.await;
+			Ok(())
+		})
+	}
+
+	pub(crate) fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+}
+
+impl fmt::Debug for Circuit {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Circuit")
+			.field("remote_address", &self.remote_address)
+			.field("remote_peer_id", &self.remote_peer_id)
+			.field("closed", &self.inner.closed.peek().is_some())
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Connection for Circuit {
+	type Error = Error;
+	type Output = CircuitStream;
+
+	type Close = BoxFuture<'static, Result<(), Self::Error>>;
+	type Stream = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+	fn open_stream(&mut self) -> Self::Stream {
+		let (reply, opened) = oneshot::channel();
+		let sent = self.inner.commands.unbounded_send(Command::Open(reply)).is_ok();
+		async move {
+			if !sent {
+				return Err(Error::CircuitClosed);
+			}
+			// The driver drops the reply once the session is closed.
+			let stream = opened.await.map_err(|_| Error::CircuitClosed)??;
+			Ok(CircuitStream(stream))
+		}
+		.boxed()
+	}
+
+	fn accept_stream(&mut self) -> Self::Stream {
+		let inner = self.inner.clone();
+		async move {
+			let mut incoming = inner.incoming.lock().await;
+			incoming.next().await.map(CircuitStream).ok_or(Error::CircuitClosed)
+		}
+		.boxed()
+	}
+
+	fn close(&mut self) -> Self::Close {
+		self.close_with_reason(0, "Closing circuit")
+	}
+
+	fn remote_address(&self) -> &Multiaddr {
+		&self.remote_address
+	}
+
+	fn remote_peer_id(&self) -> Option<PeerId> {
+		self.remote_peer_id
+	}
+}
+
+/// A bidirectional stream of a [`Circuit`].
+pub struct CircuitStream(yamux::Stream);
+
+impl fmt::Debug for CircuitStream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("CircuitStream").field(&self.0.id()).finish()
+	}
+}
+
+impl sf_core::Stream for CircuitStream {
+	type Error = Error;
+
+	fn close_send(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		Box::pin(async move { Ok(futures::future::poll_fn(|cx| Pin::new(&mut self.0).poll_close(cx)).await?) })
+	}
+
+	fn close_read(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		// yamux has no way to stop the remote's writes, whatever it sends past this point is discarded with the stream.
+		Box::pin(async { Ok(()) })
+	}
+}
+
+impl AsyncWrite for CircuitStream {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.0).poll_write(cx, buf)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.0).poll_flush(cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.0).poll_close(cx)
+	}
+}
+
+impl AsyncRead for CircuitStream {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.0).poll_read(cx, buf)
+	}
+}
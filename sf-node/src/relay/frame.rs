@@ -0,0 +1,302 @@
+//! Frames of a relayed circuit. Both ends frame the bytes they send, so that a last frame can follow them and tell the
+//! other end why the circuit ended: closed by an end with a code and a reason, or ended by the relay at a limit.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, ready};
+
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
+use serde::{Deserialize, Serialize};
+
+use super::LimitReached;
+use crate::error::Error;
+
+const DATA: u8 = 0;
+const END: u8 = 1;
+/// A tag byte followed by the length of the payload as a big endian `u32`.
+const HEADER_SIZE: usize = 5;
+/// Largest payload of a frame.
+pub(crate) const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Why a circuit ended, sent in its last frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum End {
+	/// Closed by one of its ends.
+	Closed { code: u32, reason: String },
+	/// Ended by the relay.
+	Limit(LimitReached),
+}
+
+impl From<End> for Error {
+	fn from(end: End) -> Self {
+		match end {
+			End::Closed { code, reason } => Error::CircuitClosedWithReason { code, reason },
+			End::Limit(reached) => Error::CircuitLimit(reached),
+		}
+	}
+}
+
+/// A frame as read by the relay.
+pub(crate) enum Frame {
+	Data(Vec<u8>),
+	End(End),
+}
+
+impl Frame {
+	pub(crate) fn encode(&self) -> Result<Vec<u8>, Error> {
+		let (tag, payload) = match self {
+			Frame::Data(data) => (DATA, data.clone()),
+			Frame::End(end) => (END, serde_json::to_vec(end)?),
+		};
+		let mut frame = header(tag, payload.len()).to_vec();
+		frame.extend_from_slice(&payload);
+		Ok(frame)
+	}
+}
+
+fn header(tag: u8, len: usize) -> [u8; HEADER_SIZE] {
+	let len = u32::try_from(len).expect("frames are smaller than MAX_FRAME_SIZE");
+	let mut header = [tag; HEADER_SIZE];
+	header[1..].copy_from_slice(&len.to_be_bytes());
+	header
+}
+
+/// Tag and payload length of `header`, checked against [`MAX_FRAME_SIZE`].
+fn parse_header(header: [u8; HEADER_SIZE]) -> io::Result<(u8, usize)> {
+	let len = u32::from_be_bytes(header[1..].try_into().expect("4 bytes")) as usize;
+	if !matches!(header[0], DATA | END) || len > MAX_FRAME_SIZE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid circuit frame"));
+	}
+	Ok((header[0], len))
+}
+
+fn decode_end(payload: &[u8]) -> io::Result<End> {
+	serde_json::from_slice(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Read the next frame of `reader`, `None` once it finished between two frames.
+pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Frame>> {
+	let mut header = [0; HEADER_SIZE];
+	if reader.read(&mut header[..1]).await? == 0 {
+		return Ok(None);
+	}
+	reader.read_exact(&mut header[1..]).await?;
+	let (tag, len) = parse_header(header)?;
+	let mut payload = vec![0; len];
+	reader.read_exact(&mut payload).await?;
+	Ok(Some(match tag {
+		DATA => Frame::Data(payload),
+		_ => Frame::End(decode_end(&payload)?),
+	}))
+}
+
+/// Why the circuit ended, as sent by the local end and as received from the relay.
+#[derive(Debug, Default)]
+pub(crate) struct Ending {
+	pub(crate) sent: Option<End>,
+	pub(crate) received: Option<End>,
+}
+
+enum ReadState {
+	Header { header: [u8; HEADER_SIZE], filled: usize },
+	Data { remaining: usize },
+	End { payload: Vec<u8>, filled: usize },
+	Finished,
+}
+
+/// The relayed stream of a circuit end: frames what is written, reads the data of the frames received until the one
+/// ending the circuit.
+///
+/// Once [`Ending::sent`] is set the next write sends it instead, and discards whatever yamux writes after it: the
+/// remote stops reading at the end frame, while yamux stops at its own close frame.
+pub(crate) struct Framed<S> {
+	inner: S,
+	ending: Arc<Mutex<Ending>>,
+	read: ReadState,
+	/// Encoded frames not written to `inner` yet, from `written` on.
+	write: Vec<u8>,
+	written: usize,
+	end_queued: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Framed<S> {
+	pub(crate) fn new(inner: S, ending: Arc<Mutex<Ending>>) -> Self {
+		Self {
+			inner,
+			ending,
+			read: ReadState::Header {
+				header: [0; HEADER_SIZE],
+				filled: 0,
+			},
+			write: Vec::new(),
+			written: 0,
+			end_queued: false,
+		}
+	}
+
+	/// Queue [`Ending::sent`] once set, returns whether it is queued, after which nothing else is sent.
+	fn queue_end(&mut self) -> io::Result<bool> {
+		if !self.end_queued {
+			let sent = self.ending.lock().unwrap_or_else(PoisonError::into_inner).sent.clone();
+			let Some(end) = sent else {
+				return Ok(false);
+			};
+			let frame = Frame::End(end).encode().map_err(io::Error::other)?;
+			self.write.extend_from_slice(&frame);
+			self.end_queued = true;
+		}
+		Ok(true)
+	}
+
+	fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while self.written < self.write.len() {
+			let len = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write[self.written..]))?;
+			if len == 0 {
+				return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+			}
+			self.written += len;
+		}
+		self.write.clear();
+		self.written = 0;
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Framed<S> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+		loop {
+			match &mut this.read {
+				ReadState::Header { header, filled } => {
+					let len = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut header[*filled..]))?;
+					if len == 0 {
+						if *filled > 0 {
+							return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+						}
+						this.read = ReadState::Finished;
+						continue;
+					}
+					*filled += len;
+					if *filled == HEADER_SIZE {
+						this.read = match parse_header(*header)? {
+							(DATA, remaining) => ReadState::Data { remaining },
+							(_, len) => ReadState::End {
+								payload: vec![0; len],
+								filled: 0,
+							},
+						};
+					}
+				}
+				ReadState::Data { remaining: 0 } => {
+					this.read = ReadState::Header {
+						header: [0; HEADER_SIZE],
+						filled: 0,
+					};
+				}
+				ReadState::Data { remaining } => {
+					let max = buf.len().min(*remaining);
+					let len = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..max]))?;
+					if len == 0 {
+						return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+					}
+					*remaining -= len;
+					return Poll::Ready(Ok(len));
+				}
+				ReadState::End { payload, filled } if *filled < payload.len() => {
+					let len = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut payload[*filled..]))?;
+					if len == 0 {
+						return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+					}
+					*filled += len;
+				}
+				ReadState::End { payload, .. } => {
+					let end = decode_end(payload)?;
+					this.ending.lock().unwrap_or_else(PoisonError::into_inner).received = Some(end);
+					this.read = ReadState::Finished;
+				}
+				ReadState::Finished => return Poll::Ready(Ok(0)),
+			}
+		}
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Framed<S> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		ready!(this.poll_drain(cx))?;
+		if this.queue_end()? {
+			ready!(this.poll_drain(cx))?;
+			return Poll::Ready(Ok(buf.len()));
+		}
+		let len = buf.len().min(MAX_FRAME_SIZE);
+		if len == 0 {
+			return Poll::Ready(Ok(0));
+		}
+		this.write.extend_from_slice(&header(DATA, len));
+		this.write.extend_from_slice(&buf[..len]);
+		// Flushed by the next write, or by `poll_flush`.
+		let _ = this.poll_drain(cx)?;
+		Poll::Ready(Ok(len))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_drain(cx))?;
+		Pin::new(&mut this.inner).poll_flush(cx)
+	}
+
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		this.queue_end()?;
+		ready!(this.poll_drain(cx))?;
+		Pin::new(&mut this.inner).poll_close(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::{AsyncWriteExt, io::Cursor};
+
+	#[tokio::test]
+	async fn test_frames_round_trip() {
+		let ending = Arc::new(Mutex::new(Ending::default()));
+		let mut sender = Framed::new(Cursor::new(Vec::new()), Arc::clone(&ending));
+		let data = vec![3; MAX_FRAME_SIZE + 10];
+		sender.write_all(&data).await.unwrap();
+		ending.lock().unwrap().sent = Some(End::Closed {
+			code: 7,
+			reason: "bye".into(),
+		});
+		// Written after the end of the circuit, discarded.
+		sender.write_all(b"ignored").await.unwrap();
+		sender.close().await.unwrap();
+		let sent = sender.inner.into_inner();
+
+		// The relay reads the frames as they were written.
+		let mut reader = Cursor::new(sent.clone());
+		let mut relayed = Vec::new();
+		while let Some(frame) = read(&mut reader).await.unwrap() {
+			match frame {
+				Frame::Data(payload) => relayed.extend(payload),
+				Frame::End(end) => assert_eq!(end, ending.lock().unwrap().sent.clone().unwrap()),
+			}
+		}
+		assert_eq!(relayed, data);
+
+		let received = Arc::new(Mutex::new(Ending::default()));
+		let mut receiver = Framed::new(Cursor::new(sent), Arc::clone(&received));
+		let mut read = Vec::new();
+		receiver.read_to_end(&mut read).await.unwrap();
+		assert_eq!(read, data);
+		assert!(matches!(
+			received.lock().unwrap().received,
+			Some(End::Closed { code: 7, ref reason }) if reason == "bye"
+		));
+	}
+}
@@ -0,0 +1,877 @@
+//! Reach peers which cannot be dialed, such as browsers and NATed nodes, through a public node.
+//!
+//! A peer behind a NAT connects to a relay and reserves a slot on it with a [`HOP_PROTOCOL`] stream. Dialers then
+//! reach it on `/p2p/<relay>/p2p-circuit/p2p/<peer>`: they ask the relay to connect them, the relay opens a
+//! [`STOP_PROTOCOL`] stream to the reserved peer and splices both streams into a circuit. Both ends run a yamux
+//! session over the circuit, which becomes a [`Circuit`] connection authenticated like any other one.
+//!
+//! The relay bounds the duration and the bytes of every circuit, and ends it once either limit is reached. Both ends
+//! frame what they send over a circuit, so that its last frame tells them why it ended: the code and reason of the end
+//! which closed it, or the limit the relay ended it at, see [`Error::CircuitLimit`].
+
+mod circuit;
+mod frame;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, BoxFuture, Either};
+use futures::io::{ReadHalf, WriteHalf};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use multiaddr::{Multiaddr, PeerId, Protocol};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+pub use circuit::{Circuit, CircuitStream};
+use frame::Frame;
+
+use crate::connection::Connection;
+use crate::connection_manager::ConnectionManager;
+use crate::error::Error;
+use crate::framing::{read_length_prefixed, write_length_prefixed};
+use crate::runtime;
+use crate::stream::Stream;
+use crate::stream_handler::Closing;
+
+pub(crate) const HOP_PROTOCOL: &str = "/sf/relay/hop/1.0.0";
+pub(crate) const STOP_PROTOCOL: &str = "/sf/relay/stop/1.0.0";
+const MAX_MESSAGE_SIZE: usize = 4096;
+/// Time given to a reserved peer to accept a circuit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time given to a relay to renew a reservation.
+const RENEW_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time given to the ends of a circuit to read why the relay ended it.
+const END_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Limits of every circuit relayed for a reservation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitLimit {
+	/// Time after which the relay ends the circuit.
+	pub duration: Duration,
+	/// Bytes relayed in both directions after which the relay ends the circuit.
+	pub bytes: u64,
+}
+
+/// Limit of a [`CircuitLimit`] a relay ended a circuit at, see [`Error::CircuitLimit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LimitReached {
+	/// The circuit relayed this many bytes.
+	Bytes(u64),
+	/// The circuit lasted this long.
+	Duration(Duration),
+}
+
+/// Why a relay, or the peer reached through it, refused a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Refusal {
+	/// The relay holds as many reservations as it accepts.
+	TooManyReservations,
+	/// The target has no reservation on the relay, or the target holds none with the relay.
+	NoReservation,
+	/// The reservation of the target has as many circuits as it accepts.
+	TooManyCircuits,
+	/// The relay has no connection to the target, or the target did not answer.
+	TargetUnreachable,
+}
+
+/// A slot reserved on a relay, see [`crate::Node::reserve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+	pub relay: PeerId,
+	/// `/p2p/<relay>/p2p-circuit/p2p/<local peer>`, reachable by the peers which know an address of the relay.
+	pub address: Multiaddr,
+	/// Time after which the reservation ends unless renewed. The node renews it in the background for as long as its
+	/// connection to the relay stays open.
+	pub expires_in: Duration,
+	pub limit: CircuitLimit,
+}
+
+/// Configuration of the relay service, see [`crate::Builder::with_relay`].
+#[derive(Debug, Clone, Copy)]
+pub struct RelayConfig {
+	max_reservations: usize,
+	reservation_duration: Duration,
+	max_circuits_per_reservation: usize,
+	limit: CircuitLimit,
+}
+
+impl Default for RelayConfig {
+	fn default() -> Self {
+		Self {
+			max_reservations: 128,
+			reservation_duration: Duration::from_secs(60 * 60),
+			max_circuits_per_reservation: 16,
+			limit: CircuitLimit {
+				duration: Duration::from_secs(2 * 60),
+				bytes: 128 * 1024,
+			},
+		}
+	}
+}
+
+impl RelayConfig {
+	/// Peers holding a reservation at once, renewals included.
+	pub fn with_max_reservations(mut self, max_reservations: usize) -> Self {
+		self.max_reservations = max_reservations;
+		self
+	}
+
+	/// Time a reservation lasts unless renewed. It ends earlier when the connection it was made on closes.
+	pub fn with_reservation_duration(mut self, duration: Duration) -> Self {
+		self.reservation_duration = duration;
+		self
+	}
+
+	/// Circuits relayed at once to the same reserved peer.
+	pub fn with_max_circuits_per_reservation(mut self, max_circuits: usize) -> Self {
+		self.max_circuits_per_reservation = max_circuits;
+		self
+	}
+
+	/// Time after which a circuit is ended.
+	pub fn with_circuit_duration(mut self, duration: Duration) -> Self {
+		self.limit.duration = duration;
+		self
+	}
+
+	/// Bytes relayed in both directions after which a circuit is ended.
+	pub fn with_circuit_bytes(mut self, bytes: u64) -> Self {
+		self.limit.bytes = bytes;
+		self
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum HopRequest {
+	Reserve,
+	Connect { target: PeerId },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum HopResponse {
+	Reserved { expires_in: Duration, limit: CircuitLimit },
+	Connected { limit: CircuitLimit },
+	Refused(Refusal),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StopRequest {
+	source: PeerId,
+	limit: CircuitLimit,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum StopResponse {
+	Accepted,
+	Refused(Refusal),
+}
+
+/// Relay service run by the nodes built [`crate::Builder::with_relay`].
+pub(crate) struct Relay {
+	config: RelayConfig,
+	reservations: Mutex<HashMap<PeerId, Slot>>,
+}
+
+struct Slot {
+	expires: Instant,
+	circuits: usize,
+	/// Dropped once the slot is renewed or removed, ending the task watching the connection it was reserved on.
+	_reserved_on: oneshot::Sender<Infallible>,
+}
+
+impl Relay {
+	pub(crate) fn new(config: RelayConfig) -> Self {
+		Self {
+			config,
+			reservations: Mutex::default(),
+		}
+	}
+
+	fn reservations(&self) -> std::sync::MutexGuard<'_, HashMap<PeerId, Slot>> {
+		self.reservations.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Reserve a slot for `peer_id` or renew its reservation, returns a receiver resolving once it is renewed again or
+	/// removed.
+	fn reserve(&self, peer_id: PeerId) -> Result<oneshot::Receiver<Infallible>, Refusal> {
+		let now = Instant::now();
+		let mut reservations = self.reservations();
+		reservations.retain(|_, slot| slot.expires > now || slot.circuits > 0);
+		let expires = now + self.config.reservation_duration;
+		let (reserved_on, renewed) = oneshot::channel();
+
+		if let Some(slot) = reservations.get_mut(&peer_id) {
+			slot.expires = expires;
+			slot._reserved_on = reserved_on;
+		} else if reservations.len() >= self.config.max_reservations {
+			return Err(Refusal::TooManyReservations);
+		} else {
+			reservations.insert(
+				peer_id,
+				Slot {
+					expires,
+					circuits: 0,
+					_reserved_on: reserved_on,
+				},
+			);
+		}
+		Ok(renewed)
+	}
+
+	/// End the reservation of `peer_id`, its circuits keep counting against it until they end.
+	fn expire(&self, peer_id: &PeerId) {
+		let mut reservations = self.reservations();
+		match reservations.get_mut(peer_id) {
+			Some(slot) if slot.circuits > 0 => slot.expires = Instant::now(),
+			Some(_) => {
+				reservations.remove(peer_id);
+			}
+			None => {}
+		}
+	}
+
+	/// Count a new circuit to `target`, released by [`Relay::release`].
+	fn acquire(&self, target: &PeerId) -> Result<(), Refusal> {
+		let mut reservations = self.reservations();
+		let slot = reservations
+			.get_mut(target)
+			.filter(|slot| slot.expires > Instant::now())
+			.ok_or(Refusal::NoReservation)?;
+		if slot.circuits >= self.config.max_circuits_per_reservation {
+			return Err(Refusal::TooManyCircuits);
+		}
+		slot.circuits += 1;
+		Ok(())
+	}
+
+	fn release(&self, target: &PeerId) {
+		if let Some(slot) = self.reservations().get_mut(target) {
+			slot.circuits -= 1;
+		}
+	}
+}
+
+/// Serve a reservation or a circuit request of `peer_id`, circuits end early once `closing` resolves.
+pub(crate) async fn handle_hop(
+	relay: Arc<Relay>,
+	connections: Arc<Mutex<ConnectionManager<Connection>>>,
+	closing: Closing,
+	peer_id: PeerId,
+	mut stream: Stream,
+) {
+	let result = async {
+		match recv(&mut stream).await? {
+			HopRequest::Reserve => {
+				let response = match relay.reserve(peer_id) {
+					Ok(renewed) => {
+						let connection = connections
+							.lock()
+							.unwrap_or_else(PoisonError::into_inner)
+							.direct_connection(&peer_id);
+						if let Some(connection) = connection {
+							runtime::spawn(expire_on_close(
+								Arc::clone(&relay),
+								peer_id,
+								connection.closed(),
+								renewed,
+							));
+						}
+						HopResponse::Reserved {
+							expires_in: relay.config.reservation_duration,
+							limit: relay.config.limit,
+						}
+					}
+					Err(refusal) => HopResponse::Refused(refusal),
+				};
+				debug!(%peer_id, ?response, "Reservation request");
+				send(&mut stream, &response).await
+			}
+			HopRequest::Connect { target } => {
+				if let Err(refusal) = relay.acquire(&target) {
+					debug!(source = %peer_id, %target, ?refusal, "Refused circuit");
+					return send(&mut stream, &HopResponse::Refused(refusal)).await;
+				}
+				let result = connect_target(&relay, &connections, closing, peer_id, target, stream).await;
+				relay.release(&target);
+				result
+			}
+		}
+	}
+	.await;
+	if let Err(error) = result {
+		debug!(%peer_id, ?error, "Relay request failed");
+	}
+}
+
+/// End the reservation of `peer_id` once `closed` resolves, unless it was `renewed` or removed first.
+async fn expire_on_close(
+	relay: Arc<Relay>,
+	peer_id: PeerId,
+	closed: BoxFuture<'static, Error>,
+	renewed: oneshot::Receiver<Infallible>,
+) {
+	if let Either::Left(_) = future::select(closed, renewed).await {
+		debug!(%peer_id, "Reserved peer disconnected, ending its reservation");
+		relay.expire(&peer_id);
+	}
+}
+
+/// Open the circuit from `source` to `target` and relay it until it ends.
+async fn connect_target(
+	relay: &Relay,
+	connections: &Mutex<ConnectionManager<Connection>>,
+	closing: Closing,
+	source: PeerId,
+	target: PeerId,
+	mut stream: Stream,
+) -> Result<(), Error> {
+	let limit = relay.config.limit;
+	let connection = connections
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.connection(&target);
+	let stop = async {
+		let mut connection = connection.ok_or(Error::RelayRefused(Refusal::TargetUnreachable))?;
+		let mut stop = connection.open_stream_with_protocol(STOP_PROTOCOL).await?;
+		send(&mut stop, &StopRequest { source, limit }).await?;
+		match recv(&mut stop).await? {
+			StopResponse::Accepted => Ok(stop),
+			StopResponse::Refused(refusal) => Err(Error::RelayRefused(refusal)),
+		}
+	};
+
+	let mut stop = match runtime::timeout(STOP_TIMEOUT, stop).await.and_then(|result| result) {
+		Ok(stop) => stop,
+		Err(error) => {
+			debug!(%source, %target, ?error, "Failed to reach the target");
+			let refusal = match error {
+				Error::RelayRefused(refusal) => refusal,
+				_ => Refusal::TargetUnreachable,
+			};
+			send(&mut stream, &HopResponse::Refused(refusal)).await?;
+			return Ok(());
+		}
+	};
+	if let Err(error) = send(&mut stream, &HopResponse::Connected { limit }).await {
+		let _ = sf_core::Stream::close(&mut stop).await;
+		return Err(error);
+	}
+
+	debug!(%source, %target, "Relaying circuit");
+	if let Either::Right(_) = future::select(pin!(splice(stream, stop, limit)), closing).await {
+		debug!(%source, %target, "Ending circuit, shutting down");
+	}
+	debug!(%source, %target, "Circuit ended");
+	Ok(())
+}
+
+/// Relay the frames of each stream to the other until both ends finish, one of them closes the circuit, or `limit` is
+/// reached and the relay tells both ends.
+async fn splice(a: Stream, b: Stream, limit: CircuitLimit) {
+	let relayed = AtomicU64::new(0);
+	let (mut a_read, a_write) = a.split();
+	let (mut b_read, b_write) = b.split();
+	let (mut a_write, mut b_write) = (Outgoing::new(a_write), Outgoing::new(b_write));
+	let both = future::try_join(
+		copy(&mut a_read, &mut b_write, &relayed, limit.bytes),
+		copy(&mut b_read, &mut a_write, &relayed, limit.bytes),
+	);
+	let end = match runtime::timeout(limit.duration, both).await {
+		Ok(Ok(_)) => return,
+		// Dropping the halves of a failed circuit resets both streams.
+		Ok(Err(Stop::Failed(error))) => {
+			debug!(?error, "Circuit interrupted");
+			return;
+		}
+		Ok(Err(Stop::Closed)) => None,
+		Ok(Err(Stop::Limit(reached))) => Some(frame::End::Limit(reached)),
+		Err(_) => Some(frame::End::Limit(LimitReached::Duration(limit.duration))),
+	};
+	debug!(?end, "Circuit ended");
+	let _ = runtime::timeout(
+		END_TIMEOUT,
+		future::join(
+			finish(&mut a_read, &mut a_write, end.as_ref()),
+			finish(&mut b_read, &mut b_write, end.as_ref()),
+		),
+	)
+	.await;
+}
+
+/// Why the relay stopped copying a circuit before both ends finished.
+enum Stop {
+	/// An end closed the circuit, the frame saying so was relayed to the other one.
+	Closed,
+	Limit(LimitReached),
+	Failed(Error),
+}
+
+/// Send `end` to an end of an ended circuit, then discard what it sends until it hangs up: resetting its stream first
+/// could fail its session before it reads the end.
+async fn finish(reader: &mut ReadHalf<Stream>, writer: &mut Outgoing, end: Option<&frame::End>) {
+	if let Some(end) = end
+		&& writer.end(end).await.is_err()
+	{
+		return;
+	}
+	let _ = futures::io::copy(reader, &mut futures::io::sink()).await;
+}
+
+/// Copy the frames of `reader` to `writer` until the end sending them finishes, counting the bytes relayed in both
+/// directions against `max_bytes`.
+async fn copy(
+	reader: &mut ReadHalf<Stream>,
+	writer: &mut Outgoing,
+	relayed: &AtomicU64,
+	max_bytes: u64,
+) -> Result<(), Stop> {
+	loop {
+		let Some(frame) = frame::read(reader).await.map_err(|e| Stop::Failed(e.into()))? else {
+			return writer.close().await.map_err(Stop::Failed);
+		};
+		if let Frame::Data(data) = &frame {
+			let len = data.len() as u64;
+			if relayed.fetch_add(len, Ordering::Relaxed) + len > max_bytes {
+				return Err(Stop::Limit(LimitReached::Bytes(max_bytes)));
+			}
+		}
+		writer.send(&frame).await.map_err(Stop::Failed)?;
+		if let Frame::End(_) = frame {
+			writer.close().await.map_err(Stop::Failed)?;
+			return Err(Stop::Closed);
+		}
+	}
+}
+
+/// Write half of a relayed stream, which keeps the part of a frame left unsent when a copy stops midway.
+struct Outgoing {
+	writer: WriteHalf<Stream>,
+	pending: Vec<u8>,
+	closed: bool,
+}
+
+impl Outgoing {
+	fn new(writer: WriteHalf<Stream>) -> Self {
+		Self {
+			writer,
+			pending: Vec::new(),
+			closed: false,
+		}
+	}
+
+	async fn send(&mut self, frame: &Frame) -> Result<(), Error> {
+		self.pending.extend(frame.encode()?);
+		while !self.pending.is_empty() {
+			let len = self.writer.write(&self.pending).await?;
+			if len == 0 {
+				return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+			}
+			self.pending.drain(..len);
+		}
+		Ok(self.writer.flush().await?)
+	}
+
+	async fn close(&mut self) -> Result<(), Error> {
+		self.closed = true;
+		Ok(self.writer.close().await?)
+	}
+
+	/// Tell the end why the relay ends the circuit, unless it finished already.
+	async fn end(&mut self, end: &frame::End) -> Result<(), Error> {
+		if self.closed {
+			return Ok(());
+		}
+		self.send(&Frame::End(end.clone())).await?;
+		self.close().await
+	}
+}
+
+/// Relays the local node holds a reservation with, and when each reservation expires.
+pub(crate) type Reservations = Arc<Mutex<HashMap<PeerId, Instant>>>;
+
+/// A circuit accepted by the local node, to authenticate like any inbound connection.
+pub(crate) struct Inbound {
+	pub(crate) relay: PeerId,
+	pub(crate) source: PeerId,
+	pub(crate) stream: Stream,
+}
+
+impl Inbound {
+	/// `/p2p/<relay>/p2p-circuit/p2p/<source>`, the address the source is reached on.
+	pub(crate) fn remote_address(&self) -> Multiaddr {
+		circuit_address(self.relay, self.source)
+	}
+}
+
+/// Renew the reservation with `relay` expiring at `expires` before it does, over `connection`, until the connection
+/// closes or another reservation with the relay replaces it.
+pub(crate) async fn keep_reserved(
+	reservations: Reservations,
+	relay: PeerId,
+	mut connection: Connection,
+	mut expires: Instant,
+) {
+	let mut closed = connection.closed();
+	let replaced = |reservations: &HashMap<PeerId, Instant>, expires| reservations.get(&relay) != Some(&expires);
+	loop {
+		let renew_in = expires.saturating_duration_since(Instant::now()) * 3 / 4;
+		// `None` once the connection closed.
+		let renewed = match future::select(pin!(runtime::sleep(renew_in)), &mut closed).await {
+			Either::Left(_) if !replaced(&reservations.lock().unwrap_or_else(PoisonError::into_inner), expires) => {
+				Some(
+					runtime::timeout(RENEW_TIMEOUT, reserve(&mut connection))
+						.await
+						.and_then(|result| result),
+				)
+			}
+			Either::Left(_) => return,
+			Either::Right(_) => None,
+		};
+
+		let mut reservations = reservations.lock().unwrap_or_else(PoisonError::into_inner);
+		if replaced(&reservations, expires) {
+			return;
+		}
+		match renewed {
+			Some(Ok((expires_in, _))) => {
+				debug!(%relay, ?expires_in, "Renewed the relay reservation");
+				expires = Instant::now() + expires_in;
+				reservations.insert(relay, expires);
+				continue;
+			}
+			Some(Err(error)) => warn!(%relay, ?error, "Failed to renew the relay reservation"),
+			None => debug!(%relay, "Connection to the relay closed, the reservation ends"),
+		}
+		reservations.remove(&relay);
+		return;
+	}
+}
+
+/// Accept the circuits a relay opens to the local node, if it holds a reservation with that relay.
+pub(crate) async fn handle_stop(
+	reservations: Reservations,
+	inbound: mpsc::UnboundedSender<Inbound>,
+	relay: PeerId,
+	mut stream: Stream,
+) {
+	let result = async {
+		let StopRequest { source, limit } = recv(&mut stream).await?;
+		let reserved = reservations
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.get(&relay)
+			.is_some_and(|expires| *expires > Instant::now());
+		if !reserved {
+			return send(&mut stream, &StopResponse::Refused(Refusal::NoReservation)).await;
+		}
+
+		send(&mut stream, &StopResponse::Accepted).await?;
+		debug!(%relay, %source, ?limit, "Accepted circuit");
+		let _ = inbound.unbounded_send(Inbound { relay, source, stream });
+		Ok(())
+	}
+	.await;
+	if let Err(error) = result {
+		debug!(%relay, ?error, "Failed to accept circuit");
+	}
+}
+
+/// Reserve a slot on the relay at the other end of `connection`.
+pub(crate) async fn reserve(connection: &mut Connection) -> Result<(Duration, CircuitLimit), Error> {
+	let mut stream = connection.open_stream_with_protocol(HOP_PROTOCOL).await?;
+	send(&mut stream, &HopRequest::Reserve).await?;
+	let response = recv(&mut stream).await?;
+	let _ = sf_core::Stream::close(&mut stream).await;
+	match response {
+		HopResponse::Reserved { expires_in, limit } => Ok((expires_in, limit)),
+		HopResponse::Refused(refusal) => Err(Error::RelayRefused(refusal)),
+		HopResponse::Connected { .. } => Err(Error::InvalidRelayResponse),
+	}
+}
+
+/// Ask the relay at the other end of `connection` for a circuit to `target`, returns the relayed stream.
+pub(crate) async fn connect(connection: &mut Connection, target: PeerId) -> Result<Stream, Error> {
+	let mut stream = connection.open_stream_with_protocol(HOP_PROTOCOL).await?;
+	send(&mut stream, &HopRequest::Connect { target }).await?;
+	match recv(&mut stream).await? {
+		HopResponse::Connected { limit } => {
+			debug!(%target, ?limit, "Circuit opened");
+			Ok(stream)
+		}
+		HopResponse::Refused(refusal) => Err(Error::RelayRefused(refusal)),
+		HopResponse::Reserved { .. } => Err(Error::InvalidRelayResponse),
+	}
+}
+
+/// `/p2p/<relay>/p2p-circuit/p2p/<target>`.
+pub(crate) fn circuit_address(relay: PeerId, target: PeerId) -> Multiaddr {
+	Multiaddr::empty()
+		.with(Protocol::P2p(relay))
+		.with(Protocol::P2pCircuit)
+		.with(Protocol::P2p(target))
+}
+
+/// Whether `address` reaches its peer through a relay.
+pub(crate) fn is_circuit(address: &Multiaddr) -> bool {
+	address.iter().any(|protocol| protocol == Protocol::P2pCircuit)
+}
+
+/// Split `[<relay address>]/p2p/<relay>/p2p-circuit[/p2p/<target>]` into the address and the peer of the relay.
+pub(crate) fn parse_circuit_address(address: &Multiaddr) -> Result<(Multiaddr, PeerId), Error> {
+	let invalid = || Error::InvalidRelayAddress(address.clone());
+	let position = address
+		.iter()
+		.position(|protocol| protocol == Protocol::P2pCircuit)
+		.ok_or_else(invalid)?;
+
+	let relay_address: Multiaddr = address.iter().take(position).collect();
+	let Some(Protocol::P2p(relay)) = relay_address.iter().last() else {
+		return Err(invalid());
+	};
+	let mut target = address.iter().skip(position + 1);
+	match (target.next(), target.next()) {
+		(None, _) | (Some(Protocol::P2p(_)), None) => Ok((relay_address, relay)),
+		_ => Err(invalid()),
+	}
+}
+
+async fn send<S, M>(stream: &mut S, message: &M) -> Result<(), Error>
+where
+	S: AsyncWrite + Unpin,
+	M: Serialize,
+{
+	write_length_prefixed(stream, serde_json::to_vec(message)?).await
+}
+
+async fn recv<S, M>(stream: &mut S) -> Result<M, Error>
+where
+	S: AsyncRead + Unpin,
+	M: for<'de> Deserialize<'de>,
+{
+	Ok(serde_json::from_slice(
+		&read_length_prefixed(stream, MAX_MESSAGE_SIZE).await?,
+	)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::StreamExt;
+
+	use crate::test_util::{self, drive};
+	use crate::{CloseCause, Event, Node};
+
+	fn node(relay: Option<RelayConfig>) -> Node {
+		test_util::node(|builder| {
+			if let Some(config) = relay {
+				builder.with_relay(config);
+			}
+		})
+	}
+
+	/// A relay listening on a memory address, driven in the background.
+	async fn relay(config: RelayConfig) -> (PeerId, Multiaddr) {
+		let (peer_id, address, _) = test_util::listening(node(Some(config))).await;
+		(peer_id, address)
+	}
+
+	/// A node echoing the `/echo/1` streams, connected to `relay` and driven in the background.
+	fn echo_node(relay: PeerId, relay_address: &Multiaddr) -> Node {
+		let node = node(None);
+		node.set_stream_handler("/echo/1", |_, mut stream| async move {
+			let (mut reader, mut writer) = (&mut stream).split();
+			let _ = futures::io::copy(&mut reader, &mut writer).await;
+			let _ = writer.close().await;
+		});
+		node.add_address(relay, relay_address.clone());
+		node
+	}
+
+	/// A node reachable through a reservation on `relay`, driven in the background.
+	async fn reserved(relay: PeerId, relay_address: &Multiaddr) -> (PeerId, Reservation) {
+		let target = echo_node(relay, relay_address);
+		let reservation = target.reserve(relay).await.unwrap();
+		let peer_id = target.peer_id;
+		drive(target);
+		(peer_id, reservation)
+	}
+
+	fn dialer(relay: PeerId, relay_address: &Multiaddr) -> Node {
+		let dialer = node(None);
+		dialer.add_address(relay, relay_address.clone());
+		dialer
+	}
+
+	async fn echo(connection: &mut Connection, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let mut stream = connection.open_stream_with_protocol("/echo/1").await?;
+		stream.write_all(data).await?;
+		sf_core::Stream::close_send(&mut stream).await?;
+		let mut echoed = Vec::new();
+		stream.read_to_end(&mut echoed).await?;
+		Ok(echoed)
+	}
+
+	#[test]
+	fn test_parse_circuit_address() {
+		let (relay, target) = (PeerId::random(), PeerId::random());
+		let relay_address: Multiaddr = format!("/memory/7/p2p/{relay}").parse().unwrap();
+
+		assert_eq!(
+			parse_circuit_address(&circuit_address(relay, target)).unwrap(),
+			(Multiaddr::empty().with(Protocol::P2p(relay)), relay)
+		);
+		assert_eq!(
+			parse_circuit_address(&relay_address.clone().with(Protocol::P2pCircuit)).unwrap(),
+			(relay_address, relay)
+		);
+		assert!(parse_circuit_address(&"/memory/7/p2p-circuit".parse().unwrap()).is_err());
+		assert!(parse_circuit_address(&format!("/p2p/{relay}/p2p-circuit/memory/7").parse().unwrap()).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_circuit_reaches_reserved_peer() {
+		let (relay, relay_address) = relay(RelayConfig::default()).await;
+		let (target, reservation) = reserved(relay, &relay_address).await;
+		assert_eq!(reservation.address, circuit_address(relay, target));
+
+		let dialer = dialer(relay, &relay_address);
+		let mut connection = dialer.dial(target, reservation.address.clone()).await.unwrap();
+
+		assert!(matches!(connection, Connection::Relayed(_)));
+		assert_eq!(sf_core::Connection::remote_peer_id(&connection), Some(target));
+		assert_eq!(sf_core::Connection::remote_address(&connection), &reservation.address);
+		let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+		assert_eq!(echo(&mut connection, &data).await.unwrap(), data);
+	}
+
+	#[tokio::test]
+	async fn test_circuit_requires_a_reservation() {
+		let (relay, relay_address) = relay(RelayConfig::default()).await;
+		let target = echo_node(relay, &relay_address);
+		target.dial_peer(relay).await.unwrap();
+
+		let result = dialer(relay, &relay_address)
+			.dial(target.peer_id, circuit_address(relay, target.peer_id))
+			.await;
+
+		assert!(matches!(result, Err(Error::RelayRefused(Refusal::NoReservation))));
+	}
+
+	#[tokio::test]
+	async fn test_reservation_limit() {
+		let (relay, relay_address) = relay(RelayConfig::default().with_max_reservations(1)).await;
+		let first = echo_node(relay, &relay_address);
+		let second = echo_node(relay, &relay_address);
+
+		first.reserve(relay).await.unwrap();
+		// Renewing does not take another slot.
+		first.reserve(relay).await.unwrap();
+
+		assert!(matches!(
+			second.reserve(relay).await,
+			Err(Error::RelayRefused(Refusal::TooManyReservations))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_reservation_ends_with_its_connection() {
+		let (relay, relay_address) = relay(RelayConfig::default().with_max_reservations(1)).await;
+		let mut first = echo_node(relay, &relay_address);
+		let second = echo_node(relay, &relay_address);
+		first.reserve(relay).await.unwrap();
+
+		first.shutdown(Duration::from_secs(1)).await;
+
+		let reserved = runtime::timeout(Duration::from_secs(5), async {
+			while second.reserve(relay).await.is_err() {
+				runtime::sleep(Duration::from_millis(20)).await;
+			}
+		});
+		reserved.await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_reservation_is_renewed() {
+		let duration = Duration::from_millis(300);
+		let (relay, relay_address) = relay(RelayConfig::default().with_reservation_duration(duration)).await;
+		let (target, reservation) = reserved(relay, &relay_address).await;
+
+		runtime::sleep(duration * 3).await;
+
+		let mut connection = dialer(relay, &relay_address)
+			.dial(target, reservation.address)
+			.await
+			.unwrap();
+		assert_eq!(echo(&mut connection, b"still there").await.unwrap(), b"still there");
+	}
+
+	#[tokio::test]
+	async fn test_circuit_byte_limit() {
+		let (relay, relay_address) = relay(RelayConfig::default().with_circuit_bytes(16 * 1024)).await;
+		let (target, reservation) = reserved(relay, &relay_address).await;
+		let mut connection = dialer(relay, &relay_address)
+			.dial(target, reservation.address)
+			.await
+			.unwrap();
+		let closed = connection.closed();
+
+		// The circuit ends midway, the stream either fails or sees its end early.
+		let echoed = echo(&mut connection, &[7; 64 * 1024]).await.unwrap_or_default();
+		assert!(echoed.len() < 64 * 1024);
+		let closed = runtime::timeout(Duration::from_secs(5), closed).await.unwrap();
+		assert!(matches!(
+			closed,
+			Error::CircuitLimit(LimitReached::Bytes(bytes)) if bytes == 16 * 1024
+		));
+	}
+
+	#[tokio::test]
+	async fn test_circuit_duration_limit() {
+		let config = RelayConfig::default().with_circuit_duration(Duration::from_millis(300));
+		let (relay, relay_address) = relay(config).await;
+		let (target, reservation) = reserved(relay, &relay_address).await;
+		let connection = dialer(relay, &relay_address)
+			.dial(target, reservation.address)
+			.await
+			.unwrap();
+
+		assert!(matches!(
+			runtime::timeout(Duration::from_secs(5), connection.closed()).await.unwrap(),
+			Error::CircuitLimit(LimitReached::Duration(duration)) if duration == Duration::from_millis(300)
+		));
+	}
+
+	#[tokio::test]
+	async fn test_circuit_close_reason_reaches_remote() {
+		let (relay, relay_address) = relay(RelayConfig::default()).await;
+		let target = echo_node(relay, &relay_address);
+		let reservation = target.reserve(relay).await.unwrap();
+		let target_id = target.peer_id;
+		let mut target_events = drive(target);
+		let dialer = dialer(relay, &relay_address);
+		let mut connection = dialer.dial(target_id, reservation.address).await.unwrap();
+
+		connection.close_with_reason(7, "bye").await.unwrap();
+
+		let closed = async {
+			loop {
+				if let Some(Event::ConnectionClosed { peer_id, cause, .. }) = target_events.next().await {
+					break (peer_id, cause);
+				}
+			}
+		};
+		let (peer_id, cause) = runtime::timeout(Duration::from_secs(5), closed).await.unwrap();
+		assert_eq!(peer_id, dialer.peer_id);
+		assert!(matches!(
+			cause,
+			CloseCause::Transport(Error::CircuitClosedWithReason { code: 7, reason }) if reason == "bye"
+		));
+	}
+}
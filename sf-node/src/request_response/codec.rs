@@ -0,0 +1,160 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Turns the requests and responses of a protocol into bytes and back, see [`super::RequestResponse`].
+pub trait Codec: Send + Sync + 'static {
+	type Request: Send + 'static;
+	type Response: Send + 'static;
+	type Error: std::error::Error + Send + Sync + 'static;
+
+	fn encode_request(&self, request: &Self::Request) -> Result<Vec<u8>, Self::Error>;
+	fn decode_request(&self, bytes: &[u8]) -> Result<Self::Request, Self::Error>;
+	fn encode_response(&self, response: &Self::Response) -> Result<Vec<u8>, Self::Error>;
+	fn decode_response(&self, bytes: &[u8]) -> Result<Self::Response, Self::Error>;
+}
+
+/// Encodes requests and responses as JSON.
+pub struct JsonCodec<Req, Resp>(PhantomData<fn() -> (Req, Resp)>);
+
+impl<Req, Resp> Default for JsonCodec<Req, Resp> {
+	fn default() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<Req, Resp> fmt::Debug for JsonCodec<Req, Resp> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("JsonCodec")
+	}
+}
+
+impl<Req, Resp> Codec for JsonCodec<Req, Resp>
+where
+	Req: Serialize + DeserializeOwned + Send + 'static,
+	Resp: Serialize + DeserializeOwned + Send + 'static,
+{
+	type Request = Req;
+	type Response = Resp;
+	type Error = serde_json::Error;
+
+	fn encode_request(&self, request: &Req) -> Result<Vec<u8>, Self::Error> {
+		serde_json::to_vec(request)
+	}
+
+	fn decode_request(&self, bytes: &[u8]) -> Result<Req, Self::Error> {
+		serde_json::from_slice(bytes)
+	}
+
+	fn encode_response(&self, response: &Resp) -> Result<Vec<u8>, Self::Error> {
+		serde_json::to_vec(response)
+	}
+
+	fn decode_response(&self, bytes: &[u8]) -> Result<Resp, Self::Error> {
+		serde_json::from_slice(bytes)
+	}
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BincodeError {
+	#[error("bincode encoding error: {0}")]
+	Encode(#[from] bincode::error::EncodeError),
+
+	#[error("bincode decoding error: {0}")]
+	Decode(#[from] bincode::error::DecodeError),
+}
+
+/// Encodes requests and responses with bincode, through their serde implementations.
+pub struct BincodeCodec<Req, Resp>(PhantomData<fn() -> (Req, Resp)>);
+
+impl<Req, Resp> Default for BincodeCodec<Req, Resp> {
+	fn default() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<Req, Resp> fmt::Debug for BincodeCodec<Req, Resp> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("BincodeCodec")
+	}
+}
+
+impl<Req, Resp> BincodeCodec<Req, Resp> {
+	fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BincodeError> {
+		Ok(bincode::serde::encode_to_vec(value, bincode::config::standard())?)
+	}
+
+	fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BincodeError> {
+		let (value, _) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+		Ok(value)
+	}
+}
+
+impl<Req, Resp> Codec for BincodeCodec<Req, Resp>
+where
+	Req: Serialize + DeserializeOwned + Send + 'static,
+	Resp: Serialize + DeserializeOwned + Send + 'static,
+{
+	type Request = Req;
+	type Response = Resp;
+	type Error = BincodeError;
+
+	fn encode_request(&self, request: &Req) -> Result<Vec<u8>, Self::Error> {
+		Self::encode(request)
+	}
+
+	fn decode_request(&self, bytes: &[u8]) -> Result<Req, Self::Error> {
+		Self::decode(bytes)
+	}
+
+	fn encode_response(&self, response: &Resp) -> Result<Vec<u8>, Self::Error> {
+		Self::encode(response)
+	}
+
+	fn decode_response(&self, bytes: &[u8]) -> Result<Resp, Self::Error> {
+		Self::decode(bytes)
+	}
+}
+
+/// Encodes requests and responses as protobuf messages, generated or derived with prost.
+pub struct ProstCodec<Req, Resp>(PhantomData<fn() -> (Req, Resp)>);
+
+impl<Req, Resp> Default for ProstCodec<Req, Resp> {
+	fn default() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<Req, Resp> fmt::Debug for ProstCodec<Req, Resp> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("ProstCodec")
+	}
+}
+
+impl<Req, Resp> Codec for ProstCodec<Req, Resp>
+where
+	Req: prost::Message + Default + 'static,
+	Resp: prost::Message + Default + 'static,
+{
+	type Request = Req;
+	type Response = Resp;
+	type Error = prost::DecodeError;
+
+	fn encode_request(&self, request: &Req) -> Result<Vec<u8>, Self::Error> {
+		Ok(request.encode_to_vec())
+	}
+
+	fn decode_request(&self, bytes: &[u8]) -> Result<Req, Self::Error> {
+		Req::decode(bytes)
+	}
+
+	fn encode_response(&self, response: &Resp) -> Result<Vec<u8>, Self::Error> {
+		Ok(response.encode_to_vec())
+	}
+
+	fn decode_response(&self, bytes: &[u8]) -> Result<Resp, Self::Error> {
+		Resp::decode(bytes)
+	}
+}
@@ -0,0 +1,472 @@
+//! Send typed requests to peers and answer theirs, one stream per request.
+//!
+//! Every request opens a new stream negotiating the protocol of the [`RequestResponse`], writes the request encoded by
+//! its [`Codec`] as a length-prefixed frame and closes its sending side. The remote answers with a single frame made of
+//! a status byte followed by the encoded response when the request succeeded.
+
+mod codec;
+
+pub use codec::{BincodeCodec, BincodeError, Codec, JsonCodec, ProstCodec};
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use multiaddr::PeerId;
+use sf_core::Stream as _;
+use tracing::debug;
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::framing::{discard_length_prefixed, read_length_prefixed, write_length_prefixed};
+use crate::node::Node;
+use crate::runtime;
+use crate::stream::Stream;
+
+/// First byte of a response frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Status {
+	/// The encoded response follows.
+	Ok = 0,
+	/// The remote already answers as many requests as it accepts at once.
+	TooManyRequests = 1,
+	/// The remote could not decode the request, or it was too large.
+	InvalidRequest = 2,
+	/// The response exceeded the size limit of the remote.
+	ResponseTooLarge = 3,
+}
+
+impl TryFrom<u8> for Status {
+	type Error = Error;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(Self::Ok),
+			1 => Ok(Self::TooManyRequests),
+			2 => Ok(Self::InvalidRequest),
+			3 => Ok(Self::ResponseTooLarge),
+			_ => Err(Error::InvalidResponse),
+		}
+	}
+}
+
+/// Limits of a [`RequestResponse`] protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestResponseConfig {
+	request_timeout: Duration,
+	max_request_size: usize,
+	max_response_size: usize,
+	max_concurrent_inbound: usize,
+}
+
+impl Default for RequestResponseConfig {
+	fn default() -> Self {
+		Self {
+			request_timeout: Duration::from_secs(10),
+			max_request_size: 1024 * 1024,
+			max_response_size: 1024 * 1024,
+			max_concurrent_inbound: 100,
+		}
+	}
+}
+
+impl RequestResponseConfig {
+	/// Time given to a whole exchange, from opening the stream to reading the response.
+	///
+	/// Also bounds the time spent answering an inbound request, which holds one of the
+	/// [`Self::with_max_concurrent_inbound`] slots meanwhile.
+	pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+		self.request_timeout = timeout;
+		self
+	}
+
+	/// Largest encoded request sent or accepted, in bytes.
+	pub fn with_max_request_size(mut self, size: usize) -> Self {
+		self.max_request_size = size;
+		self
+	}
+
+	/// Largest encoded response sent or accepted, in bytes.
+	pub fn with_max_response_size(mut self, size: usize) -> Self {
+		self.max_response_size = size;
+		self
+	}
+
+	/// Number of inbound requests answered at once, the others are refused with [`Error::TooManyRequests`].
+	pub fn with_max_concurrent_inbound(mut self, max: usize) -> Self {
+		self.max_concurrent_inbound = max;
+		self
+	}
+}
+
+/// A request/response protocol, encoding its messages with `C`.
+///
+/// The clones share the count of inbound requests being answered.
+pub struct RequestResponse<C> {
+	protocol: String,
+	codec: Arc<C>,
+	config: RequestResponseConfig,
+	inbound: Arc<AtomicUsize>,
+}
+
+impl<C> Clone for RequestResponse<C> {
+	fn clone(&self) -> Self {
+		Self {
+			protocol: self.protocol.clone(),
+			codec: Arc::clone(&self.codec),
+			config: self.config,
+			inbound: Arc::clone(&self.inbound),
+		}
+	}
+}
+
+impl<C: Codec> RequestResponse<C> {
+	pub fn new(protocol: impl Into<String>, codec: C) -> Self {
+		Self {
+			protocol: protocol.into(),
+			codec: Arc::new(codec),
+			config: RequestResponseConfig::default(),
+			inbound: Arc::default(),
+		}
+	}
+
+	pub fn with_config(mut self, config: RequestResponseConfig) -> Self {
+		self.config = config;
+		self
+	}
+
+	pub fn protocol(&self) -> &str {
+		&self.protocol
+	}
+
+	/// Send `request` to `peer`, dialing it if needed, and wait for its response.
+	pub async fn send_request(&self, node: &Node, peer: PeerId, request: &C::Request) -> Result<C::Response, Error> {
+		let mut connection = node.dial_peer(peer).await?;
+		self.request(&mut connection, request).await
+	}
+
+	/// Send `request` on a new stream of `connection` and wait for the response.
+	pub async fn request(&self, connection: &mut Connection, request: &C::Request) -> Result<C::Response, Error> {
+		let request = self.codec.encode_request(request).map_err(codec_error)?;
+		if request.len() > self.config.max_request_size {
+			return Err(Error::MessageTooLarge(request.len()));
+		}
+
+		runtime::timeout(self.config.request_timeout, async {
+			let mut stream = connection.open_stream_with_protocol(&self.protocol).await?;
+			write_length_prefixed(&mut stream, request).await?;
+			let _ = stream.close_send().await;
+
+			let frame = read_length_prefixed(&mut stream, self.config.max_response_size + 1).await?;
+			let _ = stream.close_read().await;
+			let (&status, response) = frame.split_first().ok_or(Error::InvalidResponse)?;
+			match Status::try_from(status)? {
+				Status::Ok => self.codec.decode_response(response).map_err(codec_error),
+				Status::TooManyRequests => Err(Error::TooManyRequests),
+				Status::InvalidRequest => Err(Error::InvalidRequest),
+				Status::ResponseTooLarge => Err(Error::ResponseTooLarge),
+			}
+		})
+		.await?
+	}
+
+	/// Answer the requests of the peers with `handler`, registered as the stream handler of the protocol on `node`.
+	///
+	/// Replaces the handler of a previous call.
+	pub fn serve<F, Fut>(&self, node: &Node, handler: F)
+	where
+		F: Fn(PeerId, C::Request) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = C::Response> + Send + 'static,
+	{
+		let handler = Arc::new(handler);
+		let this = self.clone();
+		node.set_stream_handler(self.protocol.clone(), move |peer, stream| {
+			let this = this.clone();
+			let handler = Arc::clone(&handler);
+			async move { this.handle(peer, stream, |request| handler(peer, request)).await }
+		});
+	}
+
+	async fn handle<Fut>(&self, peer: PeerId, mut stream: Stream, handler: impl FnOnce(C::Request) -> Fut)
+	where
+		Fut: Future<Output = C::Response>,
+	{
+		let result = runtime::timeout(self.config.request_timeout, self.answer(peer, &mut stream, handler)).await;
+		if let Err(error) = result.and_then(|result| result) {
+			debug!(%peer, protocol = self.protocol, ?error, "Failed to answer request");
+		}
+		let _ = stream.close().await;
+	}
+
+	async fn answer<Fut>(
+		&self,
+		peer: PeerId,
+		stream: &mut Stream,
+		handler: impl FnOnce(C::Request) -> Fut,
+	) -> Result<(), Error>
+	where
+		Fut: Future<Output = C::Response>,
+	{
+		// Taken before reading, for the limit to bound the requests buffered too.
+		let Some(_slot) = InboundSlot::acquire(&self.inbound, self.config.max_concurrent_inbound) else {
+			debug!(%peer, protocol = self.protocol, "Refusing request, too many in flight");
+			// The remote only reads the status once done writing.
+			discard_length_prefixed(stream, self.config.max_request_size).await?;
+			return write_length_prefixed(stream, [Status::TooManyRequests as u8]).await;
+		};
+
+		let request = read_length_prefixed(stream, self.config.max_request_size)
+			.await
+			.and_then(|request| self.codec.decode_request(&request).map_err(codec_error));
+		let request = match request {
+			Ok(request) => request,
+			Err(error @ (Error::MessageTooLarge(_) | Error::Codec(_))) => {
+				write_length_prefixed(stream, [Status::InvalidRequest as u8]).await?;
+				return Err(error);
+			}
+			Err(error) => return Err(error),
+		};
+
+		let response = self
+			.codec
+			.encode_response(&handler(request).await)
+			.map_err(codec_error)?;
+		if response.len() > self.config.max_response_size {
+			write_length_prefixed(stream, [Status::ResponseTooLarge as u8]).await?;
+			return Err(Error::MessageTooLarge(response.len()));
+		}
+		write_length_prefixed(stream, [&[Status::Ok as u8], response.as_slice()].concat()).await
+	}
+}
+
+/// One of the inbound requests being answered, released on drop.
+struct InboundSlot<'a>(&'a AtomicUsize);
+
+impl<'a> InboundSlot<'a> {
+	fn acquire(inbound: &'a AtomicUsize, max: usize) -> Option<Self> {
+		inbound
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+				(count < max).then_some(count + 1)
+			})
+			.ok()
+			.map(|_| Self(inbound))
+	}
+}
+
+impl Drop for InboundSlot<'_> {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::AcqRel);
+	}
+}
+
+fn codec_error(error: impl std::error::Error + Send + Sync + 'static) -> Error {
+	Error::Codec(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::StreamExt;
+	use futures::channel::mpsc;
+	use multiaddr::Multiaddr;
+	use serde::{Deserialize, Serialize};
+
+	use crate::Event;
+	use crate::test_util;
+
+	#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+	struct Greeting {
+		name: String,
+		count: u32,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	struct Sum {
+		#[prost(uint64, repeated, tag = "1")]
+		values: Vec<u64>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	struct Total {
+		#[prost(uint64, tag = "1")]
+		total: u64,
+	}
+
+	fn node() -> Node {
+		test_util::node(|_| {})
+	}
+
+	/// A node serving `protocol` in the background, along with its peer id and address.
+	async fn serving<C: Codec>(
+		protocol: &RequestResponse<C>,
+		handler: impl Fn(PeerId, C::Request) -> C::Response + Send + Sync + 'static,
+	) -> (PeerId, Multiaddr) {
+		let node = node();
+		protocol.serve(&node, move |peer, request| std::future::ready(handler(peer, request)));
+		let (peer, address, _) = test_util::listening(node).await;
+		(peer, address)
+	}
+
+	fn greet(_: PeerId, greeting: Greeting) -> Greeting {
+		Greeting {
+			name: format!("hello {}", greeting.name),
+			count: greeting.count + 1,
+		}
+	}
+
+	fn greeting() -> Greeting {
+		Greeting {
+			name: "sf".to_owned(),
+			count: 1,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_json_round_trip() {
+		let protocol = RequestResponse::new("/test/json/1", JsonCodec::default());
+		let (server, address) = serving(&protocol, greet).await;
+		let client = node();
+		client.add_address(server, address);
+
+		for count in 0..3 {
+			let request = Greeting { count, ..greeting() };
+			let response = protocol.send_request(&client, server, &request).await.unwrap();
+			assert_eq!(response, greet(server, request));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_bincode_round_trip() {
+		let protocol = RequestResponse::new("/test/bincode/1", BincodeCodec::default());
+		let (server, address) = serving(&protocol, greet).await;
+		let client = node();
+
+		let mut connection = client.dial(server, address).await.unwrap();
+		let response = protocol.request(&mut connection, &greeting()).await.unwrap();
+		assert_eq!(response, greet(server, greeting()));
+	}
+
+	#[tokio::test]
+	async fn test_prost_round_trip() {
+		let protocol = RequestResponse::new("/test/prost/1", ProstCodec::<Sum, Total>::default());
+		let (server, address) = serving(&protocol, |_, sum: Sum| Total {
+			total: sum.values.iter().sum(),
+		})
+		.await;
+		let client = node();
+
+		let mut connection = client.dial(server, address).await.unwrap();
+		let response = protocol
+			.request(&mut connection, &Sum { values: vec![1, 2, 3] })
+			.await
+			.unwrap();
+		assert_eq!(response, Total { total: 6 });
+	}
+
+	#[tokio::test]
+	async fn test_times_out_slow_responses() {
+		let protocol = RequestResponse::new("/test/slow/1", JsonCodec::<Greeting, Greeting>::default())
+			.with_config(RequestResponseConfig::default().with_request_timeout(Duration::from_millis(200)));
+		let mut server = node();
+		protocol.serve(&server, |_, greeting| async move {
+			runtime::sleep(Duration::from_secs(5)).await;
+			greeting
+		});
+		server.listen("/memory/0".parse().unwrap()).await.unwrap();
+		let Some(Event::NewListenAddr { address, .. }) = server.next().await else {
+			panic!("expected a listen address");
+		};
+		let server_id = server.peer_id;
+		tokio::spawn(async move { while server.next().await.is_some() {} });
+
+		let mut connection = node().dial(server_id, address).await.unwrap();
+		let result = protocol.request(&mut connection, &greeting()).await;
+		assert!(matches!(result, Err(Error::Timeout)));
+	}
+
+	#[tokio::test]
+	async fn test_enforces_max_sizes() {
+		let config = RequestResponseConfig::default().with_max_request_size(64);
+		let protocol = RequestResponse::new("/test/size/1", JsonCodec::default()).with_config(config);
+		let (server, address) = serving(&protocol, greet).await;
+		let mut connection = node().dial(server, address).await.unwrap();
+
+		let large = Greeting {
+			name: "a".repeat(100),
+			count: 0,
+		};
+		let result = protocol.request(&mut connection, &large).await;
+		assert!(matches!(result, Err(Error::MessageTooLarge(_))));
+
+		// A client with a larger limit is refused by the server.
+		let lenient = RequestResponse::new("/test/size/1", JsonCodec::<Greeting, Greeting>::default());
+		let result = lenient.request(&mut connection, &large).await;
+		assert!(matches!(result, Err(Error::InvalidRequest)));
+
+		// A server with a lower limit reports its response as too large.
+		let config = RequestResponseConfig::default().with_max_response_size(8);
+		let small = RequestResponse::new("/test/small/1", JsonCodec::default()).with_config(config);
+		let (server, address) = serving(&small, greet).await;
+		let mut small_connection = node().dial(server, address).await.unwrap();
+		let result = small.request(&mut small_connection, &greeting()).await;
+		assert!(matches!(result, Err(Error::ResponseTooLarge)));
+
+		// Nor does the client accept responses larger than its limit.
+		let strict = RequestResponse::new("/test/size/1", JsonCodec::<Greeting, Greeting>::default())
+			.with_config(RequestResponseConfig::default().with_max_response_size(8));
+		let result = strict.request(&mut connection, &greeting()).await;
+		assert!(matches!(result, Err(Error::MessageTooLarge(_))));
+	}
+
+	#[tokio::test]
+	async fn test_refuses_requests_over_the_inbound_limit() {
+		let config = RequestResponseConfig::default().with_max_concurrent_inbound(1);
+		let protocol =
+			RequestResponse::new("/test/limit/1", JsonCodec::<Greeting, Greeting>::default()).with_config(config);
+		let (entered_tx, mut entered_rx) = mpsc::unbounded();
+		let mut server = node();
+		protocol.serve(&server, move |_, greeting| {
+			let _ = entered_tx.unbounded_send(());
+			async move {
+				runtime::sleep(Duration::from_millis(300)).await;
+				greeting
+			}
+		});
+		server.listen("/memory/0".parse().unwrap()).await.unwrap();
+		let Some(Event::NewListenAddr { address, .. }) = server.next().await else {
+			panic!("expected a listen address");
+		};
+		let server_id = server.peer_id;
+		tokio::spawn(async move { while server.next().await.is_some() {} });
+
+		let client = node();
+		let connection = client.dial(server_id, address).await.unwrap();
+		let first = tokio::spawn({
+			let (protocol, mut connection) = (protocol.clone(), connection.clone());
+			async move { protocol.request(&mut connection, &greeting()).await }
+		});
+		entered_rx.next().await.unwrap();
+
+		let result = protocol.request(&mut connection.clone(), &greeting()).await;
+		assert!(matches!(result, Err(Error::TooManyRequests)));
+		assert_eq!(first.await.unwrap().unwrap(), greeting());
+
+		// The slot is released once answered.
+		let result = protocol.request(&mut connection.clone(), &greeting()).await;
+		assert_eq!(result.unwrap(), greeting());
+	}
+
+	#[tokio::test]
+	async fn test_unsupported_protocol() {
+		let served = RequestResponse::new("/test/served/1", JsonCodec::default());
+		let (server, address) = serving(&served, greet).await;
+		let mut connection = node().dial(server, address).await.unwrap();
+
+		let other = RequestResponse::new("/test/other/1", JsonCodec::<Greeting, Greeting>::default());
+		let result = other.request(&mut connection, &greeting()).await;
+		assert!(matches!(result, Err(Error::ProtocolNotSupported(protocol)) if protocol == "/test/other/1"));
+	}
+}
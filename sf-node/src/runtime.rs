@@ -0,0 +1,56 @@
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+
+use crate::error::Error;
+
+/// Run `future` in the background on the platform executor.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn<F>(future: F)
+where
+	F: Future<Output = ()> + Send + 'static,
+{
+	tokio::spawn(future);
+}
+
+/// Run `future` in the background on the platform executor.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn spawn<F>(future: F)
+where
+	F: Future<Output = ()> + 'static,
+{
+	wasm_bindgen_futures::spawn_local(future);
+}
+
+/// Run the blocking `f` in the background, away from the executor threads.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn_blocking<F>(f: F)
+where
+	F: FnOnce() + Send + 'static,
+{
+	tokio::task::spawn_blocking(f);
+}
+
+/// Run the blocking `f`, the browser has no thread to move it to.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn spawn_blocking<F>(f: F)
+where
+	F: FnOnce() + Send + 'static,
+{
+	f();
+}
+
+/// Wait for `duration`.
+pub(crate) async fn sleep(duration: Duration) {
+	futures_timer::Delay::new(duration).await;
+}
+
+/// Run `future` to completion unless it takes longer than `duration`.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Error> {
+	match future::select(pin!(future), pin!(sleep(duration))).await {
+		Either::Left((output, _)) => Ok(output),
+		Either::Right(((), _)) => Err(Error::Timeout),
+	}
+}
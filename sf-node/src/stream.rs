@@ -6,10 +6,17 @@ use std::{
 	task::{Context, Poll},
 };
 
-use crate::error::Error;
+use crate::{error::Error, relay};
 
+#[derive(Debug)]
 pub enum Stream {
 	WebTransport(sf_wt_transport::Stream),
+	Memory(sf_memory_transport::Stream),
+	#[cfg(not(target_arch = "wasm32"))]
+	WebRtc(sf_webrtc_transport::Stream),
+	#[cfg(not(target_arch = "wasm32"))]
+	Tcp(sf_tcp_transport::Stream),
+	Relayed(relay::CircuitStream),
 }
 
 impl StreamTrait for Stream {
@@ -20,6 +27,16 @@ impl StreamTrait for Stream {
 			Self::WebTransport(stream) => {
 				Box::pin(async move { stream.close_send().await.map_err(|e| Error::Transport(Box::new(e))) })
 			}
+			Self::Memory(stream) => {
+				Box::pin(async move { stream.close_send().await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => {
+				Box::pin(async move { stream.close_send().await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Box::pin(async move { stream.close_send().await.map_err(|e| Error::Transport(Box::new(e))) }),
+			Self::Relayed(stream) => stream.close_send(),
 		}
 	}
 
@@ -28,6 +45,16 @@ impl StreamTrait for Stream {
 			Self::WebTransport(stream) => {
 				Box::pin(async move { stream.close_read().await.map_err(|e| Error::Transport(Box::new(e))) })
 			}
+			Self::Memory(stream) => {
+				Box::pin(async move { stream.close_read().await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => {
+				Box::pin(async move { stream.close_read().await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Box::pin(async move { stream.close_read().await.map_err(|e| Error::Transport(Box::new(e))) }),
+			Self::Relayed(stream) => stream.close_read(),
 		}
 	}
 
@@ -36,6 +63,62 @@ impl StreamTrait for Stream {
 			Self::WebTransport(stream) => {
 				Box::pin(async move { stream.close().await.map_err(|e| Error::Transport(Box::new(e))) })
 			}
+			Self::Memory(stream) => {
+				Box::pin(async move { stream.close().await.map_err(|e| Error::Transport(Box::new(e))) })
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => Box::pin(async move { stream.close().await.map_err(|e| Error::Transport(Box::new(e))) }),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Box::pin(async move { stream.close().await.map_err(|e| Error::Transport(Box::new(e))) }),
+			Self::Relayed(stream) => stream.close(),
+		}
+	}
+
+	fn set_priority(&mut self, priority: i32) {
+		match self {
+			Self::WebTransport(stream) => stream.set_priority(priority),
+			Self::Memory(stream) => stream.set_priority(priority),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => stream.set_priority(priority),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => stream.set_priority(priority),
+			Self::Relayed(stream) => stream.set_priority(priority),
+		}
+	}
+
+	fn priority(&self) -> i32 {
+		match self {
+			Self::WebTransport(stream) => stream.priority(),
+			Self::Memory(stream) => stream.priority(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => stream.priority(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => stream.priority(),
+			Self::Relayed(stream) => stream.priority(),
+		}
+	}
+
+	fn set_send_window(&mut self, window: usize) {
+		match self {
+			Self::WebTransport(stream) => stream.set_send_window(window),
+			Self::Memory(stream) => stream.set_send_window(window),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => stream.set_send_window(window),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => stream.set_send_window(window),
+			Self::Relayed(stream) => stream.set_send_window(window),
+		}
+	}
+
+	fn buffered_send(&self) -> usize {
+		match self {
+			Self::WebTransport(stream) => stream.buffered_send(),
+			Self::Memory(stream) => stream.buffered_send(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => stream.buffered_send(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => stream.buffered_send(),
+			Self::Relayed(stream) => stream.buffered_send(),
 		}
 	}
 }
@@ -44,6 +127,12 @@ impl AsyncRead for Stream {
 	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
 		match self.get_mut() {
 			Self::WebTransport(stream) => Pin::new(stream).poll_read(cx, buf).map_err(Into::into),
+			Self::Memory(stream) => Pin::new(stream).poll_read(cx, buf).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => Pin::new(stream).poll_read(cx, buf).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf).map_err(Into::into),
+			Self::Relayed(stream) => Pin::new(stream).poll_read(cx, buf),
 		}
 	}
 }
@@ -52,18 +141,36 @@ impl AsyncWrite for Stream {
 	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
 		match self.get_mut() {
 			Self::WebTransport(stream) => Pin::new(stream).poll_write(cx, buf).map_err(Into::into),
+			Self::Memory(stream) => Pin::new(stream).poll_write(cx, buf).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => Pin::new(stream).poll_write(cx, buf).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf).map_err(Into::into),
+			Self::Relayed(stream) => Pin::new(stream).poll_write(cx, buf),
 		}
 	}
 
 	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
 		match self.get_mut() {
 			Self::WebTransport(stream) => Pin::new(stream).poll_flush(cx).map_err(Into::into),
+			Self::Memory(stream) => Pin::new(stream).poll_flush(cx).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => Pin::new(stream).poll_flush(cx).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Pin::new(stream).poll_flush(cx).map_err(Into::into),
+			Self::Relayed(stream) => Pin::new(stream).poll_flush(cx),
 		}
 	}
 
 	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
 		match self.get_mut() {
 			Self::WebTransport(stream) => Pin::new(stream).poll_close(_cx).map_err(Into::into),
+			Self::Memory(stream) => Pin::new(stream).poll_close(_cx).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(stream) => Pin::new(stream).poll_close(_cx).map_err(Into::into),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(stream) => Pin::new(stream).poll_close(_cx).map_err(Into::into),
+			Self::Relayed(stream) => Pin::new(stream).poll_close(_cx),
 		}
 	}
 }
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, Weak};
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::{BoxFuture, Shared};
+use futures::{FutureExt, StreamExt};
+use multiaddr::PeerId;
+use sf_core::Stream as _;
+use tracing::debug;
+
+use crate::connection::Connection;
+use crate::negotiation;
+use crate::runtime;
+use crate::stream::Stream;
+
+type Handler = Arc<dyn Fn(PeerId, Stream) -> BoxFuture<'static, ()> + Send + Sync>;
+type Handlers = RwLock<HashMap<String, Handler>>;
+
+/// Resolves once the handlers are closed, for the handlers of long-lived streams to end them instead of holding up
+/// [`StreamHandlers::close`] until the remote does.
+pub(crate) type Closing = Shared<BoxFuture<'static, ()>>;
+
+/// Handlers of inbound streams, keyed by the protocol negotiated on them.
+#[derive(Clone)]
+pub(crate) struct StreamHandlers {
+	handlers: Arc<Handlers>,
+	in_flight: Arc<Mutex<InFlight>>,
+	closing: Closing,
+}
+
+impl Default for StreamHandlers {
+	fn default() -> Self {
+		let (closing_tx, closing_rx) = oneshot::channel();
+		Self {
+			handlers: Arc::default(),
+			in_flight: Arc::new(Mutex::new(InFlight::new(closing_tx))),
+			closing: closing_rx.map(|_| ()).boxed().shared(),
+		}
+	}
+}
+
+/// Tracks the streams being handled: each of them holds a clone of `tx`, so `rx` ends once they all finished and
+/// `tx` was taken.
+struct InFlight {
+	tx: Option<mpsc::Sender<Infallible>>,
+	rx: Option<mpsc::Receiver<Infallible>>,
+	/// Dropped on close, which resolves [`Closing`].
+	closing_tx: Option<oneshot::Sender<Infallible>>,
+}
+
+impl InFlight {
+	fn new(closing_tx: oneshot::Sender<Infallible>) -> Self {
+		let (tx, rx) = mpsc::channel(0);
+		Self {
+			tx: Some(tx),
+			rx: Some(rx),
+			closing_tx: Some(closing_tx),
+		}
+	}
+}
+
+/// Non-owning reference to the handlers of a [`StreamHandlers`].
+#[derive(Clone)]
+pub(crate) struct WeakStreamHandlers(Weak<Handlers>);
+
+impl WeakStreamHandlers {
+	/// Protocols with a registered handler, none once the handlers are dropped.
+	pub(crate) fn protocols(&self) -> Vec<String> {
+		self.0
+			.upgrade()
+			.map(|handlers| {
+				handlers
+					.read()
+					.unwrap_or_else(PoisonError::into_inner)
+					.keys()
+					.cloned()
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+}
+
+impl StreamHandlers {
+	pub(crate) fn insert<F, Fut>(&self, protocol: String, handler: F)
+	where
+		F: Fn(PeerId, Stream) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = ()> + Send + 'static,
+	{
+		let handler: Handler = Arc::new(move |peer_id, stream| Box::pin(handler(peer_id, stream)));
+		self.handlers
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(protocol, handler);
+	}
+
+	pub(crate) fn remove(&self, protocol: &str) -> bool {
+		self.handlers
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(protocol)
+			.is_some()
+	}
+
+	pub(crate) fn contains(&self, protocol: &str) -> bool {
+		self.handlers
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.contains_key(protocol)
+	}
+
+	pub(crate) fn downgrade(&self) -> WeakStreamHandlers {
+		WeakStreamHandlers(Arc::downgrade(&self.handlers))
+	}
+
+	/// Resolves once [`StreamHandlers::close`] is called.
+	pub(crate) fn closing(&self) -> Closing {
+		self.closing.clone()
+	}
+
+	/// Stop accepting streams, signal [`StreamHandlers::closing`] and resolve once the streams being handled finished.
+	pub(crate) fn close(&self) -> impl Future<Output = ()> + use<> {
+		let mut in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+		in_flight.tx = None;
+		in_flight.closing_tx = None;
+		let rx = in_flight.rx.take();
+		async move {
+			if let Some(mut rx) = rx {
+				rx.next().await;
+			}
+		}
+	}
+
+	/// Held while handling a stream, `None` once closed.
+	fn in_flight(&self) -> Option<mpsc::Sender<Infallible>> {
+		self.in_flight.lock().unwrap_or_else(PoisonError::into_inner).tx.clone()
+	}
+
+	/// Start handling `stream`, returns `None` when nothing handles `protocol` anymore.
+	pub(crate) fn handle(&self, protocol: &str, peer_id: PeerId, stream: Stream) -> Option<BoxFuture<'static, ()>> {
+		let handler = self
+			.handlers
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.get(protocol)
+			.cloned()?;
+		Some(handler(peer_id, stream))
+	}
+
+	/// Accept the inbound streams of `connection` until it or the handlers close and dispatch each of them to the handler of the
+	/// protocol negotiated on it.
+	pub(crate) async fn accept_streams(self, peer_id: PeerId, mut connection: Connection) {
+		loop {
+			let mut stream = match connection.accept_stream().await {
+				Ok(stream) => stream,
+				Err(error) => {
+					debug!(%peer_id, ?error, "Stopped accepting streams");
+					return;
+				}
+			};
+
+			let Some(in_flight) = self.in_flight() else {
+				debug!(%peer_id, "Stopped accepting streams, closing");
+				let _ = stream.close().await;
+				return;
+			};
+
+			let handlers = self.clone();
+			runtime::spawn(async move {
+				let _in_flight = in_flight;
+				match negotiation::accept(&mut stream, |protocol| handlers.contains(protocol)).await {
+					Ok(Some(protocol)) => {
+						if let Some(handler) = handlers.handle(&protocol, peer_id, stream) {
+							handler.await;
+						}
+					}
+					Ok(None) => {
+						debug!(%peer_id, "Rejected stream with unsupported protocol");
+						let _ = stream.close().await;
+					}
+					Err(error) => debug!(%peer_id, ?error, "Failed to negotiate stream protocol"),
+				}
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::FutureExt;
+
+	#[test]
+	fn test_close_waits_for_in_flight_streams() {
+		let handlers = StreamHandlers::default();
+		let in_flight = handlers.in_flight().unwrap();
+
+		let mut closing = handlers.closing();
+		assert!((&mut closing).now_or_never().is_none());
+
+		let mut closed = Box::pin(handlers.close());
+		assert!(handlers.in_flight().is_none());
+		assert!(closing.now_or_never().is_some());
+		assert!((&mut closed).now_or_never().is_none());
+
+		drop(in_flight);
+		assert!(closed.now_or_never().is_some());
+	}
+}
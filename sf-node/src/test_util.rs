@@ -0,0 +1,55 @@
+//! Fixtures shared by the tests of the node and of its protocols.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::channel::mpsc;
+use libp2p_identity::Keypair;
+use multiaddr::{Multiaddr, PeerId};
+use sf_memory_transport::MemoryTransport;
+
+use crate::{Builder, Event, Node, runtime};
+
+/// A node on the memory transport, with the rest of its configuration left to `configure`.
+pub(crate) fn node(configure: impl FnOnce(&mut Builder)) -> Node {
+	let mut builder = Builder::new(Keypair::generate_ed25519());
+	builder.with_memory_transport(MemoryTransport::new());
+	configure(&mut builder);
+	builder.build()
+}
+
+pub(crate) async fn next_event(node: &mut Node) -> Event {
+	runtime::timeout(Duration::from_secs(10), node.next())
+		.await
+		.expect("no event within 10s")
+		.expect("the node ended")
+}
+
+/// Start `node` listening on `address`, returns the address it listens on. The events reported meanwhile are skipped.
+pub(crate) async fn listen(node: &mut Node, address: &str) -> Multiaddr {
+	node.listen(address.parse().unwrap()).await.unwrap();
+	loop {
+		if let Event::NewListenAddr { address, .. } = next_event(node).await {
+			return address;
+		}
+	}
+}
+
+/// Drive `node` in the background, forwarding its events.
+pub(crate) fn drive(mut node: Node) -> mpsc::UnboundedReceiver<Event> {
+	let (events_tx, events_rx) = mpsc::unbounded();
+	tokio::spawn(async move {
+		while let Some(event) = node.next().await {
+			let _ = events_tx.unbounded_send(event);
+		}
+	});
+	events_rx
+}
+
+/// Start `node` listening on a memory address and drive it in the background, returns its peer id, its address and its
+/// events.
+pub(crate) async fn listening(mut node: Node) -> (PeerId, Multiaddr, mpsc::UnboundedReceiver<Event>) {
+	let address = listen(&mut node, "/memory/0").await;
+	let peer_id = node.peer_id;
+	(peer_id, address, drive(node))
+}
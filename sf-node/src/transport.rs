@@ -1,11 +1,18 @@
 use crate::{connection::Connection, error::Error};
 use multiaddr::{Multiaddr, PeerId};
-use sf_core::{Protocol, Transport as TransportTrait};
+use sf_core::{ListenerId, Protocol, Transport as TransportTrait};
 use std::future::Future;
 use std::pin::Pin;
 
+// Transports are built once and stay in the node's map, boxing them would only add an indirection.
+#[allow(clippy::large_enum_variant)]
 pub enum Transport {
 	WebTransport(sf_wt_transport::WebTransport),
+	Memory(sf_memory_transport::MemoryTransport),
+	#[cfg(not(target_arch = "wasm32"))]
+	WebRtc(sf_webrtc_transport::WebRtc),
+	#[cfg(not(target_arch = "wasm32"))]
+	Tcp(sf_tcp_transport::TcpTransport),
 }
 
 impl TransportTrait for Transport {
@@ -16,6 +23,11 @@ impl TransportTrait for Transport {
 	fn supported_protocols_for_dialing(&self) -> Protocol {
 		match self {
 			Self::WebTransport(transport) => transport.supported_protocols_for_dialing(),
+			Self::Memory(transport) => transport.supported_protocols_for_dialing(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => transport.supported_protocols_for_dialing(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => transport.supported_protocols_for_dialing(),
 		}
 	}
 
@@ -28,18 +40,84 @@ impl TransportTrait for Transport {
 					Ok(Connection::WebTransport(connection))
 				})
 			}
+			Self::Memory(transport) => {
+				let fut = transport.dial(peer_id, address);
+				Box::pin(async move {
+					let connection = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Connection::Memory(connection))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => {
+				let fut = transport.dial(peer_id, address);
+				Box::pin(async move {
+					let connection = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Connection::WebRtc(connection))
+				})
+			}
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => {
+				let fut = transport.dial(peer_id, address);
+				Box::pin(async move {
+					let connection = fut.await.map_err(|e| Error::Transport(Box::new(e)))?;
+					Ok(Connection::Tcp(connection))
+				})
+			}
 		}
 	}
 
-	fn listen_on(&mut self, address: Multiaddr) -> Result<(), Self::Error> {
+	fn listen_on(&mut self, address: Multiaddr) -> Result<ListenerId, Self::Error> {
 		match self {
 			Self::WebTransport(transport) => transport.listen_on(address).map_err(|e| Error::Transport(Box::new(e))),
+			Self::Memory(transport) => transport.listen_on(address).map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => transport.listen_on(address).map_err(|e| Error::Transport(Box::new(e))),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => transport.listen_on(address).map_err(|e| Error::Transport(Box::new(e))),
+		}
+	}
+
+	fn remove_listener(&mut self, id: ListenerId) -> bool {
+		match self {
+			Self::WebTransport(transport) => transport.remove_listener(id),
+			Self::Memory(transport) => transport.remove_listener(id),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => transport.remove_listener(id),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => transport.remove_listener(id),
+		}
+	}
+
+	fn shutdown(&mut self) {
+		match self {
+			Self::WebTransport(transport) => transport.shutdown(),
+			Self::Memory(transport) => transport.shutdown(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => transport.shutdown(),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => transport.shutdown(),
 		}
 	}
 
-	fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<sf_core::TransportEvent> {
+	fn poll(
+		self: Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> std::task::Poll<sf_core::TransportEvent<Self::Connection>> {
 		match self.get_mut() {
-			Self::WebTransport(transport) => Pin::new(transport).poll(cx),
+			Self::WebTransport(transport) => Pin::new(transport)
+				.poll(cx)
+				.map(|event| event.map_connection(Connection::WebTransport)),
+			Self::Memory(transport) => Pin::new(transport)
+				.poll(cx)
+				.map(|event| event.map_connection(Connection::Memory)),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(transport) => Pin::new(transport)
+				.poll(cx)
+				.map(|event| event.map_connection(Connection::WebRtc)),
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(transport) => Pin::new(transport)
+				.poll(cx)
+				.map(|event| event.map_connection(Connection::Tcp)),
 		}
 	}
 }
@@ -49,6 +127,9 @@ impl Transport {
 		let mojave_protocol = self.supported_protocols_for_dialing();
 		addr.iter().any(|protocol| match protocol {
 			multiaddr::Protocol::WebTransport => mojave_protocol == Protocol::WebTransport,
+			multiaddr::Protocol::WebRTCDirect => mojave_protocol == Protocol::WebRTC,
+			multiaddr::Protocol::Memory(_) => mojave_protocol == Protocol::Memory,
+			multiaddr::Protocol::Tls => mojave_protocol == Protocol::Tcp,
 			_ => false,
 		})
 	}
@@ -56,6 +137,11 @@ impl Transport {
 	pub fn protocol_name(&self) -> &'static str {
 		match self {
 			Self::WebTransport(_) => "webtransport",
+			Self::Memory(_) => "memory",
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::WebRtc(_) => "webrtc-direct",
+			#[cfg(not(target_arch = "wasm32"))]
+			Self::Tcp(_) => "tls",
 		}
 	}
 }
@@ -65,3 +151,23 @@ impl From<sf_wt_transport::WebTransport> for Transport {
 		Self::WebTransport(transport)
 	}
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<sf_webrtc_transport::WebRtc> for Transport {
+	fn from(transport: sf_webrtc_transport::WebRtc) -> Self {
+		Self::WebRtc(transport)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<sf_tcp_transport::TcpTransport> for Transport {
+	fn from(transport: sf_tcp_transport::TcpTransport) -> Self {
+		Self::Tcp(transport)
+	}
+}
+
+impl From<sf_memory_transport::MemoryTransport> for Transport {
+	fn from(transport: sf_memory_transport::MemoryTransport) -> Self {
+		Self::Memory(transport)
+	}
+}
@@ -0,0 +1,186 @@
+use std::net::{IpAddr, SocketAddr};
+
+use multiaddr::multihash::Multihash;
+use multiaddr::{Multiaddr, PeerId, Protocol};
+use rustls::pki_types::ServerName;
+
+use crate::error::Error;
+
+/// Multihash code of SHA-256, the hash of the pinned certificates.
+pub(crate) const SHA2_256: u64 = 0x12;
+
+/// `/<ip4|ip6>/<ip>/tcp/<port>/tls` address of a socket.
+pub(crate) fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
+	Multiaddr::empty()
+		.with(socket_addr.ip().to_canonical().into())
+		.with(Protocol::Tcp(socket_addr.port()))
+		.with(Protocol::Tls)
+}
+
+/// The IP and TCP port of a `/<ip4|ip6>/<ip>/tcp/<port>/tls` listen address.
+pub(crate) fn listen_socket_addr(address: &Multiaddr) -> Result<SocketAddr, Error> {
+	let mut iter = address.iter();
+	let ip: IpAddr = match iter.next() {
+		Some(Protocol::Ip4(ip)) => ip.into(),
+		Some(Protocol::Ip6(ip)) => ip.into(),
+		_ => return Err(Error::InvalidMultiaddr(address.clone())),
+	};
+	match (iter.next(), iter.next(), iter.next()) {
+		(Some(Protocol::Tcp(port)), Some(Protocol::Tls), None) => Ok(SocketAddr::new(ip, port)),
+		_ => Err(Error::InvalidMultiaddr(address.clone())),
+	}
+}
+
+/// `/certhash/` component pinning the certificate of SHA-256 digest `fingerprint`.
+pub(crate) fn certhash(fingerprint: &[u8; 32]) -> Multihash<64> {
+	Multihash::wrap(SHA2_256, fingerprint).expect("a SHA-256 digest fits in a multihash")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Host {
+	Ip(IpAddr),
+	/// A name to resolve, restricted to one address family by `/dns4/` and `/dns6/`.
+	Dns {
+		name: String,
+		ipv4: bool,
+		ipv6: bool,
+	},
+}
+
+/// Parsed form of `/<ip4|ip6|dns|dns4|dns6>/<host>/tcp/<port>/tls[/certhash/<hash>]*[/p2p/<peer>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DialAddr {
+	pub(crate) host: Host,
+	pub(crate) port: u16,
+	/// SHA-256 digests of the certificates the server may present, its certificate is verified against the system
+	/// roots when empty.
+	pub(crate) certhashes: Vec<[u8; 32]>,
+	pub(crate) peer_id: Option<PeerId>,
+}
+
+impl DialAddr {
+	pub(crate) fn parse(ma: &Multiaddr) -> Result<Self, Error> {
+		Self::try_parse(ma).ok_or_else(|| Error::InvalidMultiaddr(ma.clone()))
+	}
+
+	fn try_parse(ma: &Multiaddr) -> Option<Self> {
+		let mut iter = ma.iter().peekable();
+
+		let host = match iter.next()? {
+			Protocol::Ip4(ip) => Host::Ip(ip.into()),
+			Protocol::Ip6(ip) => Host::Ip(ip.into()),
+			Protocol::Dns(name) => Host::Dns {
+				name: name.into_owned(),
+				ipv4: true,
+				ipv6: true,
+			},
+			Protocol::Dns4(name) => Host::Dns {
+				name: name.into_owned(),
+				ipv4: true,
+				ipv6: false,
+			},
+			Protocol::Dns6(name) => Host::Dns {
+				name: name.into_owned(),
+				ipv4: false,
+				ipv6: true,
+			},
+			_ => return None,
+		};
+		let Protocol::Tcp(port) = iter.next()? else {
+			return None;
+		};
+		let Protocol::Tls = iter.next()? else {
+			return None;
+		};
+
+		let mut certhashes = Vec::new();
+		while let Some(Protocol::Certhash(hash)) = iter.peek() {
+			if hash.code() != SHA2_256 {
+				return None;
+			}
+			certhashes.push(hash.digest().try_into().ok()?);
+			iter.next();
+		}
+
+		let peer_id = match iter.next() {
+			Some(Protocol::P2p(peer_id)) => Some(peer_id),
+			Some(_) => return None,
+			None => None,
+		};
+		if iter.next().is_some() {
+			return None;
+		}
+
+		Some(Self {
+			host,
+			port,
+			certhashes,
+			peer_id,
+		})
+	}
+
+	/// The socket address to connect to, resolving the host name if needed.
+	pub(crate) async fn resolve(&self) -> Result<SocketAddr, Error> {
+		let (name, ipv4, ipv6) = match &self.host {
+			Host::Ip(ip) => return Ok(SocketAddr::new(*ip, self.port)),
+			Host::Dns { name, ipv4, ipv6 } => (name, *ipv4, *ipv6),
+		};
+		tokio::net::lookup_host((name.as_str(), self.port))
+			.await?
+			.find(|addr| (addr.is_ipv4() && ipv4) || (addr.is_ipv6() && ipv6))
+			.ok_or_else(|| Error::UnresolvedHost(name.clone()))
+	}
+
+	/// The name the server certificate is verified against.
+	pub(crate) fn server_name(&self) -> Result<ServerName<'static>, Error> {
+		match &self.host {
+			Host::Ip(ip) => Ok(ServerName::IpAddress((*ip).into())),
+			Host::Dns { name, .. } => {
+				ServerName::try_from(name.clone()).map_err(|_| Error::UnresolvedHost(name.clone()))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_dial_addr() {
+		let fingerprint = [7; 32];
+		let address = "/dns4/example.com/tcp/443/tls"
+			.parse::<Multiaddr>()
+			.unwrap()
+			.with(Protocol::Certhash(certhash(&fingerprint)))
+			.with(Protocol::P2p(
+				"12D3KooWQWBgSAg1Z4kjoonCwSmCwmtbP4ZQFAYyna6oYQPLhc8i".parse().unwrap(),
+			));
+
+		let parsed = DialAddr::parse(&address).unwrap();
+
+		assert_eq!(
+			parsed.host,
+			Host::Dns {
+				name: "example.com".to_owned(),
+				ipv4: true,
+				ipv6: false
+			}
+		);
+		assert_eq!(parsed.port, 443);
+		assert_eq!(parsed.certhashes, [fingerprint]);
+		assert!(parsed.peer_id.is_some());
+
+		assert!(DialAddr::parse(&"/ip4/127.0.0.1/tcp/443".parse().unwrap()).is_err());
+		assert!(DialAddr::parse(&"/ip4/127.0.0.1/udp/443/quic-v1/webtransport".parse().unwrap()).is_err());
+	}
+
+	#[test]
+	fn test_listen_socket_addr() {
+		assert_eq!(
+			listen_socket_addr(&"/ip6/::1/tcp/0/tls".parse().unwrap()).unwrap(),
+			"[::1]:0".parse().unwrap()
+		);
+		assert!(listen_socket_addr(&"/dns/localhost/tcp/0/tls".parse().unwrap()).is_err());
+	}
+}
@@ -0,0 +1,61 @@
+use std::{fmt, sync::Arc};
+
+use rcgen::{CertificateParams, KeyPair, PKCS_ECDSA_P256_SHA256};
+use ring::digest::{SHA256, digest};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+use crate::error::Error;
+
+/// Certificate chain and private key served by the listeners.
+#[derive(Clone)]
+pub struct Certificate {
+	chain: Vec<CertificateDer<'static>>,
+	key: Arc<PrivateKeyDer<'static>>,
+}
+
+impl Certificate {
+	/// Self-signed certificate for `localhost`, which dialers pin through the `/certhash/` of the listen addresses.
+	pub fn generate() -> Result<Self, Error> {
+		let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?;
+		let certificate = CertificateParams::new(vec!["localhost".to_owned()])?.self_signed(&key)?;
+		Ok(Self::new(
+			vec![certificate.der().clone()],
+			PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+		))
+	}
+
+	/// Certificate `chain`, starting with the end-entity one, and its private `key`.
+	///
+	/// A chain issued by a certificate authority is also accepted by dialers which do not pin it.
+	pub fn new(chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+		Self {
+			chain,
+			key: Arc::new(key),
+		}
+	}
+
+	/// SHA-256 digest of the end-entity certificate, published in `/certhash/`.
+	pub fn fingerprint(&self) -> [u8; 32] {
+		let end_entity = self.chain.first().map_or(&[][..], |certificate| certificate.as_ref());
+		digest(&SHA256, end_entity)
+			.as_ref()
+			.try_into()
+			.expect("SHA-256 digests are 32 bytes")
+	}
+
+	pub(crate) fn chain(&self) -> Vec<CertificateDer<'static>> {
+		self.chain.clone()
+	}
+
+	pub(crate) fn key(&self) -> PrivateKeyDer<'static> {
+		self.key.clone_key()
+	}
+}
+
+impl fmt::Debug for Certificate {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Certificate")
+			.field("chain", &self.chain.len())
+			.finish_non_exhaustive()
+	}
+}
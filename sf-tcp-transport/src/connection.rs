@@ -0,0 +1,219 @@
+use std::{
+	collections::VecDeque,
+	fmt,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use futures::{FutureExt, future::BoxFuture, ready};
+use multiaddr::{Multiaddr, PeerId};
+use tokio::{
+	net::TcpStream,
+	sync::{Mutex, mpsc, oneshot, watch},
+};
+use tokio_rustls::TlsStream;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+use crate::{error::Error, stream::Stream};
+
+type Muxer = yamux::Connection<Compat<TlsStream<TcpStream>>>;
+
+enum Command {
+	Open(oneshot::Sender<Result<yamux::Stream, Error>>),
+	Close,
+}
+
+/// Drives the yamux session of a connection, which only makes progress while polled.
+///
+/// Stops once the session fails, the remote closes it, or every handle to the connection is dropped.
+struct Driver {
+	muxer: Muxer,
+	commands: mpsc::UnboundedReceiver<Command>,
+	/// Outbound streams waiting for the session to accept a new one.
+	opening: VecDeque<oneshot::Sender<Result<yamux::Stream, Error>>>,
+	incoming: mpsc::UnboundedSender<yamux::Stream>,
+	closing: bool,
+	closed: watch::Sender<bool>,
+}
+
+impl Future for Driver {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		let this = self.get_mut();
+
+		while !this.closing {
+			match this.commands.poll_recv(cx) {
+				Poll::Ready(Some(Command::Open(reply))) => this.opening.push_back(reply),
+				Poll::Ready(Some(Command::Close) | None) => this.closing = true,
+				Poll::Pending => break,
+			}
+		}
+		if this.closing {
+			if let Err(error) = ready!(this.muxer.poll_close(cx)) {
+				tracing::debug!(?error, "Failed to close the yamux session");
+			}
+			return Poll::Ready(());
+		}
+
+		while let Some(reply) = this.opening.front() {
+			if reply.is_closed() {
+				this.opening.pop_front();
+				continue;
+			}
+			match this.muxer.poll_new_outbound(cx) {
+				Poll::Ready(result) => {
+					let failed = result.is_err();
+					let reply = this.opening.pop_front().expect("checked above");
+					let _ = reply.send(result.map_err(Error::Yamux));
+					if failed {
+						return Poll::Ready(());
+					}
+				}
+				Poll::Pending => break,
+			}
+		}
+
+		loop {
+			match ready!(this.muxer.poll_next_inbound(cx)) {
+				Some(Ok(stream)) => {
+					let _ = this.incoming.send(stream);
+				}
+				Some(Err(error)) => {
+					tracing::debug!(?error, "yamux session failed");
+					return Poll::Ready(());
+				}
+				None => return Poll::Ready(()),
+			}
+		}
+	}
+}
+
+impl Drop for Driver {
+	fn drop(&mut self) {
+		self.closed.send_replace(true);
+	}
+}
+
+struct Inner {
+	commands: mpsc::UnboundedSender<Command>,
+	closed: watch::Receiver<bool>,
+	incoming: Mutex<mpsc::UnboundedReceiver<yamux::Stream>>,
+}
+
+/// A TLS connection over TCP, each stream being a yamux stream.
+///
+/// Clones share the yamux session, which is closed once they are all dropped.
+#[derive(Clone)]
+pub struct Connection {
+	inner: Arc<Inner>,
+	remote_address: Multiaddr,
+	remote_peer_id: Option<PeerId>,
+}
+
+impl Connection {
+	/// Start the yamux session over `socket`, must be called within a tokio runtime.
+	pub(crate) fn new(socket: TlsStream<TcpStream>, mode: yamux::Mode, remote_address: Multiaddr) -> Self {
+		let (commands, commands_rx) = mpsc::unbounded_channel();
+		let (incoming_tx, incoming) = mpsc::unbounded_channel();
+		let (closed_tx, closed) = watch::channel(false);
+
+		tokio::spawn(Driver {
+			muxer: yamux::Connection::new(socket.compat(), yamux::Config::default(), mode),
+			commands: commands_rx,
+			opening: VecDeque::new(),
+			incoming: incoming_tx,
+			closing: false,
+			closed: closed_tx,
+		});
+
+		Self {
+			inner: Arc::new(Inner {
+				commands,
+				closed,
+				incoming: Mutex::new(incoming),
+			}),
+			remote_address,
+			remote_peer_id: None,
+		}
+	}
+
+	/// Resolve once the connection is closed, by either side.
+	pub fn closed(&self) -> BoxFuture<'static, Error> {
+		let mut closed = self.inner.closed.clone();
+		Box::pin(async move {
+			let _ = closed.wait_for(|closed| *closed).await;
+			Error::ConnectionClosed
+		})
+	}
+
+	/// Close the connection, yamux does not carry `code` and `reason` to the remote.
+	pub fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		tracing::debug!(code, reason, remote_address = %self.remote_address, "Closing connection");
+		let _ = self.inner.commands.send(Command::Close);
+		let closed = self.closed();
+		Box::pin(async move {
+			closed.await;
+			Ok(())
+		})
+	}
+
+	/// Record the identity of the remote once it has been authenticated by the upper layer.
+	pub fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+}
+
+impl fmt::Debug for Connection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Connection")
+			.field("remote_address", &self.remote_address)
+			.field("remote_peer_id", &self.remote_peer_id)
+			.field("closed", &*self.inner.closed.borrow())
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Connection for Connection {
+	type Error = Error;
+	type Output = Stream;
+
+	type Close = BoxFuture<'static, Result<(), Self::Error>>;
+	type Stream = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+	fn open_stream(&mut self) -> Self::Stream {
+		let (reply, opened) = oneshot::channel();
+		let sent = self.inner.commands.send(Command::Open(reply)).is_ok();
+		async move {
+			if !sent {
+				return Err(Error::ConnectionClosed);
+			}
+			// The driver drops the reply once the session is closed.
+			let stream = opened.await.map_err(|_| Error::ConnectionClosed)??;
+			Ok(Stream::new(stream))
+		}
+		.boxed()
+	}
+
+	fn accept_stream(&mut self) -> Self::Stream {
+		let inner = self.inner.clone();
+		async move {
+			let mut incoming = inner.incoming.lock().await;
+			incoming.recv().await.map(Stream::new).ok_or(Error::ConnectionClosed)
+		}
+		.boxed()
+	}
+
+	fn close(&mut self) -> Self::Close {
+		self.close_with_reason(0, "Closing connection")
+	}
+
+	fn remote_address(&self) -> &Multiaddr {
+		&self.remote_address
+	}
+
+	fn remote_peer_id(&self) -> Option<PeerId> {
+		self.remote_peer_id
+	}
+}
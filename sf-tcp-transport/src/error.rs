@@ -0,0 +1,33 @@
+use multiaddr::Multiaddr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("invalid multiaddr: {0}")]
+	InvalidMultiaddr(Multiaddr),
+
+	#[error("no address found for host {0}")]
+	UnresolvedHost(String),
+
+	#[error("certificate error: {0}")]
+	Certificate(#[from] rcgen::Error),
+
+	#[error("tls error: {0}")]
+	Tls(#[from] rustls::Error),
+
+	#[error("yamux error: {0}")]
+	Yamux(#[from] yamux::ConnectionError),
+
+	#[error("the connection was not established within {0:?}")]
+	Timeout(std::time::Duration),
+
+	#[error("the connection is closed")]
+	ConnectionClosed,
+
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error(transparent)]
+	Unsupported(#[from] sf_core::Unsupported),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,218 @@
+//! `/tcp/<port>/tls` transport: TLS 1.3 over TCP, with yamux multiplexing the streams of a connection.
+//!
+//! Reaches nodes from networks which block UDP, where neither WebTransport nor WebRTC connections can be established.
+
+mod address;
+pub mod certificate;
+pub mod connection;
+pub mod error;
+mod listener;
+pub mod stream;
+mod tls;
+
+use std::{
+	collections::VecDeque,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use futures::{FutureExt, StreamExt, future::BoxFuture};
+use multiaddr::{Multiaddr, PeerId};
+use sf_core::{Listener as _, ListenerId, Protocol, Transport, TransportEvent};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use address::{DialAddr, listen_socket_addr};
+pub use certificate::Certificate;
+pub use connection::Connection;
+pub use error::Error;
+use listener::HANDSHAKE_TIMEOUT;
+pub use listener::Listener;
+pub use stream::Stream;
+
+pub struct TcpTransport {
+	/// Served by every listener, its hash is published in their addresses.
+	certificate: Certificate,
+	acceptor: TlsAcceptor,
+
+	pending_events: VecDeque<TransportEvent<Connection>>,
+
+	listeners: Vec<Listener>,
+}
+
+impl TcpTransport {
+	pub fn new(certificate: Certificate) -> Result<Self, Error> {
+		let acceptor = TlsAcceptor::from(Arc::new(tls::server_config(&certificate)?));
+		Ok(Self {
+			certificate,
+			acceptor,
+			pending_events: VecDeque::new(),
+			listeners: Vec::new(),
+		})
+	}
+
+	/// Report the addresses of a stopped listener as expired, then the listener as closed.
+	fn close_listener(&mut self, listener: Listener) {
+		let listener_id = listener.id();
+		for address in listener.addresses() {
+			self.pending_events.push_back(TransportEvent::AddrExpired {
+				listener_id,
+				address: address.clone(),
+			});
+		}
+		self.pending_events
+			.push_back(TransportEvent::ListenerClosed { listener_id });
+	}
+}
+
+impl Transport for TcpTransport {
+	type Connection = Connection;
+	type Error = Error;
+	type Dial = BoxFuture<'static, Result<Connection, Error>>;
+
+	fn supported_protocols_for_dialing(&self) -> Protocol {
+		Protocol::Tcp
+	}
+
+	fn dial(&self, _peer_id: PeerId, ma: Multiaddr) -> Self::Dial {
+		let addr = match DialAddr::parse(&ma) {
+			Ok(addr) => addr,
+			Err(error) => return futures::future::ready(Err(error)).boxed(),
+		};
+		tracing::debug!(?addr, "dial");
+
+		Box::pin(async move {
+			let connector = TlsConnector::from(Arc::new(tls::client_config(&addr.certhashes)?));
+			let server_name = addr.server_name()?;
+			let handshake = async {
+				let socket = tokio::net::TcpStream::connect(addr.resolve().await?).await?;
+				socket.set_nodelay(true)?;
+				Ok::<_, Error>(connector.connect(server_name, socket).await?)
+			};
+			let socket = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+				.await
+				.map_err(|_| Error::Timeout(HANDSHAKE_TIMEOUT))??;
+
+			Ok(Connection::new(socket.into(), yamux::Mode::Client, ma))
+		})
+	}
+
+	fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Self::Error> {
+		let bind = listen_socket_addr(&addr)?;
+		let id = ListenerId::next();
+		let listener = Listener::new(id, bind, addr, self.acceptor.clone(), self.certificate.fingerprint())?;
+		self.listeners.push(listener);
+		Ok(id)
+	}
+
+	fn remove_listener(&mut self, id: ListenerId) -> bool {
+		let Some(position) = self.listeners.iter().position(|listener| listener.id() == id) else {
+			return false;
+		};
+		let listener = self.listeners.remove(position);
+		self.close_listener(listener);
+		true
+	}
+
+	fn shutdown(&mut self) {
+		self.pending_events.clear();
+		self.listeners.clear();
+	}
+
+	#[tracing::instrument(level = "trace", name = "Transport::poll", skip(self, cx))]
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Connection>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(event);
+		}
+
+		let mut i = 0;
+		while i < self.listeners.len() {
+			match self.listeners[i].poll_next_unpin(cx) {
+				Poll::Ready(Some(event)) => return Poll::Ready(event),
+				Poll::Ready(None) => {
+					let listener = self.listeners.remove(i);
+					self.close_listener(listener);
+					if let Some(event) = self.pending_events.pop_front() {
+						return Poll::Ready(event);
+					}
+				}
+				Poll::Pending => i += 1,
+			}
+		}
+
+		Poll::Pending
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sf_core::test_util;
+
+	const LISTEN_ADDRESS: &str = "/ip4/127.0.0.1/tcp/0/tls";
+
+	fn transport() -> TcpTransport {
+		TcpTransport::new(Certificate::generate().unwrap()).unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_listen_address_publishes_certhash() {
+		let certificate = Certificate::generate().unwrap();
+		let mut transport = TcpTransport::new(certificate.clone()).unwrap();
+
+		let address = test_util::listen(&mut transport, LISTEN_ADDRESS).await;
+
+		let parsed = DialAddr::parse(&address).unwrap();
+		assert_ne!(parsed.port, 0);
+		assert_eq!(parsed.certhashes, [certificate.fingerprint()]);
+	}
+
+	#[tokio::test]
+	async fn test_streams_both_ways() {
+		test_util::streams_both_ways(transport, LISTEN_ADDRESS, 1_000_000).await;
+	}
+
+	#[tokio::test]
+	async fn test_close_is_seen_by_the_remote() {
+		let (_dialer, listener, _transport) = test_util::close_ends_streams(transport, LISTEN_ADDRESS).await;
+
+		assert!(matches!(listener.closed().await, Error::ConnectionClosed));
+	}
+
+	#[tokio::test]
+	async fn test_dial_invalid_multiaddr() {
+		let address: Multiaddr = "/ip4/127.0.0.1/tcp/4433".parse().unwrap();
+
+		let result = test_util::dial_fails(&transport(), address.clone()).await;
+
+		assert!(matches!(result, Error::InvalidMultiaddr(invalid) if invalid == address));
+	}
+
+	#[tokio::test]
+	async fn test_dial_wrong_certhash_fails() {
+		let mut listener = transport();
+		let address = test_util::listen(&mut listener, LISTEN_ADDRESS).await;
+		let address = address
+			.iter()
+			.map(|protocol| match protocol {
+				multiaddr::Protocol::Certhash(_) => multiaddr::Protocol::Certhash(address::certhash(&[0; 32])),
+				protocol => protocol,
+			})
+			.collect();
+
+		let dialer = transport();
+
+		let listen = async {
+			loop {
+				test_util::next_event(&mut listener).await;
+			}
+		};
+		let result = tokio::select! {
+			result = test_util::dial_fails(&dialer, address) => result,
+			() = listen => unreachable!(),
+		};
+
+		assert!(matches!(result, Error::Io(_)));
+	}
+}
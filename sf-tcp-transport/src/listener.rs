@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream, StreamExt, ready};
+use multiaddr::{Multiaddr, Protocol};
+use sf_core::{Listener as ListenerTrait, ListenerId, TransportEvent};
+use sync_wrapper::SyncWrapper;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use crate::address::{certhash, socketaddr_to_multiaddr};
+use crate::connection::Connection;
+use crate::error::Error;
+
+/// Time for a dialer to complete the TLS handshake.
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Accept = BoxFuture<'static, (SocketAddr, Result<Connection, Error>)>;
+
+pub struct Listener {
+	id: ListenerId,
+	bind: SocketAddr,
+	addr: Multiaddr,
+	/// SHA-256 digest of the served certificate, published in the listen addresses.
+	fingerprint: [u8; 32],
+
+	socket: TcpListener,
+	acceptor: TlsAcceptor,
+	/// Inbound connections completing their handshakes.
+	accepting: SyncWrapper<FuturesUnordered<Accept>>,
+	if_watcher: Option<SyncWrapper<if_watch::tokio::IfWatcher>>,
+
+	pending_events: VecDeque<<Self as Stream>::Item>,
+
+	/// Addresses reported through [`TransportEvent::ListenAddr`] and not expired since.
+	addresses: Vec<Multiaddr>,
+}
+
+impl Listener {
+	/// Bind the TCP socket of `addr`, must be called within a tokio runtime.
+	pub(crate) fn new(
+		id: ListenerId,
+		bind: SocketAddr,
+		addr: Multiaddr,
+		acceptor: TlsAcceptor,
+		fingerprint: [u8; 32],
+	) -> Result<Self, Error> {
+		let socket = std::net::TcpListener::bind(bind)?;
+		socket.set_nonblocking(true)?;
+		let socket = TcpListener::from_std(socket)?;
+		// Report the bound port rather than the requested one, which may be 0.
+		let bind = socket.local_addr()?;
+
+		let mut pending_events = VecDeque::new();
+		let if_watcher = if bind.ip().is_unspecified() {
+			Some(SyncWrapper::new(if_watch::tokio::IfWatcher::new()?))
+		} else {
+			pending_events.push_back(TransportEvent::ListenAddr {
+				listener_id: id,
+				address: listen_addr(bind, &fingerprint),
+			});
+			None
+		};
+
+		Ok(Self {
+			id,
+			bind,
+			addr,
+			fingerprint,
+			socket,
+			acceptor,
+			accepting: SyncWrapper::new(FuturesUnordered::new()),
+			if_watcher,
+			pending_events,
+			addresses: Vec::new(),
+		})
+	}
+
+	/// Addresses the listener currently reports.
+	pub fn addresses(&self) -> &[Multiaddr] {
+		&self.addresses
+	}
+
+	/// Keep track of the addresses reported by `event`.
+	fn track(&mut self, event: TransportEvent<Connection>) -> TransportEvent<Connection> {
+		match &event {
+			TransportEvent::ListenAddr { address, .. } if !self.addresses.contains(address) => {
+				self.addresses.push(address.clone());
+			}
+			TransportEvent::AddrExpired { address, .. } => self.addresses.retain(|a| a != address),
+			_ => {}
+		}
+		event
+	}
+
+	/// Complete the TLS handshake of a dialer, then start the yamux session over it.
+	fn accept(&self, socket: tokio::net::TcpStream, address: SocketAddr) -> Accept {
+		let acceptor = self.acceptor.clone();
+		async move {
+			let result = async move {
+				socket.set_nodelay(true)?;
+				let socket = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket))
+					.await
+					.map_err(|_| Error::Timeout(HANDSHAKE_TIMEOUT))??;
+				Ok(Connection::new(
+					socket.into(),
+					yamux::Mode::Server,
+					socketaddr_to_multiaddr(&address),
+				))
+			}
+			.await;
+			(address, result)
+		}
+		.boxed()
+	}
+}
+
+impl Stream for Listener {
+	type Item = TransportEvent<Connection>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(Some(self.track(event)));
+		}
+		if let Poll::Ready(event) = self.poll_if_addr(cx) {
+			return Poll::Ready(Some(self.track(event)));
+		}
+
+		while let Poll::Ready(accepted) = self.socket.poll_accept(cx) {
+			match accepted {
+				Ok((socket, address)) => {
+					tracing::trace!(%address, "New dialer");
+					let accept = self.accept(socket, address);
+					self.accepting.get_mut().push(accept);
+				}
+				Err(error) => {
+					return Poll::Ready(Some(TransportEvent::ListenError {
+						listener_id: self.id,
+						error,
+					}));
+				}
+			}
+		}
+
+		loop {
+			match ready!(self.accepting.get_mut().poll_next_unpin(cx)) {
+				Some((_, Ok(connection))) => {
+					let address = sf_core::Connection::remote_address(&connection).clone();
+					tracing::trace!(address = %address, "New connection");
+					return Poll::Ready(Some(TransportEvent::NewConnection {
+						listener_id: self.id,
+						address,
+						connection,
+					}));
+				}
+				Some((address, Err(error))) => {
+					tracing::debug!(%address, ?error, "Failed to accept a connection");
+				}
+				None => return Poll::Pending,
+			}
+		}
+	}
+}
+
+impl ListenerTrait for Listener {
+	type Error = Error;
+	type Connection = Connection;
+
+	fn id(&self) -> ListenerId {
+		self.id
+	}
+
+	fn local_address(&self) -> Multiaddr {
+		self.addr.clone()
+	}
+
+	fn poll_if_addr(&mut self, cx: &mut Context<'_>) -> Poll<<Self as Stream>::Item> {
+		let Some(if_watcher) = self.if_watcher.as_mut() else {
+			return Poll::Pending;
+		};
+
+		loop {
+			match ready!(if_watcher.get_mut().poll_if_event(cx)) {
+				Ok(if_watch::IfEvent::Up(inet)) => {
+					if let Some(address) = ip_to_listen_addr(&self.bind, inet.addr(), &self.fingerprint) {
+						tracing::debug!(%address, "New listen address");
+						return Poll::Ready(TransportEvent::ListenAddr {
+							listener_id: self.id,
+							address,
+						});
+					}
+				}
+				Ok(if_watch::IfEvent::Down(inet)) => {
+					if let Some(address) = ip_to_listen_addr(&self.bind, inet.addr(), &self.fingerprint) {
+						tracing::debug!(%address, "Expired listen address");
+						return Poll::Ready(TransportEvent::AddrExpired {
+							listener_id: self.id,
+							address,
+						});
+					}
+				}
+				Err(error) => {
+					return Poll::Ready(TransportEvent::ListenError {
+						listener_id: self.id,
+						error,
+					});
+				}
+			}
+		}
+	}
+}
+
+/// Published address of `socket_addr`, with the hash of the served certificate.
+fn listen_addr(socket_addr: SocketAddr, fingerprint: &[u8; 32]) -> Multiaddr {
+	socketaddr_to_multiaddr(&socket_addr).with(Protocol::Certhash(certhash(fingerprint)))
+}
+
+fn ip_to_listen_addr(bind: &SocketAddr, ip: IpAddr, fingerprint: &[u8; 32]) -> Option<Multiaddr> {
+	// Only report the interfaces of the address family of the socket.
+	if bind.is_ipv4() != ip.is_ipv4() {
+		return None;
+	}
+	Some(listen_addr(SocketAddr::new(ip, bind.port()), fingerprint))
+}
@@ -0,0 +1,61 @@
+use std::{
+	fmt, io,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use futures::{AsyncRead, AsyncWrite, future::BoxFuture};
+
+/// A bidirectional stream over a yamux stream.
+pub struct Stream {
+	inner: yamux::Stream,
+}
+
+impl Stream {
+	pub(crate) fn new(inner: yamux::Stream) -> Self {
+		Self { inner }
+	}
+}
+
+impl fmt::Debug for Stream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Stream").field("id", &self.inner.id()).finish()
+	}
+}
+
+impl sf_core::Stream for Stream {
+	type Error = crate::Error;
+
+	fn close_send(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		Box::pin(async move {
+			futures::future::poll_fn(|cx| Pin::new(&mut self.inner).poll_close(cx))
+				.await
+				.map_err(crate::Error::Io)
+		})
+	}
+
+	fn close_read(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		// yamux has no way to stop the remote's writes, whatever it sends past this point is discarded with the stream.
+		Box::pin(async { Ok(()) })
+	}
+}
+
+impl AsyncWrite for Stream {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
+
+impl AsyncRead for Stream {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
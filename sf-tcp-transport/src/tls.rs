@@ -0,0 +1,103 @@
+//! TLS 1.3 configurations, with the ring provider so that they do not depend on a process-level one.
+
+use std::sync::Arc;
+
+use ring::digest::{SHA256, digest};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+
+use crate::certificate::Certificate;
+use crate::error::Error;
+
+fn provider() -> Arc<CryptoProvider> {
+	Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Serve `certificate`, without authenticating the clients which prove their identity in the node handshake.
+pub(crate) fn server_config(certificate: &Certificate) -> Result<ServerConfig, Error> {
+	Ok(ServerConfig::builder_with_provider(provider())
+		.with_protocol_versions(&[&rustls::version::TLS13])?
+		.with_no_client_auth()
+		.with_single_cert(certificate.chain(), certificate.key())?)
+}
+
+/// Accept the server certificates of SHA-256 digest in `certhashes`, or those issued by the system roots when empty.
+pub(crate) fn client_config(certhashes: &[[u8; 32]]) -> Result<ClientConfig, Error> {
+	let provider = provider();
+	let builder =
+		ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&[&rustls::version::TLS13])?;
+
+	let config = if certhashes.is_empty() {
+		let mut roots = RootCertStore::empty();
+		let native = rustls_native_certs::load_native_certs();
+		for error in native.errors {
+			tracing::debug!(?error, "Failed to load a system root certificate");
+		}
+		roots.add_parsable_certificates(native.certs);
+		builder.with_root_certificates(roots).with_no_client_auth()
+	} else {
+		builder
+			.dangerous()
+			.with_custom_certificate_verifier(Arc::new(PinnedCertificates {
+				certhashes: certhashes.to_vec(),
+				algorithms: provider.signature_verification_algorithms,
+			}))
+			.with_no_client_auth()
+	};
+	Ok(config)
+}
+
+/// Accepts the certificates published in the dialed address, whatever their issuer and names.
+#[derive(Debug)]
+struct PinnedCertificates {
+	certhashes: Vec<[u8; 32]>,
+	algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertificates {
+	fn verify_server_cert(
+		&self,
+		end_entity: &CertificateDer<'_>,
+		_intermediates: &[CertificateDer<'_>],
+		_server_name: &ServerName<'_>,
+		_ocsp_response: &[u8],
+		_now: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		let fingerprint = digest(&SHA256, end_entity);
+		if self
+			.certhashes
+			.iter()
+			.any(|certhash| certhash[..] == *fingerprint.as_ref())
+		{
+			Ok(ServerCertVerified::assertion())
+		} else {
+			Err(rustls::Error::InvalidCertificate(
+				rustls::CertificateError::ApplicationVerificationFailure,
+			))
+		}
+	}
+
+	fn verify_tls12_signature(
+		&self,
+		message: &[u8],
+		certificate: &CertificateDer<'_>,
+		signature: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		rustls::crypto::verify_tls12_signature(message, certificate, signature, &self.algorithms)
+	}
+
+	fn verify_tls13_signature(
+		&self,
+		message: &[u8],
+		certificate: &CertificateDer<'_>,
+		signature: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		rustls::crypto::verify_tls13_signature(message, certificate, signature, &self.algorithms)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		self.algorithms.supported_schemes()
+	}
+}
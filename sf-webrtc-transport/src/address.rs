@@ -0,0 +1,132 @@
+use std::net::{IpAddr, SocketAddr};
+
+use multiaddr::multihash::Multihash;
+use multiaddr::{Multiaddr, PeerId, Protocol};
+
+use crate::error::Error;
+
+/// Multihash code of SHA-256, the hash of the DTLS certificate fingerprints.
+pub(crate) const SHA2_256: u64 = 0x12;
+
+/// `/<ip4|ip6>/<ip>/udp/<port>/webrtc-direct` address of a socket.
+pub(crate) fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
+	Multiaddr::empty()
+		.with(socket_addr.ip().to_canonical().into())
+		.with(Protocol::Udp(socket_addr.port()))
+		.with(Protocol::WebRTCDirect)
+}
+
+/// The IP and UDP port of a `/<ip4|ip6>/<ip>/udp/<port>/webrtc-direct` listen address.
+pub(crate) fn listen_socket_addr(address: &Multiaddr) -> Result<SocketAddr, Error> {
+	let mut iter = address.iter();
+	let ip: IpAddr = match iter.next() {
+		Some(Protocol::Ip4(ip)) => ip.into(),
+		Some(Protocol::Ip6(ip)) => ip.into(),
+		_ => return Err(Error::InvalidMultiaddr(address.clone())),
+	};
+	match (iter.next(), iter.next(), iter.next()) {
+		(Some(Protocol::Udp(port)), Some(Protocol::WebRTCDirect), None) => Ok(SocketAddr::new(ip, port)),
+		_ => Err(Error::InvalidMultiaddr(address.clone())),
+	}
+}
+
+/// Parsed form of `/<ip4|ip6>/<ip>/udp/<port>/webrtc-direct/certhash/<hash>[/certhash/<hash>]*[/p2p/<peer>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DialAddr {
+	pub(crate) socket_addr: SocketAddr,
+	/// SHA-256 digest of the certificate the server presents, the first one published.
+	pub(crate) fingerprint: [u8; 32],
+	pub(crate) peer_id: Option<PeerId>,
+}
+
+impl DialAddr {
+	pub(crate) fn parse(ma: &Multiaddr) -> Result<Self, Error> {
+		Self::try_parse(ma).ok_or_else(|| Error::InvalidMultiaddr(ma.clone()))
+	}
+
+	fn try_parse(ma: &Multiaddr) -> Option<Self> {
+		let mut iter = ma.iter().peekable();
+
+		let ip: IpAddr = match iter.next()? {
+			Protocol::Ip4(ip) => ip.into(),
+			Protocol::Ip6(ip) => ip.into(),
+			_ => return None,
+		};
+		let Protocol::Udp(port) = iter.next()? else {
+			return None;
+		};
+		let Protocol::WebRTCDirect = iter.next()? else {
+			return None;
+		};
+
+		let mut fingerprint = None;
+		while let Some(Protocol::Certhash(hash)) = iter.peek() {
+			if hash.code() != SHA2_256 {
+				return None;
+			}
+			let digest = hash.digest().try_into().ok()?;
+			fingerprint.get_or_insert(digest);
+			iter.next();
+		}
+
+		let peer_id = match iter.next() {
+			Some(Protocol::P2p(peer_id)) => Some(peer_id),
+			Some(_) => return None,
+			None => None,
+		};
+		if iter.next().is_some() {
+			return None;
+		}
+
+		Some(Self {
+			socket_addr: SocketAddr::new(ip, port),
+			fingerprint: fingerprint?,
+			peer_id,
+		})
+	}
+}
+
+/// `/certhash/` multihash of a SHA-256 certificate fingerprint.
+pub(crate) fn certhash(fingerprint: &[u8; 32]) -> Multihash<64> {
+	Multihash::wrap(SHA2_256, fingerprint).expect("a SHA-256 digest fits in a multihash")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_dial_addr() {
+		let fingerprint = [7; 32];
+		let peer_id = PeerId::random();
+		let address = Multiaddr::empty()
+			.with(Protocol::Ip6("::1".parse().unwrap()))
+			.with(Protocol::Udp(4433))
+			.with(Protocol::WebRTCDirect)
+			.with(Protocol::Certhash(certhash(&fingerprint)))
+			.with(Protocol::Certhash(certhash(&[8; 32])))
+			.with(Protocol::P2p(peer_id));
+
+		assert_eq!(
+			DialAddr::parse(&address).unwrap(),
+			DialAddr {
+				socket_addr: "[::1]:4433".parse().unwrap(),
+				fingerprint,
+				peer_id: Some(peer_id),
+			}
+		);
+	}
+
+	#[test]
+	fn test_reject_invalid_dial_addr() {
+		for address in [
+			"/ip4/127.0.0.1/udp/4433/webrtc-direct",
+			"/ip4/127.0.0.1/udp/4433/quic-v1/webtransport",
+			"/dns4/localhost/udp/4433/webrtc-direct",
+			"/ip4/127.0.0.1/tcp/4433/webrtc-direct",
+		] {
+			let address: Multiaddr = address.parse().unwrap();
+			assert!(matches!(DialAddr::parse(&address), Err(Error::InvalidMultiaddr(a)) if a == address));
+		}
+	}
+}
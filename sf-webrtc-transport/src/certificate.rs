@@ -0,0 +1,55 @@
+use webrtc::peer_connection::certificate::RTCCertificate;
+
+use crate::error::Error;
+
+/// DTLS certificate of a listener, its hash is published in the `/certhash/` of the listen addresses.
+///
+/// Keep the same certificate across restarts, through [`Certificate::to_pem`], for the addresses to stay dialable.
+#[derive(Debug, Clone)]
+pub struct Certificate(RTCCertificate);
+
+impl Certificate {
+	/// Generate a new ECDSA P-256 certificate.
+	pub fn generate() -> Result<Self, Error> {
+		let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)
+			.map_err(|error| Error::Certificate(webrtc::Error::new(error.to_string())))?;
+		RTCCertificate::from_key_pair(key_pair)
+			.map(Self)
+			.map_err(Error::Certificate)
+	}
+
+	/// Load a certificate saved with [`Certificate::to_pem`].
+	pub fn from_pem(pem: &str) -> Result<Self, Error> {
+		RTCCertificate::from_pem(pem).map(Self).map_err(Error::Certificate)
+	}
+
+	/// The certificate and its private key in PEM.
+	pub fn to_pem(&self) -> String {
+		self.0.serialize_pem()
+	}
+
+	/// SHA-256 digest of the certificate.
+	pub fn fingerprint(&self) -> [u8; 32] {
+		let fingerprint = self.0.get_fingerprints().remove(0);
+		let digest = hex::decode(fingerprint.value.replace(':', "")).expect("fingerprints are hex encoded");
+		digest.try_into().expect("fingerprints are SHA-256 digests")
+	}
+
+	pub(crate) fn rtc_certificate(&self) -> RTCCertificate {
+		self.0.clone()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_pem_round_trip() {
+		let certificate = Certificate::generate().unwrap();
+
+		let loaded = Certificate::from_pem(&certificate.to_pem()).unwrap();
+
+		assert_eq!(loaded.fingerprint(), certificate.fingerprint());
+	}
+}
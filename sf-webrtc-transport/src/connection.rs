@@ -0,0 +1,245 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use futures::{FutureExt, channel::oneshot, future::BoxFuture};
+use multiaddr::{Multiaddr, PeerId};
+use tokio::sync::{Mutex, mpsc, watch};
+use webrtc::{
+	data::data_channel::DataChannel,
+	data_channel::{RTCDataChannel, data_channel_init::RTCDataChannelInit},
+	peer_connection::{RTCPeerConnection, peer_connection_state::RTCPeerConnectionState},
+};
+
+use crate::{error::Error, listener::Mux, stream::Stream, udp_mux::Seen};
+
+/// Time for the ICE, DTLS and SCTP handshakes to complete.
+const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Identifier of the negotiated data channel both sides open to learn the connection is established.
+const HANDSHAKE_CHANNEL_ID: u16 = 0;
+
+/// Resources of the listener an inbound connection keeps alive, only ever dropped.
+pub(crate) struct Inbound {
+	/// Lets the dialer's ufrag start a new connection once this one is dropped.
+	pub(crate) _seen: Seen,
+	/// Keeps routing the packets of the connection after the listener is removed.
+	pub(crate) _mux: Arc<Mux>,
+}
+
+/// A peer connection whose descriptions are being set, its handlers are registered before any of them.
+pub(crate) struct Connecting {
+	peer_connection: Arc<RTCPeerConnection>,
+	handshake: Arc<RTCDataChannel>,
+	opened: oneshot::Receiver<()>,
+	closed: watch::Receiver<bool>,
+	incoming: mpsc::UnboundedReceiver<Arc<DataChannel>>,
+	inbound: Option<Inbound>,
+}
+
+impl Connecting {
+	pub(crate) async fn new(peer_connection: RTCPeerConnection, inbound: Option<Inbound>) -> Result<Self, Error> {
+		let peer_connection = Arc::new(peer_connection);
+
+		let (closed_tx, closed) = watch::channel(false);
+		let closed_tx = Arc::new(closed_tx);
+		peer_connection.on_peer_connection_state_change(Box::new({
+			let closed_tx = closed_tx.clone();
+			move |state| {
+				if matches!(state, RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed) {
+					closed_tx.send_replace(true);
+				}
+				Box::pin(async {})
+			}
+		}));
+
+		let (incoming_tx, incoming) = mpsc::unbounded_channel();
+		peer_connection.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+			let incoming_tx = incoming_tx.clone();
+			Box::pin(async move {
+				let opened = channel.clone();
+				channel.on_open(Box::new(move || {
+					Box::pin(async move {
+						match opened.detach().await {
+							Ok(channel) => {
+								let _ = incoming_tx.send(channel);
+							}
+							Err(error) => tracing::debug!(?error, "Failed to detach an inbound data channel"),
+						}
+					})
+				}));
+			})
+		}));
+
+		let handshake = peer_connection
+			.create_data_channel(
+				"",
+				Some(RTCDataChannelInit {
+					negotiated: Some(HANDSHAKE_CHANNEL_ID),
+					..Default::default()
+				}),
+			)
+			.await?;
+		let (opened_tx, opened) = oneshot::channel();
+		handshake.on_open(Box::new(move || {
+			let _ = opened_tx.send(());
+			Box::pin(async {})
+		}));
+		// The remote closing the connection resets every channel, this one included.
+		handshake.on_close(Box::new(move || {
+			closed_tx.send_replace(true);
+			Box::pin(async {})
+		}));
+
+		Ok(Self {
+			peer_connection,
+			handshake,
+			opened,
+			closed,
+			incoming,
+			inbound,
+		})
+	}
+
+	pub(crate) fn peer_connection(&self) -> &RTCPeerConnection {
+		&self.peer_connection
+	}
+
+	/// Wait for the handshakes to complete, closing the peer connection if they fail.
+	pub(crate) async fn established(self, remote_address: Multiaddr) -> Result<Connection, Error> {
+		let mut closed = self.closed.clone();
+		let result = tokio::select! {
+			opened = self.opened => opened.map_err(|_| Error::ConnectionClosed),
+			_ = closed.wait_for(|closed| *closed) => Err(Error::ConnectionClosed),
+			_ = tokio::time::sleep(ESTABLISH_TIMEOUT) => Err(Error::Timeout(ESTABLISH_TIMEOUT)),
+		};
+		if let Err(error) = result {
+			let _ = self.peer_connection.close().await;
+			return Err(error);
+		}
+
+		Ok(Connection {
+			inner: Arc::new(Inner {
+				peer_connection: self.peer_connection,
+				_handshake: self.handshake,
+				closed: self.closed,
+				incoming: Mutex::new(self.incoming),
+				_inbound: self.inbound,
+			}),
+			remote_address,
+			remote_peer_id: None,
+		})
+	}
+}
+
+struct Inner {
+	peer_connection: Arc<RTCPeerConnection>,
+	/// Kept open for as long as the connection, its reset tells the remote the connection is closed.
+	_handshake: Arc<RTCDataChannel>,
+	closed: watch::Receiver<bool>,
+	incoming: Mutex<mpsc::UnboundedReceiver<Arc<DataChannel>>>,
+	_inbound: Option<Inbound>,
+}
+
+impl Drop for Inner {
+	fn drop(&mut self) {
+		let peer_connection = self.peer_connection.clone();
+		if let Ok(handle) = tokio::runtime::Handle::try_current() {
+			handle.spawn(async move {
+				let _ = peer_connection.close().await;
+			});
+		}
+	}
+}
+
+/// A WebRTC peer connection, each stream being a data channel.
+///
+/// Clones share the peer connection, which is closed once they are all dropped.
+#[derive(Clone)]
+pub struct Connection {
+	inner: Arc<Inner>,
+	remote_address: Multiaddr,
+	remote_peer_id: Option<PeerId>,
+}
+
+impl Connection {
+	/// Resolve once the connection is closed, by either side.
+	pub fn closed(&self) -> BoxFuture<'static, Error> {
+		let mut closed = self.inner.closed.clone();
+		Box::pin(async move {
+			let _ = closed.wait_for(|closed| *closed).await;
+			Error::ConnectionClosed
+		})
+	}
+
+	/// Close the connection, WebRTC does not carry `code` and `reason` to the remote.
+	pub fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		tracing::debug!(code, reason, remote_address = %self.remote_address, "Closing connection");
+		let peer_connection = self.inner.peer_connection.clone();
+		Box::pin(async move { Ok(peer_connection.close().await?) })
+	}
+
+	/// Record the identity of the remote once it has been authenticated by the upper layer.
+	pub fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+}
+
+impl fmt::Debug for Connection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Connection")
+			.field("remote_address", &self.remote_address)
+			.field("remote_peer_id", &self.remote_peer_id)
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Connection for Connection {
+	type Error = Error;
+	type Output = Stream;
+
+	type Close = BoxFuture<'static, Result<(), Self::Error>>;
+	type Stream = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+	fn open_stream(&mut self) -> Self::Stream {
+		let inner = self.inner.clone();
+		let closed = self.closed();
+		async move {
+			let channel = inner.peer_connection.create_data_channel("", None).await?;
+			let (opened_tx, opened) = oneshot::channel();
+			channel.on_open(Box::new(move || {
+				let _ = opened_tx.send(());
+				Box::pin(async {})
+			}));
+			tokio::select! {
+				opened = opened => opened.map_err(|_| Error::ConnectionClosed)?,
+				error = closed => return Err(error),
+			}
+			Ok(Stream::new(channel.detach().await?))
+		}
+		.boxed()
+	}
+
+	fn accept_stream(&mut self) -> Self::Stream {
+		let inner = self.inner.clone();
+		let closed = self.closed();
+		async move {
+			let mut incoming = inner.incoming.lock().await;
+			tokio::select! {
+				channel = incoming.recv() => channel.map(Stream::new).ok_or(Error::ConnectionClosed),
+				error = closed => Err(error),
+			}
+		}
+		.boxed()
+	}
+
+	fn close(&mut self) -> Self::Close {
+		self.close_with_reason(0, "Closing connection")
+	}
+
+	fn remote_address(&self) -> &Multiaddr {
+		&self.remote_address
+	}
+
+	fn remote_peer_id(&self) -> Option<PeerId> {
+		self.remote_peer_id
+	}
+}
@@ -0,0 +1,30 @@
+use multiaddr::Multiaddr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("invalid multiaddr: {0}")]
+	InvalidMultiaddr(Multiaddr),
+
+	#[error("certificate error: {0}")]
+	Certificate(webrtc::Error),
+
+	#[error("webrtc error: {0}")]
+	WebRtc(#[from] webrtc::Error),
+
+	#[error("data channel error: {0}")]
+	DataChannel(#[from] webrtc::data::Error),
+
+	#[error("the connection was not established within {0:?}")]
+	Timeout(std::time::Duration),
+
+	#[error("the connection is closed")]
+	ConnectionClosed,
+
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error(transparent)]
+	Unsupported(#[from] sf_core::Unsupported),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,239 @@
+//! `/webrtc-direct/` transport: WebRTC data channels to a listener known by its address and certificate hash.
+//!
+//! Browsers reach such a listener with no signaling server, which lets native nodes accept browsers that cannot open
+//! WebTransport sessions.
+
+mod address;
+pub mod certificate;
+pub mod connection;
+pub mod error;
+mod listener;
+mod sdp;
+pub mod stream;
+mod udp_mux;
+
+use std::{
+	collections::VecDeque,
+	net::SocketAddr,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use futures::{FutureExt, StreamExt, future::BoxFuture};
+use multiaddr::{Multiaddr, PeerId};
+use sf_core::{Listener as _, ListenerId, Protocol, Transport, TransportEvent};
+use webrtc::{
+	api::{APIBuilder, setting_engine::SettingEngine},
+	ice::network_type::NetworkType,
+	peer_connection::configuration::RTCConfiguration,
+};
+
+use address::{DialAddr, listen_socket_addr};
+pub use certificate::Certificate;
+use connection::Connecting;
+pub use connection::Connection;
+pub use error::Error;
+pub use listener::Listener;
+pub use stream::Stream;
+
+pub struct WebRtc {
+	/// Served by every listener, its hash is published in their addresses.
+	certificate: Certificate,
+
+	pending_events: VecDeque<TransportEvent<Connection>>,
+
+	listeners: Vec<Listener>,
+}
+
+impl WebRtc {
+	/// Installs the `ring` rustls provider for the process, unless one is already installed.
+	pub fn new(certificate: Certificate) -> Self {
+		let _ = rustls::crypto::ring::default_provider().install_default();
+		Self {
+			certificate,
+			pending_events: VecDeque::new(),
+			listeners: Vec::new(),
+		}
+	}
+
+	/// Report the addresses of a stopped listener as expired, then the listener as closed.
+	fn close_listener(&mut self, listener: Listener) {
+		let listener_id = listener.id();
+		for address in listener.addresses() {
+			self.pending_events.push_back(TransportEvent::AddrExpired {
+				listener_id,
+				address: address.clone(),
+			});
+		}
+		self.pending_events
+			.push_back(TransportEvent::ListenerClosed { listener_id });
+	}
+}
+
+/// Settings shared by both sides of a connection to or from `remote`, both using `ufrag` as ICE credentials.
+pub(crate) fn setting_engine(ufrag: &str, remote: SocketAddr) -> SettingEngine {
+	let mut settings = SettingEngine::default();
+	settings.set_ice_credentials(ufrag.to_owned(), ufrag.to_owned());
+	settings.detach_data_channels();
+	// Gather candidates of the address family of the remote only, or ICE may settle on an unreachable pair.
+	settings.set_network_types(vec![if remote.is_ipv4() {
+		NetworkType::Udp4
+	} else {
+		NetworkType::Udp6
+	}]);
+	settings.set_include_loopback_candidate(remote.ip().is_loopback());
+	settings
+}
+
+impl Transport for WebRtc {
+	type Connection = Connection;
+	type Error = Error;
+	type Dial = BoxFuture<'static, Result<Connection, Error>>;
+
+	fn supported_protocols_for_dialing(&self) -> Protocol {
+		Protocol::WebRTC
+	}
+
+	fn dial(&self, _peer_id: PeerId, ma: Multiaddr) -> Self::Dial {
+		let addr = match DialAddr::parse(&ma) {
+			Ok(addr) => addr,
+			Err(error) => return futures::future::ready(Err(error)).boxed(),
+		};
+		tracing::debug!(?addr, "dial");
+
+		Box::pin(async move {
+			let ufrag = sdp::random_ufrag();
+			let peer_connection = APIBuilder::new()
+				.with_setting_engine(setting_engine(&ufrag, addr.socket_addr))
+				.build()
+				.new_peer_connection(RTCConfiguration::default())
+				.await?;
+			let connecting = Connecting::new(peer_connection, None).await?;
+
+			// The listener answers as rebuilt here from its address, the DTLS handshake checks its certificate.
+			let peer_connection = connecting.peer_connection();
+			let offer = peer_connection.create_offer(None).await?;
+			peer_connection.set_local_description(offer).await?;
+			peer_connection
+				.set_remote_description(sdp::answer(addr.socket_addr, &addr.fingerprint, &ufrag)?)
+				.await?;
+
+			connecting.established(ma).await
+		})
+	}
+
+	fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Self::Error> {
+		let bind = listen_socket_addr(&addr)?;
+		let id = ListenerId::next();
+		let listener = Listener::new(id, bind, addr, self.certificate.clone())?;
+		self.listeners.push(listener);
+		Ok(id)
+	}
+
+	fn remove_listener(&mut self, id: ListenerId) -> bool {
+		let Some(position) = self.listeners.iter().position(|listener| listener.id() == id) else {
+			return false;
+		};
+		let listener = self.listeners.remove(position);
+		self.close_listener(listener);
+		true
+	}
+
+	fn shutdown(&mut self) {
+		self.pending_events.clear();
+		self.listeners.clear();
+	}
+
+	#[tracing::instrument(level = "trace", name = "Transport::poll", skip(self, cx))]
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Connection>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(event);
+		}
+
+		let mut i = 0;
+		while i < self.listeners.len() {
+			match self.listeners[i].poll_next_unpin(cx) {
+				Poll::Ready(Some(event)) => return Poll::Ready(event),
+				Poll::Ready(None) => {
+					let listener = self.listeners.remove(i);
+					self.close_listener(listener);
+					if let Some(event) = self.pending_events.pop_front() {
+						return Poll::Ready(event);
+					}
+				}
+				Poll::Pending => i += 1,
+			}
+		}
+
+		Poll::Pending
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sf_core::test_util;
+
+	const LISTEN_ADDRESS: &str = "/ip4/127.0.0.1/udp/0/webrtc-direct";
+
+	fn transport() -> WebRtc {
+		WebRtc::new(Certificate::generate().unwrap())
+	}
+
+	#[tokio::test]
+	async fn test_listen_address_publishes_certhash() {
+		let certificate = Certificate::generate().unwrap();
+		let mut transport = WebRtc::new(certificate.clone());
+
+		let address = test_util::listen(&mut transport, LISTEN_ADDRESS).await;
+
+		assert!(!address.iter().any(|protocol| protocol == multiaddr::Protocol::Udp(0)));
+		assert_eq!(
+			DialAddr::parse(&address).unwrap().fingerprint,
+			certificate.fingerprint()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_streams_both_ways() {
+		test_util::streams_both_ways(transport, LISTEN_ADDRESS, 100_000).await;
+	}
+
+	#[tokio::test]
+	async fn test_dial_invalid_multiaddr() {
+		let address: Multiaddr = "/ip4/127.0.0.1/udp/4433/webrtc-direct".parse().unwrap();
+
+		let result = test_util::dial_fails(&transport(), address.clone()).await;
+
+		assert!(matches!(result, Error::InvalidMultiaddr(invalid) if invalid == address));
+	}
+
+	#[tokio::test]
+	async fn test_dial_wrong_certhash_fails() {
+		let mut listener = transport();
+		let address = test_util::listen(&mut listener, LISTEN_ADDRESS).await;
+		let address = address::socketaddr_to_multiaddr(&listen_socket_addr(&strip(&address)).unwrap())
+			.with(multiaddr::Protocol::Certhash(address::certhash(&[0; 32])));
+
+		let dialer = transport();
+
+		let listen = async {
+			loop {
+				test_util::next_event(&mut listener).await;
+			}
+		};
+		tokio::select! {
+			_ = test_util::dial_fails(&dialer, address) => {}
+			() = listen => unreachable!(),
+		}
+	}
+
+	/// `address` without its `/certhash/` components.
+	fn strip(address: &Multiaddr) -> Multiaddr {
+		address
+			.iter()
+			.filter(|protocol| !matches!(protocol, multiaddr::Protocol::Certhash(_)))
+			.collect()
+	}
+}
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream, StreamExt, ready};
+use multiaddr::{Multiaddr, Protocol};
+use sf_core::{Listener as ListenerTrait, ListenerId, TransportEvent};
+use sync_wrapper::SyncWrapper;
+use webrtc::api::APIBuilder;
+use webrtc::dtls_transport::dtls_role::DTLSRole;
+use webrtc::ice::udp_mux::{UDPMux, UDPMuxDefault, UDPMuxParams};
+use webrtc::ice::udp_network::UDPNetwork;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+
+use crate::address::{certhash, socketaddr_to_multiaddr};
+use crate::certificate::Certificate;
+use crate::connection::{Connecting, Connection, Inbound};
+use crate::error::Error;
+use crate::sdp;
+use crate::setting_engine;
+use crate::udp_mux::{NewDialer, Socket};
+
+/// The ICE UDP mux of a listener, closed once the listener and its connections are dropped.
+pub(crate) struct Mux(Arc<UDPMuxDefault>);
+
+impl Drop for Mux {
+	fn drop(&mut self) {
+		let mux = self.0.clone();
+		if let Ok(handle) = tokio::runtime::Handle::try_current() {
+			handle.spawn(async move {
+				let _ = mux.close().await;
+			});
+		}
+	}
+}
+
+type Accept = BoxFuture<'static, (SocketAddr, Result<Connection, Error>)>;
+
+pub struct Listener {
+	id: ListenerId,
+	bind: SocketAddr,
+	addr: Multiaddr,
+	certificate: Certificate,
+
+	mux: Arc<Mux>,
+	new_dialers: mpsc::UnboundedReceiver<NewDialer>,
+	/// Inbound connections completing their handshakes.
+	accepting: SyncWrapper<FuturesUnordered<Accept>>,
+	if_watcher: Option<SyncWrapper<if_watch::tokio::IfWatcher>>,
+
+	pending_events: VecDeque<<Self as Stream>::Item>,
+
+	/// Addresses reported through [`TransportEvent::ListenAddr`] and not expired since.
+	addresses: Vec<Multiaddr>,
+}
+
+impl Listener {
+	/// Bind the UDP socket of `addr`, must be called within a tokio runtime.
+	pub(crate) fn new(
+		id: ListenerId,
+		bind: SocketAddr,
+		addr: Multiaddr,
+		certificate: Certificate,
+	) -> Result<Self, Error> {
+		let socket = std::net::UdpSocket::bind(bind)?;
+		socket.set_nonblocking(true)?;
+		let (socket, new_dialers) = Socket::new(tokio::net::UdpSocket::from_std(socket)?);
+		// Report the bound port rather than the requested one, which may be 0.
+		let bind = webrtc::util::Conn::local_addr(&socket).map_err(|error| Error::Io(std::io::Error::other(error)))?;
+		let mux = Arc::new(Mux(UDPMuxDefault::new(UDPMuxParams::new(socket))));
+
+		let mut pending_events = VecDeque::new();
+		let if_watcher = if bind.ip().is_unspecified() {
+			Some(SyncWrapper::new(if_watch::tokio::IfWatcher::new()?))
+		} else {
+			pending_events.push_back(TransportEvent::ListenAddr {
+				listener_id: id,
+				address: listen_addr(bind, &certificate),
+			});
+			None
+		};
+
+		Ok(Self {
+			id,
+			bind,
+			addr,
+			certificate,
+			mux,
+			new_dialers,
+			accepting: SyncWrapper::new(FuturesUnordered::new()),
+			if_watcher,
+			pending_events,
+			addresses: Vec::new(),
+		})
+	}
+
+	/// Addresses the listener currently reports.
+	pub fn addresses(&self) -> &[Multiaddr] {
+		&self.addresses
+	}
+
+	/// Keep track of the addresses reported by `event`.
+	fn track(&mut self, event: TransportEvent<Connection>) -> TransportEvent<Connection> {
+		match &event {
+			TransportEvent::ListenAddr { address, .. } if !self.addresses.contains(address) => {
+				self.addresses.push(address.clone());
+			}
+			TransportEvent::AddrExpired { address, .. } => self.addresses.retain(|a| a != address),
+			_ => {}
+		}
+		event
+	}
+
+	/// Answer the connection of a dialer, whose offer is rebuilt from its first binding request.
+	fn accept(&self, dialer: NewDialer) -> Accept {
+		let mux = self.mux.clone();
+		let certificate = self.certificate.rtc_certificate();
+		async move {
+			let address = dialer.address;
+			let result = async move {
+				let mut settings = setting_engine(&dialer.ufrag, address);
+				settings.set_udp_network(UDPNetwork::Muxed(mux.0.clone()));
+				settings.set_lite(true);
+				// The dialer's certificate is unknown, see `sdp::offer`.
+				settings.disable_certificate_fingerprint_verification(true);
+				settings.set_answering_dtls_role(DTLSRole::Server)?;
+
+				let peer_connection = APIBuilder::new()
+					.with_setting_engine(settings)
+					.build()
+					.new_peer_connection(RTCConfiguration {
+						certificates: vec![certificate],
+						..Default::default()
+					})
+					.await?;
+				let connecting = Connecting::new(
+					peer_connection,
+					Some(Inbound {
+						_seen: dialer.seen,
+						_mux: mux,
+					}),
+				)
+				.await?;
+
+				let peer_connection = connecting.peer_connection();
+				peer_connection
+					.set_remote_description(sdp::offer(address, &dialer.ufrag)?)
+					.await?;
+				let answer = peer_connection.create_answer(None).await?;
+				peer_connection.set_local_description(answer).await?;
+
+				connecting.established(socketaddr_to_multiaddr(&address)).await
+			}
+			.await;
+			(address, result)
+		}
+		.boxed()
+	}
+}
+
+impl Stream for Listener {
+	type Item = TransportEvent<Connection>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(Some(self.track(event)));
+		}
+		if let Poll::Ready(event) = self.poll_if_addr(cx) {
+			return Poll::Ready(Some(self.track(event)));
+		}
+
+		while let Poll::Ready(dialer) = self.new_dialers.poll_next_unpin(cx) {
+			let Some(dialer) = dialer else {
+				tracing::debug!(listener_id = ?self.id, "UDP socket stopped receiving");
+				return Poll::Ready(None);
+			};
+			tracing::trace!(address = %dialer.address, "New dialer");
+			let accept = self.accept(dialer);
+			self.accepting.get_mut().push(accept);
+		}
+
+		loop {
+			match ready!(self.accepting.get_mut().poll_next_unpin(cx)) {
+				Some((_, Ok(connection))) => {
+					let address = sf_core::Connection::remote_address(&connection).clone();
+					tracing::trace!(address = %address, "New connection");
+					return Poll::Ready(Some(TransportEvent::NewConnection {
+						listener_id: self.id,
+						address,
+						connection,
+					}));
+				}
+				Some((address, Err(error))) => {
+					tracing::debug!(%address, ?error, "Failed to accept a connection");
+				}
+				None => return Poll::Pending,
+			}
+		}
+	}
+}
+
+impl ListenerTrait for Listener {
+	type Error = Error;
+	type Connection = Connection;
+
+	fn id(&self) -> ListenerId {
+		self.id
+	}
+
+	fn local_address(&self) -> Multiaddr {
+		self.addr.clone()
+	}
+
+	fn poll_if_addr(&mut self, cx: &mut Context<'_>) -> Poll<<Self as Stream>::Item> {
+		let Some(if_watcher) = self.if_watcher.as_mut() else {
+			return Poll::Pending;
+		};
+
+		loop {
+			match ready!(if_watcher.get_mut().poll_if_event(cx)) {
+				Ok(if_watch::IfEvent::Up(inet)) => {
+					if let Some(address) = ip_to_listen_addr(&self.bind, inet.addr(), &self.certificate) {
+						tracing::debug!(%address, "New listen address");
+						return Poll::Ready(TransportEvent::ListenAddr {
+							listener_id: self.id,
+							address,
+						});
+					}
+				}
+				Ok(if_watch::IfEvent::Down(inet)) => {
+					if let Some(address) = ip_to_listen_addr(&self.bind, inet.addr(), &self.certificate) {
+						tracing::debug!(%address, "Expired listen address");
+						return Poll::Ready(TransportEvent::AddrExpired {
+							listener_id: self.id,
+							address,
+						});
+					}
+				}
+				Err(error) => {
+					return Poll::Ready(TransportEvent::ListenError {
+						listener_id: self.id,
+						error,
+					});
+				}
+			}
+		}
+	}
+}
+
+/// Published address of `socket_addr`, with the hash of the served certificate.
+fn listen_addr(socket_addr: SocketAddr, certificate: &Certificate) -> Multiaddr {
+	socketaddr_to_multiaddr(&socket_addr).with(Protocol::Certhash(certhash(&certificate.fingerprint())))
+}
+
+fn ip_to_listen_addr(bind: &SocketAddr, ip: IpAddr, certificate: &Certificate) -> Option<Multiaddr> {
+	// Only report the interfaces of the address family of the socket.
+	if bind.is_ipv4() != ip.is_ipv4() {
+		return None;
+	}
+	Some(listen_addr(SocketAddr::new(ip, bind.port()), certificate))
+}
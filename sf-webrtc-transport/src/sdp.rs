@@ -0,0 +1,122 @@
+//! Session descriptions of `/webrtc-direct/` connections.
+//!
+//! No signaling channel exists between the peers, so each side builds the description of the other: the dialer knows
+//! the address and certificate hash of the listener from the multiaddr, the listener learns the address and ICE
+//! credentials of the dialer from its first STUN binding request. Both sides use the dialer's random ufrag as the
+//! ICE username and password.
+
+use std::net::SocketAddr;
+
+use rand::{Rng, distributions::Alphanumeric};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+use crate::error::Error;
+
+/// Prefix of the ufrags, telling the listener which protocol the dialer speaks.
+const UFRAG_PREFIX: &str = "sf+webrtc+v1/";
+
+/// Generate the ICE username fragment of a dialed connection, also used as the ICE password.
+pub(crate) fn random_ufrag() -> String {
+	let suffix: String = rand::thread_rng()
+		.sample_iter(&Alphanumeric)
+		.take(32)
+		.map(char::from)
+		.collect();
+	format!("{UFRAG_PREFIX}{suffix}")
+}
+
+/// Whether `ufrag` was generated by [`random_ufrag`].
+pub(crate) fn is_dialer_ufrag(ufrag: &str) -> bool {
+	ufrag.starts_with(UFRAG_PREFIX)
+}
+
+/// Answer of the listener at `addr`, as built by the dialer: ICE lite, a single host candidate and the DTLS server
+/// role with the published certificate.
+pub(crate) fn answer(addr: SocketAddr, fingerprint: &[u8; 32], ufrag: &str) -> Result<RTCSessionDescription, Error> {
+	let description = format!(
+		"v=0\r\n\
+		 o=- 0 0 IN {ip_version} {ip}\r\n\
+		 s=-\r\n\
+		 t=0 0\r\n\
+		 a=ice-lite\r\n\
+		 m=application {port} UDP/DTLS/SCTP webrtc-datachannel\r\n\
+		 c=IN {ip_version} {ip}\r\n\
+		 a=mid:0\r\n\
+		 a=ice-options:ice2\r\n\
+		 a=ice-ufrag:{ufrag}\r\n\
+		 a=ice-pwd:{ufrag}\r\n\
+		 a=fingerprint:sha-256 {fingerprint}\r\n\
+		 a=setup:passive\r\n\
+		 a=sctp-port:5000\r\n\
+		 a=max-message-size:{MAX_MESSAGE_SIZE}\r\n\
+		 a=candidate:1 1 UDP 2130706431 {ip} {port} typ host\r\n\
+		 a=end-of-candidates\r\n",
+		ip_version = ip_version(addr),
+		ip = addr.ip(),
+		port = addr.port(),
+		fingerprint = sdp_fingerprint(fingerprint),
+	);
+	Ok(RTCSessionDescription::answer(description)?)
+}
+
+/// Offer of the dialer at `addr`, as built by the listener.
+///
+/// The dialer's certificate is unknown to the listener, which does not verify it: the dialer is authenticated by the
+/// handshake of the node running over the connection.
+pub(crate) fn offer(addr: SocketAddr, ufrag: &str) -> Result<RTCSessionDescription, Error> {
+	let description = format!(
+		"v=0\r\n\
+		 o=- 0 0 IN {ip_version} {ip}\r\n\
+		 s=-\r\n\
+		 c=IN {ip_version} {ip}\r\n\
+		 t=0 0\r\n\
+		 m=application {port} UDP/DTLS/SCTP webrtc-datachannel\r\n\
+		 a=mid:0\r\n\
+		 a=ice-options:ice2\r\n\
+		 a=ice-ufrag:{ufrag}\r\n\
+		 a=ice-pwd:{ufrag}\r\n\
+		 a=fingerprint:sha-256 {fingerprint}\r\n\
+		 a=setup:actpass\r\n\
+		 a=sctp-port:5000\r\n\
+		 a=max-message-size:{MAX_MESSAGE_SIZE}\r\n",
+		ip_version = ip_version(addr),
+		ip = addr.ip(),
+		port = addr.port(),
+		fingerprint = sdp_fingerprint(&[0xff; 32]),
+	);
+	Ok(RTCSessionDescription::offer(description)?)
+}
+
+/// Largest message sent on a data channel, browsers do not reliably handle larger ones.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+fn ip_version(addr: SocketAddr) -> &'static str {
+	if addr.is_ipv4() { "IP4" } else { "IP6" }
+}
+
+/// `AB:CD:...` form of a fingerprint.
+fn sdp_fingerprint(fingerprint: &[u8; 32]) -> String {
+	fingerprint
+		.iter()
+		.map(|byte| format!("{byte:02X}"))
+		.collect::<Vec<_>>()
+		.join(":")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_answer_pins_fingerprint() {
+		let ufrag = random_ufrag();
+		assert!(is_dialer_ufrag(&ufrag));
+
+		let answer = answer("[::1]:4433".parse().unwrap(), &[0xab; 32], &ufrag).unwrap();
+
+		assert!(answer.sdp.contains("c=IN IP6 ::1\r\n"));
+		assert!(answer.sdp.contains(&format!("a=ice-ufrag:{ufrag}\r\n")));
+		assert!(answer.sdp.contains("a=fingerprint:sha-256 AB:AB:"));
+		assert!(answer.sdp.contains("::1 4433 typ host"));
+	}
+}
@@ -0,0 +1,274 @@
+use std::{
+	fmt, io,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+	time::Duration,
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{AsyncRead, AsyncWrite, FutureExt, future::BoxFuture, ready};
+use sync_wrapper::SyncWrapper;
+use tokio::sync::Notify;
+use webrtc::data::data_channel::DataChannel;
+
+use crate::sdp::MAX_MESSAGE_SIZE;
+
+/// Default bytes queued on a data channel before writes wait for the remote to acknowledge some.
+const DEFAULT_SEND_WINDOW: usize = 256 * 1024;
+
+/// How often a dropped stream checks whether its queued messages are acknowledged.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a dropped stream waits for its queued messages to be acknowledged before resetting the channel.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First byte of every message, data channels have no half-close so the end of the writes is a message of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Flag {
+	Data = 0,
+	Fin = 1,
+}
+
+/// Write in flight, resolving once the message is queued on the data channel.
+type Write = SyncWrapper<BoxFuture<'static, io::Result<()>>>;
+/// Read in flight, resolving to the next message, `None` once the channel is closed.
+type Read = SyncWrapper<BoxFuture<'static, io::Result<Option<Bytes>>>>;
+
+/// A bidirectional stream over a WebRTC data channel.
+pub struct Stream {
+	channel: Arc<DataChannel>,
+	/// Notified when the queued bytes drop below the send window.
+	drained: Arc<Notify>,
+
+	write: Option<Write>,
+	/// Length of the write in flight.
+	writing: usize,
+	finished: bool,
+
+	read: Option<Read>,
+	/// Received bytes not handed to the reader yet.
+	read_buf: Bytes,
+	eof: bool,
+}
+
+impl Stream {
+	pub(crate) fn new(channel: Arc<DataChannel>) -> Self {
+		let drained = Arc::new(Notify::new());
+		channel.set_buffered_amount_low_threshold(DEFAULT_SEND_WINDOW);
+		channel.on_buffered_amount_low(Box::new({
+			let drained = drained.clone();
+			move || {
+				drained.notify_waiters();
+				Box::pin(async {})
+			}
+		}));
+
+		Self {
+			channel,
+			drained,
+			write: None,
+			writing: 0,
+			finished: false,
+			read: None,
+			read_buf: Bytes::new(),
+			eof: false,
+		}
+	}
+
+	/// Wait for the write in flight, if any, to complete.
+	fn poll_send_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if let Some(write) = &mut self.write {
+			let result = ready!(write.get_mut().poll_unpin(cx));
+			self.write = None;
+			self.writing = 0;
+			result?;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	/// Queue `payload` as a message flagged with `flag`, once the queued bytes are below the send window.
+	fn start_send(&mut self, flag: Flag, payload: &[u8]) {
+		let mut message = BytesMut::with_capacity(1 + payload.len());
+		message.extend_from_slice(&[flag as u8]);
+		message.extend_from_slice(payload);
+		let message = message.freeze();
+
+		let channel = self.channel.clone();
+		let drained = self.drained.clone();
+		self.writing = payload.len();
+		self.write = Some(SyncWrapper::new(
+			async move {
+				loop {
+					let notified = drained.notified();
+					tokio::pin!(notified);
+					notified.as_mut().enable();
+					if channel.buffered_amount() <= channel.buffered_amount_low_threshold() {
+						break;
+					}
+					notified.await;
+				}
+				channel.write(&message).await.map_err(io::Error::other)?;
+				Ok(())
+			}
+			.boxed(),
+		));
+	}
+
+	/// Wait until some received bytes are buffered or the remote finished the stream.
+	fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while self.read_buf.is_empty() && !self.eof {
+			let read = self.read.get_or_insert_with(|| {
+				let channel = self.channel.clone();
+				SyncWrapper::new(
+					async move {
+						let mut buf = vec![0; MAX_MESSAGE_SIZE];
+						loop {
+							match channel.read(&mut buf).await {
+								// The remote closed the channel.
+								Ok(0) => return Ok(None),
+								Ok(len) => {
+									buf.truncate(len);
+									return Ok(Some(buf.into()));
+								}
+								Err(webrtc::data::Error::Sctp(webrtc::sctp::Error::ErrTryAgain)) => continue,
+								Err(webrtc::data::Error::Sctp(webrtc::sctp::Error::ErrEof)) => return Ok(None),
+								Err(error) => return Err(io::Error::other(error)),
+							}
+						}
+					}
+					.boxed(),
+				)
+			});
+
+			let result = ready!(read.get_mut().poll_unpin(cx));
+			self.read = None;
+			let Some(mut message) = result? else {
+				self.eof = true;
+				break;
+			};
+			match message.first().copied() {
+				Some(flag) if flag == Flag::Data as u8 => {
+					message.advance(1);
+					self.read_buf = message;
+				}
+				Some(flag) if flag == Flag::Fin as u8 => self.eof = true,
+				_ => {
+					return Poll::Ready(Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"invalid data channel message",
+					)));
+				}
+			}
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl Drop for Stream {
+	fn drop(&mut self) {
+		// Resetting the channel drops the messages not acknowledged yet, let them drain first.
+		let channel = self.channel.clone();
+		if let Ok(handle) = tokio::runtime::Handle::try_current() {
+			handle.spawn(async move {
+				let drain = async {
+					while channel.buffered_amount() > 0 {
+						tokio::time::sleep(DRAIN_INTERVAL).await;
+					}
+				};
+				let _ = tokio::time::timeout(DRAIN_TIMEOUT, drain).await;
+				let _ = channel.close().await;
+			});
+		}
+	}
+}
+
+impl fmt::Debug for Stream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Stream")
+			.field("id", &self.channel.stream_identifier())
+			.field("writing", &self.writing)
+			.field("finished", &self.finished)
+			.field("buffered", &self.read_buf.len())
+			.field("eof", &self.eof)
+			.finish_non_exhaustive()
+	}
+}
+
+impl sf_core::Stream for Stream {
+	type Error = crate::Error;
+
+	fn close_read(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		// The remote learns the stream is over when it is dropped.
+		Box::pin(async { Ok(()) })
+	}
+
+	fn close_send(&mut self) -> BoxFuture<'_, Result<(), Self::Error>> {
+		Box::pin(async move {
+			futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_close(cx))
+				.await
+				.map_err(crate::Error::Io)
+		})
+	}
+
+	fn set_send_window(&mut self, window: usize) {
+		self.channel.set_buffered_amount_low_threshold(window.max(1));
+	}
+
+	fn buffered_send(&self) -> usize {
+		self.channel.buffered_amount() + self.writing
+	}
+}
+
+impl AsyncWrite for Stream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		ready!(this.poll_send_ready(cx))?;
+		if this.finished {
+			return Poll::Ready(Err(io::Error::new(
+				io::ErrorKind::BrokenPipe,
+				"the send stream is finished",
+			)));
+		}
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+
+		let len = buf.len().min(MAX_MESSAGE_SIZE - 1);
+		this.start_send(Flag::Data, &buf[..len]);
+		// Get the write going, the bytes are accepted unless it fails right away.
+		if let Poll::Ready(Err(error)) = this.poll_send_ready(cx) {
+			return Poll::Ready(Err(error));
+		}
+		Poll::Ready(Ok(len))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.get_mut().poll_send_ready(cx)
+	}
+
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_send_ready(cx))?;
+		if !this.finished {
+			this.finished = true;
+			this.start_send(Flag::Fin, &[]);
+		}
+		this.poll_send_ready(cx)
+	}
+}
+
+impl AsyncRead for Stream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+		ready!(this.poll_fill(cx))?;
+
+		let len = buf.len().min(this.read_buf.len());
+		buf[..len].copy_from_slice(&this.read_buf[..len]);
+		this.read_buf.advance(len);
+		Poll::Ready(Ok(len))
+	}
+}
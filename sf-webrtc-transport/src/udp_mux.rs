@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use futures::channel::mpsc;
+use tokio::net::UdpSocket;
+use webrtc::stun::attributes::ATTR_USERNAME;
+use webrtc::stun::message::{Message, is_message};
+use webrtc::util::{Conn, Error};
+
+use crate::sdp::is_dialer_ufrag;
+
+/// A dialer seen for the first time by a listener.
+#[derive(Debug)]
+pub(crate) struct NewDialer {
+	pub(crate) ufrag: String,
+	pub(crate) address: SocketAddr,
+	/// Keeps the dialer from being reported again, until dropped with its connection.
+	pub(crate) seen: Seen,
+}
+
+/// Removes a ufrag from the ufrags seen by a [`Socket`] on drop.
+#[derive(Debug)]
+pub(crate) struct Seen {
+	ufrag: String,
+	seen: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for Seen {
+	fn drop(&mut self) {
+		self.seen
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&self.ufrag);
+	}
+}
+
+/// UDP socket shared by every connection of a listener through the ICE UDP mux.
+///
+/// The mux drops the packets of ufrags without connection, this reports them first so that the listener creates the
+/// connection. The dialer retransmits the dropped binding request.
+pub(crate) struct Socket {
+	socket: UdpSocket,
+	seen: Arc<Mutex<HashSet<String>>>,
+	new_dialers: mpsc::UnboundedSender<NewDialer>,
+}
+
+impl Socket {
+	pub(crate) fn new(socket: UdpSocket) -> (Self, mpsc::UnboundedReceiver<NewDialer>) {
+		let (new_dialers, rx) = mpsc::unbounded();
+		let socket = Self {
+			socket,
+			seen: Arc::default(),
+			new_dialers,
+		};
+		(socket, rx)
+	}
+
+	/// Report the sender of `packet` if it is the first binding request of a dialer.
+	fn inspect(&self, packet: &[u8], address: SocketAddr) {
+		if !is_message(packet) {
+			return;
+		}
+		let mut message = Message::new();
+		if message.unmarshal_binary(packet).is_err() {
+			return;
+		}
+		let Ok(username) = message.get(ATTR_USERNAME) else {
+			return;
+		};
+		let Some(ufrag) = std::str::from_utf8(&username).ok().and_then(|u| u.split(':').next()) else {
+			return;
+		};
+		if !is_dialer_ufrag(ufrag) {
+			return;
+		}
+
+		let inserted = self
+			.seen
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(ufrag.to_owned());
+		if inserted {
+			// Sent without holding the lock, which a dropped report takes to forget the ufrag.
+			let _ = self.new_dialers.unbounded_send(NewDialer {
+				ufrag: ufrag.to_owned(),
+				address,
+				seen: Seen {
+					ufrag: ufrag.to_owned(),
+					seen: self.seen.clone(),
+				},
+			});
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl Conn for Socket {
+	async fn connect(&self, addr: SocketAddr) -> Result<(), Error> {
+		Ok(self.socket.connect(addr).await?)
+	}
+
+	async fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		Ok(self.socket.recv(buf).await?)
+	}
+
+	async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+		let (len, address) = self.socket.recv_from(buf).await?;
+		self.inspect(&buf[..len], address);
+		Ok((len, address))
+	}
+
+	async fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+		Ok(self.socket.send(buf).await?)
+	}
+
+	async fn send_to(&self, buf: &[u8], target: SocketAddr) -> Result<usize, Error> {
+		Ok(self.socket.send_to(buf, target).await?)
+	}
+
+	fn local_addr(&self) -> Result<SocketAddr, Error> {
+		Ok(self.socket.local_addr()?)
+	}
+
+	fn remote_addr(&self) -> Option<SocketAddr> {
+		None
+	}
+
+	async fn close(&self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+		self
+	}
+}
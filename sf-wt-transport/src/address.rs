@@ -0,0 +1,224 @@
+use std::net::{IpAddr, SocketAddr};
+
+use multiaddr::multihash::Multihash;
+use multiaddr::{Multiaddr, PeerId, Protocol};
+
+use crate::error::Error;
+
+/// Multihash code of SHA-256, the only hash WebTransport accepts for certificate pinning.
+pub(crate) const SHA2_256: u64 = 0x12;
+
+/// `/certhash/` multihash of a certificate from its hex encoded SHA-256 `fingerprint`.
+pub(crate) fn certhash(fingerprint: &str) -> Result<Multihash<64>, Error> {
+	let digest = hex::decode(fingerprint).map_err(Error::HexError)?;
+	if digest.len() != 32 {
+		return Err(Error::InvalidFingerprint(fingerprint.to_owned()));
+	}
+	Ok(Multihash::wrap(SHA2_256, &digest).expect("a SHA-256 digest fits in a multihash"))
+}
+
+/// Append a `/certhash/` component to `address` for each of `certhashes`.
+pub(crate) fn with_certhashes(address: Multiaddr, certhashes: &[Multihash<64>]) -> Multiaddr {
+	certhashes
+		.iter()
+		.fold(address, |address, hash| address.with(Protocol::Certhash(*hash)))
+}
+
+/// `address` without its `/certhash/` components.
+pub(crate) fn without_certhashes(address: &Multiaddr) -> Multiaddr {
+	address
+		.iter()
+		.filter(|protocol| !matches!(protocol, Protocol::Certhash(_)))
+		.collect()
+}
+
+/// Host part of a dialed multiaddr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Host {
+	Ip(IpAddr),
+	/// `/dns`, resolved to any address family.
+	Dns(String),
+	/// `/dns4`, resolved to an IPv4 address only.
+	Dns4(String),
+	/// `/dns6`, resolved to an IPv6 address only.
+	Dns6(String),
+}
+
+/// Parsed form of `/<ip4|ip6|dns|dns4|dns6>/<host>/udp/<port>/quic-v1/webtransport[/certhash/<hash>]*[/p2p/<peer>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DialAddr {
+	pub(crate) host: Host,
+	pub(crate) port: u16,
+	/// SHA-256 digests of the certificates the server may present.
+	pub(crate) certhashes: Vec<Vec<u8>>,
+	pub(crate) peer_id: Option<PeerId>,
+}
+
+impl DialAddr {
+	pub(crate) fn parse(ma: &Multiaddr) -> Result<Self, Error> {
+		Self::try_parse(ma).ok_or_else(|| Error::InvalidMultiaddr(ma.clone()))
+	}
+
+	fn try_parse(ma: &Multiaddr) -> Option<Self> {
+		let mut iter = ma.iter().peekable();
+
+		let host = match iter.next()? {
+			Protocol::Ip4(ip) => Host::Ip(ip.into()),
+			Protocol::Ip6(ip) => Host::Ip(ip.into()),
+			Protocol::Dns(name) => Host::Dns(name.into_owned()),
+			Protocol::Dns4(name) => Host::Dns4(name.into_owned()),
+			Protocol::Dns6(name) => Host::Dns6(name.into_owned()),
+			_ => return None,
+		};
+		let Protocol::Udp(port) = iter.next()? else {
+			return None;
+		};
+		let Protocol::QuicV1 = iter.next()? else {
+			return None;
+		};
+		let Protocol::WebTransport = iter.next()? else {
+			return None;
+		};
+
+		let mut certhashes = Vec::new();
+		while let Some(Protocol::Certhash(hash)) = iter.peek() {
+			if hash.code() != SHA2_256 || hash.digest().len() != 32 {
+				return None;
+			}
+			certhashes.push(hash.digest().to_vec());
+			iter.next();
+		}
+
+		let peer_id = match iter.next() {
+			Some(Protocol::P2p(peer_id)) => Some(peer_id),
+			Some(_) => return None,
+			None => None,
+		};
+		if iter.next().is_some() {
+			return None;
+		}
+
+		Some(Self {
+			host,
+			port,
+			certhashes,
+			peer_id,
+		})
+	}
+
+	/// Resolve the host to a socket address, honouring the address family of `/dns4` and `/dns6`.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub(crate) async fn resolve(&self) -> Result<SocketAddr, Error> {
+		let name = match &self.host {
+			Host::Ip(ip) => return Ok(SocketAddr::new(*ip, self.port)),
+			Host::Dns(name) | Host::Dns4(name) | Host::Dns6(name) => name,
+		};
+
+		let mut addrs = tokio::net::lookup_host((name.as_str(), self.port))
+			.await
+			.map_err(Error::Io)?;
+		addrs
+			.find(|addr| match self.host {
+				Host::Dns4(_) => addr.is_ipv4(),
+				Host::Dns6(_) => addr.is_ipv6(),
+				_ => true,
+			})
+			.ok_or_else(|| Error::UnresolvedHost(name.clone()))
+	}
+
+	/// URL of the WebTransport session.
+	///
+	/// The host name is kept when there is one, so that the server certificate can be checked against it, otherwise
+	/// the resolved address is used.
+	pub(crate) fn url(&self, resolved: SocketAddr) -> url::Url {
+		let host = match &self.host {
+			Host::Dns(name) | Host::Dns4(name) | Host::Dns6(name) if self.certhashes.is_empty() => name.clone(),
+			_ => match resolved.ip() {
+				IpAddr::V6(ip) => format!("[{ip}]"), // brackets required for IPv6 in URLs
+				ip => ip.to_string(),
+			},
+		};
+		url::Url::parse(&format!("https://{host}:{}", self.port)).expect("invalid URL")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(ma: &str) -> Result<DialAddr, Error> {
+		DialAddr::parse(&ma.parse().unwrap())
+	}
+
+	fn certhash_component(code: u64, digest: &[u8]) -> Multiaddr {
+		Multiaddr::empty().with(Protocol::Certhash(Multihash::wrap(code, digest).unwrap()))
+	}
+
+	#[test]
+	fn test_parse_dial_addr() {
+		let peer_id = PeerId::random();
+
+		let addr = parse(&format!("/ip6/::1/udp/4433/quic-v1/webtransport/p2p/{peer_id}")).unwrap();
+		assert_eq!(addr.host, Host::Ip("::1".parse().unwrap()));
+		assert_eq!(addr.port, 4433);
+		assert_eq!(addr.peer_id, Some(peer_id));
+
+		let ma = format!(
+			"/dns4/example.com/udp/443/quic-v1/webtransport{}{}/p2p/{peer_id}",
+			certhash_component(SHA2_256, &[1; 32]),
+			certhash_component(SHA2_256, &[2; 32])
+		);
+		let addr = parse(&ma).unwrap();
+		assert_eq!(addr.host, Host::Dns4("example.com".to_owned()));
+		assert_eq!(addr.certhashes, [[1; 32], [2; 32]]);
+		assert_eq!(addr.peer_id, Some(peer_id));
+
+		let addr = parse("/dns/example.com/udp/4433/quic-v1/webtransport").unwrap();
+		assert_eq!(
+			addr.url("127.0.0.1:4433".parse().unwrap()).as_str(),
+			"https://example.com:4433/"
+		);
+	}
+
+	#[test]
+	fn test_reject_invalid_dial_addr() {
+		let invalid = [
+			"/ip4/127.0.0.1/udp/4433/quic-v1".to_owned(),
+			"/ip4/127.0.0.1/tcp/4433/quic-v1/webtransport".to_owned(),
+			"/ip4/127.0.0.1/udp/4433/quic/webtransport".to_owned(),
+			"/ip4/127.0.0.1/udp/4433/quic-v1/webtransport/udp/1".to_owned(),
+			format!(
+				"/ip4/127.0.0.1/udp/4433/quic-v1/webtransport/p2p/{}/p2p-circuit",
+				PeerId::random()
+			),
+			format!(
+				"/ip4/127.0.0.1/udp/4433/quic-v1/webtransport{}",
+				certhash_component(0x13, &[0; 64])
+			),
+			format!(
+				"/ip4/127.0.0.1/udp/4433/quic-v1/webtransport{}",
+				certhash_component(SHA2_256, &[0; 16])
+			),
+		];
+		for ma in invalid {
+			assert!(matches!(parse(&ma), Err(Error::InvalidMultiaddr(_))), "{ma}");
+		}
+	}
+
+	#[test]
+	fn test_published_certhashes_are_pinned() {
+		let fingerprint = hex::encode([7u8; 32]);
+		let hash = certhash(&fingerprint).unwrap();
+
+		let address = with_certhashes("/ip4/127.0.0.1/udp/4433/quic-v1/webtransport".parse().unwrap(), &[hash]);
+
+		assert_eq!(DialAddr::parse(&address).unwrap().certhashes, [[7u8; 32]]);
+		assert!(matches!(certhash("abcd"), Err(Error::InvalidFingerprint(_))));
+	}
+
+	#[tokio::test]
+	async fn test_resolve_dns4() {
+		let addr = parse("/dns4/localhost/udp/4433/quic-v1/webtransport").unwrap();
+		assert_eq!(addr.resolve().await.unwrap(), "127.0.0.1:4433".parse().unwrap());
+	}
+}
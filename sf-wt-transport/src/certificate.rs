@@ -0,0 +1,291 @@
+//! Self-signed certificates for WebTransport listeners.
+//!
+//! Browsers only accept a pinned certificate (`serverCertificateHashes`) when it is an ECDSA certificate valid for at
+//! most 14 days, so the certificates are short-lived and rotated. Listen addresses publish the hashes of the served
+//! certificate and of the next one, which makes addresses learned before a rotation still dialable after it.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+
+use futures::channel::mpsc;
+use moq_native::tls;
+use multiaddr::multihash::Multihash;
+use ring::digest::{SHA256, digest};
+use rustls::pki_types::PrivatePkcs8KeyDer;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::task::AbortHandle;
+
+use crate::address::SHA2_256;
+use crate::error::Error;
+
+/// Longest validity browsers accept for a pinned certificate.
+pub const MAX_VALIDITY: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+const MIN_VALIDITY: Duration = Duration::from_secs(60);
+/// Certificates start before their creation to tolerate clocks running late.
+const CLOCK_SKEW: Duration = Duration::from_secs(60 * 60);
+
+/// Names and lifetime of the generated certificates.
+#[derive(Debug, Clone)]
+pub struct CertificateConfig {
+	hostnames: Vec<String>,
+	validity: Duration,
+}
+
+impl Default for CertificateConfig {
+	fn default() -> Self {
+		Self {
+			hostnames: vec!["localhost".to_owned()],
+			validity: MAX_VALIDITY,
+		}
+	}
+}
+
+impl CertificateConfig {
+	/// Names the certificates are issued for.
+	pub fn with_hostnames(mut self, hostnames: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		self.hostnames = hostnames.into_iter().map(Into::into).collect();
+		self
+	}
+
+	/// Validity of each certificate, clamped between one minute and [`MAX_VALIDITY`].
+	///
+	/// A certificate is rotated out after half of it.
+	pub fn with_validity(mut self, validity: Duration) -> Self {
+		self.validity = validity.clamp(MIN_VALIDITY, MAX_VALIDITY);
+		self
+	}
+
+	fn rotation_period(&self) -> Duration {
+		self.validity / 2
+	}
+
+	fn backdate(&self) -> Duration {
+		CLOCK_SKEW.min(self.validity / 4)
+	}
+}
+
+/// Generates the certificates served by the listeners and rotates them before they expire.
+///
+/// Cloning returns a handle to the same certificates. Rotation runs on the tokio runtime the manager was created in
+/// and stops once the manager and the TLS configurations it produced are dropped.
+#[derive(Clone)]
+pub struct CertificateManager {
+	shared: Arc<Shared>,
+}
+
+impl CertificateManager {
+	/// Generate the first certificates and schedule their rotation, must be called within a tokio runtime.
+	pub fn new(config: CertificateConfig) -> Result<Self, Error> {
+		let current = Certificate::generate(&config, SystemTime::now() - config.backdate())?;
+		let next = Certificate::generate(&config, current.not_before + config.rotation_period())?;
+		let period = config.rotation_period();
+
+		let shared = Arc::new_cyclic(|weak: &Weak<Shared>| {
+			let weak = weak.clone();
+			let rotation = tokio::spawn(async move {
+				loop {
+					tokio::time::sleep(period).await;
+					let Some(shared) = weak.upgrade() else {
+						return;
+					};
+					if let Err(error) = shared.rotate() {
+						tracing::warn!(?error, "Failed to rotate the certificates");
+					}
+				}
+			});
+
+			Shared {
+				config,
+				state: RwLock::new(State { current, next }),
+				subscribers: Mutex::new(Vec::new()),
+				rotation: rotation.abort_handle(),
+			}
+		});
+
+		Ok(Self { shared })
+	}
+
+	/// TLS configuration serving the current certificate, verifying servers against the system roots when dialing.
+	pub fn tls_config(&self) -> Result<tls::Config, Error> {
+		let mut config = tls::Args::default().load().map_err(Error::Certificate)?;
+		let provider = Arc::new(rustls::crypto::ring::default_provider());
+		let server = rustls::ServerConfig::builder_with_provider(provider)
+			.with_protocol_versions(&[&rustls::version::TLS13])
+			.map_err(|error| Error::Certificate(error.into()))?
+			.with_no_client_auth()
+			.with_cert_resolver(Arc::new(Resolver(self.shared.clone())));
+
+		config.server = Some(server);
+		config.fingerprints = vec![self.fingerprint()];
+		Ok(config)
+	}
+
+	/// Hashes of the served certificate and of the one replacing it at the next rotation.
+	pub fn certhashes(&self) -> Vec<Multihash<64>> {
+		self.shared.state().certhashes()
+	}
+
+	/// Hex encoded SHA-256 fingerprint of the served certificate.
+	pub fn fingerprint(&self) -> String {
+		hex::encode(self.shared.state().current.hash.digest())
+	}
+
+	/// Receive the new certhashes after each rotation.
+	pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<Vec<Multihash<64>>> {
+		let (tx, rx) = mpsc::unbounded();
+		self.shared
+			.subscribers
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.push(tx);
+		rx
+	}
+
+	#[cfg(test)]
+	pub(crate) fn rotate(&self) -> Result<(), Error> {
+		self.shared.rotate()
+	}
+}
+
+impl fmt::Debug for CertificateManager {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("CertificateManager")
+			.field("config", &self.shared.config)
+			.field("certhashes", &self.certhashes())
+			.finish()
+	}
+}
+
+struct Shared {
+	config: CertificateConfig,
+	state: RwLock<State>,
+	subscribers: Mutex<Vec<mpsc::UnboundedSender<Vec<Multihash<64>>>>>,
+	rotation: AbortHandle,
+}
+
+impl Shared {
+	fn state(&self) -> std::sync::RwLockReadGuard<'_, State> {
+		self.state.read().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Serve the next certificate and generate the one following it.
+	fn rotate(&self) -> Result<(), Error> {
+		let not_before = self.state().next.not_before + self.config.rotation_period();
+		let next = Certificate::generate(&self.config, not_before)?;
+
+		let certhashes = {
+			let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+			state.current = std::mem::replace(&mut state.next, next);
+			state.certhashes()
+		};
+		tracing::debug!(?certhashes, "Rotated the certificates");
+
+		self.subscribers
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.retain(|tx| tx.unbounded_send(certhashes.clone()).is_ok());
+		Ok(())
+	}
+}
+
+impl Drop for Shared {
+	fn drop(&mut self) {
+		self.rotation.abort();
+	}
+}
+
+struct State {
+	current: Certificate,
+	next: Certificate,
+}
+
+impl State {
+	fn certhashes(&self) -> Vec<Multihash<64>> {
+		vec![self.current.hash, self.next.hash]
+	}
+}
+
+struct Certificate {
+	key: Arc<CertifiedKey>,
+	hash: Multihash<64>,
+	not_before: SystemTime,
+}
+
+impl Certificate {
+	fn generate(config: &CertificateConfig, not_before: SystemTime) -> Result<Self, Error> {
+		let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)
+			.map_err(|error| Error::Certificate(error.into()))?;
+
+		let mut params = rcgen::CertificateParams::new(config.hostnames.clone())
+			.map_err(|error| Error::Certificate(error.into()))?;
+		params.not_before = time::OffsetDateTime::from(not_before);
+		params.not_after = time::OffsetDateTime::from(not_before + config.validity);
+		let cert = params
+			.self_signed(&key_pair)
+			.map_err(|error| Error::Certificate(error.into()))?;
+
+		let hash = Multihash::wrap(SHA2_256, digest(&SHA256, cert.der()).as_ref())
+			.expect("a SHA-256 digest fits in a multihash");
+		let key = PrivatePkcs8KeyDer::from(key_pair.serialize_der());
+		let key = rustls::crypto::ring::sign::any_supported_type(&key.into())
+			.map_err(|error| Error::Certificate(error.into()))?;
+
+		Ok(Self {
+			key: Arc::new(CertifiedKey::new(vec![cert.into()], key)),
+			hash,
+			not_before,
+		})
+	}
+}
+
+/// Always serves the current certificate, whatever the requested server name.
+struct Resolver(Arc<Shared>);
+
+impl fmt::Debug for Resolver {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("Resolver").finish()
+	}
+}
+
+impl ResolvesServerCert for Resolver {
+	fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		Some(self.0.state().current.key.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::StreamExt;
+
+	#[tokio::test]
+	async fn test_certificates_are_short_lived_ecdsa() {
+		let config = CertificateConfig::default().with_validity(Duration::from_secs(30 * 24 * 60 * 60));
+		assert_eq!(config.validity, MAX_VALIDITY);
+
+		let manager = CertificateManager::new(config).unwrap();
+		let state = manager.shared.state();
+		let now = SystemTime::now();
+		assert_eq!(state.current.key.key.algorithm(), rustls::SignatureAlgorithm::ECDSA);
+		assert!(state.current.not_before <= now);
+		assert!(state.next.not_before > now);
+	}
+
+	#[tokio::test]
+	async fn test_rotation_keeps_next_certificate_advertised() {
+		let manager = CertificateManager::new(CertificateConfig::default()).unwrap();
+		let mut updates = manager.subscribe();
+		let before = manager.certhashes();
+
+		manager.rotate().unwrap();
+
+		let after = manager.certhashes();
+		assert_eq!(after[0], before[1]);
+		assert_ne!(after[1], before[1]);
+		assert_eq!(updates.next().await.unwrap(), after);
+		assert_eq!(manager.fingerprint(), hex::encode(before[1].digest()));
+	}
+}
@@ -1,27 +1,61 @@
-use std::sync::Arc;
+use std::fmt;
 
+use bytes::Bytes;
 use futures::future::BoxFuture;
 use multiaddr::{Multiaddr, PeerId};
-use tokio::sync::Mutex;
+use sf_core::Capabilities;
 use web_transport::Session;
 
 use crate::error::Error;
 use crate::stream::Stream;
 
+/// A WebTransport session.
+///
+/// The session is a cheap handle shared by the clones, every operation works on its own copy so that opening and
+/// accepting streams never wait on each other.
+#[derive(Clone)]
 pub struct Connection {
-	session: Arc<Mutex<Session>>,
+	session: Session,
 	remote_address: Multiaddr,
 	remote_peer_id: Option<PeerId>,
 }
 
 impl Connection {
-	pub fn new(session: Session) -> Self {
+	/// Wrap an established session, `remote_address` being the dialed multiaddr or the address the remote connected
+	/// from.
+	pub fn new(session: Session, remote_address: Multiaddr) -> Self {
 		Self {
-			session: Arc::new(Mutex::new(session)),
-			remote_address: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+			session,
+			remote_address,
 			remote_peer_id: None,
 		}
 	}
+
+	/// Resolve once the session is closed, by either side, with the reason.
+	pub fn closed(&self) -> BoxFuture<'static, Error> {
+		let session = self.session.clone();
+		Box::pin(async move { session.closed().await.into() })
+	}
+
+	/// Close the session, sending `code` and `reason` to the remote.
+	pub fn close_with_reason(&mut self, code: u32, reason: &str) -> BoxFuture<'static, Result<(), Error>> {
+		self.session.close(code, reason);
+		Box::pin(async { Ok(()) })
+	}
+
+	/// Record the identity of the remote once it has been authenticated by the upper layer.
+	pub fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+}
+
+impl fmt::Debug for Connection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Connection")
+			.field("remote_address", &self.remote_address)
+			.field("remote_peer_id", &self.remote_peer_id)
+			.finish_non_exhaustive()
+	}
 }
 
 impl sf_core::Connection for Connection {
@@ -32,30 +66,50 @@ impl sf_core::Connection for Connection {
 	type Stream = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
 	fn open_stream(&mut self) -> Self::Stream {
-		let session = Arc::clone(&self.session);
+		let mut session = self.session.clone();
 		Box::pin(async move {
-			let mut session = session.lock().await;
 			let (send, recv) = session.open_bi().await?;
 			Ok(Stream::new(send, recv))
 		})
 	}
 
 	fn accept_stream(&mut self) -> Self::Stream {
-		let session = Arc::clone(&self.session);
+		let mut session = self.session.clone();
 		Box::pin(async move {
-			let mut session = session.lock().await;
 			let (send, recv) = session.accept_bi().await?;
 			Ok(Stream::new(send, recv))
 		})
 	}
 
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			uni_streams: true,
+			datagrams: true,
+		}
+	}
+
+	fn open_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		let mut session = self.session.clone();
+		Box::pin(async move { Ok(Stream::send_only(session.open_uni().await?)) })
+	}
+
+	fn accept_uni(&mut self) -> BoxFuture<'static, Result<Self::Output, Self::Error>> {
+		let mut session = self.session.clone();
+		Box::pin(async move { Ok(Stream::recv_only(session.accept_uni().await?)) })
+	}
+
+	fn send_datagram(&mut self, payload: Bytes) -> BoxFuture<'static, Result<(), Self::Error>> {
+		let mut session = self.session.clone();
+		Box::pin(async move { Ok(session.send_datagram(payload).await?) })
+	}
+
+	fn recv_datagram(&mut self) -> BoxFuture<'static, Result<Bytes, Self::Error>> {
+		let mut session = self.session.clone();
+		Box::pin(async move { Ok(session.recv_datagram().await?) })
+	}
+
 	fn close(&mut self) -> Self::Close {
-		let session = Arc::clone(&self.session);
-		Box::pin(async move {
-			let mut session = session.lock().await;
-			session.close(0u32, "Closing connection");
-			Ok(())
-		})
+		self.close_with_reason(0, "Closing connection")
 	}
 
 	fn remote_address(&self) -> &Multiaddr {
@@ -67,8 +121,88 @@ impl sf_core::Connection for Connection {
 	}
 }
 
-impl From<Session> for Connection {
-	fn from(session: Session) -> Self {
-		Self::new(session)
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::future::join_all;
+	use futures::{AsyncReadExt, AsyncWriteExt};
+	use sf_core::Connection as _;
+
+	use crate::tests::connected;
+
+	const STREAMS: usize = 300;
+
+	/// Echo every stream accepted on `connection`, each on its own task.
+	fn echo(mut connection: Connection) -> tokio::task::JoinHandle<()> {
+		tokio::spawn(async move {
+			while let Ok(mut stream) = connection.accept_stream().await {
+				tokio::spawn(async move {
+					let mut buf = Vec::new();
+					stream.read_to_end(&mut buf).await.unwrap();
+					stream.write_all(&buf).await.unwrap();
+					stream.close().await.unwrap();
+				});
+			}
+		})
+	}
+
+	/// Open [`STREAMS`] streams at once on `connection` and check each is echoed back.
+	async fn ping_all(connection: &Connection) {
+		join_all((0..STREAMS).map(|i| {
+			let mut connection = connection.clone();
+			async move {
+				let payload = i.to_be_bytes();
+				let mut stream = connection.open_stream().await.unwrap();
+				stream.write_all(&payload).await.unwrap();
+				stream.close().await.unwrap();
+				let mut echoed = Vec::new();
+				stream.read_to_end(&mut echoed).await.unwrap();
+				assert_eq!(echoed, payload);
+			}
+		}))
+		.await;
+	}
+
+	#[tokio::test]
+	async fn test_open_and_accept_hundreds_of_streams() {
+		let (dialer, listener, _transport) = connected().await;
+		let echoes = [echo(dialer.clone()), echo(listener.clone())];
+
+		tokio::join!(ping_all(&dialer), ping_all(&listener));
+
+		for echo in echoes {
+			echo.abort();
+		}
+	}
+
+	#[tokio::test]
+	async fn test_uni_stream() {
+		let (mut dialer, mut listener, _transport) = connected().await;
+		assert!(dialer.capabilities().uni_streams);
+
+		let mut send = dialer.open_uni().await.unwrap();
+		send.write_all(b"one way").await.unwrap();
+		send.close().await.unwrap();
+
+		let mut recv = listener.accept_uni().await.unwrap();
+		let mut received = Vec::new();
+		recv.read_to_end(&mut received).await.unwrap();
+		assert_eq!(received, b"one way");
+
+		let error = recv.write_all(b"back").await.unwrap_err();
+		assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+		assert_eq!(send.read(&mut [0; 8]).await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_datagrams() {
+		let (mut dialer, mut listener, _transport) = connected().await;
+		assert!(dialer.capabilities().datagrams);
+
+		dialer.send_datagram(Bytes::from_static(b"ping")).await.unwrap();
+		assert_eq!(listener.recv_datagram().await.unwrap(), "ping");
+		listener.send_datagram(Bytes::from_static(b"pong")).await.unwrap();
+		assert_eq!(dialer.recv_datagram().await.unwrap(), "pong");
 	}
 }
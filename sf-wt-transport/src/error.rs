@@ -9,10 +9,20 @@ pub enum Error {
 	#[error("invalid quic endpoint {0}")]
 	InvalidQuicEndpoint(anyhow::Error),
 
-	#[cfg(not(target_arch = "wasm32"))]
 	#[error("invalid multiaddr: {0}")]
 	InvalidMultiaddr(Multiaddr),
 
+	#[cfg(not(target_arch = "wasm32"))]
+	#[error("certificate error: {0}")]
+	Certificate(anyhow::Error),
+
+	#[error("invalid certificate fingerprint: {0}")]
+	InvalidFingerprint(String),
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[error("no address found for host {0}")]
+	UnresolvedHost(String),
+
 	#[cfg(not(target_arch = "wasm32"))]
 	#[error("invalid web transport session: {0}")]
 	InvalidWebTransportSession(web_transport::Error),
@@ -34,6 +44,9 @@ pub enum Error {
 	#[error("web transport error: {0}")]
 	WebTransport(web_transport::Error),
 
+	#[error(transparent)]
+	Unsupported(#[from] sf_core::Unsupported),
+
 	#[error("moq transfork error: {0}")]
 	MoqTransfork(moq_transfork::Error),
 }
@@ -1,3 +1,6 @@
+mod address;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod certificate;
 pub mod connection;
 pub mod error;
 mod listener;
@@ -6,30 +9,38 @@ pub mod stream;
 
 use std::{
 	collections::VecDeque,
-	net::SocketAddr,
 	pin::Pin,
 	task::{Context, Poll},
 };
 
-use futures::{StreamExt, future::BoxFuture};
+use futures::{FutureExt, StreamExt, future::BoxFuture};
 
+use address::DialAddr;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use certificate::{CertificateConfig, CertificateManager};
 pub use connection::Connection;
 pub use error::Error;
 pub use listener::Listener;
 use moq_native::quic;
 use multiaddr::{Multiaddr, PeerId};
-use sf_core::{Protocol, Transport, TransportEvent};
+use sf_core::{Listener as _, ListenerId, Protocol, Transport, TransportEvent};
 pub use stream::Stream;
 
 pub struct WebTransport {
 	#[cfg(not(target_arch = "wasm32"))]
 	config: quic::Config,
-	/// Allow dialing the MA by tcp to get the fingerprint.
+	/// Serve the certificate fingerprint over plain HTTP, and fetch it when dialing an address without `/certhash/`.
+	///
+	/// The fetched fingerprint is not authenticated, only use this with peers that do not publish their certhashes.
 	allow_tcp_fingerprint: bool,
+	/// Rotates the served certificates, set when they are self-signed by the transport.
+	#[cfg(not(target_arch = "wasm32"))]
+	certificates: Option<CertificateManager>,
 
-	pending_events: VecDeque<TransportEvent>,
+	pending_events: VecDeque<TransportEvent<Connection>>,
 
-	listener: Option<Listener>,
+	listeners: Vec<Listener>,
 }
 
 impl WebTransport {
@@ -38,15 +49,42 @@ impl WebTransport {
 		Self {
 			config,
 			allow_tcp_fingerprint,
+			certificates: None,
 			pending_events: VecDeque::new(),
-			listener: None,
+			listeners: Vec::new(),
 		}
 	}
 
+	/// Serve the self-signed certificates of `certificates`, publishing their hashes as they rotate.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn self_signed(
+		bind: std::net::SocketAddr,
+		certificates: CertificateManager,
+		allow_tcp_fingerprint: bool,
+	) -> Result<Self, Error> {
+		let tls = certificates.tls_config()?;
+		let mut transport = Self::new(quic::Config { bind, tls }, allow_tcp_fingerprint);
+		transport.certificates = Some(certificates);
+		Ok(transport)
+	}
+
 	#[cfg(target_arch = "wasm32")]
 	pub fn new(allow_tcp_fingerprint: bool) -> Self {
 		Self { allow_tcp_fingerprint }
 	}
+
+	/// Report the addresses of a stopped listener as expired, then the listener as closed.
+	fn close_listener(&mut self, listener: Listener) {
+		let listener_id = listener.id();
+		for address in listener.addresses() {
+			self.pending_events.push_back(TransportEvent::AddrExpired {
+				listener_id,
+				address: address.clone(),
+			});
+		}
+		self.pending_events
+			.push_back(TransportEvent::ListenerClosed { listener_id });
+	}
 }
 
 impl Transport for WebTransport {
@@ -59,63 +97,102 @@ impl Transport for WebTransport {
 	}
 
 	fn dial(&self, _peer_id: PeerId, ma: Multiaddr) -> Self::Dial {
-		let (addr, peer_id) = remote_ma_to_socketaddr(&ma).unwrap();
-		tracing::debug!(%addr, ?peer_id, "dial");
+		let addr = match DialAddr::parse(&ma) {
+			Ok(addr) => addr,
+			Err(error) => return futures::future::ready(Err(error)).boxed(),
+		};
+		tracing::debug!(?addr, "dial");
 
 		let allow_tcp_fingerprint = self.allow_tcp_fingerprint;
+		let tls = self.config.tls.clone();
+		let endpoints: Vec<_> = self
+			.listeners
+			.iter()
+			.map(|listener| listener.endpoint().clone())
+			.collect();
 
 		Box::pin(async move {
-			let fingerprint = if allow_tcp_fingerprint {
-				let response = reqwest::get(format!("http://{}:{}/fingerprint", addr.ip(), addr.port()))
+			let resolved = addr.resolve().await?;
+
+			// Pin the certificates published in the address, falling back to the HTTP fingerprint when opted in.
+			let certhashes = if addr.certhashes.is_empty() && allow_tcp_fingerprint {
+				let response = reqwest::get(format!("http://{}:{}/fingerprint", resolved.ip(), resolved.port()))
 					.await
 					.map_err(Error::ReqwestError)?;
 				let fingerprint =
 					hex::decode(response.text().await.map_err(Error::ReqwestError)?).map_err(Error::HexError)?;
-				Some(fingerprint)
-			} else {
-				None
-			};
-
-			let client = web_transport::ClientBuilder::new()
-				.with_congestion_control(web_transport::CongestionControl::LowLatency);
-
-			let client = if let Some(fingerprint) = fingerprint {
-				client
-					.with_server_certificate_hashes(vec![fingerprint])
-					.map_err(Error::WebTransport)?
+				vec![fingerprint]
 			} else {
-				client.with_system_roots().map_err(Error::WebTransport)?
+				addr.certhashes.clone()
 			};
 
-			let url = url_from_socket_addr(addr, "https");
+			// Send from a listening socket when one reaches the peer, so that hole punches open the listen port.
+			let endpoint = endpoints.into_iter().find(|endpoint| {
+				endpoint
+					.local_addr()
+					.is_ok_and(|local| platform::reaches(local, resolved))
+			});
+			let client = platform::client(&tls, endpoint, certhashes)?;
 
-			let session = client.connect(&url).await.map_err(Error::WebTransport)?;
+			let url = addr.url(resolved);
+			let session: web_transport::Session = client
+				.connect(&url)
+				.await
+				.map_err(|error| Error::WebTransport(error.into()))?
+				.into();
 			//let session = moq_transfork::Session::connect(session)
 			//	.await
 			//	.map_err(Error::MoqTransfork)?;
 
-			Ok(Connection::from(session))
+			Ok(Connection::new(session, ma))
 		})
 	}
 
-	fn listen_on(&mut self, addr: Multiaddr) -> Result<(), Self::Error> {
-		let listener = platform::listen_on(&self.config, self.allow_tcp_fingerprint, addr.clone())?;
+	fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, Self::Error> {
+		let id = ListenerId::next();
+		let listener = platform::listen_on(
+			&self.config,
+			self.allow_tcp_fingerprint,
+			self.certificates.as_ref(),
+			id,
+			addr,
+		)?;
+		self.listeners.push(listener);
+		Ok(id)
+	}
 
-		self.pending_events
-			.push_back(TransportEvent::ListenAddr { address: addr });
-		self.listener = Some(listener);
-		Ok(())
+	fn remove_listener(&mut self, id: ListenerId) -> bool {
+		let Some(position) = self.listeners.iter().position(|listener| listener.id() == id) else {
+			return false;
+		};
+		let listener = self.listeners.remove(position);
+		self.close_listener(listener);
+		true
+	}
+
+	fn shutdown(&mut self) {
+		self.pending_events.clear();
+		self.listeners.clear();
 	}
 
 	#[tracing::instrument(level = "trace", name = "Transport::poll", skip(self, cx))]
-	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent> {
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Connection>> {
 		if let Some(event) = self.pending_events.pop_front() {
 			return Poll::Ready(event);
 		}
 
-		if let Some(listener) = self.listener.as_mut() {
-			if let Poll::Ready(Some(event)) = listener.poll_next_unpin(cx) {
-				return Poll::Ready(event);
+		let mut i = 0;
+		while i < self.listeners.len() {
+			match self.listeners[i].poll_next_unpin(cx) {
+				Poll::Ready(Some(event)) => return Poll::Ready(event),
+				Poll::Ready(None) => {
+					let listener = self.listeners.remove(i);
+					self.close_listener(listener);
+					if let Some(event) = self.pending_events.pop_front() {
+						return Poll::Ready(event);
+					}
+				}
+				Poll::Pending => i += 1,
 			}
 		}
 
@@ -123,53 +200,187 @@ impl Transport for WebTransport {
 	}
 }
 
-fn url_from_socket_addr(addr: SocketAddr, scheme: &str) -> url::Url {
-	let host = match addr.ip() {
-		std::net::IpAddr::V6(ipv6) => format!("[{}]", ipv6), // brackets required for IPv6 in URLs
-		ip => ip.to_string(),
-	};
-	let url_str = format!("{}://{}:{}", scheme, host, addr.port());
-	url::Url::parse(&url_str).expect("invalid URL")
-}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::HashMap;
+
+	use crate::address::without_certhashes;
 
-fn remote_ma_to_socketaddr(ma: &Multiaddr) -> Result<(SocketAddr, Option<PeerId>), Error> {
-	if let Some((addr, peer_id)) = multiaddr_to_socketaddr(ma) {
-		return Ok((addr, peer_id));
+	fn transport() -> WebTransport {
+		let tls = moq_native::tls::Args {
+			self_sign: vec!["localhost".into()],
+			..Default::default()
+		}
+		.load()
+		.unwrap();
+		let bind = "127.0.0.1:0".parse().unwrap();
+		WebTransport::new(quic::Config { bind, tls }, false)
 	}
-	Err(Error::InvalidMultiaddr(ma.clone()))
-}
 
-fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Option<(SocketAddr, Option<PeerId>)> {
-	let mut iter = addr.iter();
-	let proto1 = iter.next()?;
-	let proto2 = iter.next()?;
-	// quic version
-	let _ = iter.next()?;
-	// webtransport part
-	let proto3 = iter.next()?;
-
-	match proto3 {
-		multiaddr::Protocol::WebTransport => {}
-		_ => return None,
-	}
-
-	let mut peer_id = None;
-	for proto in iter {
-		match proto {
-			multiaddr::Protocol::P2p(id) => {
-				peer_id = Some(id);
+	async fn next_event(transport: &mut WebTransport) -> TransportEvent<Connection> {
+		futures::future::poll_fn(|cx| Pin::new(&mut *transport).poll(cx)).await
+	}
+
+	/// A dialed connection and the listener side of it, the listener being kept alive alongside.
+	pub(crate) async fn connected() -> (Connection, Connection, WebTransport) {
+		let mut listener = transport();
+		listener
+			.listen_on("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap())
+			.unwrap();
+		let TransportEvent::ListenAddr { address, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+
+		let accept = async {
+			loop {
+				if let TransportEvent::NewConnection { connection, .. } = next_event(&mut listener).await {
+					break connection;
+				}
 			}
-			_ => return None,
-		}
+		};
+		let (dialed, accepted) = tokio::join!(transport().dial(PeerId::random(), address), accept);
+		(dialed.unwrap(), accepted, listener)
 	}
 
-	match (proto1, proto2) {
-		(multiaddr::Protocol::Ip4(ip), multiaddr::Protocol::Udp(port)) => {
-			Some((SocketAddr::new(ip.into(), port), peer_id))
-		}
-		(multiaddr::Protocol::Ip6(ip), multiaddr::Protocol::Udp(port)) => {
-			Some((SocketAddr::new(ip.into(), port), peer_id))
+	#[tokio::test]
+	async fn test_multiple_listeners() {
+		let mut transport = transport();
+		let address: Multiaddr = "/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap();
+
+		let first = transport.listen_on(address.clone()).unwrap();
+		let second = transport.listen_on(address).unwrap();
+		assert_ne!(first, second);
+
+		let mut addresses = HashMap::new();
+		for _ in 0..2 {
+			let TransportEvent::ListenAddr { listener_id, address } = next_event(&mut transport).await else {
+				panic!("expected a listen address");
+			};
+			assert!(!address.iter().any(|protocol| protocol == multiaddr::Protocol::Udp(0)));
+			assert!(
+				address
+					.iter()
+					.any(|protocol| matches!(protocol, multiaddr::Protocol::Certhash(_)))
+			);
+			addresses.insert(listener_id, address);
 		}
-		_ => None,
+		assert_eq!(addresses.len(), 2);
+		assert_ne!(addresses[&first], addresses[&second]);
+
+		assert!(transport.remove_listener(first));
+		assert!(!transport.remove_listener(first));
+		assert!(matches!(
+			next_event(&mut transport).await,
+			TransportEvent::AddrExpired { listener_id, address } if listener_id == first && address == addresses[&first]
+		));
+		assert!(matches!(
+			next_event(&mut transport).await,
+			TransportEvent::ListenerClosed { listener_id } if listener_id == first
+		));
+		assert_eq!(transport.listeners.len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_dial_invalid_multiaddr() {
+		let address: Multiaddr = "/ip4/127.0.0.1/tcp/4433".parse().unwrap();
+
+		let result = transport().dial(PeerId::random(), address.clone()).await;
+
+		assert!(matches!(result, Err(Error::InvalidMultiaddr(invalid)) if invalid == address));
+	}
+
+	#[tokio::test]
+	async fn test_dial_pins_published_certhash() {
+		let mut listener = transport();
+		listener
+			.listen_on("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap())
+			.unwrap();
+		let TransportEvent::ListenAddr { address, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+
+		let accept = async {
+			loop {
+				if let TransportEvent::NewConnection { .. } = next_event(&mut listener).await {
+					break;
+				}
+			}
+		};
+		let (dialed, ()) = tokio::join!(transport().dial(PeerId::random(), address), accept);
+
+		dialed.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_dial_sends_from_listen_port() {
+		let mut listener = transport();
+		listener
+			.listen_on("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap())
+			.unwrap();
+		let TransportEvent::ListenAddr { address, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+		let mut dialer = transport();
+		dialer
+			.listen_on("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap())
+			.unwrap();
+		let TransportEvent::ListenAddr {
+			address: dialer_address,
+			..
+		} = next_event(&mut dialer).await
+		else {
+			panic!("expected a listen address");
+		};
+
+		let accept = async {
+			loop {
+				if let TransportEvent::NewConnection { address, .. } = next_event(&mut listener).await {
+					break address;
+				}
+			}
+		};
+		let (dialed, remote) = tokio::join!(dialer.dial(PeerId::random(), address), accept);
+
+		dialed.unwrap();
+		assert_eq!(remote, without_certhashes(&dialer_address));
+	}
+
+	#[tokio::test]
+	async fn test_rotated_certificates_keep_addresses_dialable() {
+		let certificates = CertificateManager::new(CertificateConfig::default()).unwrap();
+		let bind = "127.0.0.1:0".parse().unwrap();
+		let mut listener = WebTransport::self_signed(bind, certificates.clone(), false).unwrap();
+		listener
+			.listen_on("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().unwrap())
+			.unwrap();
+		let TransportEvent::ListenAddr { address: before, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+
+		certificates.rotate().unwrap();
+
+		assert!(matches!(
+			next_event(&mut listener).await,
+			TransportEvent::AddrExpired { address, .. } if address == before
+		));
+		let TransportEvent::ListenAddr { address: after, .. } = next_event(&mut listener).await else {
+			panic!("expected a listen address");
+		};
+		assert_ne!(after, before);
+		assert_eq!(listener.listeners[0].addresses(), [after]);
+
+		// The address published before the rotation already pinned the certificate now served.
+		let accept = async {
+			loop {
+				if let TransportEvent::NewConnection { .. } = next_event(&mut listener).await {
+					break;
+				}
+			}
+		};
+		let (dialed, ()) = tokio::join!(transport().dial(PeerId::random(), before), accept);
+
+		dialed.unwrap();
 	}
 }
@@ -1,60 +1,143 @@
-use futures::{Stream, ready};
-use moq_native::quic;
+use anyhow::Context as _;
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream, StreamExt, ready};
+use multiaddr::multihash::Multihash;
 use multiaddr::{Multiaddr, Protocol};
-use sf_core::{Connection as ConnectionTrait, Listener as ListenerTrait, TransportEvent};
+use sf_core::{Connection as ConnectionTrait, Listener as ListenerTrait, ListenerId, TransportEvent};
+use std::collections::VecDeque;
 use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
+use web_transport::quinn::quinn;
 
+use crate::address::{with_certhashes, without_certhashes};
 use crate::connection::Connection;
 use crate::error::Error;
 
 pub struct Listener {
+	id: ListenerId,
 	bind: SocketAddr,
 	handle: Option<hyper_serve::Handle>,
 	addr: Multiaddr,
 
+	/// Socket the listener accepts on, also used to dial so that dials leave from the listen port.
+	endpoint: quinn::Endpoint,
 	accept: tokio::sync::mpsc::Receiver<web_transport::quinn::Session>,
+	/// Accepts the QUIC connections, aborted on drop to stop accepting.
+	accept_task: tokio::task::JoinHandle<()>,
 	if_watcher: Option<if_watch::tokio::IfWatcher>,
+	/// Hashes of the served certificates, published in every listen address.
+	certhashes: Vec<Multihash<64>>,
+	/// New hashes after each certificate rotation.
+	certhash_updates: Option<mpsc::UnboundedReceiver<Vec<Multihash<64>>>>,
 
-	pending_event: Option<<Self as Stream>::Item>,
+	pending_events: VecDeque<<Self as Stream>::Item>,
 	accept_ready: bool,
+
+	/// Addresses reported through [`TransportEvent::ListenAddr`] and not expired since.
+	addresses: Vec<Multiaddr>,
 }
 
 impl Listener {
 	pub fn new(
-		mut quic: quic::Server,
+		id: ListenerId,
+		endpoint: quinn::Endpoint,
 		bind: SocketAddr,
 		handle: Option<hyper_serve::Handle>,
 		addr: Multiaddr,
-		if_watcher: Option<if_watch::tokio::IfWatcher>,
-		pending_event: Option<<Self as Stream>::Item>,
-	) -> Self {
+		certhashes: Vec<Multihash<64>>,
+		certhash_updates: Option<mpsc::UnboundedReceiver<Vec<Multihash<64>>>>,
+	) -> Result<Self, Error> {
+		// Report the bound port rather than the requested one, which may be 0.
+		let mut pending_events = VecDeque::new();
+		let if_watcher = if bind.ip().is_unspecified() {
+			Some(if_watch::tokio::IfWatcher::new().map_err(Error::Io)?)
+		} else {
+			pending_events.push_back(TransportEvent::ListenAddr {
+				listener_id: id,
+				address: with_certhashes(socketaddr_to_multiaddr(&bind), &certhashes),
+			});
+			None
+		};
+
 		let (tx, rx) = tokio::sync::mpsc::channel(16);
 
-		tokio::spawn(async move {
-			while let Some(session) = quic.accept().await {
-				if tx.send(session).await.is_err() {
-					break;
-				}
-			}
-		});
+		let accept_task = tokio::spawn(accept(endpoint.clone(), tx));
 
-		Self {
+		Ok(Self {
+			id,
+			endpoint,
 			accept: rx,
+			accept_task,
 			bind,
 			handle,
 			addr,
 			if_watcher,
-			pending_event,
+			certhashes,
+			certhash_updates,
+			pending_events,
 			accept_ready: false,
+			addresses: Vec::new(),
+		})
+	}
+
+	/// Addresses the listener currently reports.
+	pub fn addresses(&self) -> &[Multiaddr] {
+		&self.addresses
+	}
+
+	/// QUIC endpoint of the listener.
+	pub(crate) fn endpoint(&self) -> &quinn::Endpoint {
+		&self.endpoint
+	}
+
+	/// Keep track of the addresses reported by `event`.
+	fn track(&mut self, event: TransportEvent<Connection>) -> TransportEvent<Connection> {
+		match &event {
+			TransportEvent::ListenAddr { address, .. } if !self.addresses.contains(address) => {
+				self.addresses.push(address.clone());
+			}
+			TransportEvent::AddrExpired { address, .. } => self.addresses.retain(|a| a != address),
+			_ => {}
+		}
+		event
+	}
+
+	/// Republish every address with the certhashes of the rotated certificates.
+	fn poll_certhash_update(&mut self, cx: &mut Context<'_>) {
+		let Some(updates) = self.certhash_updates.as_mut() else {
+			return;
+		};
+		while let Poll::Ready(update) = updates.poll_next_unpin(cx) {
+			let Some(certhashes) = update else {
+				self.certhash_updates = None;
+				return;
+			};
+			// Track the new addresses right away, a later update must replace them rather than the queued ones.
+			for address in &mut self.addresses {
+				let expired = std::mem::replace(address, with_certhashes(without_certhashes(address), &certhashes));
+				self.pending_events.push_back(TransportEvent::AddrExpired {
+					listener_id: self.id,
+					address: expired,
+				});
+				self.pending_events.push_back(TransportEvent::ListenAddr {
+					listener_id: self.id,
+					address: address.clone(),
+				});
+			}
+			self.certhashes = certhashes;
 		}
 	}
 }
 
 impl Drop for Listener {
 	fn drop(&mut self) {
+		self.accept_task.abort();
+		// Dialed connections keep the endpoint alive, refuse new ones now that nothing accepts them.
+		self.endpoint.set_server_config(None);
 		if let Some(handle) = self.handle.take() {
 			handle.graceful_shutdown(Some(Duration::from_secs(10)));
 		}
@@ -62,35 +145,35 @@ impl Drop for Listener {
 }
 
 impl Stream for Listener {
-	type Item = TransportEvent;
+	type Item = TransportEvent<Connection>;
 
 	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-		loop {
-			tracing::info!("poll_next");
-			if let Some(event) = self.pending_event.take() {
-				return Poll::Ready(Some(event));
+		tracing::trace!("poll_next");
+		self.poll_certhash_update(cx);
+		if let Some(event) = self.pending_events.pop_front() {
+			return Poll::Ready(Some(self.track(event)));
+		}
+		if let Poll::Ready(event) = self.poll_if_addr(cx) {
+			return Poll::Ready(Some(self.track(event)));
+		}
+
+		match ready!(self.accept.poll_recv(cx)) {
+			Some(session) => {
+				self.accept_ready = false;
+				let remote_address = remote_to_multiaddr(session.remote_address());
+				let connection = Connection::new(session.into(), remote_address);
+				let address = connection.remote_address().clone();
+				tracing::trace!(address = %address, "New connection");
+				Poll::Ready(Some(TransportEvent::NewConnection {
+					listener_id: self.id,
+					address,
+					connection,
+				}))
 			}
-			if let Poll::Ready(event) = self.poll_if_addr(cx) {
-				return Poll::Ready(Some(event));
+			None => {
+				tracing::debug!(listener_id = ?self.id, "QUIC server stopped accepting");
+				Poll::Ready(None)
 			}
-
-			match self.accept.poll_recv(cx) {
-				Poll::Ready(Some(session)) => {
-					self.accept_ready = false;
-					let connection = Connection::new(session.into());
-					let address = connection.remote_address().clone();
-					tracing::trace!(address = %address, "New connection");
-					return Poll::Ready(Some(TransportEvent::NewConnection { address }));
-				}
-				Poll::Ready(None) => {
-					tracing::info!("poll_next quic none");
-					// TODO: maybe shall close here ?
-					continue;
-				}
-				Poll::Pending => {}
-			};
-
-			return Poll::Pending;
 		}
 	}
 }
@@ -99,6 +182,10 @@ impl ListenerTrait for Listener {
 	type Error = Error;
 	type Connection = Connection;
 
+	fn id(&self) -> ListenerId {
+		self.id
+	}
+
 	fn local_address(&self) -> Multiaddr {
 		self.addr.clone()
 	}
@@ -111,37 +198,94 @@ impl ListenerTrait for Listener {
 		loop {
 			match ready!(if_watcher.poll_if_event(cx)) {
 				Ok(if_watch::IfEvent::Up(inet)) => {
-					if let Some(listen_addr) = ip_to_listenaddr(&self.bind, inet.addr()) {
+					if let Some(listen_addr) = ip_to_listenaddr(&self.bind, inet.addr(), &self.certhashes) {
 						tracing::debug!(address = %listen_addr, "New listen address");
-						return Poll::Ready(TransportEvent::ListenAddr { address: listen_addr });
+						return Poll::Ready(TransportEvent::ListenAddr {
+							listener_id: self.id,
+							address: listen_addr,
+						});
 					}
 				}
 				Ok(if_watch::IfEvent::Down(inet)) => {
-					if let Some(listen_addr) = ip_to_listenaddr(&self.bind, inet.addr()) {
+					if let Some(listen_addr) = ip_to_listenaddr(&self.bind, inet.addr(), &self.certhashes) {
 						tracing::debug!(address = %listen_addr, "Expired listen address");
-						return Poll::Ready(TransportEvent::AddrExpired { address: listen_addr });
+						return Poll::Ready(TransportEvent::AddrExpired {
+							listener_id: self.id,
+							address: listen_addr,
+						});
 					}
 				}
-				Err(error) => return Poll::Ready(TransportEvent::ListenError { error }),
+				Err(error) => {
+					return Poll::Ready(TransportEvent::ListenError {
+						listener_id: self.id,
+						error,
+					});
+				}
 			}
 		}
 	}
 }
 
+/// Accept the connections of `endpoint` and forward their sessions once established.
+async fn accept(endpoint: quinn::Endpoint, sessions: tokio::sync::mpsc::Sender<web_transport::quinn::Session>) {
+	let mut pending = FuturesUnordered::<BoxFuture<'static, anyhow::Result<web_transport::quinn::Session>>>::new();
+	loop {
+		tokio::select! {
+			incoming = endpoint.accept() => {
+				let Some(incoming) = incoming else {
+					return;
+				};
+				pending.push(accept_session(incoming).boxed());
+			}
+			Some(session) = pending.next() => match session {
+				Ok(session) => {
+					if sessions.send(session).await.is_err() {
+						return;
+					}
+				}
+				Err(error) => tracing::debug!(%error, "failed to accept session"),
+			},
+		}
+	}
+}
+
+/// Complete the handshake of `incoming`, answering the WebTransport `CONNECT` request for the h3 ALPN.
+async fn accept_session(incoming: quinn::Incoming) -> anyhow::Result<web_transport::quinn::Session> {
+	let mut connecting = incoming.accept()?;
+	let handshake = connecting
+		.handshake_data()
+		.await?
+		.downcast::<quinn::crypto::rustls::HandshakeData>()
+		.map_err(|_| anyhow::anyhow!("unexpected handshake data"))?;
+	let alpn = handshake.protocol.context("missing ALPN")?;
+	let connection = connecting.await.context("failed to establish QUIC connection")?;
+
+	match alpn.as_slice() {
+		web_transport::quinn::ALPN => {
+			let request = web_transport::quinn::Request::accept(connection)
+				.await
+				.context("failed to receive WebTransport request")?;
+			request.ok().await.context("failed to respond to WebTransport request")
+		}
+		moq_transfork::ALPN => Ok(connection.into()),
+		alpn => anyhow::bail!("unsupported ALPN: {}", String::from_utf8_lossy(alpn)),
+	}
+}
+
 unsafe impl Send for Listener {}
 
 unsafe impl Sync for Listener {}
 
-fn ip_to_listenaddr(endpoint_addr: &SocketAddr, ip: IpAddr) -> Option<Multiaddr> {
+fn ip_to_listenaddr(endpoint_addr: &SocketAddr, ip: IpAddr, certhashes: &[Multihash<64>]) -> Option<Multiaddr> {
 	// True if either both addresses are Ipv4 or both Ipv6.
 	if !is_same(&endpoint_addr.ip(), &ip) {
 		return None;
 	}
 	let socket_addr = SocketAddr::new(ip, endpoint_addr.port());
-	Some(socketaddr_to_multiaddr(&socket_addr))
+	Some(with_certhashes(socketaddr_to_multiaddr(&socket_addr), certhashes))
 }
 
-fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
+pub(crate) fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
 	Multiaddr::empty()
 		.with(socket_addr.ip().into())
 		.with(Protocol::Udp(socket_addr.port()))
@@ -149,6 +293,37 @@ fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
 		.with(Protocol::WebTransport)
 }
 
+/// Dual-stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses, report them as plain IPv4.
+fn remote_to_multiaddr(remote: SocketAddr) -> Multiaddr {
+	socketaddr_to_multiaddr(&SocketAddr::new(remote.ip().to_canonical(), remote.port()))
+}
+
 fn is_same(a: &IpAddr, b: &IpAddr) -> bool {
 	matches!((a, b), (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_remote_to_multiaddr() {
+		let v4: SocketAddr = "192.168.1.2:4433".parse().unwrap();
+		assert_eq!(
+			remote_to_multiaddr(v4),
+			"/ip4/192.168.1.2/udp/4433/quic-v1/webtransport".parse().unwrap()
+		);
+
+		let v6: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+		assert_eq!(
+			remote_to_multiaddr(v6),
+			"/ip6/2001:db8::1/udp/4433/quic-v1/webtransport".parse().unwrap()
+		);
+
+		let mapped: SocketAddr = "[::ffff:10.0.0.1]:9000".parse().unwrap();
+		assert_eq!(
+			remote_to_multiaddr(mapped),
+			"/ip4/10.0.0.1/udp/9000/quic-v1/webtransport".parse().unwrap()
+		);
+	}
+}
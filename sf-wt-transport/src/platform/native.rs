@@ -1,4 +1,4 @@
-use crate::{Error, Listener};
+use crate::{Error, Listener, address::certhash, certificate::CertificateManager};
 
 use axum::{
 	Router,
@@ -6,16 +6,26 @@ use axum::{
 };
 use core::net;
 use hyper_serve::accept::DefaultAcceptor;
-use moq_native::quic;
+use moq_native::{quic, tls};
 use multiaddr::{Multiaddr, Protocol};
-use sf_core::TransportEvent;
+use ring::digest::{SHA256, digest};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use sf_core::ListenerId;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::instrument;
+use web_transport::quinn::quinn;
+
+/// Fingerprint of the certificate currently served, read on every request as it changes on rotation.
+type Fingerprint = Arc<dyn Fn() -> String + Send + Sync>;
 
 pub(crate) struct WebConfig {
 	pub(crate) bind: net::SocketAddr,
-	pub(crate) tls: moq_native::tls::Config,
+	pub(crate) fingerprint: Fingerprint,
 }
 
 pub fn extract_ip_port(addr: Multiaddr) -> Result<(IpAddr, u16), Error> {
@@ -47,42 +57,165 @@ pub fn extract_ip_port(addr: Multiaddr) -> Result<(IpAddr, u16), Error> {
 	}
 }
 
-pub fn listen_on(config: &quic::Config, allow_tcp_fingerprint: bool, addr: Multiaddr) -> Result<Listener, Error> {
+pub fn listen_on(
+	config: &quic::Config,
+	allow_tcp_fingerprint: bool,
+	certificates: Option<&CertificateManager>,
+	id: ListenerId,
+	addr: Multiaddr,
+) -> Result<Listener, Error> {
 	let (ip, port) = extract_ip_port(addr.clone())?;
 	let bind = SocketAddr::new(ip, port);
-	let (if_watcher, pending_event) = if bind.ip().is_unspecified() {
-		(Some(if_watch::tokio::IfWatcher::new().map_err(Error::Io)?), None)
-	} else {
-		(None, Some(TransportEvent::ListenAddr { address: addr.clone() }))
-	};
-
-	let quic = quic::Endpoint::new(quic::Config {
-		bind,
-		tls: config.tls.clone(),
-	})
-	.map_err(Error::InvalidQuicEndpoint)?;
-	let server = quic.server.ok_or(Error::InvalidServer)?;
 
-	let local_addr = server.local_addr().map_err(Error::InvalidQuicEndpoint)?;
+	let endpoint = endpoint(bind, &config.tls)?;
+	let local_addr = endpoint.local_addr().map_err(Error::Io)?;
+	let (certhashes, certhash_updates) = match certificates {
+		Some(certificates) => (certificates.certhashes(), Some(certificates.subscribe())),
+		None => (
+			config
+				.tls
+				.fingerprints
+				.iter()
+				.map(|fingerprint| certhash(fingerprint))
+				.collect::<Result<Vec<_>, _>>()?,
+			None,
+		),
+	};
 
 	let mut handle = None;
 	if allow_tcp_fingerprint {
+		let fingerprint: Fingerprint = match certificates {
+			Some(certificates) => {
+				let certificates = certificates.clone();
+				Arc::new(move || certificates.fingerprint())
+			}
+			None => {
+				let fingerprint = config.tls.fingerprints.first().expect("missing certificate").clone();
+				Arc::new(move || fingerprint.clone())
+			}
+		};
 		let web_server = Web::new(WebConfig {
 			bind: local_addr,
-			tls: config.tls.clone(),
+			fingerprint,
 		});
 		handle = Some(web_server.handle.clone());
 		tokio::spawn(async move { web_server.run().await.expect("failed to start web server") });
 	}
 
-	Ok(Listener::new(
-		server,
-		local_addr,
-		handle,
-		addr,
-		if_watcher,
-		pending_event,
-	))
+	Listener::new(id, endpoint, local_addr, handle, addr, certhashes, certhash_updates)
+}
+
+/// Transport settings of every connection, the same as `moq_native` uses.
+fn transport_config() -> Arc<quinn::TransportConfig> {
+	let mut transport = quinn::TransportConfig::default();
+	transport.max_idle_timeout(Some(Duration::from_secs(30).try_into().expect("valid idle timeout")));
+	transport.keep_alive_interval(Some(Duration::from_secs(10)));
+	transport.congestion_controller_factory(Arc::new(quinn::congestion::BbrConfig::default()));
+	transport.mtu_discovery_config(None);
+	Arc::new(transport)
+}
+
+/// QUIC endpoint accepting WebTransport sessions on `bind`.
+///
+/// Dials are sent from the same socket, so that they leave from the listen port and open NAT mappings for it.
+fn endpoint(bind: SocketAddr, tls: &tls::Config) -> Result<quinn::Endpoint, Error> {
+	let mut crypto = tls.server.clone().ok_or(Error::InvalidServer)?;
+	crypto.alpn_protocols = vec![web_transport::quinn::ALPN.to_vec(), moq_transfork::ALPN.to_vec()];
+	let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(crypto)
+		.map_err(|error| Error::InvalidQuicEndpoint(error.into()))?;
+	let mut server = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+	server.transport_config(transport_config());
+
+	let runtime = quinn::default_runtime().ok_or(Error::InvalidQuicEndpoint(anyhow::anyhow!("no async runtime")))?;
+	let socket = std::net::UdpSocket::bind(bind).map_err(Error::Io)?;
+	quinn::Endpoint::new(quinn::EndpointConfig::default(), Some(server), socket, runtime).map_err(Error::Io)
+}
+
+/// WebTransport client sending from `endpoint`, or from a fresh socket when there is none.
+///
+/// The server certificate is pinned to one of `certhashes`, or verified against the roots of `tls` when empty.
+pub(crate) fn client(
+	tls: &tls::Config,
+	endpoint: Option<quinn::Endpoint>,
+	certhashes: Vec<Vec<u8>>,
+) -> Result<web_transport::quinn::Client, Error> {
+	let mut crypto = if certhashes.is_empty() {
+		tls.client.clone()
+	} else {
+		let provider = tls.client.crypto_provider().clone();
+		rustls::ClientConfig::builder_with_provider(provider.clone())
+			.with_protocol_versions(&[&rustls::version::TLS13])
+			.map_err(|error| Error::Certificate(error.into()))?
+			.dangerous()
+			.with_custom_certificate_verifier(Arc::new(Certhashes { provider, certhashes }))
+			.with_no_client_auth()
+	};
+	crypto.alpn_protocols = vec![web_transport::quinn::ALPN.to_vec()];
+	let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+		.map_err(|error| Error::InvalidQuicEndpoint(error.into()))?;
+	let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+	config.transport_config(transport_config());
+
+	let endpoint = match endpoint {
+		Some(endpoint) => endpoint,
+		None => quinn::Endpoint::client(SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0))).map_err(Error::Io)?,
+	};
+	Ok(web_transport::quinn::Client::new(endpoint, config))
+}
+
+/// Whether a socket bound to `local` can send to `remote`.
+pub(crate) fn reaches(local: SocketAddr, remote: SocketAddr) -> bool {
+	let (local, remote) = (local.ip(), remote.ip().to_canonical());
+	local.is_ipv4() == remote.is_ipv4() && (local.is_unspecified() || local.is_loopback() == remote.is_loopback())
+}
+
+/// Accepts the server certificates whose SHA-256 digest is one of `certhashes`.
+#[derive(Debug)]
+struct Certhashes {
+	provider: Arc<rustls::crypto::CryptoProvider>,
+	certhashes: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for Certhashes {
+	fn verify_server_cert(
+		&self,
+		end_entity: &CertificateDer<'_>,
+		_intermediates: &[CertificateDer<'_>],
+		_server_name: &ServerName<'_>,
+		_ocsp_response: &[u8],
+		_now: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		let hash = digest(&SHA256, end_entity);
+		if self.certhashes.iter().any(|certhash| certhash == hash.as_ref()) {
+			Ok(ServerCertVerified::assertion())
+		} else {
+			Err(rustls::Error::InvalidCertificate(
+				rustls::CertificateError::UnknownIssuer,
+			))
+		}
+	}
+
+	fn verify_tls12_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+	}
+
+	fn verify_tls13_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		self.provider.signature_verification_algorithms.supported_schemes()
+	}
 }
 
 struct Web {
@@ -93,11 +226,9 @@ struct Web {
 
 impl Web {
 	pub fn new(config: WebConfig) -> Self {
-		let fingerprint = config.tls.fingerprints.first().expect("missing certificate").clone();
-
 		let app = axum::Router::new()
 			.route("/fingerprint", axum::routing::get(get_fingerprint))
-			.layer(Extension(fingerprint.clone()))
+			.layer(Extension(config.fingerprint))
 			.layer(CorsLayer::new().allow_origin(Any).allow_methods(Any));
 
 		let handle = hyper_serve::Handle::new();
@@ -117,7 +248,7 @@ impl Web {
 #[instrument(name = "get_fingerprint", skip_all, fields(remote_addr = %addr))]
 async fn get_fingerprint(
 	ConnectInfo(addr): ConnectInfo<SocketAddr>,
-	Extension(fingerprint): Extension<String>,
+	Extension(fingerprint): Extension<Fingerprint>,
 ) -> String {
-	fingerprint
+	fingerprint()
 }
@@ -1,24 +1,206 @@
-use bytes::Bytes;
-use futures::{AsyncRead, AsyncWrite, FutureExt, ready};
+use bytes::{Buf, BufMut, Bytes};
+use futures::{AsyncRead, AsyncWrite, FutureExt, future::BoxFuture, ready};
 use std::{
+	fmt,
+	future::poll_fn,
 	io,
 	pin::Pin,
 	task::{Context, Poll},
 };
+use sync_wrapper::SyncWrapper;
 
+/// Largest chunk requested from the receive stream at once.
+const MAX_READ_CHUNK: usize = 64 * 1024;
+/// Default largest part of a caller's buffer accepted by a single [`AsyncWrite::poll_write`].
+const DEFAULT_SEND_WINDOW: usize = 64 * 1024;
+
+/// Write in flight, owning the send stream until it completes.
+type Write = SyncWrapper<BoxFuture<'static, (web_transport::SendStream, Result<(), web_transport::Error>)>>;
+/// Read in flight, owning the receive stream until it completes.
+type Read = SyncWrapper<BoxFuture<'static, (web_transport::RecvStream, Result<Option<Bytes>, web_transport::Error>)>>;
+
+/// A WebTransport stream, bidirectional or only one of its halves for unidirectional streams.
+///
+/// Reads and writes are futures kept across polls, so a pending operation is resumed rather than restarted. Written
+/// bytes are accepted as soon as no previous write is in flight and sent in the background, flushing waits for them.
 pub struct Stream {
-	send_stream: web_transport::SendStream,
-	recv_stream: web_transport::RecvStream,
-	read_buf: Option<Bytes>,
+	send_stream: Option<web_transport::SendStream>,
+	write: Option<Write>,
+	/// Length of the write in flight.
+	writing: usize,
+	finished: bool,
+	send_window: usize,
+	priority: i32,
+	/// Priority to apply once the write in flight gives the send stream back.
+	pending_priority: Option<i32>,
+
+	recv_stream: Option<web_transport::RecvStream>,
+	read: Option<Read>,
+	/// Received bytes not handed to the reader yet.
+	read_buf: Bytes,
+	eof: bool,
 }
 
 impl Stream {
 	pub fn new(send_stream: web_transport::SendStream, recv_stream: web_transport::RecvStream) -> Self {
 		Self {
-			send_stream,
-			recv_stream,
-			read_buf: None,
+			send_stream: Some(send_stream),
+			write: None,
+			writing: 0,
+			finished: false,
+			send_window: DEFAULT_SEND_WINDOW,
+			priority: 0,
+			pending_priority: None,
+			recv_stream: Some(recv_stream),
+			read: None,
+			read_buf: Bytes::new(),
+			eof: false,
+		}
+	}
+
+	/// A unidirectional stream opened locally, reading from it returns EOF right away.
+	pub fn send_only(send_stream: web_transport::SendStream) -> Self {
+		Self {
+			send_stream: Some(send_stream),
+			write: None,
+			writing: 0,
+			finished: false,
+			send_window: DEFAULT_SEND_WINDOW,
+			priority: 0,
+			pending_priority: None,
+			recv_stream: None,
+			read: None,
+			read_buf: Bytes::new(),
+			eof: true,
+		}
+	}
+
+	/// A unidirectional stream accepted from the remote, writing to it fails.
+	pub fn recv_only(recv_stream: web_transport::RecvStream) -> Self {
+		Self {
+			send_stream: None,
+			write: None,
+			writing: 0,
+			finished: true,
+			send_window: DEFAULT_SEND_WINDOW,
+			priority: 0,
+			pending_priority: None,
+			recv_stream: Some(recv_stream),
+			read: None,
+			read_buf: Bytes::new(),
+			eof: false,
+		}
+	}
+
+	/// Wait for the write in flight, if any, to complete.
+	pub fn poll_send_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if let Some(write) = &mut self.write {
+			let (mut send_stream, result) = ready!(write.get_mut().poll_unpin(cx));
+			self.write = None;
+			self.writing = 0;
+			if let Some(priority) = self.pending_priority.take() {
+				send_stream.set_priority(priority);
+			}
+			self.send_stream = Some(send_stream);
+			result.map_err(io::Error::other)?;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	/// Start writing `bytes` without copying them, [`Stream::poll_send_ready`] must have returned `Ready(Ok(()))`.
+	pub fn start_send_bytes(&mut self, bytes: Bytes) -> io::Result<()> {
+		if self.finished {
+			return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the send stream is finished"));
+		}
+		let Some(mut send_stream) = self.send_stream.take() else {
+			return Err(io::Error::other("a write is already in flight"));
+		};
+		self.writing = bytes.len();
+		self.write = Some(SyncWrapper::new(
+			async move {
+				let result = send_stream.write(&bytes).await;
+				(send_stream, result)
+			}
+			.boxed(),
+		));
+		Ok(())
+	}
+
+	/// Write all of `bytes` without copying them.
+	pub async fn write_bytes(&mut self, bytes: Bytes) -> io::Result<()> {
+		poll_fn(|cx| self.poll_send_ready(cx)).await?;
+		self.start_send_bytes(bytes)?;
+		poll_fn(|cx| self.poll_send_ready(cx)).await
+	}
+
+	/// Read the next received bytes, at most `max` of them, without copying.
+	///
+	/// Returns `None` once the remote finished the stream.
+	pub fn poll_read_bytes(&mut self, cx: &mut Context<'_>, max: usize) -> Poll<io::Result<Option<Bytes>>> {
+		ready!(self.poll_fill(cx))?;
+		if self.read_buf.is_empty() {
+			return Poll::Ready(Ok(None));
 		}
+		let len = max.min(self.read_buf.len());
+		Poll::Ready(Ok(Some(self.read_buf.split_to(len))))
+	}
+
+	/// Read the next received bytes, at most `max` of them, without copying.
+	pub async fn read_bytes(&mut self, max: usize) -> io::Result<Option<Bytes>> {
+		poll_fn(|cx| self.poll_read_bytes(cx, max)).await
+	}
+
+	/// Read received bytes into `buf`, returning how many were read, 0 once the remote finished the stream.
+	pub fn poll_read_buf<B: BufMut>(&mut self, cx: &mut Context<'_>, buf: &mut B) -> Poll<io::Result<usize>> {
+		if !buf.has_remaining_mut() {
+			return Poll::Ready(Ok(0));
+		}
+		ready!(self.poll_fill(cx))?;
+		let len = buf.remaining_mut().min(self.read_buf.len());
+		buf.put(self.read_buf.split_to(len));
+		Poll::Ready(Ok(len))
+	}
+
+	/// Wait until some received bytes are buffered or the remote finished the stream.
+	fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while self.read_buf.is_empty() && !self.eof {
+			let read = match &mut self.read {
+				Some(read) => read,
+				None => {
+					let Some(mut recv_stream) = self.recv_stream.take() else {
+						return Poll::Ready(Err(io::Error::other("missing receive stream")));
+					};
+					self.read.insert(SyncWrapper::new(
+						async move {
+							let result = recv_stream.read(MAX_READ_CHUNK).await;
+							(recv_stream, result)
+						}
+						.boxed(),
+					))
+				}
+			};
+
+			let (recv_stream, result) = ready!(read.get_mut().poll_unpin(cx));
+			self.read = None;
+			self.recv_stream = Some(recv_stream);
+			match result.map_err(io::Error::other)? {
+				Some(bytes) => self.read_buf = bytes,
+				None => self.eof = true,
+			}
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl fmt::Debug for Stream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Stream")
+			.field("writing", &self.writing)
+			.field("priority", &self.priority)
+			.field("finished", &self.finished)
+			.field("buffered", &self.read_buf.len())
+			.field("eof", &self.eof)
+			.finish_non_exhaustive()
 	}
 }
 
@@ -35,71 +217,190 @@ impl sf_core::Stream for Stream {
 
 	fn close_send(&mut self) -> futures::future::BoxFuture<'_, Result<(), Self::Error>> {
 		async move {
-			self.send_stream.finish()?;
-			Ok(())
+			poll_fn(|cx| Pin::new(&mut *self).poll_close(cx))
+				.await
+				.map_err(crate::Error::Io)
 		}
 		.boxed()
 	}
+
+	fn set_priority(&mut self, priority: i32) {
+		self.priority = priority;
+		match &mut self.send_stream {
+			Some(send_stream) => send_stream.set_priority(priority),
+			None => self.pending_priority = Some(priority),
+		}
+	}
+
+	fn priority(&self) -> i32 {
+		self.priority
+	}
+
+	fn set_send_window(&mut self, window: usize) {
+		self.send_window = window.max(1);
+	}
+
+	fn buffered_send(&self) -> usize {
+		self.writing
+	}
 }
 
 impl AsyncWrite for Stream {
 	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
-		let len = buf.len();
-		let fut = self.get_mut().send_stream.write(buf);
-		let mut fut = Box::pin(fut);
+		let this = self.get_mut();
+		ready!(this.poll_send_ready(cx))?;
 
-		match ready!(fut.as_mut().poll(cx)) {
-			Ok(_) => Poll::Ready(Ok(len)),
-			Err(e) => Poll::Ready(Err(io::Error::other(e))),
+		let len = buf.len().min(this.send_window);
+		this.start_send_bytes(Bytes::copy_from_slice(&buf[..len]))?;
+		// Get the write going, the bytes are accepted unless it fails right away.
+		if let Poll::Ready(Err(error)) = this.poll_send_ready(cx) {
+			return Poll::Ready(Err(error));
 		}
+		Poll::Ready(Ok(len))
 	}
 
-	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		Poll::Ready(Ok(()))
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.get_mut().poll_send_ready(cx)
 	}
 
-	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		match self.get_mut().send_stream.finish() {
-			Ok(_) => Poll::Ready(Ok(())),
-			Err(e) => Poll::Ready(Err(io::Error::other(e))),
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_send_ready(cx))?;
+		if !this.finished {
+			let send_stream = this.send_stream.as_mut().expect("no write in flight");
+			send_stream.finish().map_err(io::Error::other)?;
+			this.finished = true;
 		}
+		Poll::Ready(Ok(()))
 	}
 }
 
 impl AsyncRead for Stream {
-	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
-		if let Some(bytes) = &mut self.read_buf {
-			let len = buf.len();
-			buf[..len].copy_from_slice(&bytes[..len]);
-
-			if len < bytes.len() {
-				self.read_buf = Some(bytes.slice(len..));
-				return Poll::Ready(Ok(len));
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+		ready!(this.poll_fill(cx))?;
+
+		let len = buf.len().min(this.read_buf.len());
+		buf[..len].copy_from_slice(&this.read_buf[..len]);
+		this.read_buf.advance(len);
+		Poll::Ready(Ok(len))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::time::Duration;
+
+	use futures::{AsyncReadExt, AsyncWriteExt};
+	use sf_core::Connection as _;
+
+	use crate::tests::connected;
+
+	fn payload(len: usize) -> Vec<u8> {
+		(0..len).map(|i| (i % 251) as u8).collect()
+	}
+
+	/// Send `data` with `write_len` sized writes to a reader using `read_len` sized reads and pausing between them.
+	async fn transfer(data: &[u8], write_len: usize, read_len: usize) -> Vec<u8> {
+		let (mut dialer, mut listener, _transport) = connected().await;
+
+		let send = async {
+			let mut stream = dialer.open_stream().await.unwrap();
+			for chunk in data.chunks(write_len) {
+				stream.write_all(chunk).await.unwrap();
+			}
+			stream.close().await.unwrap();
+			stream
+		};
+		let recv = async {
+			let mut stream = listener.accept_stream().await.unwrap();
+			let mut received = Vec::new();
+			let mut buf = vec![0; read_len];
+			loop {
+				let len = stream.read(&mut buf).await.unwrap();
+				if len == 0 {
+					break received;
+				}
+				received.extend_from_slice(&buf[..len]);
+				if received.len() % 16 == 0 {
+					tokio::time::sleep(Duration::from_millis(1)).await;
+				}
 			}
+		};
 
-			self.read_buf = None;
-			return Poll::Ready(Ok(len));
-		}
+		let (_stream, received) = tokio::join!(send, recv);
+		received
+	}
+
+	#[tokio::test]
+	async fn test_small_buffers_slow_reader() {
+		let data = payload(256 * 1024);
+		assert_eq!(transfer(&data, 1000, 7).await, data);
+	}
+
+	#[tokio::test]
+	async fn test_large_buffers_slow_reader() {
+		let data = payload(4 * 1024 * 1024);
+		assert_eq!(transfer(&data, 1024 * 1024, 256 * 1024).await, data);
+	}
 
-		let bytes = {
-			let read_fut = self.recv_stream.read(8192);
-			let mut fut = Box::pin(read_fut);
-			match ready!(fut.as_mut().poll(cx)) {
-				Ok(Some(b)) => b,
-				Ok(None) => return Poll::Ready(Ok(0)), // EOF
-				Err(e) => return Poll::Ready(Err(io::Error::other(e))),
+	#[tokio::test]
+	async fn test_bytes_fast_path() {
+		let (mut dialer, mut listener, _transport) = connected().await;
+		let data = Bytes::from(payload(200 * 1024));
+
+		let send = async {
+			let mut stream = dialer.open_stream().await.unwrap();
+			stream.write_bytes(data.clone()).await.unwrap();
+			stream.close().await.unwrap();
+			stream
+		};
+		let recv = async {
+			let mut stream = listener.accept_stream().await.unwrap();
+			let mut received = Vec::new();
+			let first = stream.read_bytes(10).await.unwrap().unwrap();
+			assert!(first.len() <= 10);
+			received.extend_from_slice(&first);
+			poll_fn(|cx| stream.poll_read_buf(cx, &mut received)).await.unwrap();
+			while let Some(bytes) = stream.read_bytes(usize::MAX).await.unwrap() {
+				received.extend_from_slice(&bytes);
 			}
+			received
 		};
 
-		let len = buf.len().min(bytes.len());
-		buf[..len].copy_from_slice(&bytes[..len]);
+		let (_stream, received) = tokio::join!(send, recv);
+		assert_eq!(received, data);
+	}
 
-		if len < bytes.len() {
-			self.read_buf = Some(bytes.slice(len..));
-			return Poll::Ready(Ok(len));
-		}
+	#[tokio::test]
+	async fn test_send_window_and_priority() {
+		let (mut dialer, mut listener, _transport) = connected().await;
+		let data = payload(1000);
 
-		self.read_buf = None;
-		Poll::Ready(Ok(len))
+		let mut stream = dialer.open_stream().await.unwrap();
+		sf_core::Stream::set_send_window(&mut stream, 100);
+		sf_core::Stream::set_priority(&mut stream, 7);
+		assert_eq!(sf_core::Stream::priority(&stream), 7);
+
+		let written = poll_fn(|cx| Pin::new(&mut stream).poll_write(cx, &data)).await.unwrap();
+		assert_eq!(written, 100);
+		assert!(sf_core::Stream::buffered_send(&stream) <= 100);
+		// Changed while the write is in flight, applied once it completes.
+		sf_core::Stream::set_priority(&mut stream, -1);
+		stream.write_all(&data[written..]).await.unwrap();
+		stream.flush().await.unwrap();
+		assert_eq!(sf_core::Stream::buffered_send(&stream), 0);
+		assert_eq!(sf_core::Stream::priority(&stream), -1);
+		stream.close().await.unwrap();
+
+		let mut received = Vec::new();
+		let mut accepted = listener.accept_stream().await.unwrap();
+		accepted.read_to_end(&mut received).await.unwrap();
+		assert_eq!(received, data);
 	}
 }
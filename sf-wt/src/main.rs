@@ -5,12 +5,14 @@ mod proto {
 	include!("./proto/keep_alive.rs");
 }
 
-use crate::web::{Config as WebConfig, Web};
+use crate::web::{Config as WebConfig, Fingerprint, Web};
 use anyhow::Context;
 use clap::Parser;
 use connection::Connection;
 use moq_native::quic;
 use routes::Routes;
+use sf_wt_transport::{CertificateConfig, CertificateManager};
+use std::sync::Arc;
 use tracing::info;
 
 #[derive(Parser, Clone)]
@@ -39,15 +41,21 @@ async fn main() -> anyhow::Result<()> {
 		.context("invalid bind address")?;
 
 	let tls = config.tls.load()?;
-	if tls.server.is_none() {
-		anyhow::bail!("missing TLS certificates");
-	}
+	let (tls, fingerprint): (_, Fingerprint) = if tls.server.is_some() {
+		let fingerprint = tls.fingerprints.first().context("missing certificate")?.clone();
+		(tls, Arc::new(move || fingerprint.clone()))
+	} else {
+		info!("No TLS certificate given, serving self-signed ones");
+		let certificates = CertificateManager::new(CertificateConfig::default())?;
+		let tls = certificates.tls_config()?;
+		(tls, Arc::new(move || certificates.fingerprint()))
+	};
 
 	tracing::info!("TLS fingerprints: {:?}", tls.fingerprints);
-	let quic = quic::Endpoint::new(quic::Config { bind, tls: tls.clone() })?;
+	let quic = quic::Endpoint::new(quic::Config { bind, tls })?;
 	let mut server = quic.server.context("missing TLS certificate")?;
 
-	let web = Web::new(WebConfig { bind, tls });
+	let web = Web::new(WebConfig { bind, fingerprint });
 	tokio::spawn(async move { web.run().await.expect("failed to start web server") });
 
 	tracing::info!(addr = %bind, "listening");
@@ -5,12 +5,16 @@ use axum::{
 use core::net;
 use hyper_serve::accept::DefaultAcceptor;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::instrument;
 
+/// Fingerprint of the certificate currently served, read on every request as self-signed ones rotate.
+pub type Fingerprint = Arc<dyn Fn() -> String + Send + Sync>;
+
 pub struct Config {
 	pub bind: net::SocketAddr,
-	pub tls: moq_native::tls::Config,
+	pub fingerprint: Fingerprint,
 }
 
 pub struct Web {
@@ -20,11 +24,9 @@ pub struct Web {
 
 impl Web {
 	pub fn new(config: Config) -> Self {
-		let fingerprint = config.tls.fingerprints.first().expect("missing certificate").clone();
-
 		let app = axum::Router::new()
 			.route("/fingerprint", axum::routing::get(get_fingerprint))
-			.layer(Extension(fingerprint.clone()))
+			.layer(Extension(config.fingerprint))
 			.layer(CorsLayer::new().allow_origin(Any).allow_methods(Any));
 
 		let server = hyper_serve::bind(config.bind);
@@ -43,7 +45,7 @@ impl Web {
 #[instrument(name = "get_fingerprint", skip_all, fields(remote_addr = %addr))]
 async fn get_fingerprint(
 	ConnectInfo(addr): ConnectInfo<SocketAddr>,
-	Extension(fingerprint): Extension<String>,
+	Extension(fingerprint): Extension<Fingerprint>,
 ) -> String {
-	fingerprint
+	fingerprint()
 }